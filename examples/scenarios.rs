@@ -0,0 +1,162 @@
+//! Classic Hard Scenarios
+//!
+//! This example synthesizes the situations that challenge every echo canceller and prints the
+//! canceller's metrics over time, four times per second. It documents how the canceller
+//! behaves and doubles as a manual regression tool: run it before and after a change and
+//! compare the tables.
+//!
+//! - `double-talk`: far-end noise with near-end speech (a modulated tone) from 4 s to 6 s.
+//! - `path-change`: the echo path jumps to a different delay and gain at 4 s, as when the
+//!   device is moved.
+//! - `drift`: the far-end is rendered with a clock that runs 100 ppm fast, so the echo
+//!   slowly slides against the reference.
+//!
+//! Every row shows the ERLE of the last quarter second (mic power over output power), the
+//! long-term ERLE tracked by the canceller, its conversation state, and the delay of the
+//! strongest tap of the estimated echo path.
+//!
+//! ## How to Run
+//!
+//! ```sh
+//! cargo run --example scenarios --release -- --scenario double-talk
+//! cargo run --example scenarios --release -- --scenario all --coherence-dtd
+//! ```
+//! The `--release` flag is recommended for faster processing.
+
+use clap::{Parser, ValueEnum};
+use fdaf_aec::{CoherenceDtdConfig, FdafAec, FdafAecConfig};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const SAMPLE_RATE: usize = 16000;
+const DURATION_S: usize = 10;
+const FFT_SIZE: usize = 1024;
+const FRAME_SIZE: usize = FFT_SIZE / 2;
+/// Frames per printed row, a quarter second at 16 kHz.
+const ROW_FRAMES: usize = SAMPLE_RATE / 4 / FRAME_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Scenario {
+    DoubleTalk,
+    PathChange,
+    Drift,
+    All,
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// The scenario to run.
+    #[clap(long, value_enum, default_value = "all")]
+    scenario: Scenario,
+
+    /// The learning rate (mu) of the canceller.
+    #[clap(long, default_value_t = 0.1)]
+    step_size: f32,
+
+    /// Enable the coherence double-talk detector.
+    #[clap(long)]
+    coherence_dtd: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+    let scenarios = match args.scenario {
+        Scenario::All => vec![Scenario::DoubleTalk, Scenario::PathChange, Scenario::Drift],
+        scenario => vec![scenario],
+    };
+    for scenario in scenarios {
+        let (far_end, mic) = synthesize(scenario);
+        let config = FdafAecConfig {
+            fft_size: FFT_SIZE,
+            step_size: args.step_size,
+            coherence_dtd: args.coherence_dtd.then(CoherenceDtdConfig::default),
+            ..FdafAecConfig::default()
+        };
+        run(scenario, FdafAec::with_config(config), &far_end, &mic);
+    }
+}
+
+/// Returns the far-end and microphone signals of a scenario.
+fn synthesize(scenario: Scenario) -> (Vec<f32>, Vec<f32>) {
+    let len = SAMPLE_RATE * DURATION_S;
+    let mut rng = StdRng::seed_from_u64(7);
+    let far_end: Vec<f32> = (0..len).map(|_| rng.gen_range(-0.3..0.3)).collect();
+    let change = 4 * SAMPLE_RATE;
+
+    let mic = match scenario {
+        Scenario::DoubleTalk => {
+            let mut mic = convolve(&far_end, &[(40, 0.5), (120, -0.2)]);
+            // Near-end "speech": a 300 Hz tone with a 4 Hz syllable envelope, 4 s to 6 s.
+            for (i, sample) in mic.iter_mut().enumerate().take(6 * SAMPLE_RATE).skip(change) {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let envelope = (std::f32::consts::PI * 4.0 * t).sin().abs();
+                *sample += 0.3 * envelope * (2.0 * std::f32::consts::PI * 300.0 * t).sin();
+            }
+            mic
+        }
+        Scenario::PathChange => {
+            let before = convolve(&far_end, &[(40, 0.5), (120, -0.2)]);
+            let after = convolve(&far_end, &[(90, 0.3), (200, 0.25)]);
+            before[..change].iter().chain(&after[change..]).copied().collect()
+        }
+        Scenario::Drift => {
+            // The loudspeaker plays sample `i` of the reference at time `i / (1 + 100 ppm)`, so
+            // the microphone hears the reference at a slowly advancing fractional position.
+            let drifted: Vec<f32> = (0..len)
+                .map(|i| {
+                    let position = i as f64 * (1.0 + 100e-6);
+                    let (index, frac) = (position as usize, (position.fract()) as f32);
+                    let next = far_end.get(index + 1).copied().unwrap_or(0.0);
+                    far_end.get(index).map_or(0.0, |&x| x * (1.0 - frac) + next * frac)
+                })
+                .collect();
+            convolve(&drifted, &[(40, 0.5), (120, -0.2)])
+        }
+        Scenario::All => unreachable!("expanded in main"),
+    };
+    (far_end, mic)
+}
+
+/// Simulates an echo path given as sparse `(delay, gain)` taps.
+fn convolve(signal: &[f32], taps: &[(usize, f32)]) -> Vec<f32> {
+    (0..signal.len())
+        .map(|i| taps.iter().filter(|&&(delay, _)| i >= delay).map(|&(delay, gain)| signal[i - delay] * gain).sum())
+        .collect()
+}
+
+/// Processes the signals and prints one row of metrics per quarter second.
+fn run(scenario: Scenario, mut aec: FdafAec, far_end: &[f32], mic: &[f32]) {
+    println!("\n--- Scenario: {:?} ---", scenario);
+    println!("{:>6}  {:>10}  {:>10}  {:<12}  {:>8}  {:>9}", "time_s", "erle_db", "long_db", "state", "peak_tap", "dt_prob");
+
+    let handle = aec.metrics_handle();
+    let (mut mic_power, mut output_power) = (0.0f32, 0.0f32);
+    for (frame, (far_frame, mic_frame)) in far_end.chunks_exact(FRAME_SIZE).zip(mic.chunks_exact(FRAME_SIZE)).enumerate() {
+        let output = aec.process(far_frame, mic_frame);
+        mic_power += mic_frame.iter().map(|x| x * x).sum::<f32>();
+        output_power += output.iter().map(|x| x * x).sum::<f32>();
+
+        if (frame + 1) % ROW_FRAMES == 0 {
+            let snapshot = handle.snapshot();
+            let peak_tap = aec
+                .impulse_response()
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+                .map_or(0, |(i, _)| i);
+            let probability = aec.double_talk_probability().map_or("-".to_string(), |p| format!("{:.2}", p));
+            println!(
+                "{:>6.2}  {:>10.1}  {:>10.1}  {:<12}  {:>8}  {:>9}",
+                ((frame + 1) * FRAME_SIZE) as f32 / SAMPLE_RATE as f32,
+                10.0 * (mic_power / output_power.max(1e-12)).log10(),
+                snapshot.erle_db,
+                format!("{:?}", snapshot.duplex_state),
+                peak_tap,
+                probability,
+            );
+            mic_power = 0.0;
+            output_power = 0.0;
+        }
+    }
+}
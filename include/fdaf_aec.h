@@ -0,0 +1,87 @@
+/*
+ * C API of the fdaf-aec crate, built with the `ffi` feature.
+ *
+ * Link against the `cdylib` (libfdaf_aec.so, .dylib, fdaf_aec.dll) or the `staticlib`
+ * (libfdaf_aec.a, fdaf_aec.lib). See src/ffi.rs for the documentation of every item.
+ */
+
+#ifndef FDAF_AEC_H
+#define FDAF_AEC_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Bits of FdafAecStatsC.flags. */
+#define FDAF_AEC_FLAG_CONVERGED (1u << 0)
+#define FDAF_AEC_FLAG_ECHO_FREE (1u << 1)
+#define FDAF_AEC_FLAG_DOUBLE_TALK (1u << 2)
+/* Shift of the two-bit conversation state: 0 silence, 1 far-end only, 2 near-end only,
+ * 3 double talk. */
+#define FDAF_AEC_DUPLEX_STATE_SHIFT 3
+#define FDAF_AEC_FLAG_INPUTS_SWAPPED (1u << 5)
+#define FDAF_AEC_FLAG_POLARITY_INVERTED (1u << 6)
+#define FDAF_AEC_FLAG_OUTPUT_LOUDER (1u << 7)
+
+/* An echo canceller, created by fdaf_aec_create and freed by fdaf_aec_destroy. */
+typedef struct FdafAec FdafAec;
+
+/* The parameters of a canceller; every other parameter keeps its default. */
+typedef struct FdafAecConfigC {
+    /* The FFT size, a power of two. Frames have half as many samples. */
+    uint32_t fft_size;
+    /* The sample rate of the microphone signal, in Hz. */
+    uint32_t sample_rate;
+    /* The sample rate of the far-end signal, in Hz, or 0 if it equals sample_rate. */
+    uint32_t far_end_sample_rate;
+    /* The step size of the weight update. */
+    float step_size;
+    /* The smoothing factor of the far-end PSD estimate. */
+    float psd_smoothing;
+    /* The NLP level: -1 disabled, 0 conservative, 1 moderate, 2 aggressive. */
+    int32_t nlp_level;
+    /* The far-end lookahead, in samples. */
+    uint32_t far_end_lookahead;
+} FdafAecConfigC;
+
+/* A snapshot of the canceller statistics. Counters saturate instead of wrapping. */
+typedef struct FdafAecStatsC {
+    uint64_t frames;
+    float erle_db;
+    /* The most frequent echo delay in samples, or -1 if unknown. */
+    int32_t delay_samples;
+    uint32_t flags;
+    uint32_t saturated_bins;
+    uint32_t echo_path_clamps;
+    uint32_t deadline_overruns;
+} FdafAecStatsC;
+
+/* Fills config with the defaults. Returns 0, or -1 if config is null. */
+int32_t fdaf_aec_config_default(FdafAecConfigC *config);
+
+/* Creates a canceller, or returns null if config is null or invalid. */
+FdafAec *fdaf_aec_create(const FdafAecConfigC *config);
+
+/* Frees a canceller. Does nothing if aec is null. */
+void fdaf_aec_destroy(FdafAec *aec);
+
+/* Returns the number of microphone and output samples per frame, or 0 if aec is null. */
+uint32_t fdaf_aec_frame_size(const FdafAec *aec);
+
+/* Returns the number of far-end samples per frame, or 0 if aec is null. */
+uint32_t fdaf_aec_far_end_frame_size(const FdafAec *aec);
+
+/* Processes one frame into output, which may alias mic. Returns 0, or -1 if a pointer is
+ * null. */
+int32_t fdaf_aec_process(FdafAec *aec, const float *far_end, const float *mic, float *output);
+
+/* Fills stats. Returns 0, or -1 if a pointer is null. */
+int32_t fdaf_aec_get_stats(const FdafAec *aec, FdafAecStatsC *stats);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FDAF_AEC_H */
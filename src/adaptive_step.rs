@@ -0,0 +1,108 @@
+//! Step size driven by the echo-to-error ratio.
+//!
+//! A fixed step size is a compromise: large enough to converge quickly, small enough not to
+//! diverge when near-end speech or a level change enters the error signal. The adaptive step
+//! size compares the power of the echo estimate with the power of the error. A residual far
+//! below the echo estimate is clearly leftover echo and is adapted on with the full step
+//! size; a residual as large as the echo estimate, or larger, most likely carries near-end
+//! activity and is adapted on with a reduced one. `step_size` then only sets the upper bound.
+
+use crate::FdafAec;
+
+/// Parameters of the adaptive step size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdaptiveStepConfig {
+    /// The smallest factor applied to the step size, reached when the error power far
+    /// exceeds the echo estimate.
+    pub min_scale: f32,
+    /// Smoothing factor of the echo estimate and error powers.
+    pub smoothing: f32,
+}
+
+impl Default for AdaptiveStepConfig {
+    fn default() -> Self {
+        Self { min_scale: 0.1, smoothing: 0.7 }
+    }
+}
+
+/// Tracks the echo-to-error ratio and derives the step size factor.
+#[derive(Debug, Clone)]
+pub(crate) struct AdaptiveStep {
+    pub(crate) config: AdaptiveStepConfig,
+    echo_power: f32,
+    error_power: f32,
+    scale: f32,
+}
+
+impl AdaptiveStep {
+    pub(crate) fn new(config: AdaptiveStepConfig) -> Self {
+        Self { config, echo_power: 0.0, error_power: 0.0, scale: 1.0 }
+    }
+
+    /// Updates the powers with the energies of the current frame and returns the step size
+    /// factor.
+    pub(crate) fn update(&mut self, echo_estimate_energy: f32, error_energy: f32) -> f32 {
+        let a = self.config.smoothing;
+        self.echo_power = a * self.echo_power + (1.0 - a) * echo_estimate_energy;
+        self.error_power = a * self.error_power + (1.0 - a) * error_energy;
+        // eer / (1 + eer) with eer the echo-to-error ratio: 1 for a pure echo residual, 0.5
+        // when the error is as loud as the echo estimate
+        let share = self.echo_power / (self.echo_power + self.error_power + 1e-20);
+        self.scale = self.config.min_scale + (1.0 - self.config.min_scale) * share;
+        self.scale
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the adaptive step size.
+    pub fn set_adaptive_step(&mut self, config: Option<AdaptiveStepConfig>) {
+        self.note_config_change();
+        self.adaptive_step = config.map(AdaptiveStep::new);
+    }
+
+    /// Returns the factor the adaptive step size applied to the step size of the last frame,
+    /// or `None` if it is disabled.
+    pub fn adaptive_step_scale(&self) -> Option<f32> {
+        self.adaptive_step.as_ref().map(|step| step.scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn slows_down_during_near_end_activity() {
+        let far = white_noise(256 * 200, 0.3, 51);
+        let mut mic = echo(&far, &[(10, 0.5)]);
+        let near = white_noise(256 * 40, 0.2, 52);
+        for (m, n) in mic[256 * 120..256 * 160].iter_mut().zip(&near) {
+            *m += n;
+        }
+
+        let run = |adaptive: Option<AdaptiveStepConfig>| {
+            let mut aec = FdafAec::new(512, 0.5);
+            aec.set_adaptive_step(adaptive);
+            let mut scales = Vec::new();
+            let mut tap_at_end_of_near_end = 0.0;
+            for (i, (far_frame, mic_frame)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+                aec.process(far_frame, mic_frame);
+                scales.push(aec.adaptive_step_scale());
+                if i == 159 {
+                    tap_at_end_of_near_end = aec.impulse_response()[10];
+                }
+            }
+            (tap_at_end_of_near_end, aec.impulse_response()[10], scales)
+        };
+        let (fixed_tap, _, _) = run(None);
+        let (adaptive_tap, final_tap, scales) = run(Some(AdaptiveStepConfig::default()));
+
+        // Full speed on a clean echo residual, reduced speed with near-end activity.
+        assert!(scales[110].unwrap() > 0.95);
+        assert!(scales[125..160].iter().all(|s| s.unwrap() < 0.5));
+        assert!((adaptive_tap - 0.5).abs() < (fixed_tap - 0.5).abs() / 2.0);
+        assert!((final_tap - 0.5).abs() < 0.01);
+    }
+}
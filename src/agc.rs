@@ -0,0 +1,122 @@
+//! Automatic gain control of the echo-cancelled output.
+//!
+//! Softphones need the captured voice at a predictable level regardless of how far the
+//! talker sits from the microphone. The AGC measures the level of every output frame and
+//! moves it towards a target level along a compressor curve: a frame `x` dB away from the
+//! target leaves the AGC `x / compression_ratio` dB away from it. The gain falls quickly
+//! when the level rises and recovers slowly, and it is held while the output is below the
+//! noise gate or while the far-end talks alone, so neither background noise nor residual
+//! echo gets pumped up. Gain changes are ramped over the frame, and the gain is lowered
+//! further whenever the frame would otherwise clip.
+
+use crate::{DuplexState, FdafAec};
+
+/// Parameters of the automatic gain control.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AgcConfig {
+    /// The output level, in dBFS RMS, the AGC steers towards.
+    pub target_dbfs: f32,
+    /// How strongly deviations from the target are reduced. 1.0 leaves the level unchanged;
+    /// larger ratios pull it closer to the target.
+    pub compression_ratio: f32,
+    /// The largest gain, in dB, applied to quiet input. Attenuation is not limited.
+    pub max_gain_db: f32,
+    /// Frames below this level, in dBFS RMS, hold the current gain.
+    pub gate_dbfs: f32,
+    /// The time constant, in seconds, with which the gain falls.
+    pub attack_s: f32,
+    /// The time constant, in seconds, with which the gain rises.
+    pub release_s: f32,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        Self { target_dbfs: -18.0, compression_ratio: 3.0, max_gain_db: 20.0, gate_dbfs: -55.0, attack_s: 0.02, release_s: 0.5 }
+    }
+}
+
+/// A feed-forward compressor steering the output towards a target level.
+#[derive(Debug, Clone)]
+pub(crate) struct Agc {
+    pub(crate) config: AgcConfig,
+    attack: f32,
+    release: f32,
+    gain_db: f32,
+    gain: f32,
+}
+
+impl Agc {
+    pub(crate) fn new(config: AgcConfig, frame_size: usize, sample_rate: u32) -> Self {
+        assert!(config.compression_ratio >= 1.0, "The compression ratio must be at least 1.");
+        let frame_s = frame_size as f32 / sample_rate as f32;
+        Self {
+            config,
+            attack: (-frame_s / config.attack_s).exp(),
+            release: (-frame_s / config.release_s).exp(),
+            gain_db: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    /// Applies the gain to an output frame in place.
+    pub(crate) fn process(&mut self, output: &mut [f32], duplex_state: DuplexState) {
+        let level_dbfs = 10.0 * crate::mean_square(&*output).max(1e-12).log10();
+        if level_dbfs > self.config.gate_dbfs && duplex_state != DuplexState::FarEndOnly {
+            let deviation = self.config.target_dbfs - level_dbfs;
+            let target_db = (deviation * (1.0 - 1.0 / self.config.compression_ratio)).min(self.config.max_gain_db);
+            let a = if target_db < self.gain_db { self.attack } else { self.release };
+            self.gain_db = a * self.gain_db + (1.0 - a) * target_db;
+        }
+
+        let peak = output.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+        let target = 10f32.powf(self.gain_db / 20.0).min(1.0 / peak.max(1e-12));
+        let step = (target - self.gain) / output.len() as f32;
+        for sample in output.iter_mut() {
+            self.gain += step;
+            *sample *= self.gain;
+        }
+        self.gain = target;
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the automatic gain control. The
+    /// gain restarts at 0 dB.
+    pub fn set_agc(&mut self, config: Option<AgcConfig>) {
+        self.note_config_change();
+        self.agc = config.map(|config| Agc::new(config, self.frame_size, self.sample_rate));
+    }
+
+    /// Returns the gain, in dB, the AGC applied at the end of the last frame, or `None` if
+    /// it is disabled.
+    pub fn agc_gain_db(&self) -> Option<f32> {
+        self.agc.as_ref().map(|agc| 20.0 * agc.gain.log10())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{process_all, white_noise};
+
+    #[test]
+    fn compresses_towards_target_level() {
+        let config = AgcConfig::default();
+        for input_dbfs in [-40.0f32, -6.0] {
+            // Near-end speech alone, at a constant level. Uniform noise has an RMS of
+            // amplitude / sqrt(3).
+            let amplitude = 10f32.powf(input_dbfs / 20.0) * 3f32.sqrt();
+            let far = vec![0.0; 256 * 200];
+            let mic = white_noise(256 * 200, amplitude, 68);
+            let mut aec = FdafAec::new(512, 0.2);
+            aec.set_agc(Some(config));
+            let output = process_all(&mut aec, &far, &mic);
+
+            let expected = config.target_dbfs + (input_dbfs - config.target_dbfs) / config.compression_ratio;
+            let level = 10.0 * crate::mean_square(&output[150 * 256..]).log10();
+            assert!((level - expected).abs() < 1.0, "{} dBFS in, {} dBFS out, expected {}", input_dbfs, level, expected);
+            assert!(output.iter().all(|x| x.abs() <= 1.0));
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Strided views of multi-channel device buffers.
+//!
+//! Audio devices deliver multi-channel audio interleaved, often with more channels than a
+//! processor consumes, e.g. a 4-channel capture buffer of which channels 1 and 2 are the
+//! microphones. [`AudioView`] and [`AudioViewMut`] describe such a buffer by its channel
+//! count, the stride between consecutive sample frames, and the offset of the first used
+//! channel, so [`MultiChannelAec`](crate::MultiChannelAec) and
+//! [`MultiMicAec`](crate::MultiMicAec) can read and write it in place without
+//! deinterleaving copies.
+
+/// The position of a channel subset within a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    channels: usize,
+    stride: usize,
+    offset: usize,
+    frames: usize,
+}
+
+impl Layout {
+    fn new(len: usize, channels: usize, stride: usize, offset: usize) -> Self {
+        assert!(channels > 0, "A view needs at least one channel.");
+        assert!(stride >= channels, "The stride must cover all channels of the view.");
+        let frames = match len.checked_sub(offset + channels) {
+            Some(rest) => rest / stride + 1,
+            None => 0,
+        };
+        Self { channels, stride, offset, frames }
+    }
+
+    fn index(&self, channel: usize, frame: usize) -> usize {
+        debug_assert!(channel < self.channels && frame < self.frames);
+        self.offset + frame * self.stride + channel
+    }
+}
+
+/// A read-only view of `channels` channels in a strided buffer.
+///
+/// Sample `frame` of channel `channel` is `data[offset + frame * stride + channel]`.
+#[derive(Debug, Clone, Copy)]
+pub struct AudioView<'a> {
+    data: &'a [f32],
+    layout: Layout,
+}
+
+impl<'a> AudioView<'a> {
+    /// Creates a view of `channels` adjacent channels, starting at channel `offset` of a
+    /// buffer whose sample frames are `stride` samples apart.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channels` is zero or `stride` is smaller than `channels`.
+    pub fn new(data: &'a [f32], channels: usize, stride: usize, offset: usize) -> Self {
+        Self { data, layout: Layout::new(data.len(), channels, stride, offset) }
+    }
+
+    /// Creates a view of all channels of an interleaved buffer.
+    pub fn interleaved(data: &'a [f32], channels: usize) -> Self {
+        Self::new(data, channels, channels, 0)
+    }
+
+    /// Returns the number of channels in the view.
+    pub fn channels(&self) -> usize {
+        self.layout.channels
+    }
+
+    /// Returns the number of complete sample frames in the view.
+    pub fn frames(&self) -> usize {
+        self.layout.frames
+    }
+
+    /// Returns one sample.
+    pub fn sample(&self, channel: usize, frame: usize) -> f32 {
+        self.data[self.layout.index(channel, frame)]
+    }
+
+    /// Returns the samples of one channel.
+    pub fn channel(&self, channel: usize) -> impl Iterator<Item = f32> + '_ {
+        (0..self.frames()).map(move |frame| self.sample(channel, frame))
+    }
+}
+
+/// A mutable view of `channels` channels in a strided buffer, see [`AudioView`].
+#[derive(Debug)]
+pub struct AudioViewMut<'a> {
+    data: &'a mut [f32],
+    layout: Layout,
+}
+
+impl<'a> AudioViewMut<'a> {
+    /// Creates a mutable view, see [`AudioView::new`].
+    pub fn new(data: &'a mut [f32], channels: usize, stride: usize, offset: usize) -> Self {
+        let layout = Layout::new(data.len(), channels, stride, offset);
+        Self { data, layout }
+    }
+
+    /// Creates a mutable view of all channels of an interleaved buffer.
+    pub fn interleaved(data: &'a mut [f32], channels: usize) -> Self {
+        Self::new(data, channels, channels, 0)
+    }
+
+    /// Returns the number of channels in the view.
+    pub fn channels(&self) -> usize {
+        self.layout.channels
+    }
+
+    /// Returns the number of complete sample frames in the view.
+    pub fn frames(&self) -> usize {
+        self.layout.frames
+    }
+
+    /// Returns one sample.
+    pub fn sample(&self, channel: usize, frame: usize) -> f32 {
+        self.data[self.layout.index(channel, frame)]
+    }
+
+    /// Returns a mutable reference to one sample.
+    pub fn sample_mut(&mut self, channel: usize, frame: usize) -> &mut f32 {
+        &mut self.data[self.layout.index(channel, frame)]
+    }
+
+    /// Returns a read-only view of the same samples.
+    pub fn as_view(&self) -> AudioView<'_> {
+        AudioView { data: self.data, layout: self.layout }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_channel_subset_of_interleaved_buffer() {
+        // Four interleaved channels, three frames; the view covers channels 1 and 2.
+        let mut data: Vec<f32> = (0..12).map(|x| x as f32).collect();
+        let view = AudioView::new(&data, 2, 4, 1);
+        assert_eq!((view.channels(), view.frames()), (2, 3));
+        assert_eq!(view.channel(0).collect::<Vec<_>>(), [1.0, 5.0, 9.0]);
+        assert_eq!(view.channel(1).collect::<Vec<_>>(), [2.0, 6.0, 10.0]);
+
+        // A trailing partial frame is not part of the view.
+        assert_eq!(AudioView::new(&data[..11], 2, 4, 2).frames(), 2);
+
+        let mut view = AudioViewMut::new(&mut data, 2, 4, 1);
+        *view.sample_mut(1, 2) = -1.0;
+        assert_eq!(view.as_view().sample(1, 2), -1.0);
+        assert_eq!(data[10], -1.0);
+    }
+}
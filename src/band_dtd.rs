@@ -0,0 +1,149 @@
+//! Per-band double-talk decisions.
+//!
+//! Near-end speech rarely covers the whole spectrum at once. Instead of a single full-band
+//! decision that freezes adaptation everywhere, the spectrum is split into bands that each
+//! track their own echo attenuation and decide independently whether near-end speech is
+//! present. Only the bands with double talk stop adapting; the others keep converging.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+
+/// Band echo attenuation (linear power ratio, about 6 dB) above which a band is trusted.
+const CONVERGED_BAND_ERLE: f32 = 4.0;
+/// Smoothing factor of the band ERLE estimates.
+const BAND_ERLE_SMOOTHING: f32 = 0.9;
+
+/// Parameters of the per-band double-talk detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BandDoubleTalkConfig {
+    /// The number of bands the spectrum up to the Nyquist frequency is split into.
+    pub bands: usize,
+    /// Error-to-estimated-echo power ratio above which near-end speech is assumed present
+    /// in a converged band.
+    pub ratio: f32,
+    /// The number of frames a band keeps its double-talk decision after the speech stops.
+    pub hangover_frames: u32,
+}
+
+impl Default for BandDoubleTalkConfig {
+    fn default() -> Self {
+        Self { bands: 16, ratio: 0.5, hangover_frames: 2 }
+    }
+}
+
+/// Decides double talk per band and gates the adaptation of the affected bins.
+#[derive(Debug, Clone)]
+pub(crate) struct BandDoubleTalkDetector {
+    pub(crate) config: BandDoubleTalkConfig,
+    band_of_bin: Vec<usize>,
+    erle: Vec<f32>,
+    hangover: Vec<u32>,
+    decisions: Vec<bool>,
+    gates: Vec<f32>,
+}
+
+impl BandDoubleTalkDetector {
+    pub(crate) fn new(config: BandDoubleTalkConfig, fft_size: usize) -> Self {
+        let half = fft_size / 2 + 1;
+        assert!(config.bands > 0 && config.bands <= half, "Band count must be between 1 and fft_size / 2 + 1.");
+        let band_of_bin = (0..fft_size).map(|k| k.min(fft_size - k) * config.bands / half).collect();
+        Self {
+            config,
+            band_of_bin,
+            erle: vec![1.0; config.bands],
+            hangover: vec![0; config.bands],
+            decisions: vec![false; config.bands],
+            gates: vec![1.0; fft_size],
+        }
+    }
+
+    /// Updates the decisions from the error and estimated echo spectra of the current frame.
+    pub(crate) fn update(&mut self, e_f: &DVector<Complex<f32>>, echo_f: &[Complex<f32>]) {
+        let bands = self.config.bands;
+        let mut mic = vec![0.0f32; bands];
+        let mut error = vec![0.0f32; bands];
+        let mut echo = vec![0.0f32; bands];
+        for (k, &band) in self.band_of_bin.iter().enumerate() {
+            mic[band] += (e_f[k] + echo_f[k]).norm_sqr();
+            error[band] += e_f[k].norm_sqr();
+            echo[band] += echo_f[k].norm_sqr();
+        }
+
+        for band in 0..bands {
+            let converged = self.erle[band] >= CONVERGED_BAND_ERLE;
+            if converged && error[band] > self.config.ratio * echo[band] {
+                self.decisions[band] = true;
+                self.hangover[band] = self.config.hangover_frames;
+            } else if self.hangover[band] > 0 {
+                self.hangover[band] -= 1;
+            } else {
+                self.decisions[band] = false;
+            }
+
+            if !self.decisions[band] && echo[band] > 0.0 {
+                let erle = mic[band] / (error[band] + 1e-20);
+                self.erle[band] = BAND_ERLE_SMOOTHING * self.erle[band] + (1.0 - BAND_ERLE_SMOOTHING) * erle;
+            }
+        }
+
+        for (gate, &band) in self.gates.iter_mut().zip(&self.band_of_bin) {
+            *gate = if self.decisions[band] { 0.0 } else { 1.0 };
+        }
+    }
+
+    pub(crate) fn decisions(&self) -> &[bool] {
+        &self.decisions
+    }
+
+    /// Returns the adaptation gate of every FFT bin.
+    pub(crate) fn gates(&self) -> &[f32] {
+        &self.gates
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables per-band double-talk detection.
+    pub fn set_band_double_talk(&mut self, config: Option<BandDoubleTalkConfig>) {
+        self.note_config_change();
+        self.band_dtd = config.map(|config| BandDoubleTalkDetector::new(config, self.fft_size));
+    }
+
+    /// Returns the double-talk decision of every band for the last frame, from the lowest to
+    /// the highest frequency, or `None` if per-band detection is disabled.
+    pub fn band_double_talk(&self) -> Option<&[bool]> {
+        self.band_dtd.as_ref().map(BandDoubleTalkDetector::decisions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn narrowband_near_end_only_freezes_its_band() {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_band_double_talk(Some(BandDoubleTalkConfig::default()));
+        let far = white_noise(256 * 160, 0.3, 26);
+        let echo_signal = echo(&far, &[(10, 0.5)]);
+        for (far_frame, mic_frame) in far.chunks(256).zip(echo_signal.chunks(256)).take(150) {
+            aec.process(far_frame, mic_frame);
+        }
+        assert!(aec.band_double_talk().unwrap().iter().all(|&dt| !dt));
+
+        // A 1.25 kHz near-end tone falls into band 2 of 16 at 16 kHz.
+        let start = 150 * 256;
+        let mic: Vec<f32> = echo_signal[start..]
+            .iter()
+            .enumerate()
+            .map(|(i, e)| e + 0.05 * (2.0 * PI * 1250.0 * i as f32 / 16000.0).sin())
+            .collect();
+        process_all(&mut aec, &far[start..], &mic);
+        let decisions = aec.band_double_talk().unwrap();
+        assert!(decisions[2]);
+        assert_eq!(decisions.iter().filter(|&&dt| dt).count(), 1, "decisions were {:?}", decisions);
+    }
+}
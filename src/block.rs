@@ -0,0 +1,119 @@
+//! A minimal block-processing interface for hosting the canceller in other audio graphs.
+//!
+//! Audio frameworks usually drive their nodes with one call per block and a small set of
+//! buffers. [`BlockProcessor`] maps the canceller and its building blocks onto that shape:
+//! every processor gets the far-end reference read-only and transforms the capture signal
+//! in place, so a host needs a single adapter for all of them.
+
+use crate::{DelayLine, FdafAec, HighPassFilter, OlsConvolver};
+
+/// The buffers of one processing call.
+#[derive(Debug)]
+pub struct BlockIo<'a> {
+    /// The far-end reference played out during this block. Processors that do not need a
+    /// reference ignore it.
+    pub reference: &'a [f32],
+    /// The capture signal, replaced by the processed signal.
+    pub signal: &'a mut [f32],
+}
+
+/// A processing stage driven one block at a time.
+pub trait BlockProcessor {
+    /// Returns the number of samples every block must have, or `None` if any length works.
+    fn block_size(&self) -> Option<usize>;
+
+    /// Returns the delay, in samples, the stage adds to the signal, excluding block assembly.
+    fn latency_samples(&self) -> usize;
+
+    /// Processes one block in place.
+    fn process_block(&mut self, io: &mut BlockIo);
+}
+
+impl BlockProcessor for FdafAec {
+    fn block_size(&self) -> Option<usize> {
+        Some(self.frame_size)
+    }
+
+    fn latency_samples(&self) -> usize {
+        FdafAec::latency_samples(self)
+    }
+
+    /// Cancels the echo of `io.reference` in `io.signal`. The reference must have
+    /// [`FdafAec::far_end_frame_size`] samples.
+    fn process_block(&mut self, io: &mut BlockIo) {
+        let output = self.process(io.reference, io.signal);
+        io.signal.copy_from_slice(&output);
+    }
+}
+
+impl BlockProcessor for HighPassFilter {
+    fn block_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn latency_samples(&self) -> usize {
+        0
+    }
+
+    fn process_block(&mut self, io: &mut BlockIo) {
+        self.process(io.signal);
+    }
+}
+
+impl BlockProcessor for DelayLine {
+    fn block_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn latency_samples(&self) -> usize {
+        self.delay()
+    }
+
+    fn process_block(&mut self, io: &mut BlockIo) {
+        self.process(io.signal);
+    }
+}
+
+impl BlockProcessor for OlsConvolver {
+    fn block_size(&self) -> Option<usize> {
+        Some(OlsConvolver::block_size(self))
+    }
+
+    fn latency_samples(&self) -> usize {
+        OlsConvolver::latency_samples(self)
+    }
+
+    fn process_block(&mut self, io: &mut BlockIo) {
+        let output = self.process(io.signal);
+        io.signal.copy_from_slice(&output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use crate::HighPassConfig;
+
+    #[test]
+    fn chain_of_processors() {
+        let mut chain: Vec<Box<dyn BlockProcessor>> = vec![
+            Box::new(HighPassFilter::new(HighPassConfig::default(), 16000)),
+            Box::new(FdafAec::new(512, 0.5)),
+            Box::new(DelayLine::new(3)),
+        ];
+        assert_eq!(chain.iter().filter_map(|p| p.block_size()).collect::<Vec<_>>(), [256]);
+        assert_eq!(chain.iter().map(|p| p.latency_samples()).sum::<usize>(), 3);
+
+        let far = white_noise(256 * 100, 0.3, 43);
+        let mut signal = echo(&far, &[(10, 0.5)]);
+        let input_tail = crate::mean_square(&signal[signal.len() - 256..]);
+        for (reference, block) in far.chunks(256).zip(signal.chunks_mut(256)) {
+            let mut io = BlockIo { reference, signal: block };
+            for processor in chain.iter_mut() {
+                processor.process_block(&mut io);
+            }
+        }
+        assert!(crate::mean_square(&signal[signal.len() - 256..]) < input_tail / 100.0);
+    }
+}
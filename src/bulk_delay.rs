@@ -0,0 +1,113 @@
+//! Automatic compensation of the bulk render-to-capture delay.
+//!
+//! The adaptive filter spans `fft_size / 2` samples, but the delay between playback and
+//! capture can be far longer. Bulk delay compensation runs a [`DelayEstimator`] on the raw
+//! inputs and delays the far-end reference so that the echo arrives early in the filter
+//! span, a small margin after its start. The delay only changes when the confident estimate
+//! has left the first half of the filter span for a number of consecutive frames, so a
+//! wandering estimate does not make the canceller thrash between delays. After a change the
+//! filter re-converges on the shifted echo path.
+
+use crate::{DelayEstimator, DelayEstimatorConfig, DelayLine, FdafAec};
+
+/// Parameters of the bulk delay compensation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BulkDelayConfig {
+    /// The largest bulk delay, in samples, that is compensated.
+    pub max_delay: usize,
+    /// The number of samples the echo is placed after the start of the filter span, so the
+    /// filter also covers echo arriving slightly before the estimated delay.
+    pub margin: usize,
+    /// The smallest estimate confidence, see [`DelayEstimate::confidence`], that may change
+    /// the delay.
+    ///
+    /// [`DelayEstimate::confidence`]: crate::DelayEstimate::confidence
+    pub min_confidence: f32,
+    /// The number of consecutive frames the estimate must lie outside the filter span before
+    /// the delay changes.
+    pub hold_frames: u32,
+}
+
+impl Default for BulkDelayConfig {
+    fn default() -> Self {
+        Self { max_delay: 4096, margin: 32, min_confidence: 0.3, hold_frames: 10 }
+    }
+}
+
+/// The delay estimator and the far-end delay line it controls.
+pub(crate) struct BulkDelay {
+    pub(crate) config: BulkDelayConfig,
+    estimator: DelayEstimator,
+    line: DelayLine,
+    frames_outside: u32,
+}
+
+impl BulkDelay {
+    pub(crate) fn new(config: BulkDelayConfig) -> Self {
+        let estimator = DelayEstimator::new(DelayEstimatorConfig { max_delay: config.max_delay, ..DelayEstimatorConfig::default() });
+        Self { config, estimator, line: DelayLine::new(0), frames_outside: 0 }
+    }
+
+    /// Returns the current far-end delay, in samples.
+    pub(crate) fn delay(&self) -> usize {
+        self.line.delay()
+    }
+
+    /// Updates the estimate with the raw inputs of a frame, adjusts the delay if needed, and
+    /// delays the far-end frame in place.
+    pub(crate) fn process(&mut self, far_end: &mut [f32], mic: &[f32]) {
+        let span = far_end.len() / 2;
+        let current = self.line.delay();
+        let outside = self.estimator.process(far_end, mic).filter(|estimate| {
+            estimate.confidence >= self.config.min_confidence
+                && !(current..current + span).contains(&estimate.delay_samples)
+        });
+        match outside {
+            Some(estimate) => {
+                self.frames_outside += 1;
+                if self.frames_outside >= self.config.hold_frames {
+                    self.line.set_delay(estimate.delay_samples.saturating_sub(self.config.margin));
+                    self.frames_outside = 0;
+                }
+            }
+            None => self.frames_outside = 0,
+        }
+        self.line.process(far_end);
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the bulk delay compensation.
+    /// Reconfiguring restarts the estimation at zero delay.
+    pub fn set_bulk_delay(&mut self, config: Option<BulkDelayConfig>) {
+        self.note_config_change();
+        self.bulk_delay = config.map(BulkDelay::new);
+    }
+
+    /// Returns the delay, in samples, currently inserted into the far-end path by the bulk
+    /// delay compensation, or 0 if it is disabled.
+    pub fn current_bulk_delay(&self) -> usize {
+        self.bulk_delay.as_ref().map_or(0, BulkDelay::delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn brings_long_delay_into_filter_span() {
+        let far = white_noise(256 * 300, 0.3, 74);
+        let mic = echo(&far, &[(1000, 0.5), (1060, -0.2)]);
+        let mut aec = FdafAec::new(512, 0.2);
+        aec.set_bulk_delay(Some(BulkDelayConfig { max_delay: 2048, ..BulkDelayConfig::default() }));
+        let output = process_all(&mut aec, &far, &mic);
+
+        assert_eq!(aec.current_bulk_delay(), 1000 - 32);
+        assert!((aec.impulse_response()[32] - 0.5).abs() < 0.05);
+        let tail = output.len() - 20 * 256;
+        assert!(crate::mean_square(&output[tail..]) < crate::mean_square(&mic[tail..]) / 100.0);
+    }
+}
@@ -0,0 +1,156 @@
+//! Detection of clipping in the far-end reference.
+//!
+//! A reference that clips before it reaches the canceller, e.g. because the application
+//! applies gain in fixed point, no longer matches what the loudspeaker plays. Adapting on
+//! such blocks teaches the filter a distorted echo path. The detector looks for runs of
+//! consecutive samples at full scale in every incoming far-end frame, before any
+//! preprocessing, and reduces the step size while a clipped frame is in the far-end buffer.
+//! Its counters let the application warn about playback levels.
+
+use crate::FdafAec;
+
+/// Parameters of the far-end clipping detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClippingConfig {
+    /// The magnitude at or above which a sample counts as full scale.
+    pub threshold: f32,
+    /// The number of consecutive full-scale samples that mark a frame as clipped. A single
+    /// sample at full scale is a legitimate peak; a run of them is a flattened waveform.
+    pub min_run: usize,
+    /// The factor applied to the step size while a clipped frame is in the far-end buffer.
+    /// 0.0 freezes adaptation.
+    pub step_scale: f32,
+}
+
+impl Default for ClippingConfig {
+    fn default() -> Self {
+        Self { threshold: 0.99, min_run: 3, step_scale: 0.1 }
+    }
+}
+
+/// How often the far-end reference clipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClippingStats {
+    /// The number of far-end frames checked.
+    pub frames_checked: u64,
+    /// The number of checked frames that were clipped.
+    pub frames_clipped: u64,
+    /// The number of far-end samples at full scale, in clipped frames.
+    pub samples_clipped: u64,
+    /// Whether the last far-end frame was clipped.
+    pub last_frame_clipped: bool,
+}
+
+impl ClippingStats {
+    /// Returns the fraction of checked frames that were clipped.
+    pub fn clipped_ratio(&self) -> f32 {
+        if self.frames_checked == 0 { 0.0 } else { self.frames_clipped as f32 / self.frames_checked as f32 }
+    }
+}
+
+/// Flags clipped far-end frames.
+#[derive(Debug, Clone)]
+pub(crate) struct ClippingDetector {
+    pub(crate) config: ClippingConfig,
+    stats: ClippingStats,
+    /// The number of frames, including the current one, whose far-end buffer holds a
+    /// clipped frame.
+    frames_to_recover: u32,
+}
+
+impl ClippingDetector {
+    pub(crate) fn new(config: ClippingConfig) -> Self {
+        assert!(config.min_run > 0, "A clipped run needs at least one sample.");
+        Self { config, stats: ClippingStats::default(), frames_to_recover: 0 }
+    }
+
+    fn update(&mut self, far_end_frame: &[f32]) {
+        let mut run = 0;
+        let mut longest = 0;
+        let mut full_scale = 0;
+        for &x in far_end_frame {
+            if x.abs() >= self.config.threshold {
+                run += 1;
+                full_scale += 1;
+                longest = longest.max(run);
+            } else {
+                run = 0;
+            }
+        }
+        let clipped = longest >= self.config.min_run;
+        self.stats.frames_checked += 1;
+        self.stats.last_frame_clipped = clipped;
+        self.frames_to_recover = self.frames_to_recover.saturating_sub(1);
+        if clipped {
+            self.stats.frames_clipped += 1;
+            self.stats.samples_clipped += full_scale;
+            // The far-end buffer spans the current and the previous frame
+            self.frames_to_recover = 2;
+        }
+    }
+
+    /// Returns the step size factor for the current frame.
+    fn step_scale(&self) -> f32 {
+        if self.frames_to_recover > 0 { self.config.step_scale } else { 1.0 }
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the far-end clipping detector.
+    /// Reconfiguring resets its counters.
+    pub fn set_clipping_detection(&mut self, config: Option<ClippingConfig>) {
+        self.note_config_change();
+        self.clipping = config.map(ClippingDetector::new);
+    }
+
+    /// Returns how often the far-end reference clipped, or `None` if the detector is
+    /// disabled.
+    pub fn far_end_clipping_stats(&self) -> Option<ClippingStats> {
+        self.clipping.as_ref().map(|clipping| clipping.stats)
+    }
+
+    /// Checks an incoming far-end frame for clipping.
+    pub(crate) fn detect_far_end_clipping(&mut self, far_end_frame: &[f32]) {
+        if let Some(clipping) = self.clipping.as_mut() {
+            clipping.update(far_end_frame);
+        }
+    }
+
+    /// Returns the step size factor while a clipped frame is in the far-end buffer.
+    pub(crate) fn clipping_step_scale(&self) -> f32 {
+        self.clipping.as_ref().map_or(1.0, |clipping| clipping.step_scale())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn clipped_blocks_do_not_distort_the_filter() {
+        // Every fourth frame of the reference is driven into hard clipping, but the
+        // loudspeaker plays the clean signal.
+        let far = white_noise(256 * 200, 0.3, 103);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let clipped: Vec<f32> = far
+            .chunks(256)
+            .enumerate()
+            .flat_map(|(i, frame)| frame.iter().map(move |&x| if i % 4 == 3 { (8.0 * x).clamp(-1.0, 1.0) } else { x }))
+            .collect();
+        let run = |clipping: Option<ClippingConfig>| {
+            let mut aec = FdafAec::new(512, 0.5);
+            aec.set_clipping_detection(clipping);
+            process_all(&mut aec, &clipped, &mic);
+            let error = aec.impulse_response().iter().enumerate().map(|(n, h)| (h - if n == 10 { 0.5 } else { 0.0 }).powi(2)).sum::<f32>();
+            (error, aec.far_end_clipping_stats())
+        };
+        let (unprotected, _) = run(None);
+        let (protected, stats) = run(Some(ClippingConfig { step_scale: 0.0, ..ClippingConfig::default() }));
+        assert!(protected < unprotected / 10.0, "protected {} unprotected {}", protected, unprotected);
+        let stats = stats.unwrap();
+        assert_eq!((stats.frames_checked, stats.frames_clipped), (200, 50));
+        assert!(stats.samples_clipped > 0 && stats.last_frame_clipped);
+    }
+}
@@ -0,0 +1,57 @@
+//! Frame and sample clock of the canceller.
+//!
+//! Every processed frame advances a monotonically increasing frame counter. Events, metrics
+//! and debug dumps are stamped with it so that logs from different components can be
+//! correlated, and the helpers here convert between frames, samples and milliseconds at the
+//! configured [`FdafAecConfig::sample_rate`](crate::FdafAecConfig::sample_rate).
+
+use crate::FdafAec;
+
+impl FdafAec {
+    /// Returns the sample rate the canceller was configured for, in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the number of frames processed since construction.
+    ///
+    /// Frame-stamped events use the zero-based index of the frame, so the most recently
+    /// processed frame has index `frame_count() - 1`.
+    pub fn frame_count(&self) -> u64 {
+        self.frames_processed
+    }
+
+    /// Returns the number of microphone samples processed since construction.
+    pub fn sample_clock(&self) -> u64 {
+        self.frames_processed * self.frame_size as u64
+    }
+
+    /// Converts a number of frames to milliseconds at the configured sample rate.
+    pub fn frames_to_ms(&self, frames: u64) -> f64 {
+        frames as f64 * self.frame_size as f64 * 1000.0 / self.sample_rate as f64
+    }
+
+    /// Converts a duration in milliseconds to the number of whole frames it spans at the
+    /// configured sample rate.
+    pub fn ms_to_frames(&self, ms: f64) -> u64 {
+        (ms * self.sample_rate as f64 / (1000.0 * self.frame_size as f64)).floor() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{FdafAec, FdafAecConfig};
+
+    #[test]
+    fn clock_advances_per_frame() {
+        let mut aec = FdafAec::with_config(FdafAecConfig::for_sample_rate(8000));
+        assert_eq!(aec.sample_rate(), 8000);
+        for _ in 0..3 {
+            aec.process(&[0.0; 256], &[0.0; 256]);
+        }
+        assert_eq!(aec.frame_count(), 3);
+        assert_eq!(aec.sample_clock(), 768);
+        assert_eq!(aec.frames_to_ms(3), 96.0);
+        assert_eq!(aec.ms_to_frames(100.0), 3);
+    }
+}
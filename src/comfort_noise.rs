@@ -0,0 +1,125 @@
+//! Comfort noise after residual echo suppression.
+//!
+//! The post-filter and the NLP remove residual echo by attenuating the output, which during
+//! far-end single talk drops it to (almost) digital zero. The far-end listener hears the
+//! background noise of the near-end room vanish whenever they speak, which sounds like a
+//! dropped call. The comfort noise generator adds white noise in proportion to the energy
+//! the suppressors removed from the frame, so suppressed frames are filled at a fixed level
+//! while unsuppressed frames pass unchanged.
+//!
+//! The noise comes from a seeded xorshift generator, so two cancellers with the same
+//! configuration and input still produce identical output.
+
+use crate::FdafAec;
+
+/// Parameters of the comfort noise generator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ComfortNoiseConfig {
+    /// The RMS level, in dBFS, of the noise filling a fully suppressed frame.
+    pub level_dbfs: f32,
+    /// The seed of the noise generator.
+    pub seed: u64,
+}
+
+impl Default for ComfortNoiseConfig {
+    fn default() -> Self {
+        Self { level_dbfs: -70.0, seed: 0x2545_f491_4f6c_dd1d }
+    }
+}
+
+/// Fills the energy removed by the suppressors with white noise.
+#[derive(Debug, Clone)]
+pub(crate) struct ComfortNoise {
+    pub(crate) config: ComfortNoiseConfig,
+    state: u64,
+    rms: f32,
+}
+
+impl ComfortNoise {
+    pub(crate) fn new(config: ComfortNoiseConfig) -> Self {
+        // Xorshift gets stuck at zero, so a zero seed is replaced.
+        let state = if config.seed == 0 { ComfortNoiseConfig::default().seed } else { config.seed };
+        Self { config, state, rms: 0.0 }
+    }
+
+    /// Adds comfort noise to `output`, given the frame as it was before suppression.
+    pub(crate) fn process(&mut self, output: &mut [f32], unsuppressed: &[f32]) {
+        let before = crate::mean_square(unsuppressed);
+        let after = crate::mean_square(&*output);
+        let removed = if before > 0.0 { (1.0 - after / before).clamp(0.0, 1.0) } else { 0.0 };
+        self.rms = 10f32.powf(self.config.level_dbfs / 20.0) * removed.sqrt();
+        if self.rms == 0.0 {
+            return;
+        }
+
+        // Uniform noise on [-1, 1) has a variance of 1/3.
+        let amplitude = self.rms * 3f32.sqrt();
+        for sample in output.iter_mut() {
+            *sample += amplitude * self.next_uniform();
+        }
+    }
+
+    /// Returns a uniformly distributed value in [-1, 1).
+    fn next_uniform(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables comfort noise. The generator
+    /// restarts from the configured seed.
+    pub fn set_comfort_noise(&mut self, config: Option<ComfortNoiseConfig>) {
+        self.note_config_change();
+        self.comfort_noise = config.map(ComfortNoise::new);
+    }
+
+    /// Returns the RMS level of the comfort noise added to the last output frame, or `None`
+    /// if comfort noise is disabled.
+    pub fn comfort_noise_rms(&self) -> Option<f32> {
+        self.comfort_noise.as_ref().map(|comfort_noise| comfort_noise.rms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use crate::NlpLevel;
+
+    #[test]
+    fn fills_suppressed_frames_only() {
+        let mut far = white_noise(256 * 200, 0.5, 63);
+        let mut mic: Vec<f32> = echo(&far, &[(10, 0.5)]).iter().map(|&e| (3.0 * e).tanh() / 3.0).collect();
+        let near_end = 180 * 256;
+        far[near_end..].fill(0.0);
+        mic[near_end..].copy_from_slice(&white_noise(256 * 20, 0.1, 64));
+
+        let run = |comfort_noise: Option<ComfortNoiseConfig>| {
+            let mut aec = FdafAec::new(512, 0.2);
+            aec.set_nlp(Some(NlpLevel::Aggressive));
+            aec.set_comfort_noise(comfort_noise);
+            let output = process_all(&mut aec, &far, &mic);
+            (output, aec.comfort_noise_rms())
+        };
+        let (silent, _) = run(None);
+        let config = ComfortNoiseConfig { level_dbfs: -50.0, ..ComfortNoiseConfig::default() };
+        let (filled, rms) = run(Some(config));
+
+        // Far-end single talk is filled at close to the configured level.
+        let echo_only = 150 * 256..180 * 256;
+        let level = 10.0 * (crate::mean_square(&filled[echo_only.clone()]) - crate::mean_square(&silent[echo_only])).log10();
+        assert!((level + 50.0).abs() < 1.0, "comfort noise at {} dBFS", level);
+
+        // Near-end speech is not suppressed, so nothing is added.
+        assert_eq!(rms, Some(0.0));
+        let tail = 190 * 256..200 * 256;
+        assert_eq!(filled[tail.clone()], silent[tail]);
+
+        // The same seed produces the same noise.
+        assert_eq!(run(Some(config)).0, filled);
+    }
+}
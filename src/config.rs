@@ -0,0 +1,449 @@
+//! Construction-time configuration of the canceller.
+
+use crate::geometry::{Geometry, GeometryConstraints};
+use crate::{AdaptationMode, AdaptiveStepConfig, AgcConfig, BandDoubleTalkConfig, BlockMethod, BulkDelayConfig, ClippingConfig, ComfortNoiseConfig, FdafAec, ContentMode, ConvergenceProtectionConfig, CoherenceDtdConfig, CpuBudget, DriftConfig, CrosstalkConfig, GeigelConfig, GuardBandConfig, HighPassConfig, HumNotchConfig, LateReverbConfig, LowPowerConfig, NlpLevel, NonlinearEchoConfig, NormalizationConfig, PostFilterConfig, PrecisionConfig, RegularizationProfile, ResidualCeilingConfig, StepProfile, TonalityConfig, VadConfig, VolumeRampConfig};
+use std::time::Duration;
+
+/// Parameters used to construct an [`FdafAec`] with
+/// [`FdafAec::with_config`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FdafAecConfig {
+    /// The size of the FFT. The frame size and the adaptive filter length are both
+    /// `fft_size / 2`. Must be a power of two.
+    pub fft_size: usize,
+    /// The sample rate of the processed audio, in Hz. Used to convert between frames and
+    /// time, see [`FdafAec::frames_to_ms`](crate::FdafAec::frames_to_ms).
+    pub sample_rate: u32,
+    /// The sample rate of the far-end reference, in Hz, if it differs from `sample_rate`.
+    ///
+    /// The reference is resampled to `sample_rate` internally, so
+    /// [`FdafAec::process`](crate::FdafAec::process) then expects far-end frames of
+    /// [`FdafAec::far_end_frame_size`](crate::FdafAec::far_end_frame_size) samples. The
+    /// ratio of the two rates must turn a frame of `fft_size / 2` capture samples into a
+    /// whole number of far-end samples, e.g. 48 kHz render with 16 kHz capture. `None` by
+    /// default.
+    pub far_end_sample_rate: Option<u32>,
+    /// The learning rate (mu) of the NLMS weight update.
+    pub step_size: f32,
+    /// How the filter weights are adapted. NLMS by default.
+    pub adaptation: AdaptationMode,
+    /// The number of frames after construction during which the step size is boosted, to
+    /// shorten the echo burst at the start of a call. 0 disables fast start.
+    pub fast_start_frames: u32,
+    /// The factor applied to the step size at the beginning of the fast-start window. The
+    /// boost fades out linearly to 1.0 over `fast_start_frames` frames.
+    pub fast_start_step_boost: f32,
+    /// The per-bin scaling of the step size. The same step size in every bin by default.
+    pub step_profile: StepProfile,
+    /// The optional adaptive step size driven by the echo-to-error ratio. With it,
+    /// `step_size` is the largest step size used. Disabled by default.
+    pub adaptive_step: Option<AdaptiveStepConfig>,
+    /// The optional step size boost after render volume changes, see
+    /// [`FdafAec::notify_render_volume_change`](crate::FdafAec::notify_render_volume_change).
+    /// Disabled by default.
+    pub volume_ramp: Option<VolumeRampConfig>,
+    /// The smoothing factor of the far-end power spectral density estimate used to normalize
+    /// the weight update. Values closer to 1.0 track the far-end spectrum more slowly.
+    pub psd_smoothing: f32,
+    /// The frequency shape of the regularization added to the far-end PSD in the weight
+    /// update. Uniform by default.
+    pub regularization: RegularizationProfile,
+    /// The epsilon, floor and initial value of the far-end PSD normalization, to be adjusted
+    /// for signals far from `[-1.0, 1.0]`.
+    pub normalization: NormalizationConfig,
+    /// The floating-point precision of the PSD smoothing, the weight accumulation and the
+    /// metrics. Single precision everywhere by default.
+    pub precision: PrecisionConfig,
+    /// The bins kept out of adaptation. None by default.
+    pub guard_band: GuardBandConfig,
+    /// Whether the weight update is constrained to the `fft_size / 2` valid filter taps
+    /// (constrained FDAF), which keeps circular-convolution wrap-around out of the filter at
+    /// the cost of two extra FFTs per frame. Disabled by default.
+    pub constrained_update: bool,
+    /// How the echo estimate is synthesized, overlap-save by default. Overlap-add implies
+    /// the constrained update, which is then reported as enabled.
+    pub block_method: BlockMethod,
+    /// The optional Hammerstein model of loudspeaker distortion, whose branch filters are
+    /// adapted next to the linear filter. Disabled by default.
+    pub nonlinear_echo: Option<NonlinearEchoConfig>,
+    /// The optional DC-blocking high-pass filter applied to both inputs. Disabled by default.
+    pub high_pass: Option<HighPassConfig>,
+    /// The optional notch filter that detects and removes mains hum from the microphone
+    /// input. Disabled by default.
+    pub hum_notch: Option<HumNotchConfig>,
+    /// The optional far-end tonality detector that slows adaptation in tonal bins. Disabled
+    /// by default.
+    pub tonality: Option<TonalityConfig>,
+    /// The kind of far-end content the canceller is tuned for.
+    pub content_mode: ContentMode,
+    /// The optional coherence-based adaptation gate for continuous double talk. Disabled by
+    /// default.
+    pub crosstalk: Option<CrosstalkConfig>,
+    /// The optional per-band double-talk detector that freezes adaptation in bands with
+    /// near-end speech. Disabled by default.
+    pub band_double_talk: Option<BandDoubleTalkConfig>,
+    /// The optional Geigel double-talk detector that freezes or slows adaptation while
+    /// near-end speech is detected. Disabled by default.
+    pub geigel_dtd: Option<GeigelConfig>,
+    /// The optional coherence double-talk detector that scales the step size by the
+    /// probability of near-end speech. Disabled by default.
+    pub coherence_dtd: Option<CoherenceDtdConfig>,
+    /// The optional near-end voice activity detector on the error signal, which slows
+    /// adaptation and relaxes the NLP during near-end speech. Disabled by default.
+    pub vad: Option<VadConfig>,
+    /// The optional coherence-based echo suppression used until the filter has converged.
+    /// Disabled by default.
+    pub convergence_protection: Option<ConvergenceProtectionConfig>,
+    /// The optional suppressor of the late reverberation beyond the filter length, applied
+    /// right after the linear filter. Disabled by default.
+    pub late_reverb: Option<LateReverbConfig>,
+    /// The optional residual echo post-filter applied to the output. Disabled by default.
+    pub post_filter: Option<PostFilterConfig>,
+    /// The optional nonlinear processor that suppresses the output during far-end single
+    /// talk. Disabled by default.
+    pub nlp: Option<NlpLevel>,
+    /// The optional ceiling on the residual echo relative to near-end speech, enforced after
+    /// the NLP. Disabled by default.
+    pub residual_ceiling: Option<ResidualCeilingConfig>,
+    /// The optional comfort noise that fills the output where the post-filter or the NLP
+    /// suppressed it. Disabled by default.
+    pub comfort_noise: Option<ComfortNoiseConfig>,
+    /// The optional low-power mode entered while there is no acoustic echo, e.g. with a
+    /// headset. Disabled by default.
+    pub low_power: Option<LowPowerConfig>,
+    /// The optional automatic gain control applied last to the output. Disabled by default.
+    pub agc: Option<AgcConfig>,
+    /// The CPU budget, which decides how many frames are adapted. Full by default.
+    pub cpu_budget: CpuBudget,
+    /// The optional detector of clipping in the far-end reference, which slows adaptation
+    /// on clipped blocks. Disabled by default.
+    pub clipping_detection: Option<ClippingConfig>,
+    /// The optional compensation of a render-to-capture delay longer than the filter.
+    /// Disabled by default.
+    pub bulk_delay: Option<BulkDelayConfig>,
+    /// The optional compensation of clock drift between the render and capture streams.
+    /// Disabled by default.
+    pub drift_compensation: Option<DriftConfig>,
+    /// How many samples the far-end reference is supplied ahead of the microphone signal.
+    ///
+    /// The microphone is delayed by this amount before cancellation, so echo that reaches
+    /// the microphone up to `far_end_lookahead` samples *before* the corresponding far-end
+    /// sample is passed to [`FdafAec::process`](crate::FdafAec::process) can still be
+    /// modeled. This absorbs small alignment errors between render and capture timestamps.
+    /// The output is delayed by the same amount (see
+    /// [`FdafAec::latency_samples`](crate::FdafAec::latency_samples)) and the causal part
+    /// of the filter shrinks to `fft_size / 2 - far_end_lookahead` taps. Must be smaller
+    /// than `fft_size / 2`. Defaults to 0.
+    pub far_end_lookahead: usize,
+    /// The largest change, as a linear echo path gain, that one frame may apply to any
+    /// frequency bin of the filter. Guards against loud far-end transients. Disabled by
+    /// default.
+    pub max_weight_update: Option<f32>,
+    /// The fraction by which the weights shrink on every adapted frame, so that echo paths
+    /// no longer reinforced by the update fade out. Must be in `[0.0, 1.0)`. Defaults to 0,
+    /// no leakage.
+    pub weight_leakage: f32,
+    /// The largest broadband gain of the estimated echo path, the square root of its impulse
+    /// response energy. Physical echo paths rarely exceed 1.0; a filter above the bound is
+    /// scaled back onto it. Disabled by default.
+    pub max_echo_path_gain: Option<f32>,
+    /// The optional wall-time budget of one call to [`FdafAec::process`](crate::FdafAec::process),
+    /// monitored by the processing watchdog. Disabled by default.
+    pub deadline: Option<Duration>,
+}
+
+impl Default for FdafAecConfig {
+    fn default() -> Self {
+        Self {
+            fft_size: 1024,
+            sample_rate: 16000,
+            far_end_sample_rate: None,
+            step_size: 0.02,
+            adaptation: AdaptationMode::Nlms,
+            fast_start_frames: 0,
+            fast_start_step_boost: 4.0,
+            step_profile: StepProfile::Scalar,
+            adaptive_step: None,
+            volume_ramp: None,
+            psd_smoothing: 0.98,
+            regularization: RegularizationProfile::Uniform,
+            normalization: NormalizationConfig::default(),
+            precision: PrecisionConfig::default(),
+            guard_band: GuardBandConfig::default(),
+            constrained_update: false,
+            block_method: BlockMethod::OverlapSave,
+            nonlinear_echo: None,
+            high_pass: None,
+            hum_notch: None,
+            tonality: None,
+            content_mode: ContentMode::Speech,
+            crosstalk: None,
+            band_double_talk: None,
+            geigel_dtd: None,
+            coherence_dtd: None,
+            vad: None,
+            convergence_protection: None,
+            late_reverb: None,
+            post_filter: None,
+            nlp: None,
+            residual_ceiling: None,
+            comfort_noise: None,
+            low_power: None,
+            agc: None,
+            cpu_budget: CpuBudget::Full,
+            clipping_detection: None,
+            bulk_delay: None,
+            drift_compensation: None,
+            far_end_lookahead: 0,
+            max_weight_update: None,
+            weight_leakage: 0.0,
+            max_echo_path_gain: None,
+            deadline: None,
+        }
+    }
+}
+
+/// Echo tail covered by [`FdafAecConfig::for_sample_rate`], in milliseconds.
+const DEFAULT_TAIL_MS: f32 = 32.0;
+/// Time constant of the far-end PSD estimate, in seconds. Equivalent to the default
+/// smoothing factor of 0.98 with 512-sample frames at 16 kHz.
+const PSD_TIME_CONSTANT_S: f32 = 1.58;
+
+impl FdafAecConfig {
+    /// Returns a default configuration adapted to the given sample rate.
+    ///
+    /// The FFT size is chosen to cover a 32 ms echo tail and the PSD smoothing factor is
+    /// derived from a fixed time constant, so that the canceller behaves the same at 8 kHz
+    /// narrowband with small FFTs as at 16 or 48 kHz. At 16 kHz this equals
+    /// [`FdafAecConfig::default`].
+    pub fn for_sample_rate(sample_rate: u32) -> Self {
+        let fft_size = single_partition_fft_size(sample_rate, DEFAULT_TAIL_MS);
+        Self {
+            fft_size,
+            sample_rate,
+            psd_smoothing: Self::psd_smoothing_for(fft_size, sample_rate),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the PSD smoothing factor that gives the default PSD time constant for frames
+    /// of `fft_size / 2` samples at `sample_rate`.
+    ///
+    /// A fixed smoothing factor averages over fewer seconds of audio when frames are short,
+    /// which makes the normalization of small-FFT configurations noisier.
+    pub fn psd_smoothing_for(fft_size: usize, sample_rate: u32) -> f32 {
+        let frame_duration = (fft_size / 2) as f32 / sample_rate as f32;
+        (-frame_duration / PSD_TIME_CONSTANT_S).exp()
+    }
+}
+
+/// The parameters a canceller is actually running with, as returned by
+/// [`FdafAec::resolved_config`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedConfig {
+    /// The effective configuration, including changes made at runtime through setters.
+    pub config: FdafAecConfig,
+    /// The number of samples per frame, `fft_size / 2`.
+    pub frame_size: usize,
+    /// The fixed epsilon added to the far-end PSD in the weight update.
+    pub regularization_epsilon: f32,
+    /// The output latency, see [`FdafAec::latency_samples`].
+    pub latency_samples: usize,
+}
+
+impl FdafAec {
+    /// Returns every effective parameter of the canceller, including values derived at
+    /// construction and changes made at runtime, for logs and bug reports.
+    pub fn resolved_config(&self) -> ResolvedConfig {
+        let config = FdafAecConfig {
+            fft_size: self.fft_size,
+            sample_rate: self.sample_rate,
+            far_end_sample_rate: self.far_end_resampler.as_ref().map(|resampler| resampler.from_rate),
+            step_size: self.mu,
+            adaptation: self.adaptation_mode(),
+            fast_start_frames: self.fast_start.frames,
+            fast_start_step_boost: self.fast_start.boost,
+            step_profile: self.step_profile.profile.clone(),
+            adaptive_step: self.adaptive_step.as_ref().map(|step| step.config),
+            volume_ramp: self.volume_ramp.as_ref().map(|ramp| ramp.config),
+            psd_smoothing: self.smoothing_factor,
+            regularization: self.regularizer.profile.clone(),
+            normalization: self.normalization,
+            precision: self.precision.config,
+            guard_band: self.guard_band,
+            constrained_update: self.constrained_update,
+            block_method: self.block_method(),
+            nonlinear_echo: self.nonlinear_echo.as_ref().map(|nonlinear_echo| nonlinear_echo.config),
+            high_pass: self.high_pass.as_ref().map(|high_pass| high_pass.config),
+            hum_notch: self.hum_notch.as_ref().map(|hum_notch| hum_notch.config),
+            tonality: self.tonality.as_ref().map(|tonality| tonality.config),
+            content_mode: self.content_mode(),
+            crosstalk: self.crosstalk.as_ref().map(|crosstalk| crosstalk.config),
+            band_double_talk: self.band_dtd.as_ref().map(|band_dtd| band_dtd.config),
+            geigel_dtd: self.geigel.as_ref().map(|geigel| geigel.config),
+            coherence_dtd: self.coherence_dtd.as_ref().map(|detector| detector.config),
+            vad: self.vad.as_ref().map(|vad| vad.config),
+            convergence_protection: self.protection.as_ref().map(|protection| protection.config),
+            late_reverb: self.late_reverb.as_ref().map(|late_reverb| late_reverb.config),
+            post_filter: self.post_filter.as_ref().map(|post_filter| post_filter.config),
+            nlp: self.nlp.as_ref().map(|nlp| nlp.level),
+            residual_ceiling: self.residual_ceiling.as_ref().map(|ceiling| ceiling.config),
+            comfort_noise: self.comfort_noise.as_ref().map(|comfort_noise| comfort_noise.config),
+            low_power: self.low_power.as_ref().map(|low_power| low_power.config),
+            agc: self.agc.as_ref().map(|agc| agc.config),
+            cpu_budget: self.cpu_budget,
+            clipping_detection: self.clipping.as_ref().map(|clipping| clipping.config),
+            bulk_delay: self.bulk_delay.as_ref().map(|bulk_delay| bulk_delay.config),
+            drift_compensation: self.drift.as_ref().map(|drift| drift.config),
+            far_end_lookahead: self.latency_samples(),
+            max_weight_update: self.max_weight_update,
+            weight_leakage: self.weight_leakage,
+            max_echo_path_gain: self.max_echo_path_gain,
+            deadline: self.watchdog.as_ref().map(|watchdog| watchdog.budget),
+        };
+        ResolvedConfig {
+            config,
+            frame_size: self.frame_size,
+            regularization_epsilon: self.regularizer.epsilon,
+            latency_samples: self.latency_samples(),
+        }
+    }
+}
+
+/// Returns the smallest power-of-two FFT size whose single-partition filter covers an echo
+/// tail of `tail_ms` at `sample_rate`.
+pub(crate) fn single_partition_fft_size(sample_rate: u32, tail_ms: f32) -> usize {
+    let constraints = GeometryConstraints {
+        sample_rate: Some(sample_rate),
+        tail_ms: Some(tail_ms),
+        partitions: Some(1),
+        ..GeometryConstraints::default()
+    };
+    Geometry::solve(constraints).expect("The sample rate and tail must be positive.").fft_size()
+}
+
+/// The default fixed epsilon added to the far-end PSD in the weight update with the
+/// uniform regularization profile.
+pub(crate) const DEFAULT_REGULARIZATION: f32 = 1e-10;
+
+/// Returns the default fixed epsilon added to the far-end PSD in the weight update with a
+/// shaped regularization profile.
+///
+/// The PSD of an unnormalized FFT grows with the FFT size, so the epsilon is scaled
+/// with it to keep the same relative effect for every size.
+pub(crate) fn regularization_for(fft_size: usize) -> f32 {
+    DEFAULT_REGULARIZATION * fft_size as f32 / 1024.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use crate::{mean_square, FdafAec};
+
+    #[test]
+    fn resolved_config_reflects_runtime_changes() {
+        let config = FdafAecConfig { far_end_lookahead: 8, ..FdafAecConfig::for_sample_rate(8000) };
+        let mut aec = FdafAec::with_config(config.clone());
+        assert_eq!(aec.resolved_config().config, config);
+        assert_eq!(aec.resolved_config().latency_samples, 8);
+
+        aec.set_high_pass(Some(HighPassConfig::default()));
+        aec.set_content_mode(ContentMode::Music);
+        let resolved = aec.resolved_config();
+        assert_eq!(resolved.config.high_pass, Some(HighPassConfig::default()));
+        assert_eq!(resolved.config.content_mode, ContentMode::Music);
+        assert_eq!(resolved.frame_size, 256);
+        assert!(resolved.regularization_epsilon > 0.0);
+    }
+
+    #[test]
+    fn default_epsilon_scales_only_with_a_shaped_profile() {
+        assert_eq!(FdafAec::new(4096, 0.5).resolved_config().regularization_epsilon, DEFAULT_REGULARIZATION);
+        let pink = FdafAecConfig { fft_size: 4096, regularization: RegularizationProfile::Pink { strength: 0.1 }, ..FdafAecConfig::default() };
+        let mut aec = FdafAec::with_config(pink);
+        assert_eq!(aec.resolved_config().regularization_epsilon, 4.0 * DEFAULT_REGULARIZATION);
+        aec.set_regularization_profile(RegularizationProfile::Uniform);
+        assert_eq!(aec.resolved_config().regularization_epsilon, DEFAULT_REGULARIZATION);
+    }
+
+    #[test]
+    fn rate_aware_defaults() {
+        assert_eq!(FdafAecConfig::for_sample_rate(16000).fft_size, FdafAecConfig::default().fft_size);
+        assert!((FdafAecConfig::for_sample_rate(16000).psd_smoothing - 0.98).abs() < 1e-3);
+        assert_eq!(FdafAecConfig::for_sample_rate(8000).fft_size, 512);
+        assert_eq!(FdafAecConfig::for_sample_rate(48000).fft_size, 4096);
+
+        // Shorter frames need a smoothing factor closer to 1.0 for the same time constant.
+        assert!(FdafAecConfig::psd_smoothing_for(128, 8000) > FdafAecConfig::psd_smoothing_for(256, 8000));
+    }
+
+    #[test]
+    fn narrowband_small_fft_converges() {
+        for fft_size in [128, 256] {
+            let frame_size = fft_size / 2;
+            let config = FdafAecConfig {
+                fft_size,
+                step_size: 0.5,
+                psd_smoothing: FdafAecConfig::psd_smoothing_for(fft_size, 8000),
+                ..FdafAecConfig::for_sample_rate(8000)
+            };
+            let mut aec = FdafAec::with_config(config);
+
+            // Two seconds of narrowband audio with a short echo path.
+            let far = white_noise(16000, 0.1, 21);
+            let mic = echo(&far, &[(3, 0.4), (20, 0.1)]);
+            let mut output = Vec::new();
+            for (far_frame, mic_frame) in far.chunks_exact(frame_size).zip(mic.chunks_exact(frame_size)) {
+                output.extend(aec.process(far_frame, mic_frame));
+            }
+            let tail = output.len() - 4000;
+            let erle_db = 10.0 * (mean_square(&mic[tail..output.len()]) / mean_square(&output[tail..])).log10();
+            assert!(erle_db > 20.0, "ERLE with fft_size {} was {} dB", fft_size, erle_db);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn config_serializes() {
+        let config = FdafAecConfig {
+            far_end_sample_rate: Some(48000),
+            adaptation: AdaptationMode::Kalman(Default::default()),
+            step_profile: StepProfile::Custom(vec![0.5; 257]),
+            adaptive_step: Some(Default::default()),
+            volume_ramp: Some(Default::default()),
+            regularization: RegularizationProfile::Custom { strength: 2.0, weights: vec![1.0; 257] },
+            block_method: BlockMethod::OverlapAdd,
+            nonlinear_echo: Some(Default::default()),
+            high_pass: Some(Default::default()),
+            hum_notch: Some(Default::default()),
+            tonality: Some(Default::default()),
+            content_mode: ContentMode::Music,
+            crosstalk: Some(Default::default()),
+            band_double_talk: Some(Default::default()),
+            geigel_dtd: Some(Default::default()),
+            coherence_dtd: Some(Default::default()),
+            vad: Some(Default::default()),
+            convergence_protection: Some(Default::default()),
+            late_reverb: Some(Default::default()),
+            post_filter: Some(Default::default()),
+            nlp: Some(NlpLevel::Conservative),
+            residual_ceiling: Some(Default::default()),
+            comfort_noise: Some(Default::default()),
+            low_power: Some(Default::default()),
+            agc: Some(Default::default()),
+            cpu_budget: CpuBudget::Reduced,
+            clipping_detection: Some(Default::default()),
+            bulk_delay: Some(Default::default()),
+            drift_compensation: Some(Default::default()),
+            max_weight_update: Some(0.1),
+            max_echo_path_gain: Some(4.0),
+            deadline: Some(Duration::from_millis(8)),
+            ..FdafAecConfig::default()
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        let restored: FdafAecConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, config);
+    }
+}
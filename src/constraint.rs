@@ -0,0 +1,75 @@
+//! Gradient constraint of the weight update (constrained FDAF).
+//!
+//! The frequency-domain weights describe a filter of `fft_size` taps, but overlap-save only
+//! produces valid output for the first `fft_size / 2` of them. An unconstrained update lets
+//! circular-convolution wrap-around leak into the other half, where it models nothing and
+//! limits how deep the filter converges. The constrained update transforms the gradient to
+//! the time domain, zeroes its second half and transforms it back, at the cost of two more
+//! FFTs per frame.
+
+use crate::{FdafAec, FdafAecConfig};
+use nalgebra::DVector;
+use num_complex::Complex;
+use rustfft::Fft;
+use std::sync::Arc;
+
+/// Restricts `gradient` to the first `frame_size` taps of the time-domain filter.
+pub(crate) fn constrain_gradient(
+    fft: &Arc<dyn Fft<f32>>,
+    ifft: &Arc<dyn Fft<f32>>,
+    gradient: &mut DVector<Complex<f32>>,
+    frame_size: usize,
+) {
+    let samples = gradient.as_mut_slice();
+    ifft.process(samples);
+    samples[frame_size..].fill(Complex::new(0.0, 0.0));
+    let scale = 1.0 / samples.len() as f32;
+    samples.iter_mut().for_each(|g| *g *= scale);
+    fft.process(samples);
+}
+
+impl FdafAec {
+    /// Creates a new `FdafAec` instance with the constrained weight update, see
+    /// [`FdafAecConfig::constrained_update`].
+    pub fn new_constrained(fft_size: usize, step_size: f32) -> Self {
+        Self::with_config(FdafAecConfig { fft_size, step_size, constrained_update: true, ..FdafAecConfig::default() })
+    }
+
+    /// Returns whether the weight update is constrained to the valid filter taps.
+    pub fn is_constrained(&self) -> bool {
+        self.constrained_update
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    /// Returns the energy of the time-domain filter beyond the valid taps.
+    fn wrap_around_energy(aec: &FdafAec) -> f32 {
+        let mut h = aec.weights.as_slice().to_vec();
+        aec.ifft.process(&mut h);
+        let scale = 1.0 / aec.fft_size as f32;
+        h[aec.frame_size..].iter().map(|c| (c.re * scale).powi(2)).sum()
+    }
+
+    #[test]
+    fn keeps_wrap_around_out_of_filter() {
+        // The second tap lies beyond the 256 taps the filter can model.
+        let far = white_noise(256 * 200, 0.3, 46);
+        let mic = echo(&far, &[(10, 0.5), (300, 0.2)]);
+        let run = |mut aec: FdafAec| {
+            process_all(&mut aec, &far, &mic);
+            aec
+        };
+        let free = run(FdafAec::new(512, 0.5));
+        let constrained = run(FdafAec::new_constrained(512, 0.5));
+        assert!(constrained.is_constrained() && !free.is_constrained());
+
+        // Without the constraint, the filter tries to reach the late tap through wrap-around.
+        assert!(wrap_around_energy(&free) > 1e-3);
+        assert!(wrap_around_energy(&constrained) < 1e-9);
+        assert!((constrained.impulse_response()[10] - 0.5).abs() < 0.05);
+    }
+}
@@ -0,0 +1,237 @@
+//! Speech and music content modes.
+//!
+//! Music keeps the far-end busy across the whole spectrum for long stretches, so the filter
+//! is driven much harder than by speech and errors in the echo path estimate are clearly
+//! audible on the captured content. Music mode adapts more gently, and its NLP suppresses
+//! one level less, as music captured by the microphone during far-end playback is mangled
+//! by center clipping and deep attenuation. Switching modes at runtime glides the
+//! effective step size over a few dozen frames instead of jumping; the NLP ramps its gain
+//! within a frame as usual.
+//!
+//! In auto mode the canceller estimates the content from the band energies of the far-end
+//! PSD. Wideband playback spreads its energy evenly over the bands and masks the residual
+//! echo, while speech concentrates it in the lower bands and leaves pauses in which the
+//! residual is clearly audible. The estimate moves the step size and the NLP floor
+//! continuously between the speech and music tunings.
+
+use crate::{FdafAec, NlpLevel};
+use nalgebra::DVector;
+
+/// Fraction of the remaining distance to the target step scale covered on every frame.
+const TRANSITION_RATE: f32 = 0.05;
+/// Number of equally wide bands the far-end spectrum is split into for the estimate.
+const CONTENT_BANDS: usize = 8;
+/// Fraction of the remaining distance to the current frame's estimate covered on every frame.
+const ESTIMATE_RATE: f32 = 0.02;
+/// Flatness of the band energies at and below which the far end counts as speech.
+const SPEECH_FLATNESS: f32 = 0.15;
+/// Flatness of the band energies at and above which the far end counts as wideband playback.
+const MUSIC_FLATNESS: f32 = 0.5;
+/// Mean-square far-end level above which a frame counts as active (about -60 dBFS).
+const ACTIVITY_THRESHOLD: f32 = 1e-6;
+
+/// The kind of far-end content the canceller is tuned for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ContentMode {
+    /// Conversational speech with pauses between utterances.
+    #[default]
+    Speech,
+    /// Music or other dense, sustained playback.
+    Music,
+    /// Estimated from the far-end spectrum, between speech and music, see
+    /// [`FdafAec::content_estimate`].
+    Auto,
+}
+
+impl ContentMode {
+    /// Returns the factor applied to the configured step size in this mode. In auto mode
+    /// this is the speech factor the estimate starts from.
+    pub fn step_scale(&self) -> f32 {
+        match self {
+            ContentMode::Speech | ContentMode::Auto => 1.0,
+            ContentMode::Music => 0.5,
+        }
+    }
+
+    /// Returns the NLP level used in this mode when `level` is configured. In auto mode
+    /// this is the speech level the estimate starts from.
+    pub fn nlp_level(&self, level: NlpLevel) -> NlpLevel {
+        match (self, level) {
+            (ContentMode::Speech | ContentMode::Auto, level) => level,
+            (ContentMode::Music, NlpLevel::Aggressive) => NlpLevel::Moderate,
+            (ContentMode::Music, NlpLevel::Moderate | NlpLevel::Conservative) => NlpLevel::Conservative,
+        }
+    }
+
+    /// Returns the fixed content estimate of a manual mode, or `None` in auto mode.
+    fn fixed_estimate(&self) -> Option<f32> {
+        match self {
+            ContentMode::Speech => Some(0.0),
+            ContentMode::Music => Some(1.0),
+            ContentMode::Auto => None,
+        }
+    }
+}
+
+/// The current content mode, the content estimate and the step scale gliding towards it.
+#[derive(Debug, Clone)]
+pub(crate) struct ContentModeState {
+    mode: ContentMode,
+    estimate: f32,
+    step_scale: f32,
+}
+
+impl ContentModeState {
+    pub(crate) fn new(mode: ContentMode) -> Self {
+        Self { mode, estimate: mode.fixed_estimate().unwrap_or(0.0), step_scale: mode.step_scale() }
+    }
+
+    fn set_mode(&mut self, mode: ContentMode) {
+        self.mode = mode;
+        if let Some(estimate) = mode.fixed_estimate() {
+            self.estimate = estimate;
+        }
+    }
+
+    /// Returns how much the far end resembles wideband playback rather than speech, between
+    /// 0.0 and 1.0.
+    pub(crate) fn estimate(&self) -> f32 {
+        self.estimate
+    }
+
+    /// Updates the estimate in auto mode from the far-end PSD and the far-end frame.
+    pub(crate) fn observe(&mut self, psd: &DVector<f32>, far_end_frame: &[f32]) {
+        if self.mode != ContentMode::Auto {
+            return;
+        }
+        // Pauses in the far end leave the residual echo unmasked, like speech does
+        let target = if crate::mean_square(far_end_frame) > ACTIVITY_THRESHOLD {
+            let half = &psd.as_slice()[1..=psd.len() / 2];
+            let width = (half.len() / CONTENT_BANDS).max(1);
+            let bands: Vec<f32> = half.chunks(width).map(|band| band.iter().sum::<f32>() + 1e-20).collect();
+            let log_mean = bands.iter().map(|e| e.ln()).sum::<f32>() / bands.len() as f32;
+            let mean = bands.iter().sum::<f32>() / bands.len() as f32;
+            let flatness = log_mean.exp() / mean;
+            ((flatness - SPEECH_FLATNESS) / (MUSIC_FLATNESS - SPEECH_FLATNESS)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        self.estimate += ESTIMATE_RATE * (target - self.estimate);
+    }
+
+    /// Advances the transition by one frame and returns the step scale to use.
+    pub(crate) fn update(&mut self) -> f32 {
+        let (speech, music) = (ContentMode::Speech.step_scale(), ContentMode::Music.step_scale());
+        let target = speech + self.estimate * (music - speech);
+        self.step_scale += TRANSITION_RATE * (target - self.step_scale);
+        self.step_scale
+    }
+}
+
+impl FdafAec {
+    /// Returns the content mode the canceller is currently tuned for.
+    pub fn content_mode(&self) -> ContentMode {
+        self.content.mode
+    }
+
+    /// Switches between speech, music and auto tuning.
+    ///
+    /// The effective step size moves to the new mode's value gradually over the following
+    /// frames so the output does not change character abruptly.
+    pub fn set_content_mode(&mut self, mode: ContentMode) {
+        self.note_config_change();
+        self.content.set_mode(mode);
+    }
+
+    /// Returns how much the far end resembles wideband playback rather than speech over
+    /// silence, between 0.0 and 1.0. It is fixed at 0.0 in speech mode and at 1.0 in music
+    /// mode; in auto mode it is estimated from the band energies of the far-end PSD and
+    /// sets the step size and the NLP floor between the two tunings.
+    pub fn content_estimate(&self) -> f32 {
+        self.content.estimate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn mode_switch_glides_step_scale() {
+        let mut state = ContentModeState::new(ContentMode::Speech);
+        assert_eq!(state.update(), 1.0);
+
+        state.set_mode(ContentMode::Music);
+        let first = state.update();
+        assert!(first < 1.0 && first > 0.9, "first step scale was {}", first);
+        for _ in 0..200 {
+            state.update();
+        }
+        assert!((state.update() - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn music_mode_softens_the_nlp() {
+        assert_eq!(ContentMode::Music.nlp_level(NlpLevel::Aggressive), NlpLevel::Moderate);
+        assert_eq!(ContentMode::Music.nlp_level(NlpLevel::Conservative), NlpLevel::Conservative);
+        assert_eq!(ContentMode::Speech.nlp_level(NlpLevel::Aggressive), NlpLevel::Aggressive);
+
+        // Saturated echo leaves residual the linear filter cannot remove, which the NLP
+        // suppresses less in music mode.
+        let far = white_noise(256 * 150, 0.5, 63);
+        let mic: Vec<f32> = echo(&far, &[(10, 0.5)]).iter().map(|&e| (3.0 * e).tanh() / 3.0).collect();
+        let residual = |mode: ContentMode| {
+            let mut aec = FdafAec::new(512, 0.2);
+            aec.set_nlp(Some(NlpLevel::Aggressive));
+            aec.set_content_mode(mode);
+            let output = process_all(&mut aec, &far, &mic);
+            crate::mean_square(&output[120 * 256..])
+        };
+        let (speech, music) = (residual(ContentMode::Speech), residual(ContentMode::Music));
+        assert!(music > 10.0 * speech, "music {} vs speech {}", music, speech);
+    }
+
+    #[test]
+    fn auto_mode_suppresses_speech_deeper_than_music() {
+        // Speech-like far end: low-pass noise in 400 ms bursts with 200 ms pauses.
+        let noise = white_noise(256 * 300, 0.5, 64);
+        let mut state = 0.0;
+        let speech: Vec<f32> = noise
+            .iter()
+            .enumerate()
+            .map(|(n, &x)| {
+                state = 0.9 * state + x;
+                if n % 9600 < 6400 { 0.3 * state } else { 0.0 }
+            })
+            .collect();
+        let music = white_noise(256 * 300, 0.5, 65);
+
+        // Saturated echo leaves residual the linear filter cannot remove.
+        let run = |far: &[f32]| {
+            let mic: Vec<f32> = echo(far, &[(10, 0.5)]).iter().map(|&e| (3.0 * e).tanh() / 3.0).collect();
+            let mut aec = FdafAec::new(512, 0.2);
+            aec.set_nlp(Some(NlpLevel::Aggressive));
+            aec.set_content_mode(ContentMode::Auto);
+            let mut floor = 1.0f32;
+            for (i, (f, m)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+                aec.process(f, m);
+                if i >= 200 {
+                    floor = floor.min(aec.nlp_gain().unwrap());
+                }
+            }
+            (aec.content_estimate(), floor)
+        };
+        let (speech_estimate, speech_floor) = run(&speech);
+        let (music_estimate, music_floor) = run(&music);
+        assert!(speech_estimate < 0.2 && music_estimate > 0.8, "speech {} vs music {}", speech_estimate, music_estimate);
+        assert!(music_floor > 5.0 * speech_floor, "music {} vs speech {}", music_floor, speech_floor);
+
+        // The manual modes keep their fixed estimates.
+        let mut aec = FdafAec::new(512, 0.2);
+        aec.set_content_mode(ContentMode::Music);
+        process_all(&mut aec, &speech, &speech);
+        assert_eq!(aec.content_estimate(), 1.0);
+    }
+}
@@ -0,0 +1,102 @@
+//! Convergence state of the adaptive filter.
+//!
+//! Applications need to know when the output can be trusted and when they should fall back
+//! to muting or half-duplex operation. The canceller compares the error power with the
+//! microphone power over a sliding window of frames in which the far-end is active without
+//! double talk: a filter that cancels the echo leaves an error well below the microphone
+//! level, an adapting filter one close to it, and a diverged filter adds echo of its own and
+//! leaves an error above it.
+
+use crate::{FdafAec, FrameEnergies};
+use std::collections::VecDeque;
+
+/// The number of far-end active frames the error-to-mic ratio is measured over.
+const WINDOW_FRAMES: usize = 50;
+/// Mean-square level above which the far-end is considered active (about -60 dBFS).
+const FAR_END_ACTIVITY_THRESHOLD: f32 = 1e-6;
+/// Error-to-mic power ratio below which the filter is converged (-10 dB).
+const CONVERGED_RATIO: f32 = 0.1;
+/// Error-to-mic power ratio above which the filter has diverged (+3 dB).
+const DIVERGED_RATIO: f32 = 2.0;
+
+/// The state of the adaptive filter, as returned by [`FdafAec::convergence_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ConvergenceState {
+    /// The far-end has not been active for long enough to judge the filter.
+    #[default]
+    Initializing,
+    /// The filter removes part of the echo and is still adapting.
+    Adapting,
+    /// The filter removes the echo; the output can be trusted.
+    Converged,
+    /// The output is louder than the microphone input: the filter adds echo instead of
+    /// removing it.
+    Diverged,
+}
+
+/// Measures the error-to-mic power ratio over the recent far-end active frames.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConvergenceTracker {
+    window: VecDeque<(f32, f32)>,
+    state: ConvergenceState,
+}
+
+impl ConvergenceTracker {
+    pub(crate) fn update(&mut self, energies: &FrameEnergies, double_talk: bool) {
+        // Near-end speech raises the error towards the mic level, but never above it, so
+        // double talk frames still count when they show a diverged filter.
+        if energies.far_end <= FAR_END_ACTIVITY_THRESHOLD || (double_talk && energies.error <= energies.mic) {
+            return;
+        }
+        if self.window.len() == WINDOW_FRAMES {
+            self.window.pop_front();
+        }
+        self.window.push_back((energies.error, energies.mic));
+        if self.window.len() < WINDOW_FRAMES {
+            return;
+        }
+        let (error, mic) = self.window.iter().fold((0.0, 0.0), |(error, mic), &(e, m)| (error + e, mic + m));
+        let ratio = error / f32::max(mic, 1e-12);
+        self.state = if ratio > DIVERGED_RATIO {
+            ConvergenceState::Diverged
+        } else if ratio < CONVERGED_RATIO {
+            ConvergenceState::Converged
+        } else {
+            ConvergenceState::Adapting
+        };
+    }
+}
+
+impl FdafAec {
+    /// Returns the convergence state of the adaptive filter after the last frame.
+    pub fn convergence_state(&self) -> ConvergenceState {
+        self.convergence.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use num_complex::Complex;
+
+    #[test]
+    fn reports_convergence_and_divergence() {
+        let far = white_noise(256 * 300, 0.3, 89);
+        let mic = echo(&far, &[(10, 0.5), (40, -0.2)]);
+        let mut aec = FdafAec::new(512, 0.5);
+        let mut states = Vec::new();
+        for (i, (f, m)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+            if i == 200 {
+                // Invert and amplify the filter and stop it from adapting back.
+                aec.weights *= Complex::new(-3.0, 0.0);
+                aec.set_step_size_controller(|_| 0.0);
+            }
+            aec.process(f, m);
+            states.push(aec.convergence_state());
+        }
+        assert_eq!(states[10], ConvergenceState::Initializing);
+        assert_eq!(states[199], ConvergenceState::Converged);
+        assert_eq!(states[299], ConvergenceState::Diverged);
+    }
+}
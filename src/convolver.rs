@@ -0,0 +1,134 @@
+//! Static FIR filtering with the canceller's overlap-save machinery.
+//!
+//! [`OlsConvolver`] applies a fixed impulse response, such as a measured room response or an
+//! echo path estimated by [`FdafAec`], to a stream of blocks. Long responses are split into
+//! partitions of one block each (uniformly partitioned overlap-save), so the cost per block
+//! grows with the number of partitions instead of with an ever larger FFT. Every partition
+//! is the canceller's overlap-save filter (see the `fdaf` module) with fixed weights, so
+//! like the canceller, the convolver adds no latency beyond assembling the input into blocks.
+
+use crate::{fdaf, FdafAec};
+use nalgebra::DVector;
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// A partitioned overlap-save convolver for a fixed impulse response.
+pub struct OlsConvolver {
+    block_size: usize,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    partitions: Vec<DVector<Complex<f32>>>,
+    input_buffer: Vec<f32>,
+    spectra: VecDeque<DVector<Complex<f32>>>,
+}
+
+impl OlsConvolver {
+    /// Creates a convolver for `impulse_response`, processing blocks of `block_size` samples.
+    ///
+    /// `block_size` must be a power of two. The impulse response may have any length; it is
+    /// split into `ceil(len / block_size)` partitions.
+    pub fn new(impulse_response: &[f32], block_size: usize) -> Self {
+        assert!(block_size > 0 && block_size.is_power_of_two(), "block_size must be a power of two.");
+        let fft_size = 2 * block_size;
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        let ifft = planner.plan_fft_inverse(fft_size);
+
+        let partitions: Vec<DVector<Complex<f32>>> =
+            impulse_response.chunks(block_size).map(|taps| fdaf::weights(&fft, taps, fft_size)).collect();
+        let zero = DVector::from_element(fft_size, Complex::new(0.0, 0.0));
+        Self {
+            block_size,
+            fft,
+            ifft,
+            spectra: vec![zero; partitions.len()].into(),
+            partitions,
+            input_buffer: vec![0.0; fft_size],
+        }
+    }
+
+    /// Creates a convolver rendering the echo path currently estimated by `aec`, with the
+    /// canceller's frame size as block size.
+    pub fn from_echo_path(aec: &FdafAec) -> Self {
+        Self::new(&aec.impulse_response(), aec.frame_size)
+    }
+
+    /// Returns the number of samples per block.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the number of partitions the impulse response was split into.
+    pub fn partitions(&self) -> usize {
+        self.partitions.len()
+    }
+
+    /// Returns the delay, in samples, between an input sample and the corresponding output
+    /// sample, excluding the assembly of blocks. This is always zero.
+    pub fn latency_samples(&self) -> usize {
+        0
+    }
+
+    /// Filters one block of `block_size` samples.
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        assert_eq!(input.len(), self.block_size, "Input block size must match the convolver block size.");
+        if self.partitions.is_empty() {
+            return vec![0.0; self.block_size];
+        }
+
+        // Rolling window of the last two blocks
+        self.input_buffer.copy_within(self.block_size.., 0);
+        self.input_buffer[self.block_size..].copy_from_slice(input);
+        let x_f = fdaf::spectrum(&self.fft, &self.input_buffer);
+
+        // Frequency-domain delay line: partition `p` is applied to the spectrum `p` blocks ago
+        self.spectra.pop_back();
+        self.spectra.push_front(x_f);
+        let mut y_f = DVector::from_element(2 * self.block_size, Complex::new(0.0, 0.0));
+        for (x_f, h_f) in self.spectra.iter().zip(&self.partitions) {
+            y_f += x_f.component_mul(h_f);
+        }
+        fdaf::overlap_save(&self.ifft, &y_f).data.into()
+    }
+
+    /// Clears the input history, keeping the impulse response.
+    pub fn reset(&mut self) {
+        self.input_buffer.fill(0.0);
+        self.spectra.iter_mut().for_each(|x| x.fill(Complex::new(0.0, 0.0)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn matches_direct_convolution() {
+        let ir = white_noise(224, 0.5, 30);
+        let input = white_noise(64 * 10, 0.3, 31);
+        let mut convolver = OlsConvolver::new(&ir, 64);
+        assert_eq!(convolver.partitions(), 4);
+        let output: Vec<f32> = input.chunks(64).flat_map(|block| convolver.process(block)).collect();
+
+        for (n, &y) in output.iter().enumerate() {
+            let expected: f32 = ir.iter().enumerate().filter(|&(k, _)| k <= n).map(|(k, h)| h * input[n - k]).sum();
+            assert!((y - expected).abs() < 1e-4, "sample {}: {} != {}", n, y, expected);
+        }
+    }
+
+    #[test]
+    fn renders_estimated_echo_path() {
+        let mut aec = FdafAec::new(512, 0.5);
+        let far = white_noise(256 * 100, 0.3, 32);
+        let mic = echo(&far, &[(30, 0.5)]);
+        process_all(&mut aec, &far, &mic);
+
+        let mut convolver = OlsConvolver::from_echo_path(&aec);
+        let rendered: Vec<f32> = far[..2560].chunks(256).flat_map(|block| convolver.process(block)).collect();
+        let residual: Vec<f32> = rendered.iter().zip(&mic).map(|(r, m)| r - m).collect();
+        assert!(crate::mean_square(&residual) < crate::mean_square(&mic[..2560]) / 100.0);
+    }
+}
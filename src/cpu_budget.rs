@@ -0,0 +1,71 @@
+//! Reduced adaptation under CPU pressure.
+//!
+//! Most of the cost of a frame lies in the weight update: the error FFT, the per-bin
+//! gradient with its detectors and the optional constraint FFTs. When the host is short on
+//! CPU it can lower the budget so that only every other frame is adapted. The echo estimate
+//! is still subtracted on every frame, so the output stays cancelled; the filter merely
+//! converges and tracks at half the rate. Which frames adapt depends only on the frame
+//! count, so processing stays deterministic.
+
+use crate::FdafAec;
+
+/// How much CPU the canceller may spend per frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CpuBudget {
+    /// Every frame is adapted.
+    #[default]
+    Full,
+    /// Only every other frame is adapted, which saves roughly a third of the processing
+    /// time at the cost of half the convergence speed.
+    Reduced,
+}
+
+impl CpuBudget {
+    /// Returns whether the frame with the zero-based index `frame` is adapted.
+    pub fn adapts(&self, frame: u64) -> bool {
+        match self {
+            CpuBudget::Full => true,
+            CpuBudget::Reduced => frame.is_multiple_of(2),
+        }
+    }
+}
+
+impl FdafAec {
+    /// Sets the CPU budget, e.g. in response to a CPU pressure signal from the host. Takes
+    /// effect with the next frame.
+    pub fn set_cpu_budget(&mut self, budget: CpuBudget) {
+        self.note_config_change();
+        self.cpu_budget = budget;
+    }
+
+    /// Returns the current CPU budget.
+    pub fn cpu_budget(&self) -> CpuBudget {
+        self.cpu_budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn reduced_budget_adapts_every_other_frame() {
+        let far = white_noise(256 * 200, 0.3, 69);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let mut aec = FdafAec::new(512, 0.2);
+        aec.set_cpu_budget(CpuBudget::Reduced);
+        let mut output = Vec::new();
+        for (i, (far_frame, mic_frame)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+            let before = aec.impulse_response();
+            output.extend(aec.process(far_frame, mic_frame));
+            assert_eq!(aec.impulse_response() != before, i % 2 == 0, "frame {}", i);
+        }
+
+        // The echo is still removed from every frame.
+        assert!((aec.impulse_response()[10] - 0.5).abs() < 0.05);
+        let tail = output.len() - 20 * 256;
+        assert!(crate::mean_square(&output[tail..]) < crate::mean_square(&mic[tail..]) / 100.0);
+    }
+}
@@ -0,0 +1,128 @@
+//! Crosstalk-resistant adaptation for continuous double talk.
+//!
+//! In intercom-like scenarios both sides talk almost constantly, so waiting for far-end
+//! single talk before adapting leaves the filter unconverged. Instead, each bin is adapted in
+//! proportion to the magnitude-squared coherence between the far-end and the error signal.
+//! Residual echo is coherent with the far-end and keeps driving the update, while near-end
+//! speech is not and is largely kept out of it.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+
+/// Parameters of the coherence-based adaptation gate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CrosstalkConfig {
+    /// Smoothing factor of the cross- and auto-spectra used to estimate the coherence.
+    pub smoothing: f32,
+    /// Lower bound of the per-bin adaptation gate, so that no bin freezes completely.
+    pub min_gate: f32,
+}
+
+impl Default for CrosstalkConfig {
+    fn default() -> Self {
+        Self { smoothing: 0.9, min_gate: 0.05 }
+    }
+}
+
+/// Estimates the far-end/error coherence of every bin and derives adaptation gates.
+#[derive(Debug, Clone)]
+pub(crate) struct CoherenceGate {
+    pub(crate) config: CrosstalkConfig,
+    cross: DVector<Complex<f32>>,
+    far_power: DVector<f32>,
+    error_power: DVector<f32>,
+    gates: Vec<f32>,
+}
+
+impl CoherenceGate {
+    pub(crate) fn new(config: CrosstalkConfig, fft_size: usize) -> Self {
+        Self {
+            config,
+            cross: DVector::from_element(fft_size, Complex::new(0.0, 0.0)),
+            far_power: DVector::from_element(fft_size, 0.0),
+            error_power: DVector::from_element(fft_size, 0.0),
+            gates: vec![1.0; fft_size],
+        }
+    }
+
+    /// Updates the spectra with the far-end and error spectra of the current frame.
+    pub(crate) fn update(&mut self, x_f: &DVector<Complex<f32>>, e_f: &DVector<Complex<f32>>) {
+        let a = self.config.smoothing;
+        for i in 0..self.gates.len() {
+            self.cross[i] = self.cross[i] * a + x_f[i].conj() * e_f[i] * (1.0 - a);
+            self.far_power[i] = a * self.far_power[i] + (1.0 - a) * x_f[i].norm_sqr();
+            self.error_power[i] = a * self.error_power[i] + (1.0 - a) * e_f[i].norm_sqr();
+            let coherence = self.cross[i].norm_sqr() / (self.far_power[i] * self.error_power[i] + 1e-20);
+            self.gates[i] = coherence.clamp(self.config.min_gate, 1.0);
+        }
+    }
+
+    /// Returns the adaptation gate of every FFT bin.
+    pub(crate) fn gates(&self) -> &[f32] {
+        &self.gates
+    }
+
+    /// Returns the mean adaptation gate over all bins.
+    pub(crate) fn mean_gate(&self) -> f32 {
+        self.gates.iter().sum::<f32>() / self.gates.len() as f32
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables crosstalk-resistant adaptation.
+    pub fn set_crosstalk_resistance(&mut self, config: Option<CrosstalkConfig>) {
+        self.note_config_change();
+        self.crosstalk = config.map(|config| CoherenceGate::new(config, self.fft_size));
+    }
+
+    /// Returns the mean per-bin adaptation gate of the last frame, or `None` if
+    /// crosstalk-resistant adaptation is disabled.
+    ///
+    /// Values close to 1.0 mean the error is dominated by residual echo; values close to the
+    /// configured minimum mean it is dominated by near-end speech.
+    pub fn crosstalk_gate(&self) -> Option<f32> {
+        self.crosstalk.as_ref().map(CoherenceGate::mean_gate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    fn spectrum(samples: &[f32]) -> DVector<Complex<f32>> {
+        DVector::from_iterator(samples.len(), samples.iter().map(|&x| Complex::new(x, 0.0)))
+    }
+
+    #[test]
+    fn gate_follows_coherence() {
+        let mut gate = CoherenceGate::new(CrosstalkConfig::default(), 8);
+        for frame in 0..50 {
+            let x = spectrum(&white_noise(8, 1.0, frame));
+            let e = x.map(|c| c * 0.3);
+            gate.update(&x, &e);
+        }
+        assert!(gate.mean_gate() > 0.99);
+
+        for frame in 0..200 {
+            let x = spectrum(&white_noise(8, 1.0, 100 + frame));
+            let e = spectrum(&white_noise(8, 1.0, 1000 + frame));
+            gate.update(&x, &e);
+        }
+        assert!(gate.mean_gate() < 0.3, "mean gate was {}", gate.mean_gate());
+    }
+
+    #[test]
+    fn converges_during_continuous_double_talk() {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_crosstalk_resistance(Some(CrosstalkConfig::default()));
+        let far = white_noise(256 * 300, 0.3, 13);
+        let near = white_noise(256 * 300, 0.1, 14);
+        let mic: Vec<f32> = echo(&far, &[(20, 0.6)]).iter().zip(&near).map(|(e, n)| e + n).collect();
+        process_all(&mut aec, &far, &mic);
+        let ir = aec.impulse_response();
+        assert!((ir[20] - 0.6).abs() < 0.1, "estimated tap was {}", ir[20]);
+    }
+}
@@ -0,0 +1,153 @@
+//! Render-to-capture delay estimation with GCC-PHAT.
+//!
+//! The adaptive filter only models echo that arrives within `fft_size / 2` samples of the
+//! far-end reference. Audio stacks often add a bulk delay of tens or hundreds of
+//! milliseconds between playback and capture, which puts the echo out of reach of the
+//! filter. [`DelayEstimator`] finds that delay from the signals alone with the generalized
+//! cross-correlation with phase transform (GCC-PHAT): the cross-spectrum of far-end and
+//! microphone, smoothed over time, is whitened to unit magnitude so every frequency votes
+//! equally, and its inverse transform peaks at the delay. Whitening makes the peak sharp
+//! even for colored signals like speech, and its height doubles as a confidence measure.
+
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+
+/// Parameters of a [`DelayEstimator`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DelayEstimatorConfig {
+    /// The largest delay, in samples, the estimator searches.
+    pub max_delay: usize,
+    /// The smoothing factor of the cross-spectrum, per processed block.
+    pub smoothing: f32,
+}
+
+impl Default for DelayEstimatorConfig {
+    fn default() -> Self {
+        Self { max_delay: 4096, smoothing: 0.95 }
+    }
+}
+
+/// An estimated render-to-capture delay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DelayEstimate {
+    /// The delay, in samples, by which the microphone lags the far-end reference.
+    pub delay_samples: usize,
+    /// The height of the whitened cross-correlation peak, from 0.0 (no correlation) to 1.0
+    /// (the microphone is a delayed, filtered copy of the far-end).
+    pub confidence: f32,
+}
+
+/// Estimates the delay between a far-end reference and a microphone signal.
+pub struct DelayEstimator {
+    config: DelayEstimatorConfig,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    far_end: Vec<f32>,
+    mic: Vec<f32>,
+    cross: Vec<Complex<f32>>,
+    estimate: Option<DelayEstimate>,
+}
+
+impl DelayEstimator {
+    /// Creates an estimator. The analysis window is the smallest power of two of at least
+    /// twice `max_delay` samples, so delays up to `max_delay` keep at least half the window
+    /// overlapping.
+    pub fn new(config: DelayEstimatorConfig) -> Self {
+        assert!(config.max_delay > 0, "max_delay must be at least one sample.");
+        let window = (2 * config.max_delay).next_power_of_two();
+        let mut planner = FftPlanner::new();
+        Self {
+            config,
+            fft: planner.plan_fft_forward(window),
+            ifft: planner.plan_fft_inverse(window),
+            far_end: vec![0.0; window],
+            mic: vec![0.0; window],
+            cross: vec![Complex::new(0.0, 0.0); window],
+            estimate: None,
+        }
+    }
+
+    /// Returns the length of the analysis window, in samples.
+    pub fn window_size(&self) -> usize {
+        self.far_end.len()
+    }
+
+    /// Adds a block of time-aligned far-end and microphone samples and updates the
+    /// estimate. Blocks may have any length, but both must have the same.
+    pub fn process(&mut self, far_end: &[f32], mic: &[f32]) -> Option<DelayEstimate> {
+        assert_eq!(far_end.len(), mic.len(), "Far-end and mic blocks must have the same length.");
+        for (buffer, block) in [(&mut self.far_end, far_end), (&mut self.mic, mic)] {
+            let shift = block.len().min(buffer.len());
+            buffer.copy_within(shift.., 0);
+            let start = buffer.len() - shift;
+            buffer[start..].copy_from_slice(&block[block.len() - shift..]);
+        }
+
+        let spectrum = |samples: &[f32]| {
+            let mut buffer: Vec<Complex<f32>> = samples.iter().map(|&x| Complex::new(x, 0.0)).collect();
+            self.fft.process(&mut buffer);
+            buffer
+        };
+        let (x_f, d_f) = (spectrum(&self.far_end), spectrum(&self.mic));
+        let a = self.config.smoothing;
+        let mut whitened: Vec<Complex<f32>> = self
+            .cross
+            .iter_mut()
+            .zip(x_f.iter().zip(&d_f))
+            .map(|(cross, (x, d))| {
+                *cross = *cross * a + x.conj() * d * (1.0 - a);
+                *cross / (cross.norm() + 1e-20)
+            })
+            .collect();
+        self.ifft.process(&mut whitened);
+
+        let scale = 1.0 / whitened.len() as f32;
+        let (delay_samples, peak) = whitened
+            .iter()
+            .take(self.config.max_delay + 1)
+            .enumerate()
+            .map(|(lag, c)| (lag, c.re * scale))
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        self.estimate = (peak > 0.0).then_some(DelayEstimate { delay_samples, confidence: peak.min(1.0) });
+        self.estimate
+    }
+
+    /// Returns the estimate after the last block, or `None` before the first block or if
+    /// the signals are not correlated at any lag.
+    pub fn estimate(&self) -> Option<DelayEstimate> {
+        self.estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn finds_delay_beyond_filter_length() {
+        let far = white_noise(256 * 200, 0.3, 71);
+        let mic: Vec<f32> = echo(&far, &[(1500, 0.4), (1600, -0.1)])
+            .iter()
+            .zip(white_noise(256 * 200, 0.01, 72))
+            .map(|(echo, noise)| echo + noise)
+            .collect();
+        let mut estimator = DelayEstimator::new(DelayEstimatorConfig { max_delay: 2048, ..DelayEstimatorConfig::default() });
+        for (far_block, mic_block) in far.chunks(256).zip(mic.chunks(256)) {
+            estimator.process(far_block, mic_block);
+        }
+        let estimate = estimator.estimate().unwrap();
+        assert_eq!(estimate.delay_samples, 1500);
+        assert!(estimate.confidence > 0.5, "confidence {}", estimate.confidence);
+
+        // Unrelated signals give no confident peak.
+        let mut estimator = DelayEstimator::new(DelayEstimatorConfig { max_delay: 2048, ..DelayEstimatorConfig::default() });
+        let near = white_noise(256 * 200, 0.3, 73);
+        for (far_block, mic_block) in far.chunks(256).zip(near.chunks(256)) {
+            estimator.process(far_block, mic_block);
+        }
+        assert!(estimator.estimate().is_none_or(|estimate| estimate.confidence < 0.15), "{:?}", estimator.estimate());
+    }
+}
@@ -0,0 +1,140 @@
+//! Sample delay lines for keeping unprocessed channels in sync with the canceller output.
+//!
+//! When only some capture channels go through [`FdafAec`], the remaining channels must be
+//! delayed by [`FdafAec::latency_samples`] so that all channels stay time-aligned.
+
+use crate::FdafAec;
+use std::collections::VecDeque;
+
+/// A fixed integer-sample delay applied in place to blocks of samples.
+#[derive(Debug, Clone)]
+pub struct DelayLine {
+    buffer: VecDeque<f32>,
+    delay: usize,
+}
+
+impl DelayLine {
+    /// Creates a delay line of `delay` samples, initially filled with silence.
+    pub fn new(delay: usize) -> Self {
+        Self { buffer: std::iter::repeat_n(0.0, delay).collect(), delay }
+    }
+
+    /// Creates a delay line matching the output latency of `aec`.
+    pub fn matching(aec: &FdafAec) -> Self {
+        Self::new(aec.latency_samples())
+    }
+
+    /// Returns the current delay in samples.
+    pub fn delay(&self) -> usize {
+        self.delay
+    }
+
+    /// Changes the delay.
+    ///
+    /// Increasing the delay inserts silence; decreasing it discards the oldest pending samples.
+    pub fn set_delay(&mut self, delay: usize) {
+        if delay > self.delay {
+            for _ in 0..delay - self.delay {
+                self.buffer.push_front(0.0);
+            }
+        } else {
+            self.buffer.drain(..self.delay - delay);
+        }
+        self.delay = delay;
+    }
+
+    /// Delays a block of samples in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            self.buffer.push_back(*sample);
+            *sample = self.buffer.pop_front().unwrap_or(0.0);
+        }
+    }
+
+    /// Clears the pending samples, keeping the configured delay.
+    pub fn reset(&mut self) {
+        self.buffer.iter_mut().for_each(|x| *x = 0.0);
+    }
+}
+
+/// A bank of [`DelayLine`]s that keeps passthrough channels aligned with processed ones.
+#[derive(Debug, Clone)]
+pub struct PassthroughDelay {
+    lines: Vec<DelayLine>,
+}
+
+impl PassthroughDelay {
+    /// Creates delay lines for `channels` passthrough channels, each delayed by `delay` samples.
+    pub fn new(channels: usize, delay: usize) -> Self {
+        Self { lines: vec![DelayLine::new(delay); channels] }
+    }
+
+    /// Creates delay lines for `channels` passthrough channels matching the latency of `aec`.
+    pub fn matching(aec: &FdafAec, channels: usize) -> Self {
+        Self::new(channels, aec.latency_samples())
+    }
+
+    /// Returns the number of passthrough channels.
+    pub fn channels(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Changes the delay of every channel, e.g. after the canceller latency changed.
+    pub fn set_delay(&mut self, delay: usize) {
+        self.lines.iter_mut().for_each(|line| line.set_delay(delay));
+    }
+
+    /// Delays one block per passthrough channel in place.
+    pub fn process(&mut self, channels: &mut [&mut [f32]]) {
+        assert_eq!(channels.len(), self.lines.len(), "One block must be supplied per passthrough channel.");
+        for (line, channel) in self.lines.iter_mut().zip(channels.iter_mut()) {
+            line.process(channel);
+        }
+    }
+
+    /// Delays the selected channels of an interleaved buffer in place.
+    ///
+    /// # Arguments
+    ///
+    /// * `interleaved`: The interleaved multi-channel buffer.
+    /// * `channel_count`: The total number of channels in `interleaved`.
+    /// * `passthrough_channels`: The channel indices to delay, one per delay line.
+    pub fn process_interleaved(&mut self, interleaved: &mut [f32], channel_count: usize, passthrough_channels: &[usize]) {
+        assert_eq!(passthrough_channels.len(), self.lines.len(), "One channel index must be supplied per delay line.");
+        assert_eq!(interleaved.len() % channel_count, 0, "Interleaved buffer length must be a multiple of the channel count.");
+        for frame in interleaved.chunks_exact_mut(channel_count) {
+            for (line, &channel) in self.lines.iter_mut().zip(passthrough_channels) {
+                line.process(std::slice::from_mut(&mut frame[channel]));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_across_blocks() {
+        let mut line = DelayLine::new(3);
+        let mut block = [1.0, 2.0, 3.0, 4.0];
+        line.process(&mut block);
+        assert_eq!(block, [0.0, 0.0, 0.0, 1.0]);
+        let mut block = [5.0, 6.0];
+        line.process(&mut block);
+        assert_eq!(block, [2.0, 3.0]);
+
+        line.set_delay(1);
+        let mut block = [7.0, 8.0];
+        line.process(&mut block);
+        assert_eq!(block, [6.0, 7.0]);
+    }
+
+    #[test]
+    fn interleaved_passthrough_only_touches_selected_channels() {
+        let mut delay = PassthroughDelay::new(1, 1);
+        let mut buffer = [1.0, 10.0, 2.0, 20.0, 3.0, 30.0];
+        delay.process_interleaved(&mut buffer, 2, &[1]);
+        assert_eq!(buffer, [1.0, 0.0, 2.0, 10.0, 3.0, 20.0]);
+    }
+}
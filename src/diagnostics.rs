@@ -0,0 +1,174 @@
+//! Detection of common integration mistakes.
+//!
+//! Swapped far-end and microphone inputs or an inverted microphone polarity are easy to
+//! introduce when wiring the canceller into an audio stack, and they only show up as "the
+//! echo canceller does not work". In diagnostic mode the canceller cross-correlates its
+//! inputs and compares the output with the microphone signal, and reports actionable
+//! [`HealthFlags`].
+
+use crate::{FdafAec, FrameEnergies};
+
+/// Largest lag, in samples, examined by the input cross-correlation.
+const MAX_LAG: usize = 64;
+/// Smoothing factor of the correlation and energy estimates.
+const SMOOTHING: f32 = 0.9;
+/// Normalized correlation magnitude above which the input relation is trusted.
+const MIN_CORRELATION: f32 = 0.3;
+/// Mean-square far-end level below which the estimates are not updated (about -60 dBFS).
+const FAR_END_ACTIVITY_THRESHOLD: f32 = 1e-6;
+/// Output-to-microphone power ratio above which the output is flagged (about +3 dB).
+const OUTPUT_EXCESS_RATIO: f32 = 2.0;
+
+/// Health indicators reported in diagnostic mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HealthFlags {
+    /// The microphone signal leads the far-end reference, which suggests the two inputs are
+    /// swapped.
+    pub inputs_swapped: bool,
+    /// The echo in the microphone signal has the opposite sign of the far-end reference,
+    /// which suggests an inverted microphone or loudspeaker polarity.
+    pub polarity_inverted: bool,
+    /// The output carries clearly more energy than the microphone signal while the far-end
+    /// is active, i.e. the canceller adds echo instead of removing it.
+    pub output_louder_than_mic: bool,
+}
+
+impl HealthFlags {
+    /// Returns `true` if no problem was detected.
+    pub fn is_healthy(&self) -> bool {
+        *self == HealthFlags::default()
+    }
+}
+
+/// Tracks the input cross-correlation and output level for [`HealthFlags`].
+#[derive(Debug, Clone)]
+pub(crate) struct InputDiagnostics {
+    lag: usize,
+    far_history: Vec<f32>,
+    mic_history: Vec<f32>,
+    correlation: Vec<f32>,
+    far_power: f32,
+    mic_power: f32,
+    mic_energy: f32,
+    error_energy: f32,
+    flags: HealthFlags,
+}
+
+impl InputDiagnostics {
+    pub(crate) fn new(frame_size: usize) -> Self {
+        let lag = MAX_LAG.min(frame_size / 2);
+        Self {
+            lag,
+            far_history: vec![0.0; 2 * frame_size],
+            mic_history: vec![0.0; 2 * frame_size],
+            correlation: vec![0.0; 2 * lag + 1],
+            far_power: 0.0,
+            mic_power: 0.0,
+            mic_energy: 0.0,
+            error_energy: 0.0,
+            flags: HealthFlags::default(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, far_end_frame: &[f32], mic_frame: &[f32], energies: &FrameEnergies) {
+        let frame_size = far_end_frame.len();
+        for (history, frame) in [(&mut self.far_history, far_end_frame), (&mut self.mic_history, mic_frame)] {
+            history.copy_within(frame_size.., 0);
+            history[frame_size..].copy_from_slice(frame);
+        }
+        if energies.far_end <= FAR_END_ACTIVITY_THRESHOLD {
+            return;
+        }
+
+        // Correlate one frame of the microphone with the far-end at lags in [-lag, lag].
+        // A positive lag means the microphone follows the far-end, as echo should.
+        let lag = self.lag;
+        let positions = frame_size - lag..2 * frame_size - lag;
+        let mut far_power = 0.0;
+        let mut mic_power = 0.0;
+        for n in positions.clone() {
+            far_power += self.far_history[n] * self.far_history[n];
+            mic_power += self.mic_history[n] * self.mic_history[n];
+        }
+        for (k, correlation) in self.correlation.iter_mut().enumerate() {
+            let offset = k as isize - lag as isize;
+            let sum: f32 = positions
+                .clone()
+                .map(|n| self.mic_history[n] * self.far_history[(n as isize - offset) as usize])
+                .sum();
+            *correlation = SMOOTHING * *correlation + (1.0 - SMOOTHING) * sum;
+        }
+        self.far_power = SMOOTHING * self.far_power + (1.0 - SMOOTHING) * far_power;
+        self.mic_power = SMOOTHING * self.mic_power + (1.0 - SMOOTHING) * mic_power;
+        self.mic_energy = SMOOTHING * self.mic_energy + (1.0 - SMOOTHING) * energies.mic;
+        self.error_energy = SMOOTHING * self.error_energy + (1.0 - SMOOTHING) * energies.error;
+
+        let norm = (self.far_power * self.mic_power).sqrt() + 1e-20;
+        let (peak, value) = self
+            .correlation
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            .map(|(k, &c)| (k, c / norm))
+            .unwrap_or((lag, 0.0));
+        let reliable = value.abs() >= MIN_CORRELATION;
+        self.flags = HealthFlags {
+            inputs_swapped: reliable && peak < lag,
+            polarity_inverted: reliable && peak >= lag && value < 0.0,
+            output_louder_than_mic: self.error_energy > OUTPUT_EXCESS_RATIO * self.mic_energy,
+        };
+    }
+
+    pub(crate) fn flags(&self) -> HealthFlags {
+        self.flags
+    }
+}
+
+impl FdafAec {
+    /// Enables or disables diagnostic mode. Enabling resets the collected statistics.
+    pub fn set_diagnostics(&mut self, enabled: bool) {
+        self.diagnostics = enabled.then(|| InputDiagnostics::new(self.frame_size));
+    }
+
+    /// Returns the health flags, or `None` if diagnostic mode is disabled.
+    pub fn health(&self) -> Option<HealthFlags> {
+        self.diagnostics.as_ref().map(InputDiagnostics::flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    fn run(far: &[f32], mic: &[f32]) -> HealthFlags {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_diagnostics(true);
+        process_all(&mut aec, far, mic);
+        aec.health().unwrap()
+    }
+
+    #[test]
+    fn detects_swapped_and_inverted_inputs() {
+        let far = white_noise(256 * 200, 0.3, 22);
+        let mic = echo(&far, &[(12, 0.5)]);
+        assert!(run(&far, &mic).is_healthy());
+
+        let swapped = run(&mic, &far);
+        assert!(swapped.inputs_swapped && !swapped.polarity_inverted);
+
+        let inverted_mic: Vec<f32> = mic.iter().map(|x| -x).collect();
+        let inverted = run(&far, &inverted_mic);
+        assert!(inverted.polarity_inverted && !inverted.inputs_swapped);
+    }
+
+    #[test]
+    fn flags_output_louder_than_mic() {
+        let mut diagnostics = InputDiagnostics::new(256);
+        let energies = FrameEnergies { far_end: 0.1, mic: 0.01, echo_estimate: 0.1, error: 0.05 };
+        for _ in 0..10 {
+            diagnostics.update(&[0.0; 256], &[0.0; 256], &energies);
+        }
+        assert!(diagnostics.flags().output_louder_than_mic);
+    }
+}
@@ -0,0 +1,430 @@
+//! Clock drift compensation between the render and capture streams.
+//!
+//! When the loudspeaker and the microphone run on different clocks, as with USB headsets
+//! and Bluetooth devices, the echo slides against the reference by a fraction of a sample
+//! every frame. The adaptive filter chases the sliding echo path and never fully converges;
+//! over a long call the echo may even leave the filter span.
+//!
+//! The compensator measures the drift from the filter itself: once converged, the position
+//! of the dominant tap is located with sub-sample precision at a fixed interval, and its
+//! movement per sample is the residual drift. A fractional delay line on the far-end
+//! reference then changes its delay continuously at the estimated rate, which keeps the
+//! echo at a fixed position in the filter. The delay line starts at `headroom` samples so
+//! that echo sliding earlier can be followed too; together with its interpolation kernel it
+//! moves the echo `headroom + 8` samples towards the start of the filter span.
+//!
+//! The delay line cannot go below zero or beyond `max_delay`. Once it reaches either bound
+//! the echo slides through the filter again, and the measured residual drift would keep
+//! growing the estimate without effect; the estimate is therefore held while the delay line
+//! is saturated in the direction of the drift, and it never exceeds `max_drift_ppm`.
+//!
+//! For correlating audio glitches with the compensation, [`DriftStats`] accumulates the
+//! samples the delay line has added to and dropped from the reference, and a
+//! [`DriftResync`] is recorded whenever the delay line saturates or the echo jumps by more
+//! than drift can explain, e.g. after a device switch.
+
+use crate::FdafAec;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Half-width of the interpolation kernel, in samples.
+const HALF_WIDTH: usize = 8;
+/// Number of precomputed fractional positions of the interpolation kernel.
+const PHASES: usize = 128;
+/// Maximum number of resync events kept until they are drained.
+const MAX_PENDING_RESYNCS: usize = 64;
+
+/// Parameters of the clock drift compensation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DriftConfig {
+    /// The initial delay of the far-end reference, in samples, which bounds how far the
+    /// compensator can follow echo that slides earlier.
+    pub headroom: usize,
+    /// The largest delay of the far-end reference, in samples.
+    pub max_delay: usize,
+    /// The number of frames between two measurements of the echo position.
+    pub measure_interval_frames: u32,
+    /// The fraction of each measured residual drift added to the drift estimate.
+    pub loop_gain: f32,
+    /// Measured drifts above this rate, in parts per million, are attributed to echo path
+    /// changes and ignored. The drift estimate is limited to this rate as well.
+    pub max_drift_ppm: f32,
+}
+
+impl Default for DriftConfig {
+    fn default() -> Self {
+        Self { headroom: 16, max_delay: 2048, measure_interval_frames: 64, loop_gain: 0.5, max_drift_ppm: 1000.0 }
+    }
+}
+
+/// The long-term statistics of the drift compensation.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DriftStats {
+    /// The estimated drift in parts per million, see [`FdafAec::clock_drift_ppm`].
+    pub drift_ppm: f32,
+    /// The total number of samples the delay line has added to the far-end reference by
+    /// increasing its delay.
+    pub samples_added: f64,
+    /// The total number of samples the delay line has dropped from the far-end reference by
+    /// decreasing its delay.
+    pub samples_dropped: f64,
+    /// The number of resync events recorded, including those no longer kept.
+    pub resyncs: u64,
+}
+
+/// What made the drift compensation lose track of the echo.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DriftResyncKind {
+    /// The delay line reached zero delay (`at_max_delay == false`) or
+    /// [`DriftConfig::max_delay`] and stopped following the drift.
+    Saturated { at_max_delay: bool },
+    /// The echo moved by `samples` between two measurements, faster than
+    /// [`DriftConfig::max_drift_ppm`] allows; the move is attributed to an echo path change
+    /// and not to drift.
+    EchoJump { samples: f32 },
+}
+
+/// An event of the drift compensation that an application may want to correlate with
+/// audio glitches.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DriftResync {
+    /// The index of the frame the event occurred in, see [`FdafAec::frame_count`].
+    pub frame: u64,
+    /// What happened.
+    pub kind: DriftResyncKind,
+}
+
+/// Estimates the drift and delays the far-end reference accordingly.
+#[derive(Debug, Clone)]
+pub(crate) struct DriftCompensator {
+    pub(crate) config: DriftConfig,
+    /// `kernels[p]` interpolates at the fractional position `p / PHASES`.
+    kernels: Vec<[f32; 2 * HALF_WIDTH]>,
+    history: VecDeque<f32>,
+    /// The current delay of the reference beyond the kernel delay, in samples.
+    delay: f64,
+    /// The change of the delay per sample, the estimated drift.
+    rate: f64,
+    /// The bound of the delay line, -1 for zero and 1 for `max_delay`, the delay was held at
+    /// in the last frame, or 0.
+    saturation: i8,
+    frames_to_measure: u32,
+    /// The last measured echo position and the number of samples since.
+    last_position: Option<(f64, usize)>,
+    stats: DriftStats,
+    pending: VecDeque<DriftResync>,
+}
+
+impl DriftCompensator {
+    pub(crate) fn new(config: DriftConfig) -> Self {
+        assert!(config.headroom <= config.max_delay, "headroom must not exceed max_delay.");
+        let kernels = (0..PHASES)
+            .map(|p| {
+                let frac = p as f64 / PHASES as f64;
+                std::array::from_fn(|i| {
+                    // Tap `i` weighs the sample `i + 1` samples before the integer delay.
+                    let x = (i + 1) as f64 - HALF_WIDTH as f64 - frac;
+                    let window = 0.5 + 0.5 * (PI * x / HALF_WIDTH as f64).cos();
+                    let sinc = if x.abs() < 1e-12 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                    (sinc * window) as f32
+                })
+            })
+            .collect();
+        Self {
+            config,
+            kernels,
+            history: std::iter::repeat_n(0.0, config.max_delay + 2 * HALF_WIDTH + 2).collect(),
+            delay: config.headroom as f64,
+            rate: 0.0,
+            saturation: 0,
+            frames_to_measure: config.measure_interval_frames,
+            last_position: None,
+            stats: DriftStats::default(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Returns the long-term statistics.
+    pub(crate) fn stats(&self) -> DriftStats {
+        DriftStats { drift_ppm: self.drift_ppm(), ..self.stats }
+    }
+
+    fn record(&mut self, frame: u64, kind: DriftResyncKind) {
+        if self.pending.len() == MAX_PENDING_RESYNCS {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(DriftResync { frame, kind });
+        self.stats.resyncs += 1;
+    }
+
+    /// Returns the estimated drift in parts per million.
+    pub(crate) fn drift_ppm(&self) -> f32 {
+        (self.rate * 1e6) as f32
+    }
+
+    /// Returns whether the delay line is held at zero or at `max_delay` and no longer
+    /// follows the drift.
+    pub(crate) fn is_saturated(&self) -> bool {
+        self.saturation != 0
+    }
+
+    /// Delays far-end frame `frame` in place by the current, continuously changing delay.
+    pub(crate) fn process(&mut self, far_end: &mut [f32], frame: u64) {
+        let max_delay = self.config.max_delay as f64;
+        let (was_saturated, start_delay) = (self.saturation, self.delay);
+        self.saturation = 0;
+        for sample in far_end.iter_mut() {
+            self.history.pop_front();
+            self.history.push_back(*sample);
+            let delay = self.delay + self.rate;
+            if delay < 0.0 {
+                self.saturation = -1;
+            } else if delay > max_delay {
+                self.saturation = 1;
+            }
+            self.delay = delay.clamp(0.0, max_delay);
+
+            let position = self.delay * PHASES as f64;
+            let (whole, phase) = ((position as usize) / PHASES, (position as usize) % PHASES);
+            let newest = self.history.len() - 1;
+            *sample = self.kernels[phase]
+                .iter()
+                .enumerate()
+                .map(|(i, k)| k * self.history[newest - whole - i - 1])
+                .sum();
+        }
+        if let Some((_, samples)) = self.last_position.as_mut() {
+            *samples += far_end.len();
+        }
+
+        // The delay only changes monotonically within a frame, at the constant rate
+        let change = self.delay - start_delay;
+        if change > 0.0 {
+            self.stats.samples_added += change;
+        } else {
+            self.stats.samples_dropped -= change;
+        }
+        if self.saturation != 0 && self.saturation != was_saturated {
+            self.record(frame, DriftResyncKind::Saturated { at_max_delay: self.saturation > 0 });
+        }
+    }
+
+    /// Returns whether the echo position is due to be measured after this frame.
+    pub(crate) fn measurement_due(&mut self) -> bool {
+        self.frames_to_measure -= 1;
+        if self.frames_to_measure > 0 {
+            return false;
+        }
+        self.frames_to_measure = self.config.measure_interval_frames;
+        true
+    }
+
+    /// Updates the drift estimate from the impulse response of the converged filter after
+    /// frame `frame`, or restarts the measurement with `None` while the filter is not
+    /// converged.
+    pub(crate) fn measure(&mut self, impulse_response: Option<&[f32]>, frame: u64) {
+        let Some(position) = impulse_response.and_then(peak_position) else {
+            self.last_position = None;
+            return;
+        };
+        if let Some((last, samples)) = self.last_position {
+            let residual = (position - last) / samples as f64;
+            let max_rate = self.config.max_drift_ppm as f64 * 1e-6;
+            // A saturated delay line cannot follow the residual, so integrating it would only
+            // wind the estimate up.
+            let winding_up = residual.signum() as i8 == self.saturation;
+            if residual.abs() > max_rate {
+                self.record(frame, DriftResyncKind::EchoJump { samples: (position - last) as f32 });
+            } else if !winding_up {
+                // Echo moving later in the filter needs more delay on the reference.
+                self.rate = (self.rate + self.config.loop_gain as f64 * residual).clamp(-max_rate, max_rate);
+            }
+        }
+        self.last_position = Some((position, 0));
+    }
+}
+
+/// Returns the sub-sample position of the strongest tap, by fitting a parabola through it
+/// and its neighbors, or `None` if it lies at the edge of the filter.
+fn peak_position(ir: &[f32]) -> Option<f64> {
+    let (peak, _) = ir.iter().enumerate().max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))?;
+    if peak == 0 || peak + 1 >= ir.len() {
+        return None;
+    }
+    let (left, center, right) = (ir[peak - 1].abs() as f64, ir[peak].abs() as f64, ir[peak + 1].abs() as f64);
+    let curvature = left - 2.0 * center + right;
+    let offset = if curvature.abs() > 1e-12 { 0.5 * (left - right) / curvature } else { 0.0 };
+    Some(peak as f64 + offset.clamp(-0.5, 0.5))
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the clock drift compensation.
+    /// Reconfiguring restarts the estimation at zero drift.
+    pub fn set_drift_compensation(&mut self, config: Option<DriftConfig>) {
+        self.note_config_change();
+        self.drift = config.map(DriftCompensator::new);
+    }
+
+    /// Returns the estimated drift between the render and capture clocks in parts per
+    /// million, positive when the echo arrives later and later, or `None` if drift
+    /// compensation is disabled.
+    pub fn clock_drift_ppm(&self) -> Option<f32> {
+        self.drift.as_ref().map(DriftCompensator::drift_ppm)
+    }
+
+    /// Returns whether the drift compensation has run out of delay range, i.e. the delay of
+    /// the far-end reference is held at zero or at [`DriftConfig::max_delay`] and the echo
+    /// slides through the filter again, or `None` if drift compensation is disabled. A larger
+    /// [`DriftConfig::headroom`] or `max_delay` extends the range.
+    pub fn clock_drift_saturated(&self) -> Option<bool> {
+        self.drift.as_ref().map(DriftCompensator::is_saturated)
+    }
+
+    /// Returns the long-term statistics of the drift compensation, or `None` if it is
+    /// disabled.
+    pub fn clock_drift_stats(&self) -> Option<DriftStats> {
+        self.drift.as_ref().map(DriftCompensator::stats)
+    }
+
+    /// Removes and returns the resync events recorded since the last call.
+    ///
+    /// At most the 64 most recent events are kept; [`DriftStats::resyncs`] counts all of them.
+    pub fn drain_drift_resyncs(&mut self) -> Vec<DriftResync> {
+        self.drift.as_mut().map_or_else(Vec::new, |drift| drift.pending.drain(..).collect())
+    }
+
+    /// Measures the echo position at the end of a fully processed frame, if due.
+    pub(crate) fn update_drift(&mut self) {
+        if !self.drift.as_mut().is_some_and(DriftCompensator::measurement_due) {
+            return;
+        }
+        let impulse_response = self.quality.quality().converged.then(|| self.impulse_response());
+        let frame = self.frames_processed;
+        if let Some(drift) = self.drift.as_mut() {
+            drift.measure(impulse_response.as_deref(), frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{process_all, white_noise};
+
+    /// Returns the echo of `far` with a delay that grows from 60 samples by `drift`, rendered
+    /// with a long sinc kernel.
+    fn drifting_echo(far: &[f32], drift: f64) -> Vec<f32> {
+        let len = far.len();
+        (0..len)
+            .map(|n| {
+                let t = n as f64 - 60.0 - drift * n as f64;
+                let base = t.floor() as i64;
+                (base - 31..=base + 32)
+                    .filter(|&s| s >= 0 && (s as usize) < len)
+                    .map(|s| {
+                        let x = t - s as f64;
+                        let window = 0.5 + 0.5 * (PI * x / 32.0).cos();
+                        let sinc = if x.abs() < 1e-12 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                        0.5 * far[s as usize] as f64 * sinc * window
+                    })
+                    .sum::<f64>() as f32
+            })
+            .collect()
+    }
+
+    /// Runs a canceller over the signals and returns its output and the canceller.
+    fn run(far: &[f32], mic: &[f32], drift: Option<DriftConfig>) -> (Vec<f32>, FdafAec) {
+        let mut aec = FdafAec::new(512, 0.2);
+        aec.set_drift_compensation(drift);
+        let output = process_all(&mut aec, far, mic);
+        (output, aec)
+    }
+
+    #[test]
+    fn follows_drifting_echo() {
+        let len = 16000 * 40;
+        let far = white_noise(len, 0.3, 75);
+        let mic = drifting_echo(&far, 100e-6);
+        let (uncompensated, _) = run(&far, &mic, None);
+        let (compensated, mut aec) = run(&far, &mic, Some(DriftConfig::default()));
+
+        let drift_ppm = aec.clock_drift_ppm().unwrap();
+        assert!((drift_ppm - 100.0).abs() < 15.0, "estimated {} ppm", drift_ppm);
+        assert_eq!(aec.clock_drift_saturated(), Some(false));
+        let tail = len - 16000 * 5..len;
+        let residual = crate::mean_square(&compensated[tail.clone()]);
+        assert!(residual < crate::mean_square(&uncompensated[tail.clone()]) / 4.0);
+        assert!(residual < crate::mean_square(&mic[tail]) / 30.0);
+
+        // The echo moved 64 samples later, less what the estimate needed to settle.
+        let stats = aec.clock_drift_stats().unwrap();
+        assert_eq!(stats.drift_ppm, drift_ppm);
+        assert!(stats.samples_added > 30.0 && stats.samples_added < 64.0, "{:?}", stats);
+        assert_eq!(stats.samples_dropped, 0.0);
+        assert_eq!(stats.resyncs, 0);
+        assert!(aec.drain_drift_resyncs().is_empty());
+    }
+
+    #[test]
+    fn holds_estimate_while_delay_saturates() {
+        // The echo moves earlier by 100 ppm; the 16 samples of headroom run out after 10 s,
+        // and for the remaining 20 s the delay line is held at zero while the echo moves from
+        // 44 to 12 samples.
+        let len = 16000 * 30;
+        let far = white_noise(len, 0.3, 76);
+        let mic = drifting_echo(&far, -100e-6);
+        let (uncompensated, _) = run(&far, &mic, None);
+        let (compensated, mut aec) = run(&far, &mic, Some(DriftConfig::default()));
+
+        let drift_ppm = aec.clock_drift_ppm().unwrap();
+        assert!((drift_ppm + 100.0).abs() < 15.0, "estimated {} ppm", drift_ppm);
+        assert_eq!(aec.clock_drift_saturated(), Some(true));
+        let tail = len - 16000 * 5..len;
+        let residual = crate::mean_square(&compensated[tail.clone()]);
+        let reference = crate::mean_square(&uncompensated[tail]);
+        assert!(residual < reference * 1.1, "{} vs {}", residual, reference);
+
+        // The whole headroom was dropped, once.
+        let stats = aec.clock_drift_stats().unwrap();
+        assert!((stats.samples_dropped - 16.0).abs() < 1e-6, "{:?}", stats);
+        assert_eq!(stats.samples_added, 0.0);
+        let resyncs = aec.drain_drift_resyncs();
+        assert_eq!(resyncs.len(), 1);
+        assert_eq!(resyncs[0].kind, DriftResyncKind::Saturated { at_max_delay: false });
+        assert!(resyncs[0].frame > 250 && resyncs[0].frame < 1000, "{:?}", resyncs);
+        assert!(aec.drain_drift_resyncs().is_empty());
+
+        // The same at the upper bound, with a delay line too short for the drift.
+        let mic = drifting_echo(&far, 100e-6);
+        let short = DriftConfig { max_delay: 48, ..DriftConfig::default() };
+        let (_, mut aec) = run(&far, &mic, Some(short));
+        let drift_ppm = aec.clock_drift_ppm().unwrap();
+        assert!((drift_ppm - 100.0).abs() < 15.0, "estimated {} ppm", drift_ppm);
+        assert_eq!(aec.clock_drift_saturated(), Some(true));
+        let resyncs = aec.drain_drift_resyncs();
+        assert_eq!(resyncs.len(), 1);
+        assert_eq!(resyncs[0].kind, DriftResyncKind::Saturated { at_max_delay: true });
+    }
+
+    #[test]
+    fn reports_echo_jump() {
+        // The echo path grows by 40 samples after 10 s, without any drift.
+        let len = 16000 * 20;
+        let far = white_noise(len, 0.3, 77);
+        let mut mic = drifting_echo(&far, 0.0);
+        let later = drifting_echo(&[vec![0.0; 40], far[..len - 40].to_vec()].concat(), 0.0);
+        mic[len / 2..].copy_from_slice(&later[len / 2..]);
+        let (_, mut aec) = run(&far, &mic, Some(DriftConfig::default()));
+
+        let resyncs = aec.drain_drift_resyncs();
+        assert_eq!(resyncs.len(), 1, "{:?}", resyncs);
+        assert!(resyncs[0].frame > 625, "{:?}", resyncs);
+        match resyncs[0].kind {
+            DriftResyncKind::EchoJump { samples } => assert!((samples - 40.0).abs() < 1.0, "{}", samples),
+            kind => panic!("unexpected {:?}", kind),
+        }
+        assert!(aec.clock_drift_ppm().unwrap().abs() < 15.0);
+        assert_eq!(aec.clock_drift_stats().unwrap().resyncs, 1);
+    }
+}
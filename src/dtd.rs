@@ -0,0 +1,224 @@
+//! Full-band double-talk detection.
+//!
+//! During double talk the near-end voice enters the error signal, and the NLMS update reads
+//! it as echo path mismatch: the filter diverges and the near-end voice gets mangled. Two
+//! detectors slow the weight update down while near-end speech is present.
+//!
+//! The Geigel detector compares the microphone peak with the far-end peak over the history
+//! buffer. Echo is attenuated on its way from the loudspeaker to the microphone, so a
+//! microphone peak above a fraction of the far-end peak can only come from near-end speech.
+//! While it is detected, and for a hangover period after, the weight update is frozen or
+//! slowed down.
+//!
+//! The coherence detector does not rely on levels, which makes it robust on speakerphones
+//! with little echo return loss. The microphone spectrum is a linear function of the far-end
+//! in every bin as long as only echo is present, and so is the echo estimate once the filter
+//! is roughly shaped like the echo path, even far from converged. Their magnitude-squared
+//! coherence therefore stays near one during far-end single talk and drops as near-end
+//! speech is added. It is turned into a
+//! double-talk probability that scales the step size.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+
+/// Parameters of the Geigel double-talk detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GeigelConfig {
+    /// The microphone peak, relative to the far-end peak, above which double talk is
+    /// declared. 0.5 assumes an echo return loss of at least 6 dB.
+    pub threshold: f32,
+    /// The number of frames the decision is held after the microphone peak falls back.
+    pub hangover_frames: u32,
+    /// The factor applied to the step size during double talk. 0.0 freezes adaptation.
+    pub step_scale: f32,
+}
+
+impl Default for GeigelConfig {
+    fn default() -> Self {
+        Self { threshold: 0.5, hangover_frames: 10, step_scale: 0.0 }
+    }
+}
+
+/// Frame-based Geigel detector with hangover.
+#[derive(Debug, Clone)]
+pub(crate) struct GeigelDetector {
+    pub(crate) config: GeigelConfig,
+    hangover: u32,
+    double_talk: bool,
+}
+
+impl GeigelDetector {
+    pub(crate) fn new(config: GeigelConfig) -> Self {
+        Self { config, hangover: 0, double_talk: false }
+    }
+
+    /// Updates the decision from the far-end history and the current microphone frame, and
+    /// returns the step size factor for this frame.
+    pub(crate) fn update(&mut self, far_end_history: &[f32], mic_frame: &[f32]) -> f32 {
+        let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+        if peak(mic_frame) > self.config.threshold * peak(far_end_history) {
+            self.double_talk = true;
+            self.hangover = self.config.hangover_frames;
+        } else if self.hangover > 0 {
+            self.hangover -= 1;
+        } else {
+            self.double_talk = false;
+        }
+        if self.double_talk { self.config.step_scale } else { 1.0 }
+    }
+}
+
+/// Parameters of the coherence double-talk detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CoherenceDtdConfig {
+    /// Smoothing factor of the cross- and auto-spectra used to estimate the coherence.
+    pub smoothing: f32,
+    /// The mean coherence at and above which the double-talk probability is 0.
+    pub coherent: f32,
+    /// The mean coherence at and below which the double-talk probability is 1.
+    pub incoherent: f32,
+}
+
+impl Default for CoherenceDtdConfig {
+    fn default() -> Self {
+        Self { smoothing: 0.9, coherent: 0.9, incoherent: 0.5 }
+    }
+}
+
+/// Estimates the microphone/echo-estimate coherence and the double-talk probability.
+#[derive(Debug, Clone)]
+pub(crate) struct CoherenceDetector {
+    pub(crate) config: CoherenceDtdConfig,
+    cross: DVector<Complex<f32>>,
+    mic_power: DVector<f32>,
+    echo_power: DVector<f32>,
+    probability: f32,
+}
+
+impl CoherenceDetector {
+    pub(crate) fn new(config: CoherenceDtdConfig, fft_size: usize) -> Self {
+        assert!(config.incoherent < config.coherent, "incoherent must be below coherent.");
+        Self {
+            config,
+            cross: DVector::from_element(fft_size, Complex::new(0.0, 0.0)),
+            mic_power: DVector::from_element(fft_size, 0.0),
+            echo_power: DVector::from_element(fft_size, 0.0),
+            probability: 0.0,
+        }
+    }
+
+    /// Updates the spectra with the microphone and estimated echo spectra of the current
+    /// frame, and returns the step size factor for this frame.
+    pub(crate) fn update(&mut self, mic_f: &[Complex<f32>], echo_f: &[Complex<f32>]) -> f32 {
+        let a = self.config.smoothing;
+        let (mut weighted, mut total) = (0.0f32, 0.0f32);
+        for i in 0..mic_f.len() {
+            self.cross[i] = self.cross[i] * a + mic_f[i].conj() * echo_f[i] * (1.0 - a);
+            self.mic_power[i] = a * self.mic_power[i] + (1.0 - a) * mic_f[i].norm_sqr();
+            self.echo_power[i] = a * self.echo_power[i] + (1.0 - a) * echo_f[i].norm_sqr();
+            let coherence = self.cross[i].norm_sqr() / (self.mic_power[i] * self.echo_power[i] + 1e-20);
+            // Weight by the echo power so that bins without echo do not count as near-end
+            weighted += coherence * self.echo_power[i];
+            total += self.echo_power[i];
+        }
+        // Without an echo estimate there is no evidence either way
+        self.probability = if total > 0.0 {
+            let coherence = weighted / total;
+            let span = self.config.coherent - self.config.incoherent;
+            ((self.config.coherent - coherence) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        1.0 - self.probability
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the Geigel double-talk detector.
+    pub fn set_geigel_dtd(&mut self, config: Option<GeigelConfig>) {
+        self.note_config_change();
+        self.geigel = config.map(GeigelDetector::new);
+    }
+
+    /// Returns whether the Geigel detector held double talk in the last frame, or `None` if
+    /// it is disabled.
+    pub fn geigel_double_talk(&self) -> Option<bool> {
+        self.geigel.as_ref().map(|geigel| geigel.double_talk)
+    }
+
+    /// Enables, reconfigures, or (with `None`) disables the coherence double-talk detector.
+    pub fn set_coherence_dtd(&mut self, config: Option<CoherenceDtdConfig>) {
+        self.note_config_change();
+        self.coherence_dtd = config.map(|config| CoherenceDetector::new(config, self.fft_size));
+    }
+
+    /// Returns the double-talk probability of the last frame, from 0.0 (far-end single talk)
+    /// to 1.0 (near-end speech dominates), or `None` if the coherence detector is disabled.
+    ///
+    /// The step size of the frame was scaled by one minus this probability.
+    pub fn double_talk_probability(&self) -> Option<f32> {
+        self.coherence_dtd.as_ref().map(|detector| detector.probability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn freezes_adaptation_during_double_talk() {
+        let far = white_noise(256 * 150, 0.3, 47);
+        let mut mic = echo(&far, &[(10, 0.3)]);
+        // Loud near-end speech over frames 120..130.
+        let near = white_noise(256 * 10, 0.3, 48);
+        for (m, n) in mic[256 * 120..256 * 130].iter_mut().zip(&near) {
+            *m += n;
+        }
+
+        let run = |geigel: Option<GeigelConfig>| {
+            let mut aec = FdafAec::new(512, 0.5);
+            aec.set_geigel_dtd(geigel);
+            let mut decisions = Vec::new();
+            for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)) {
+                aec.process(far_frame, mic_frame);
+                decisions.push(aec.geigel_double_talk());
+            }
+            (aec.impulse_response()[10], decisions)
+        };
+        let (free_tap, _) = run(None);
+        let (gated_tap, decisions) = run(Some(GeigelConfig::default()));
+
+        assert!((gated_tap - 0.3).abs() < 0.01, "gated tap was {}", gated_tap);
+        assert!((free_tap - 0.3).abs() > 2.0 * (gated_tap - 0.3).abs());
+        assert_eq!(decisions[110], Some(false));
+        assert!(decisions[120..140].iter().all(|&dt| dt == Some(true)));
+        assert_eq!(decisions[149], Some(false));
+    }
+
+    #[test]
+    fn coherence_probability_tracks_near_end_speech() {
+        let far = white_noise(256 * 150, 0.3, 49);
+        let mut mic = echo(&far, &[(10, 0.5)]);
+        // Near-end speech at the level of the echo over frames 100..120.
+        let near = white_noise(256 * 20, 0.15, 50);
+        for (m, n) in mic[256 * 100..256 * 120].iter_mut().zip(&near) {
+            *m += n;
+        }
+
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_coherence_dtd(Some(CoherenceDtdConfig::default()));
+        let mut probabilities = Vec::new();
+        for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)) {
+            aec.process(far_frame, mic_frame);
+            probabilities.push(aec.double_talk_probability().unwrap());
+        }
+
+        assert!(probabilities[50..100].iter().all(|&p| p < 0.2), "{:?}", &probabilities[50..100]);
+        assert!(probabilities[105..120].iter().all(|&p| p > 0.5), "{:?}", &probabilities[100..120]);
+        assert!((aec.impulse_response()[10] - 0.5).abs() < 0.02);
+    }
+}
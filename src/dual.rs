@@ -0,0 +1,58 @@
+//! Access to the component removed by the canceller.
+//!
+//! For A/B listening and automated leak detection, QA tools need to hear exactly what the
+//! canceller took out of the microphone signal. [`FdafAec::process_dual`] returns it next to
+//! the cancelled output, sample-aligned, so that the two always sum to the microphone signal
+//! the filter saw.
+
+use crate::FdafAec;
+
+/// The two outputs of [`FdafAec::process_dual`] for one frame.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DualOutput {
+    /// The echo-cancelled signal, as returned by [`FdafAec::process`].
+    pub cancelled: Vec<f32>,
+    /// The removed component, the microphone signal minus `cancelled`.
+    ///
+    /// The microphone signal here is the one the filter saw, i.e. after the optional
+    /// high-pass filter and lookahead delay, so the difference contains the cancellation
+    /// only.
+    pub removed: Vec<f32>,
+}
+
+impl FdafAec {
+    /// Processes a frame like [`FdafAec::process`] and additionally returns the component
+    /// that was removed from the microphone signal.
+    pub fn process_dual(&mut self, far_end_frame: &[f32], mic_frame: &[f32]) -> DualOutput {
+        let (cancelled, removed) = self.process_frame(far_end_frame, mic_frame);
+        DualOutput { cancelled, removed }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn outputs_sum_to_microphone() {
+        let mut aec = FdafAec::new(512, 0.5);
+        let far = white_noise(256 * 100, 0.3, 35);
+        let near = white_noise(256 * 100, 0.05, 36);
+        let echo_signal = echo(&far, &[(10, 0.5)]);
+        let mic: Vec<f32> = echo_signal.iter().zip(&near).map(|(e, n)| e + n).collect();
+
+        let mut last = DualOutput::default();
+        for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)) {
+            last = aec.process_dual(far_frame, mic_frame);
+            for ((c, r), m) in last.cancelled.iter().zip(&last.removed).zip(mic_frame) {
+                assert!((c + r - m).abs() <= 1e-5 * (1.0 + r.abs()));
+            }
+        }
+
+        // Once converged, the removed component is the echo and the output the near-end.
+        let start = mic.len() - 256;
+        let leak: Vec<f32> = last.removed.iter().zip(&echo_signal[start..]).map(|(r, e)| r - e).collect();
+        assert!(crate::mean_square(&leak) < crate::mean_square(&echo_signal[start..]) / 10.0);
+    }
+}
@@ -0,0 +1,161 @@
+//! Conversation state tracking based on frame energies.
+//!
+//! The canceller classifies every frame into one of four [`DuplexState`]s from the activity of
+//! the far-end reference and of the near-end speech left in the error signal. Both activity
+//! decisions use a level hysteresis and a hangover so the state does not flicker, which makes
+//! the output usable for UI indicators and external ducking logic.
+
+use crate::{FdafAec, FrameEnergies};
+
+/// Mean-square level above which a signal becomes active (about -60 dBFS).
+const ACTIVITY_ON_THRESHOLD: f32 = 1e-6;
+/// Mean-square level below which an active signal starts its hangover (about -63 dBFS).
+const ACTIVITY_OFF_THRESHOLD: f32 = 0.5e-6;
+/// Factor by which the error power must exceed the expected residual echo to count as speech.
+const NEAR_END_RESIDUAL_RATIO: f32 = 2.0;
+/// Default number of frames an activity decision is held after the signal drops.
+const DEFAULT_HANGOVER_FRAMES: u32 = 4;
+
+/// The conversation state seen by the canceller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DuplexState {
+    /// Neither side is active.
+    #[default]
+    Silence,
+    /// Only the far-end is talking; the microphone contains echo only.
+    FarEndOnly,
+    /// Only the near-end is talking.
+    NearEndOnly,
+    /// Both sides are talking at the same time.
+    DoubleTalk,
+}
+
+/// An activity decision with level hysteresis and hangover.
+#[derive(Debug, Clone)]
+struct ActivityGate {
+    active: bool,
+    hangover_left: u32,
+}
+
+impl ActivityGate {
+    fn new() -> Self {
+        Self { active: false, hangover_left: 0 }
+    }
+
+    fn update(&mut self, above_on: bool, above_off: bool, hangover: u32) -> bool {
+        if above_on || (self.active && above_off) {
+            self.active = true;
+            self.hangover_left = hangover;
+        } else if self.hangover_left > 0 {
+            self.hangover_left -= 1;
+        } else {
+            self.active = false;
+        }
+        self.active
+    }
+}
+
+/// Derives the [`DuplexState`] of each frame.
+#[derive(Debug, Clone)]
+pub(crate) struct DuplexDetector {
+    far: ActivityGate,
+    near: ActivityGate,
+    hangover_frames: u32,
+    state: DuplexState,
+}
+
+impl DuplexDetector {
+    pub(crate) fn new() -> Self {
+        Self {
+            far: ActivityGate::new(),
+            near: ActivityGate::new(),
+            hangover_frames: DEFAULT_HANGOVER_FRAMES,
+            state: DuplexState::Silence,
+        }
+    }
+
+    /// Updates the state from the frame energies and the long-term ERLE (as a linear power ratio).
+    pub(crate) fn update(&mut self, energies: &FrameEnergies, erle: f32) {
+        let far_active = self.far.update(
+            energies.far_end > ACTIVITY_ON_THRESHOLD,
+            energies.far_end > ACTIVITY_OFF_THRESHOLD,
+            self.hangover_frames,
+        );
+
+        // While the far-end talks, the error contains the residual echo the filter could not
+        // remove; only power clearly exceeding that residual is attributed to the near-end.
+        let residual = if far_active { NEAR_END_RESIDUAL_RATIO * energies.mic / erle.max(1.0) } else { 0.0 };
+        let near_active = self.near.update(
+            energies.error > ACTIVITY_ON_THRESHOLD.max(residual),
+            energies.error > ACTIVITY_OFF_THRESHOLD.max(residual),
+            self.hangover_frames,
+        );
+
+        self.state = match (far_active, near_active) {
+            (false, false) => DuplexState::Silence,
+            (true, false) => DuplexState::FarEndOnly,
+            (false, true) => DuplexState::NearEndOnly,
+            (true, true) => DuplexState::DoubleTalk,
+        };
+    }
+
+    pub(crate) fn state(&self) -> DuplexState {
+        self.state
+    }
+}
+
+impl FdafAec {
+    /// Returns the conversation state of the most recently processed frame.
+    pub fn duplex_state(&self) -> DuplexState {
+        self.duplex.state()
+    }
+
+    /// Sets how many frames a talker is still considered active after its level drops.
+    ///
+    /// Larger values make the [`DuplexState`] steadier but slower to report a talker stopping.
+    pub fn set_duplex_hangover(&mut self, frames: u32) {
+        self.duplex.hangover_frames = frames;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn states_follow_talkers_with_hangover() {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_duplex_hangover(2);
+        let silence = vec![0.0; 256];
+
+        aec.process(&silence, &silence);
+        assert_eq!(aec.duplex_state(), DuplexState::Silence);
+
+        let far = white_noise(256 * 100, 0.3, 5);
+        let mic = echo(&far, &[(8, 0.5)]);
+        process_all(&mut aec, &far, &mic);
+        assert_eq!(aec.duplex_state(), DuplexState::FarEndOnly);
+
+        // The far-end stops while the near-end starts; the far-end hangover lasts two frames.
+        let near = white_noise(256, 0.3, 6);
+        aec.process(&silence, &near);
+        aec.process(&silence, &near);
+        assert_eq!(aec.duplex_state(), DuplexState::DoubleTalk);
+        aec.process(&silence, &near);
+        assert_eq!(aec.duplex_state(), DuplexState::NearEndOnly);
+
+        let far = white_noise(256, 0.3, 7);
+        let mic: Vec<f32> = echo(&far, &[(8, 0.5)]).iter().zip(&near).map(|(e, n)| e + n).collect();
+        aec.process(&far, &mic);
+        assert_eq!(aec.duplex_state(), DuplexState::DoubleTalk);
+
+        // Both talkers stop; they are held active until the hangover expires.
+        aec.process(&silence, &silence);
+        assert_eq!(aec.duplex_state(), DuplexState::DoubleTalk);
+        for _ in 0..3 {
+            aec.process(&silence, &silence);
+        }
+        assert_eq!(aec.duplex_state(), DuplexState::Silence);
+    }
+}
@@ -0,0 +1,189 @@
+//! Batteries-included echo cancellation for device callbacks.
+//!
+//! Applications that only want cancelled microphone audio should not have to pick an FFT
+//! size, match sample rates and frame sizes, and enable the right stages. [`DuplexProcessor`]
+//! takes the capture and render rates of the audio devices and the buffers of their
+//! callbacks, of any length. It resamples the render stream to the capture rate, queues both
+//! streams through a [`StreamingAec`], and runs a canceller with the high-pass filter, bulk
+//! delay compensation, the residual echo post-filter, the NLP, comfort noise and the AGC
+//! enabled, see [`DuplexProcessor::default_config`].
+
+use crate::config::single_partition_fft_size;
+use crate::resample::FarEndResampler;
+use crate::{
+    AgcConfig, BulkDelayConfig, ComfortNoiseConfig, ConfigError, EchoCancellerFactory, FdafAec, FdafAecConfig, HighPassConfig,
+    NlpLevel, PostFilterConfig, StreamError, StreamLimits, StreamingAec,
+};
+
+/// The echo tail covered by the default configuration once the bulk delay is compensated,
+/// in milliseconds.
+const DEFAULT_TAIL_MS: f32 = 32.0;
+
+/// Echo cancellation between the render and capture callbacks of audio devices.
+///
+/// The cancelled capture audio is delayed by a fixed number of samples, see
+/// [`DuplexProcessor::latency_samples`]. Both callbacks must keep running, with silence
+/// while nothing plays, as the canceller waits for the render audio of every frame.
+pub struct DuplexProcessor {
+    stream: StreamingAec,
+    capture_rate: u32,
+    render_resampler: Option<FarEndResampler>,
+    /// The samples of silence still to be written before the first cancelled sample.
+    startup_silence: usize,
+    /// The cancelled samples still to be dropped because silence was written in their place
+    /// while the render audio lagged.
+    skipped: usize,
+}
+
+impl DuplexProcessor {
+    /// Creates a processor for the given device rates, in Hz, with the default
+    /// configuration.
+    pub fn new(capture_rate: u32, render_rate: u32) -> Result<Self, ConfigError> {
+        Self::with_config(Self::default_config(capture_rate), render_rate)
+    }
+
+    /// Creates a processor running a canceller with the given configuration at its
+    /// `sample_rate`, the capture rate. The render stream at `render_rate` is resampled
+    /// before it reaches the canceller, so `far_end_sample_rate` is ignored.
+    pub fn with_config(config: FdafAecConfig, render_rate: u32) -> Result<Self, ConfigError> {
+        let config = FdafAecConfig { far_end_sample_rate: None, ..config };
+        let factory = EchoCancellerFactory::new(config.clone())?;
+        let frame_size = factory.config().fft_size / 2;
+        let capture_rate = config.sample_rate;
+        Ok(Self {
+            stream: StreamingAec::new(config, StreamLimits::default()),
+            capture_rate,
+            render_resampler: (render_rate != capture_rate).then(|| FarEndResampler::new(render_rate, capture_rate)),
+            startup_silence: frame_size,
+            skipped: 0,
+        })
+    }
+
+    /// Returns the default configuration at the capture rate: a filter covering 32 ms of
+    /// echo tail after the bulk delay, with the high-pass filter, bulk delay compensation,
+    /// the post-filter, a moderate NLP, comfort noise and the AGC.
+    pub fn default_config(capture_rate: u32) -> FdafAecConfig {
+        let fft_size = single_partition_fft_size(capture_rate, DEFAULT_TAIL_MS);
+        FdafAecConfig {
+            fft_size,
+            sample_rate: capture_rate,
+            step_size: 0.3,
+            psd_smoothing: FdafAecConfig::psd_smoothing_for(fft_size, capture_rate),
+            high_pass: Some(HighPassConfig::default()),
+            bulk_delay: Some(BulkDelayConfig::default()),
+            post_filter: Some(PostFilterConfig::default()),
+            nlp: Some(NlpLevel::Moderate),
+            comfort_noise: Some(ComfortNoiseConfig::default()),
+            agc: Some(AgcConfig::default()),
+            ..FdafAecConfig::default()
+        }
+    }
+
+    /// Returns the delay, in samples at the capture rate, between the capture input and the
+    /// cancelled output.
+    pub fn latency_samples(&self) -> usize {
+        let canceller = self.stream.canceller();
+        canceller.frame_size + canceller.latency_samples()
+    }
+
+    /// Returns the canceller, e.g. to query its state.
+    pub fn canceller(&self) -> &FdafAec {
+        self.stream.canceller()
+    }
+
+    /// Takes the buffer of a render callback, the audio about to be played.
+    ///
+    /// A rejected buffer, see [`StreamingAec::push_far_end`], is lost, and the echo of it is
+    /// not cancelled.
+    pub fn render(&mut self, samples: &[f32]) -> Result<(), StreamError> {
+        let resampled;
+        let samples = match self.render_resampler.as_mut() {
+            Some(resampler) => {
+                resampled = resampler.process_available(samples);
+                resampled.as_slice()
+            }
+            None => samples,
+        };
+        if samples.is_empty() {
+            return Ok(());
+        }
+        self.stream.push_far_end(samples, self.capture_rate).map(|_| ())
+    }
+
+    /// Takes the buffer of a capture callback and replaces it with cancelled audio.
+    ///
+    /// The buffer is left unchanged if it is rejected, see [`StreamingAec::push_mic`]. While
+    /// the render audio lags behind, silence is written in place of the cancelled audio,
+    /// which is dropped when it arrives so the latency stays the same.
+    pub fn capture(&mut self, samples: &mut [f32]) -> Result<(), StreamError> {
+        self.stream.push_mic(samples, self.capture_rate)?;
+        let silence = self.startup_silence.min(samples.len());
+        self.startup_silence -= silence;
+        while self.skipped > 0 && self.stream.output_available() > 0 {
+            let mut discard = [0.0; 256];
+            let count = self.skipped.min(discard.len());
+            self.skipped -= self.stream.pop_output(&mut discard[..count]);
+        }
+        let written = silence + self.stream.pop_output(&mut samples[silence..]);
+        samples[..silence].fill(0.0);
+        samples[written..].fill(0.0);
+        self.skipped += samples.len() - written;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn cancels_echo_between_callbacks_at_different_rates() {
+        // 10 ms callbacks: 160 samples captured at 16 kHz, 480 rendered at 48 kHz. The echo
+        // arrives 40 ms after playback, beyond the filter span.
+        let render = white_noise(48000 * 8, 0.3, 99);
+        let mut played = vec![0.0; 16000 * 8];
+        FarEndResampler::new(48000, 16000).process(&render, &mut played);
+        let mic = echo(&played, &[(640, 0.5), (660, -0.2)]);
+
+        let mut processor = DuplexProcessor::new(16000, 48000).unwrap();
+        let latency = processor.latency_samples();
+        let mut output = Vec::new();
+        for (render, capture) in render.chunks(480).zip(mic.chunks(160)) {
+            processor.render(render).unwrap();
+            let mut buffer = capture.to_vec();
+            processor.capture(&mut buffer).unwrap();
+            output.extend(buffer);
+        }
+
+        assert!(output[..latency].iter().all(|&x| x == 0.0));
+        let tail = mic.len() - 16000..mic.len() - latency;
+        let residual = crate::mean_square(&output[tail.start + latency..tail.end + latency]);
+        assert!(residual < crate::mean_square(&mic[tail]) / 100.0, "residual {}", residual);
+        assert!(processor.canceller().current_bulk_delay() > 0);
+    }
+
+    #[test]
+    fn keeps_latency_while_render_lags() {
+        let mut processor = DuplexProcessor::new(16000, 16000).unwrap();
+        let frame_size = processor.canceller().frame_size;
+        let latency = processor.latency_samples();
+        // The capture runs a second ahead of the render before the render catches up.
+        let mut written = 0;
+        for _ in 0..100 {
+            let mut buffer = vec![0.1; 160];
+            processor.capture(&mut buffer).unwrap();
+            written += buffer.len();
+        }
+        for _ in 0..100 {
+            processor.render(&[0.0; 320]).unwrap();
+            let mut buffer = vec![0.1; 160];
+            processor.capture(&mut buffer).unwrap();
+            written += buffer.len();
+        }
+        assert_eq!(written, 200 * 160);
+        assert!(processor.stream.output_available() < frame_size);
+        assert_eq!(latency, frame_size + processor.canceller().latency_samples());
+        assert_eq!(processor.render(&[f32::NAN; 160]), Err(StreamError::NonFiniteSample { index: 0 }));
+    }
+}
@@ -0,0 +1,86 @@
+//! Echo return loss and echo level estimates for application UX.
+//!
+//! The estimated echo block computed on every frame tells how loud the echo reaching the
+//! microphone is. The canceller derives two figures from it: the instantaneous echo level,
+//! and the echo return loss (ERL), the attenuation of the acoustic path from the far-end
+//! reference to the microphone. A low ERL means a loud loudspeaker close to the microphone,
+//! e.g. a laptop at full volume, while a high ERL is typical for headsets. Applications use
+//! them to display "echo likely present" indicators or to suggest lowering the volume.
+
+use crate::{FdafAec, FrameEnergies};
+
+/// Mean-square level above which a signal counts as present (about -60 dBFS).
+const ACTIVITY_THRESHOLD: f32 = 1e-6;
+/// Smoothing factor applied to the ERL estimate on every far-end single-talk frame.
+const ERL_SMOOTHING: f32 = 0.95;
+
+/// Tracks the estimated echo level and the echo return loss.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EchoLevelTracker {
+    echo_level: f32,
+    erl: Option<f32>,
+}
+
+impl EchoLevelTracker {
+    /// Updates the estimates from the energies of a frame. The ERL is only updated while the
+    /// filter is converged and the far-end talks alone, when the estimated echo is reliable.
+    pub(crate) fn update(&mut self, energies: &FrameEnergies, reliable: bool) {
+        self.echo_level = energies.echo_estimate;
+        if reliable && energies.far_end > ACTIVITY_THRESHOLD && energies.echo_estimate > 0.0 {
+            let erl = energies.far_end / energies.echo_estimate;
+            self.erl = Some(match self.erl {
+                Some(smoothed) => ERL_SMOOTHING * smoothed + (1.0 - ERL_SMOOTHING) * erl,
+                None => erl,
+            });
+        }
+    }
+}
+
+impl FdafAec {
+    /// Returns the echo return loss, the power ratio of the far-end reference to the echo it
+    /// causes at the microphone, in dB. `None` until the filter has converged during far-end
+    /// single talk.
+    pub fn echo_return_loss_db(&self) -> Option<f32> {
+        self.echo_level.erl.map(|erl| 10.0 * erl.max(1e-10).log10())
+    }
+
+    /// Returns the level of the echo estimated in the last frame, in dBFS RMS.
+    pub fn echo_level_dbfs(&self) -> f32 {
+        10.0 * self.echo_level.echo_level.max(1e-12).log10()
+    }
+
+    /// Returns whether the last frame contained audible echo at the microphone, i.e. the
+    /// estimated echo is above -60 dBFS.
+    pub fn echo_present(&self) -> bool {
+        self.echo_level.echo_level > ACTIVITY_THRESHOLD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{echo, white_noise};
+    use crate::FdafAec;
+
+    #[test]
+    fn estimates_echo_return_loss_of_the_path() {
+        let far = white_noise(256 * 150, 0.3, 88);
+        let mut mic = echo(&far, &[(10, 0.1)]);
+        mic[100 * 256..].fill(0.0);
+        let mut far = far;
+        far[100 * 256..].fill(0.0);
+
+        let mut aec = FdafAec::new(512, 0.5);
+        assert_eq!(aec.echo_return_loss_db(), None);
+        for (i, (f, m)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+            aec.process(f, m);
+            if i == 99 {
+                // A single tap of 0.1 attenuates the far-end by 20 dB.
+                let erl_db = aec.echo_return_loss_db().unwrap();
+                assert!((erl_db - 20.0).abs() < 1.0, "erl {}", erl_db);
+                let level_dbfs = 10.0 * crate::mean_square(m).log10();
+                assert!(aec.echo_present() && (aec.echo_level_dbfs() - level_dbfs).abs() < 1.0);
+            }
+        }
+        assert!(!aec.echo_present());
+    }
+}
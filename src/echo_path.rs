@@ -0,0 +1,88 @@
+//! Structured export of the estimated echo path for plotting tools.
+//!
+//! The impulse response of the adaptive filter is split into consecutive partitions, each
+//! with its taps and energy. Capturing a snapshot every few frames shows how the estimated
+//! echo path evolves over a call. With the `serde` feature enabled, snapshots can be written
+//! with any serde format.
+
+use crate::FdafAec;
+
+/// One contiguous segment of the estimated echo path impulse response.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EchoPathPartition {
+    /// The delay of the first tap of the partition, in samples.
+    pub start_sample: usize,
+    /// The impulse response taps of the partition.
+    pub taps: Vec<f32>,
+    /// The sum of the squared taps.
+    pub energy: f32,
+}
+
+/// The estimated echo path, split into partitions.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EchoPathSnapshot {
+    /// The number of frames processed when the snapshot was taken, see
+    /// [`FdafAec::frame_count`].
+    pub frame: u64,
+    /// The partitions, ordered by delay.
+    pub partitions: Vec<EchoPathPartition>,
+}
+
+impl EchoPathSnapshot {
+    /// Returns the total energy of the echo path.
+    pub fn total_energy(&self) -> f32 {
+        self.partitions.iter().map(|p| p.energy).sum()
+    }
+
+    /// Returns the index of the partition holding the most energy.
+    pub fn dominant_partition(&self) -> Option<usize> {
+        self.partitions
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.energy.total_cmp(&b.1.energy))
+            .map(|(i, _)| i)
+    }
+}
+
+impl FdafAec {
+    /// Returns the estimated echo path split into partitions of `partition_len` taps.
+    ///
+    /// The last partition is shorter if `partition_len` does not divide the filter length.
+    pub fn echo_path_snapshot(&self, partition_len: usize) -> EchoPathSnapshot {
+        assert!(partition_len > 0, "Partition length must be non-zero.");
+        let partitions = self
+            .impulse_response()
+            .chunks(partition_len)
+            .enumerate()
+            .map(|(i, taps)| EchoPathPartition {
+                start_sample: i * partition_len,
+                taps: taps.to_vec(),
+                energy: taps.iter().map(|h| h * h).sum(),
+            })
+            .collect();
+        EchoPathSnapshot { frame: self.frame_count(), partitions }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn snapshot_locates_echo_partition() {
+        let mut aec = FdafAec::new(512, 0.5);
+        let far = white_noise(256 * 100, 0.3, 10);
+        let mic = echo(&far, &[(100, 0.5)]);
+        process_all(&mut aec, &far, &mic);
+
+        let snapshot = aec.echo_path_snapshot(64);
+        assert_eq!(snapshot.frame, 100);
+        assert_eq!(snapshot.partitions.len(), 4);
+        assert_eq!(snapshot.partitions[1].start_sample, 64);
+        assert_eq!(snapshot.dominant_partition(), Some(1));
+        assert!((snapshot.total_energy() - 0.25).abs() < 0.02);
+    }
+}
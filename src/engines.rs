@@ -0,0 +1,120 @@
+//! String-keyed registry of echo cancellation engines.
+//!
+//! Hosts that build their processing pipeline from a configuration file need to pick the
+//! engine by name instead of by type. Every engine is registered under a stable identifier
+//! and builds a [`BlockProcessor`] from an [`FdafAecConfig`], so a configuration file only
+//! has to name the engine, the post-filters (see [`post_filters`](crate::post_filters)) and
+//! the parameters. The identifiers are part of the public API and are never changed or
+//! reused; an engine that is removed leaves its identifier unassigned.
+//!
+//! The two FDAF engines differ only in the weight update. A running
+//! [`FdafAec`](crate::FdafAec) switches between them in place with
+//! [`FdafAec::swap_adaptation_mode`](crate::FdafAec::swap_adaptation_mode), keeping its echo
+//! path estimate and crossfading the output, where rebuilding the engine would start over.
+
+use crate::{AdaptationMode, BlockProcessor, ConfigError, EchoCancellerFactory, FdafAecConfig, KalmanConfig, MdfAec, MdfConfig};
+
+/// [`FdafAec`](crate::FdafAec) with the NLMS weight update.
+pub const FDAF_NLMS: &str = "fdaf-nlms";
+/// [`FdafAec`](crate::FdafAec) with the frequency-domain Kalman filter update.
+pub const FDAF_KALMAN: &str = "fdaf-kalman";
+/// [`MdfAec`] covering the same echo tail with four partitions.
+pub const MDF: &str = "mdf";
+
+/// A canceller built by the registry.
+pub type Engine = Box<dyn BlockProcessor + Send>;
+
+/// A registered engine.
+#[derive(Debug, Clone, Copy)]
+pub struct EngineEntry {
+    /// The stable identifier of the engine.
+    pub name: &'static str,
+    /// A one-line description for listings.
+    pub description: &'static str,
+    build: fn(&FdafAecConfig) -> Result<Engine, ConfigError>,
+}
+
+impl EngineEntry {
+    /// Builds the engine from a configuration, rejecting configurations that would make the
+    /// engine panic. Stages an engine does not have, e.g. the post-filters of an
+    /// [`MdfAec`], are ignored.
+    pub fn build(&self, config: &FdafAecConfig) -> Result<Engine, ConfigError> {
+        (self.build)(config)
+    }
+}
+
+static ENGINES: &[EngineEntry] = &[
+    EngineEntry {
+        name: FDAF_NLMS,
+        description: "single-partition frequency-domain adaptive filter, NLMS update",
+        build: |config| {
+            let config = FdafAecConfig { adaptation: AdaptationMode::Nlms, ..config.clone() };
+            Ok(Box::new(EchoCancellerFactory::new(config)?.create()))
+        },
+    },
+    EngineEntry {
+        name: FDAF_KALMAN,
+        description: "single-partition frequency-domain adaptive filter, Kalman update",
+        build: |config| {
+            let adaptation = match config.adaptation {
+                AdaptationMode::Kalman(kalman) => kalman,
+                AdaptationMode::Nlms => KalmanConfig::default(),
+            };
+            let config = FdafAecConfig { adaptation: AdaptationMode::Kalman(adaptation), ..config.clone() };
+            Ok(Box::new(EchoCancellerFactory::new(config)?.create()))
+        },
+    },
+    EngineEntry {
+        name: MDF,
+        description: "partitioned-block frequency-domain adaptive filter, a quarter of the frame latency",
+        build: |config| {
+            EchoCancellerFactory::new(config.clone())?;
+            let frame_size = (config.fft_size / 8).max(1);
+            Ok(Box::new(MdfAec::new(MdfConfig {
+                frame_size,
+                partitions: config.fft_size / 2 / frame_size,
+                sample_rate: config.sample_rate,
+                step_size: config.step_size,
+                psd_smoothing: config.psd_smoothing,
+            })))
+        },
+    },
+];
+
+/// Returns all registered engines.
+pub fn all() -> &'static [EngineEntry] {
+    ENGINES
+}
+
+/// Returns the engine registered under `name`.
+pub fn by_name(name: &str) -> Option<&'static EngineEntry> {
+    ENGINES.iter().find(|engine| engine.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use crate::BlockIo;
+
+    #[test]
+    fn builds_every_engine_by_name() {
+        let far = white_noise(256 * 150, 0.3, 98);
+        let mic = echo(&far, &[(10, 0.5), (60, -0.2)]);
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, ..FdafAecConfig::default() };
+        for entry in all() {
+            let mut engine = by_name(entry.name).unwrap().build(&config).unwrap();
+            let block_size = engine.block_size().unwrap();
+            let mut signal = mic.clone();
+            for (reference, block) in far.chunks(block_size).zip(signal.chunks_mut(block_size)) {
+                engine.process_block(&mut BlockIo { reference, signal: block });
+            }
+            let tail = signal.len() - 4096..;
+            assert!(crate::mean_square(&signal[tail.clone()]) < crate::mean_square(&mic[tail]) / 100.0, "{}", entry.name);
+        }
+        assert_eq!(by_name(MDF).unwrap().build(&config).unwrap().block_size(), Some(64));
+        assert!(by_name("fdaf-lms").is_none());
+        let invalid = FdafAecConfig { fft_size: 500, ..FdafAecConfig::default() };
+        assert_eq!(by_name(MDF).unwrap().build(&invalid).err().unwrap().parameter, "fft_size");
+    }
+}
@@ -0,0 +1,159 @@
+//! Validated, pre-planned construction of many cancellers from one configuration.
+//!
+//! Server frameworks create a canceller per session, often thousands of them from the same
+//! configuration. [`FdafAec::with_config`] panics on an invalid configuration and plans its
+//! FFTs from scratch every time. [`EchoCancellerFactory`] checks the configuration once,
+//! reporting problems as a [`ConfigError`] instead of a panic, and plans the FFTs once; every
+//! canceller it creates shares the planned transforms, so creating one only allocates its
+//! state.
+
+use crate::{FdafAec, FdafAecConfig, ReverbDecay};
+use rustfft::{Fft, FftPlanner};
+use std::fmt;
+use std::sync::Arc;
+
+/// The reason a configuration was rejected by [`EchoCancellerFactory::new`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigError {
+    /// The offending parameter, as a path into [`FdafAecConfig`].
+    pub parameter: &'static str,
+    /// The requirement the parameter violates.
+    pub requirement: &'static str,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.parameter, self.requirement)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Creates [`FdafAec`] instances from a validated configuration with shared FFT plans.
+///
+/// The factory is `Send` and `Sync`, so it can live in shared application state and be used
+/// from any thread.
+#[derive(Clone)]
+pub struct EchoCancellerFactory {
+    config: FdafAecConfig,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+}
+
+impl fmt::Debug for EchoCancellerFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EchoCancellerFactory").field("config", &self.config).finish_non_exhaustive()
+    }
+}
+
+impl EchoCancellerFactory {
+    /// Validates a configuration and plans its FFTs.
+    ///
+    /// Returns an error for every configuration that would make [`FdafAec::with_config`]
+    /// panic.
+    pub fn new(config: FdafAecConfig) -> Result<Self, ConfigError> {
+        validate(&config)?;
+        let mut planner = FftPlanner::new();
+        Ok(Self {
+            fft: planner.plan_fft_forward(config.fft_size),
+            ifft: planner.plan_fft_inverse(config.fft_size),
+            config,
+        })
+    }
+
+    /// Returns the configuration every created canceller starts with.
+    pub fn config(&self) -> &FdafAecConfig {
+        &self.config
+    }
+
+    /// Creates a canceller in its initial state.
+    pub fn create(&self) -> FdafAec {
+        FdafAec::with_ffts(self.config.clone(), self.fft.clone(), self.ifft.clone())
+    }
+}
+
+fn validate(config: &FdafAecConfig) -> Result<(), ConfigError> {
+    let check = |valid: bool, parameter, requirement| if valid { Ok(()) } else { Err(ConfigError { parameter, requirement }) };
+    let fft_size = config.fft_size;
+    let frame_size = fft_size / 2;
+    check(fft_size > 0 && fft_size.is_power_of_two(), "fft_size", "must be a power of two")?;
+    check(config.far_end_lookahead < frame_size, "far_end_lookahead", "must be shorter than the filter")?;
+    if let Some(rate) = config.far_end_sample_rate.filter(|&rate| rate != config.sample_rate) {
+        check(
+            (frame_size * rate as usize).is_multiple_of(config.sample_rate as usize),
+            "far_end_sample_rate",
+            "must give a whole number of far-end samples per frame",
+        )?;
+    }
+    check(config.fast_start_step_boost >= 1.0, "fast_start_step_boost", "must be at least 1.0")?;
+    check((0.0..1.0).contains(&config.weight_leakage), "weight_leakage", "must be in [0, 1)")?;
+    check(config.normalization.epsilon_for(&config.regularization, fft_size) > 0.0, "normalization.epsilon", "must be positive")?;
+    if let Some(band_dtd) = config.band_double_talk {
+        check(band_dtd.bands > 0 && band_dtd.bands <= frame_size + 1, "band_double_talk.bands", "must be between 1 and fft_size / 2 + 1")?;
+    }
+    if let Some(dtd) = config.coherence_dtd {
+        check(dtd.incoherent < dtd.coherent, "coherence_dtd.incoherent", "must be below coherence_dtd.coherent")?;
+    }
+    if let Some(vad) = config.vad {
+        check((0.0..=1.0).contains(&vad.adaptation_floor), "vad.adaptation_floor", "must be between 0 and 1")?;
+        check((0.0..=1.0).contains(&vad.nlp_release), "vad.nlp_release", "must be between 0 and 1")?;
+    }
+    if let Some(nonlinear_echo) = config.nonlinear_echo {
+        check(nonlinear_echo.order >= 2, "nonlinear_echo.order", "must be at least 2")?;
+    }
+    if let Some(late_reverb) = config.late_reverb {
+        let (ReverbDecay::Fixed { t60_s } | ReverbDecay::Estimated { initial_t60_s: t60_s }) = late_reverb.decay;
+        check(t60_s > 0.0, "late_reverb.decay", "must have a positive reverberation time")?;
+    }
+    if let Some(high_pass) = config.high_pass {
+        check(
+            high_pass.cutoff_hz > 0.0 && high_pass.cutoff_hz < config.sample_rate as f32 / 2.0,
+            "high_pass.cutoff_hz",
+            "must be between 0 and the Nyquist frequency",
+        )?;
+    }
+    if let Some(hum_notch) = config.hum_notch {
+        check(hum_notch.harmonics > 0, "hum_notch.harmonics", "must be at least 1")?;
+    }
+    if let Some(low_power) = config.low_power {
+        check(low_power.probe_interval > 0, "low_power.probe_interval", "must be at least one frame")?;
+    }
+    if let Some(agc) = config.agc {
+        check(agc.compression_ratio >= 1.0, "agc.compression_ratio", "must be at least 1")?;
+    }
+    if let Some(clipping) = config.clipping_detection {
+        check(clipping.min_run > 0, "clipping_detection.min_run", "must be at least one sample")?;
+    }
+    if let Some(bulk_delay) = config.bulk_delay {
+        check(bulk_delay.max_delay > 0, "bulk_delay.max_delay", "must be at least one sample")?;
+    }
+    if let Some(drift) = config.drift_compensation {
+        check(drift.headroom <= drift.max_delay, "drift_compensation.headroom", "must not exceed max_delay")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn creates_cancellers_equal_to_direct_construction() {
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, ..FdafAecConfig::default() };
+        let factory = EchoCancellerFactory::new(config.clone()).unwrap();
+        let far = white_noise(256 * 40, 0.3, 76);
+        let mic = echo(&far, &[(20, 0.5)]);
+        let run = |mut aec: FdafAec| -> Vec<f32> {
+            process_all(&mut aec, &far, &mic)
+        };
+        let direct = run(FdafAec::with_config(config));
+        assert_eq!(run(factory.create()), direct);
+        assert_eq!(run(factory.create()), direct);
+
+        let invalid = FdafAecConfig { fft_size: 500, ..FdafAecConfig::default() };
+        assert_eq!(EchoCancellerFactory::new(invalid).unwrap_err().parameter, "fft_size");
+        let invalid = FdafAecConfig { far_end_lookahead: 256, fft_size: 512, ..FdafAecConfig::default() };
+        assert_eq!(EchoCancellerFactory::new(invalid).unwrap_err().parameter, "far_end_lookahead");
+    }
+}
@@ -0,0 +1,91 @@
+//! Faster adaptation right after start-up.
+//!
+//! A freshly constructed canceller has zero weights and lets the full echo through until it
+//! converges, which is audible as an echo burst at the start of a call. During the fast-start
+//! window the step size is boosted, and the boost fades out linearly so the canceller
+//! reaches its steady-state parameters without a sudden change.
+
+use crate::FdafAec;
+
+/// The step size boost applied during the fast-start window.
+#[derive(Debug, Clone)]
+pub(crate) struct FastStart {
+    pub(crate) frames: u32,
+    remaining: u32,
+    pub(crate) boost: f32,
+}
+
+impl FastStart {
+    pub(crate) fn new(frames: u32, boost: f32) -> Self {
+        assert!(boost >= 1.0, "Fast-start step boost must be at least 1.0.");
+        Self { frames, remaining: frames, boost }
+    }
+
+    /// Creates a window that only starts on [`FastStart::restart`].
+    pub(crate) fn idle(frames: u32, boost: f32) -> Self {
+        Self { remaining: 0, ..Self::new(frames, boost) }
+    }
+
+    pub(crate) fn restart(&mut self) {
+        self.remaining = self.frames;
+    }
+
+    /// Advances the window by one frame and returns the step size factor to use.
+    pub(crate) fn update(&mut self) -> f32 {
+        if self.remaining == 0 {
+            return 1.0;
+        }
+        let factor = 1.0 + (self.boost - 1.0) * self.remaining as f32 / self.frames as f32;
+        self.remaining -= 1;
+        factor
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.remaining > 0
+    }
+}
+
+impl FdafAec {
+    /// Returns `true` while the fast-start window is running.
+    pub fn in_fast_start(&self) -> bool {
+        self.fast_start.is_active()
+    }
+
+    /// Restarts the fast-start window, e.g. after the echo path is known to have changed.
+    pub fn restart_fast_start(&mut self) {
+        self.fast_start.restart();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use crate::FdafAecConfig;
+
+    #[test]
+    fn boost_fades_out_over_window() {
+        let mut fast_start = FastStart::new(4, 3.0);
+        let factors: Vec<f32> = (0..6).map(|_| fast_start.update()).collect();
+        assert_eq!(factors, [3.0, 2.5, 2.0, 1.5, 1.0, 1.0]);
+        assert!(!fast_start.is_active());
+    }
+
+    #[test]
+    fn fast_start_converges_sooner() {
+        let far = white_noise(256 * 30, 0.3, 25);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let residual = |config: FdafAecConfig| {
+            let mut aec = FdafAec::with_config(config);
+            let mut last = Vec::new();
+            for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)) {
+                last = aec.process(far_frame, mic_frame);
+            }
+            crate::mean_square(&last)
+        };
+
+        let base = FdafAecConfig { fft_size: 512, step_size: 0.02, ..FdafAecConfig::default() };
+        let boosted = FdafAecConfig { fast_start_frames: 20, fast_start_step_boost: 5.0, ..base.clone() };
+        assert!(residual(boosted) < residual(base) / 2.0);
+    }
+}
@@ -0,0 +1,67 @@
+//! Fault injection for resilience testing, enabled with the `fault-injection` feature.
+//!
+//! Applications should survive a canceller that diverges, loses alignment or produces
+//! non-finite output. These hooks corrupt a running canceller on purpose so that the
+//! recovery paths of an integration can be exercised in tests. They are not meant for
+//! production builds.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+
+/// A fault to inject into a running canceller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// Multiplies every filter weight by `scale`, e.g. to simulate divergence.
+    ScaleWeights(f32),
+    /// Shifts the estimated echo path by the given number of samples, as if the alignment
+    /// between far-end and microphone had suddenly jumped. Positive values delay the path.
+    DelayJump(isize),
+    /// Sets every filter weight to NaN, so that all further output is non-finite.
+    NanWeights,
+}
+
+impl FdafAec {
+    /// Injects a fault into the adaptive state.
+    pub fn inject_fault(&mut self, fault: Fault) {
+        match fault {
+            Fault::ScaleWeights(scale) => self.weights *= Complex::new(scale, 0.0),
+            Fault::DelayJump(samples) => {
+                let mut h = self.weights.as_slice().to_vec();
+                self.ifft.process(&mut h);
+                let shift = samples.rem_euclid(self.fft_size as isize) as usize;
+                h.rotate_right(shift);
+                self.fft.process(&mut h);
+                let scale = 1.0 / self.fft_size as f32;
+                self.weights = DVector::from_iterator(self.fft_size, h.into_iter().map(|w| w * scale));
+            }
+            Fault::NanWeights => self.weights.fill(Complex::new(f32::NAN, f32::NAN)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::trained_aec;
+
+    #[test]
+    fn delay_jump_moves_echo_path() {
+        let mut aec = trained_aec(&[(10, 0.5)], 23);
+        aec.inject_fault(Fault::DelayJump(5));
+        let ir = aec.impulse_response();
+        assert!((ir[15] - 0.5).abs() < 0.05, "shifted tap was {}", ir[15]);
+        assert!(ir[10].abs() < 0.05);
+    }
+
+    #[test]
+    fn nan_weights_make_output_non_finite() {
+        let mut aec = trained_aec(&[(10, 0.5)], 23);
+        aec.inject_fault(Fault::ScaleWeights(2.0));
+        assert!((aec.impulse_response()[10] - 1.0).abs() < 0.1);
+
+        aec.inject_fault(Fault::NanWeights);
+        let output = aec.process(&[0.1; 256], &[0.1; 256]);
+        assert!(output.iter().all(|x| x.is_nan()));
+    }
+}
@@ -0,0 +1,86 @@
+//! The overlap-save filter and NLMS update shared by the cancellers.
+//!
+//! [`FdafAec`](crate::FdafAec), [`MdfAec`](crate::MdfAec), [`MultiChannelAec`](crate::MultiChannelAec)
+//! and [`MultiMicAec`](crate::MultiMicAec) differ in how many weight sets they keep and how
+//! they combine them, but every weight set is the same frequency-domain filter: it is applied
+//! to the spectrum of the last two far-end frames, the current frame of the result is the
+//! echo estimate, and the weights follow the normalized, constrained LMS gradient of the
+//! error spectrum zero-padded in front. These building blocks live here once.
+
+use crate::constraint::constrain_gradient;
+use nalgebra::DVector;
+use num_complex::Complex;
+use rustfft::Fft;
+use std::sync::Arc;
+
+/// Returns the spectrum of `buffer`, which holds the last two frames of a signal.
+pub(crate) fn spectrum(fft: &Arc<dyn Fft<f32>>, buffer: &[f32]) -> DVector<Complex<f32>> {
+    let mut x: Vec<Complex<f32>> = buffer.iter().map(|&s| Complex::new(s, 0.0)).collect();
+    fft.process(&mut x);
+    DVector::from_vec(x)
+}
+
+/// Returns the spectrum of a frame zero-padded in front, aligned with the current frame of
+/// the overlap-save output.
+pub(crate) fn padded_spectrum(fft: &Arc<dyn Fft<f32>>, frame: &[f32]) -> DVector<Complex<f32>> {
+    let mut x = vec![Complex::new(0.0, 0.0); 2 * frame.len()];
+    for (c, &sample) in x[frame.len()..].iter_mut().zip(frame) {
+        *c = Complex::new(sample, 0.0);
+    }
+    fft.process(&mut x);
+    DVector::from_vec(x)
+}
+
+/// Returns the current frame of the overlap-save convolution whose spectrum is `y_f`, the
+/// valid half of the circular convolution.
+pub(crate) fn overlap_save(ifft: &Arc<dyn Fft<f32>>, y_f: &DVector<Complex<f32>>) -> DVector<f32> {
+    let mut y = y_f.as_slice().to_vec();
+    ifft.process(&mut y);
+    let frame_size = y.len() / 2;
+    let scale = 1.0 / y.len() as f32;
+    DVector::from_iterator(frame_size, y[frame_size..].iter().map(|c| c.re * scale))
+}
+
+/// Smooths the far-end power spectral density `psd` with the spectrum `x_f`.
+pub(crate) fn smooth_psd(psd: &mut DVector<f32>, x_f: &DVector<Complex<f32>>, smoothing: f32) {
+    for (p, x) in psd.iter_mut().zip(x_f.iter()) {
+        *p = smoothing * *p + (1.0 - smoothing) * x.norm_sqr();
+    }
+}
+
+/// Adds the constrained NLMS update with step size `mu` to `weights`, given the far-end
+/// spectrum `x_f` the weights filtered, the padded error spectrum `e_f` and the per-bin
+/// normalization `norm`, the inverse of the regularized far-end power.
+pub(crate) fn nlms_update(
+    fft: &Arc<dyn Fft<f32>>,
+    ifft: &Arc<dyn Fft<f32>>,
+    weights: &mut DVector<Complex<f32>>,
+    x_f: &DVector<Complex<f32>>,
+    e_f: &DVector<Complex<f32>>,
+    norm: &DVector<f32>,
+    mu: f32,
+) {
+    let mut gradient = DVector::from_iterator(x_f.len(), x_f.iter().zip(e_f.iter()).zip(norm.iter()).map(|((x, e), n)| x.conj() * e * *n));
+    constrain_gradient(fft, ifft, &mut gradient, x_f.len() / 2);
+    for (w, g) in weights.iter_mut().zip(gradient.iter()) {
+        *w += g * mu;
+    }
+}
+
+/// Returns the weights of the filter with the time-domain `taps`, at most `fft_size / 2`.
+pub(crate) fn weights(fft: &Arc<dyn Fft<f32>>, taps: &[f32], fft_size: usize) -> DVector<Complex<f32>> {
+    let mut h = vec![Complex::new(0.0, 0.0); fft_size];
+    for (c, &tap) in h.iter_mut().zip(taps) {
+        *c = Complex::new(tap, 0.0);
+    }
+    fft.process(&mut h);
+    DVector::from_vec(h)
+}
+
+/// Returns the first `fft_size / 2` taps of the time-domain filter described by `weights`.
+pub(crate) fn impulse_response(ifft: &Arc<dyn Fft<f32>>, weights: &DVector<Complex<f32>>) -> Vec<f32> {
+    let mut h = weights.as_slice().to_vec();
+    ifft.process(&mut h);
+    let scale = 1.0 / h.len() as f32;
+    h.iter().take(h.len() / 2).map(|c| c.re * scale).collect()
+}
@@ -0,0 +1,334 @@
+//! A C API for C and embedded callers, enabled with the `ffi` feature.
+//!
+//! [`fdaf_aec_create`] builds a canceller from the plain-old-data [`FdafAecConfigC`],
+//! [`fdaf_aec_process`] processes one frame into a caller-provided buffer, and
+//! [`fdaf_aec_destroy`] frees the canceller. [`FdafAecStatsC`] is a plain-old-data struct
+//! with a fixed C layout that can be filled without allocating, so that firmware and C hosts
+//! can poll the canceller from their own reporting loops; [`fdaf_aec_get_stats`] exposes it.
+//!
+//! The crate builds as a `cdylib` and a `staticlib` besides the Rust library, and
+//! `include/fdaf_aec.h` declares the API for C; a unit test checks that it declares every
+//! exported function.
+
+use crate::metrics::pack_flags;
+use crate::{ConfigError, EchoCancellerFactory, FdafAec, FdafAecConfig, NlpLevel};
+
+/// Bit of [`FdafAecStatsC::flags`] set while the filter is converged.
+pub const FDAF_AEC_FLAG_CONVERGED: u32 = 1 << 0;
+/// Bit of [`FdafAecStatsC::flags`] set when the last frame was free of residual echo.
+pub const FDAF_AEC_FLAG_ECHO_FREE: u32 = 1 << 1;
+/// Bit of [`FdafAecStatsC::flags`] set when double talk was detected in the last frame.
+pub const FDAF_AEC_FLAG_DOUBLE_TALK: u32 = 1 << 2;
+/// Shift of the two-bit conversation state in [`FdafAecStatsC::flags`]: 0 silence, 1 far-end
+/// only, 2 near-end only, 3 double talk.
+pub const FDAF_AEC_DUPLEX_STATE_SHIFT: u32 = 3;
+/// Bit of [`FdafAecStatsC::flags`] set when diagnostics suspect swapped inputs.
+pub const FDAF_AEC_FLAG_INPUTS_SWAPPED: u32 = 1 << 5;
+/// Bit of [`FdafAecStatsC::flags`] set when diagnostics suspect an inverted polarity.
+pub const FDAF_AEC_FLAG_POLARITY_INVERTED: u32 = 1 << 6;
+/// Bit of [`FdafAecStatsC::flags`] set when diagnostics find the output louder than the mic.
+pub const FDAF_AEC_FLAG_OUTPUT_LOUDER: u32 = 1 << 7;
+
+/// A snapshot of the canceller statistics with a C-compatible layout.
+///
+/// Counters saturate at their maximum instead of wrapping.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FdafAecStatsC {
+    /// The number of frames processed so far.
+    pub frames: u64,
+    /// The long-term echo return loss enhancement, in dB.
+    pub erle_db: f32,
+    /// The most frequent echo delay of the delay histogram, in samples, or -1 if the
+    /// histogram is disabled or empty.
+    pub delay_samples: i32,
+    /// State bits, see the `FDAF_AEC_FLAG_*` constants.
+    pub flags: u32,
+    /// The number of bins whose update was saturated in the last frame.
+    pub saturated_bins: u32,
+    /// The number of frames in which the echo path gain clamp engaged.
+    pub echo_path_clamps: u32,
+    /// The number of frames that exceeded the processing deadline.
+    pub deadline_overruns: u32,
+}
+
+impl FdafAec {
+    /// Returns the statistics in their C layout. Does not allocate.
+    pub fn stats_c(&self) -> FdafAecStatsC {
+        let quality = self.quality.quality();
+        let mut flags = pack_flags(quality, self.duplex.state());
+        if let Some(health) = self.health() {
+            for (set, flag) in [
+                (health.inputs_swapped, FDAF_AEC_FLAG_INPUTS_SWAPPED),
+                (health.polarity_inverted, FDAF_AEC_FLAG_POLARITY_INVERTED),
+                (health.output_louder_than_mic, FDAF_AEC_FLAG_OUTPUT_LOUDER),
+            ] {
+                if set {
+                    flags |= flag;
+                }
+            }
+        }
+        FdafAecStatsC {
+            frames: self.frames_processed,
+            erle_db: 10.0 * self.quality.erle().max(1e-10).log10(),
+            delay_samples: self
+                .delay_histogram()
+                .and_then(|histogram| histogram.mode())
+                .map_or(-1, |delay| delay.min(i32::MAX as usize) as i32),
+            flags,
+            saturated_bins: saturating_u32(self.saturated_bins as u64),
+            echo_path_clamps: saturating_u32(self.echo_path_clamps),
+            deadline_overruns: self.deadline_stats().map_or(0, |stats| saturating_u32(stats.overruns)),
+        }
+    }
+}
+
+fn saturating_u32(value: u64) -> u32 {
+    value.min(u32::MAX as u64) as u32
+}
+
+/// The parameters of a canceller created through the C API, with a C-compatible layout.
+///
+/// Every other parameter of [`FdafAecConfig`] keeps its default.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FdafAecConfigC {
+    /// The FFT size, a power of two. Frames have half as many samples.
+    pub fft_size: u32,
+    /// The sample rate of the microphone signal, in Hz.
+    pub sample_rate: u32,
+    /// The sample rate of the far-end signal, in Hz, or 0 if it equals `sample_rate`.
+    pub far_end_sample_rate: u32,
+    /// The step size of the weight update.
+    pub step_size: f32,
+    /// The smoothing factor of the far-end PSD estimate.
+    pub psd_smoothing: f32,
+    /// The NLP level: -1 disabled, 0 conservative, 1 moderate, 2 aggressive.
+    pub nlp_level: i32,
+    /// The far-end lookahead, in samples.
+    pub far_end_lookahead: u32,
+}
+
+impl Default for FdafAecConfigC {
+    fn default() -> Self {
+        let config = FdafAecConfig::default();
+        Self {
+            fft_size: config.fft_size as u32,
+            sample_rate: config.sample_rate,
+            far_end_sample_rate: 0,
+            step_size: config.step_size,
+            psd_smoothing: config.psd_smoothing,
+            nlp_level: -1,
+            far_end_lookahead: config.far_end_lookahead as u32,
+        }
+    }
+}
+
+impl FdafAecConfigC {
+    /// Returns the canceller configuration, or an error if `nlp_level` is out of range.
+    pub fn to_config(&self) -> Result<FdafAecConfig, ConfigError> {
+        let nlp = match self.nlp_level {
+            -1 => None,
+            0 => Some(NlpLevel::Conservative),
+            1 => Some(NlpLevel::Moderate),
+            2 => Some(NlpLevel::Aggressive),
+            _ => return Err(ConfigError { parameter: "nlp_level", requirement: "must be between -1 and 2" }),
+        };
+        Ok(FdafAecConfig {
+            fft_size: self.fft_size as usize,
+            sample_rate: self.sample_rate,
+            far_end_sample_rate: (self.far_end_sample_rate != 0).then_some(self.far_end_sample_rate),
+            step_size: self.step_size,
+            psd_smoothing: self.psd_smoothing,
+            nlp,
+            far_end_lookahead: self.far_end_lookahead as usize,
+            ..FdafAecConfig::default()
+        })
+    }
+}
+
+/// Fills `config` with the default configuration. Returns 0 on success and -1 if `config`
+/// is null.
+///
+/// # Safety
+///
+/// `config` must be null or point to memory valid for writing one [`FdafAecConfigC`].
+#[no_mangle]
+pub unsafe extern "C" fn fdaf_aec_config_default(config: *mut FdafAecConfigC) -> i32 {
+    // SAFETY: the caller guarantees the pointer is valid if non-null.
+    match unsafe { config.as_mut() } {
+        Some(config) => {
+            *config = FdafAecConfigC::default();
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Creates a canceller from `config`. Returns null if `config` is null or invalid. The
+/// canceller must be freed with [`fdaf_aec_destroy`].
+///
+/// # Safety
+///
+/// `config` must be null or point to a valid [`FdafAecConfigC`].
+#[no_mangle]
+pub unsafe extern "C" fn fdaf_aec_create(config: *const FdafAecConfigC) -> *mut FdafAec {
+    // SAFETY: the caller guarantees the pointer is valid if non-null.
+    let Some(config) = (unsafe { config.as_ref() }) else {
+        return std::ptr::null_mut();
+    };
+    match config.to_config().and_then(EchoCancellerFactory::new) {
+        Ok(factory) => Box::into_raw(Box::new(factory.create())),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Frees a canceller created by [`fdaf_aec_create`]. Does nothing if `aec` is null.
+///
+/// # Safety
+///
+/// `aec` must be null or a pointer returned by [`fdaf_aec_create`] that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn fdaf_aec_destroy(aec: *mut FdafAec) {
+    if !aec.is_null() {
+        // SAFETY: the caller guarantees the pointer came from `Box::into_raw` in
+        // `fdaf_aec_create` and is freed only once.
+        drop(unsafe { Box::from_raw(aec) });
+    }
+}
+
+/// Returns the number of microphone and output samples per frame, or 0 if `aec` is null.
+///
+/// # Safety
+///
+/// `aec` must be null or point to a live [`FdafAec`].
+#[no_mangle]
+pub unsafe extern "C" fn fdaf_aec_frame_size(aec: *const FdafAec) -> u32 {
+    // SAFETY: the caller guarantees the pointer is valid if non-null.
+    unsafe { aec.as_ref() }.map_or(0, |aec| aec.frame_size as u32)
+}
+
+/// Returns the number of far-end samples per frame, or 0 if `aec` is null.
+///
+/// # Safety
+///
+/// `aec` must be null or point to a live [`FdafAec`].
+#[no_mangle]
+pub unsafe extern "C" fn fdaf_aec_far_end_frame_size(aec: *const FdafAec) -> u32 {
+    // SAFETY: the caller guarantees the pointer is valid if non-null.
+    unsafe { aec.as_ref() }.map_or(0, |aec| aec.far_end_frame_size() as u32)
+}
+
+/// Processes one frame: reads [`fdaf_aec_far_end_frame_size`] samples from `far_end` and
+/// [`fdaf_aec_frame_size`] samples from `mic`, and writes as many samples to `output`, which
+/// may alias `mic`. Returns 0 on success and -1 if any pointer is null.
+///
+/// # Safety
+///
+/// `aec` must be null or point to a live [`FdafAec`] that is not used concurrently, and the
+/// buffers must be null or valid for the frame sizes above.
+#[no_mangle]
+pub unsafe extern "C" fn fdaf_aec_process(aec: *mut FdafAec, far_end: *const f32, mic: *const f32, output: *mut f32) -> i32 {
+    // SAFETY: the caller guarantees the pointer is valid if non-null.
+    let Some(aec) = (unsafe { aec.as_mut() }) else {
+        return -1;
+    };
+    if far_end.is_null() || mic.is_null() || output.is_null() {
+        return -1;
+    }
+    let (far_end_frame_size, frame_size) = (aec.far_end_frame_size(), aec.frame_size);
+    // SAFETY: the caller guarantees the buffers hold a frame each. The output is written
+    // only after the inputs have been read, so it may alias the microphone buffer.
+    let processed = aec.process(unsafe { std::slice::from_raw_parts(far_end, far_end_frame_size) }, unsafe {
+        std::slice::from_raw_parts(mic, frame_size)
+    });
+    unsafe { std::ptr::copy_nonoverlapping(processed.as_ptr(), output, frame_size) };
+    0
+}
+
+/// Fills `stats` with the statistics of `aec`. Returns 0 on success and -1 if either pointer
+/// is null.
+///
+/// # Safety
+///
+/// `aec` must be null or point to a live [`FdafAec`] that is not being mutated concurrently,
+/// and `stats` must be null or point to memory valid for writing one [`FdafAecStatsC`].
+#[no_mangle]
+pub unsafe extern "C" fn fdaf_aec_get_stats(aec: *const FdafAec, stats: *mut FdafAecStatsC) -> i32 {
+    // SAFETY: the caller guarantees both pointers are valid if non-null.
+    match unsafe { (aec.as_ref(), stats.as_mut()) } {
+        (Some(aec), Some(stats)) => {
+            *stats = aec.stats_c();
+            0
+        }
+        _ => -1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use crate::DelayHistogramConfig;
+
+    #[test]
+    fn processes_through_c_entry_points() {
+        let mut config = FdafAecConfigC { fft_size: 0, ..FdafAecConfigC::default() };
+        assert_eq!(unsafe { fdaf_aec_config_default(&mut config) }, 0);
+        assert_eq!(config.to_config().unwrap(), FdafAecConfig::default());
+        config.fft_size = 512;
+        config.step_size = 0.5;
+        assert!(unsafe { fdaf_aec_create(&FdafAecConfigC { nlp_level: 3, ..config }) }.is_null());
+        assert!(unsafe { fdaf_aec_create(&FdafAecConfigC { fft_size: 500, ..config }) }.is_null());
+
+        let aec = unsafe { fdaf_aec_create(&config) };
+        assert!(!aec.is_null());
+        assert_eq!(unsafe { (fdaf_aec_frame_size(aec), fdaf_aec_far_end_frame_size(aec)) }, (256, 256));
+        let far = white_noise(256 * 100, 0.3, 107);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let mut reference = FdafAec::with_config(config.to_config().unwrap());
+        for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)) {
+            let mut output = mic_frame.to_vec();
+            assert_eq!(unsafe { fdaf_aec_process(aec, far_frame.as_ptr(), output.as_ptr(), output.as_mut_ptr()) }, 0);
+            assert_eq!(output, reference.process(far_frame, mic_frame));
+        }
+        assert_eq!(unsafe { fdaf_aec_process(aec, std::ptr::null(), mic.as_ptr(), std::ptr::null_mut()) }, -1);
+        unsafe { fdaf_aec_destroy(aec) };
+    }
+
+    #[test]
+    fn header_declares_every_entry_point() {
+        let header = include_str!("../include/fdaf_aec.h");
+        let source = include_str!("ffi.rs");
+        let exported: Vec<&str> = source
+            .lines()
+            .filter_map(|line| line.strip_prefix("pub unsafe extern \"C\" fn "))
+            .map(|line| &line[..line.find('(').unwrap()])
+            .collect();
+        assert_eq!(exported.len(), 7);
+        for name in exported {
+            assert!(header.contains(&format!("{name}(")), "{name} is not declared in fdaf_aec.h");
+        }
+        for name in ["FdafAecConfigC", "FdafAecStatsC", "FDAF_AEC_FLAG_OUTPUT_LOUDER", "FDAF_AEC_DUPLEX_STATE_SHIFT"] {
+            assert!(header.contains(name), "{name} is not declared in fdaf_aec.h");
+        }
+    }
+
+    #[test]
+    fn stats_through_c_entry_point() {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_delay_histogram(Some(DelayHistogramConfig { bin_width: 1, window_ms: 1000.0 }));
+        let far = white_noise(256 * 100, 0.3, 38);
+        let mic = echo(&far, &[(24, 0.5)]);
+        process_all(&mut aec, &far, &mic);
+
+        let mut stats = FdafAecStatsC::default();
+        assert_eq!(unsafe { fdaf_aec_get_stats(&aec, &mut stats) }, 0);
+        assert_eq!(stats.frames, 100);
+        assert_eq!(stats.delay_samples, 24);
+        assert!(stats.erle_db > 15.0);
+        assert_ne!(stats.flags & FDAF_AEC_FLAG_CONVERGED, 0);
+        assert_eq!(stats.flags >> FDAF_AEC_DUPLEX_STATE_SHIFT & 3, 1);
+        assert_eq!(unsafe { fdaf_aec_get_stats(std::ptr::null(), &mut stats) }, -1);
+    }
+}
@@ -0,0 +1,55 @@
+//! Freezing the adaptation of the filter.
+//!
+//! Some segments are known in advance to mislead the adaptation: while the audio device is
+//! being switched the echo path is undefined, and system sounds played locally reach the
+//! microphone without passing through the far-end reference. Freezing the adaptation keeps
+//! the learned echo path untouched during such segments while the filter keeps cancelling
+//! with it, and resuming continues from exactly where it stopped.
+
+use crate::FdafAec;
+
+impl FdafAec {
+    /// Enables or freezes the adaptation of the filter weights. Enabled by default.
+    ///
+    /// While frozen, every frame is still cancelled with the current echo path estimate,
+    /// but the weights are neither updated nor leaked.
+    pub fn set_adaptation_enabled(&mut self, enabled: bool) {
+        self.adaptation_enabled = enabled;
+    }
+
+    /// Returns whether the filter weights are adapted.
+    pub fn adaptation_enabled(&self) -> bool {
+        self.adaptation_enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{echo, white_noise};
+    use crate::FdafAec;
+
+    #[test]
+    fn frozen_filter_keeps_echo_path() {
+        let far = white_noise(256 * 200, 0.3, 91);
+        let mut mic = echo(&far, &[(10, 0.5)]);
+        // Local system sounds reach the microphone during frames 100 to 149.
+        let system_sound = white_noise(256 * 50, 0.3, 92);
+        for (m, s) in mic[100 * 256..150 * 256].iter_mut().zip(&system_sound) {
+            *m += s;
+        }
+
+        let mut aec = FdafAec::new(512, 0.5);
+        for (i, (f, m)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+            aec.set_adaptation_enabled(!(100..150).contains(&i));
+            aec.process(f, m);
+            if i == 99 {
+                assert!((aec.impulse_response()[10] - 0.5).abs() < 0.01);
+            }
+            if i == 149 {
+                assert!(!aec.adaptation_enabled());
+                assert!((aec.impulse_response()[10] - 0.5).abs() < 0.01);
+            }
+        }
+        assert!(aec.adaptation_enabled() && (aec.impulse_response()[10] - 0.5).abs() < 0.01);
+    }
+}
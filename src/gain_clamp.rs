@@ -0,0 +1,72 @@
+//! Clamp on the total gain of the estimated echo path.
+//!
+//! Acoustic echo paths lose energy between loudspeaker and microphone, so the broadband gain
+//! of a physical echo path rarely exceeds 1.0. A filter whose impulse response carries more
+//! energy than a configured maximum has misadapted, e.g. during undetected double talk, and
+//! is scaled back onto the bound. How often this happens is itself a useful health metric.
+
+use crate::FdafAec;
+use num_complex::Complex;
+
+impl FdafAec {
+    /// Sets the largest broadband gain, the square root of the impulse response energy, the
+    /// estimated echo path may have. `None` disables the clamp.
+    pub fn set_max_echo_path_gain(&mut self, max_gain: Option<f32>) {
+        self.note_config_change();
+        if let Some(max_gain) = max_gain {
+            assert!(max_gain > 0.0, "Maximum echo path gain must be positive.");
+        }
+        self.max_echo_path_gain = max_gain;
+    }
+
+    /// Returns the broadband gain of the estimated echo path, the square root of the energy
+    /// of its impulse response.
+    pub fn echo_path_gain(&self) -> f32 {
+        // Parseval: the impulse response energy is the mean squared weight magnitude.
+        (self.weights.iter().map(|w| w.norm_sqr()).sum::<f32>() / self.fft_size as f32).sqrt()
+    }
+
+    /// Returns the number of frames in which the echo path gain clamp engaged.
+    pub fn echo_path_clamps(&self) -> u64 {
+        self.echo_path_clamps
+    }
+
+    /// Scales the weights back onto the configured maximum gain, if they exceed it.
+    pub(crate) fn clamp_echo_path_gain(&mut self) {
+        let Some(max_gain) = self.max_echo_path_gain else {
+            return;
+        };
+        let gain = self.echo_path_gain();
+        if gain > max_gain {
+            self.weights *= Complex::new(max_gain / gain, 0.0);
+            self.echo_path_clamps += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use crate::FdafAecConfig;
+
+    #[test]
+    fn clamp_bounds_implausible_echo_path() {
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, max_echo_path_gain: Some(1.0), ..FdafAecConfig::default() };
+        let mut aec = FdafAec::with_config(config.clone());
+        let far = white_noise(256 * 100, 0.3, 37);
+        // An "echo" four times louder than the reference.
+        let mic = echo(&far, &[(10, 4.0)]);
+        for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)) {
+            aec.process(far_frame, mic_frame);
+            assert!(aec.echo_path_gain() <= 1.0 + 1e-4);
+        }
+        assert!(aec.echo_path_clamps() > 50);
+
+        // A plausible echo path is left alone.
+        let mut aec = FdafAec::with_config(config);
+        let mic = echo(&far, &[(10, 0.5)]);
+        process_all(&mut aec, &far, &mic);
+        assert!((aec.echo_path_gain() - 0.5).abs() < 0.05);
+    }
+}
@@ -0,0 +1,115 @@
+//! Causal filters from per-bin suppression gains.
+//!
+//! Suppressors compute a real gain per frequency bin and apply it to the spectrum of the last
+//! two frames. Multiplying the spectrum by the raw gains is a circular convolution with a
+//! zero-phase response that spans the whole FFT: it wraps the filtered signal around the
+//! block edges, and gains that change from frame to frame turn into musical noise. Instead,
+//! the gains are turned into a minimum-phase response (through the real cepstrum), which is
+//! causal and concentrates its energy at the start, and that response is truncated to
+//! `fft_size / 2` taps with a tapered end. Filtering the last two frames with it and keeping
+//! the second half, as in overlap-save, is then a linear convolution without added latency,
+//! and the truncation smooths the gain over frequency.
+//!
+//! This is why the suppression stages have no windowed overlap-add (WOLA) synthesis. WOLA
+//! hides the wrap-around of raw gains by windowing overlapping blocks and crossfading them,
+//! at the cost of an extra window overlap of latency and an output no longer aligned with
+//! the microphone frame. The filters built here have no wrap-around to hide, and each output
+//! sample is the causal filter applied to the continuous input, so a gain change between
+//! frames is a time-varying filter rather than a discontinuity at the block edge. The NLP
+//! works in the time domain and ramps its gain within a frame, so it has no blocks to
+//! synthesize either.
+
+use num_complex::Complex;
+use rustfft::Fft;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// The smallest gain turned into a filter, about -80 dB, which keeps the logarithm finite.
+const MIN_GAIN: f32 = 1e-4;
+
+/// Returns the spectrum of a causal filter of at most `fft_size / 2` taps whose magnitude
+/// response approximates `gains`, given for the `fft_size / 2 + 1` bins up to Nyquist.
+pub(crate) fn causal_response(fft: &Arc<dyn Fft<f32>>, ifft: &Arc<dyn Fft<f32>>, gains: &[f32]) -> Vec<Complex<f32>> {
+    let bins = gains.len();
+    let fft_size = 2 * (bins - 1);
+    let frame_size = fft_size / 2;
+    let scale = 1.0 / fft_size as f32;
+
+    // The real cepstrum of the gains, folded onto positive quefrencies
+    let mut cepstrum: Vec<Complex<f32>> = (0..fft_size).map(|k| Complex::new(gains[k.min(fft_size - k)].max(MIN_GAIN).ln(), 0.0)).collect();
+    ifft.process(&mut cepstrum);
+    for (n, c) in cepstrum.iter_mut().enumerate() {
+        let fold = match n {
+            0 => 1.0,
+            n if n < frame_size => 2.0,
+            n if n == frame_size => 1.0,
+            _ => 0.0,
+        };
+        *c = Complex::new(c.re * fold * scale, 0.0);
+    }
+    fft.process(&mut cepstrum);
+
+    // The minimum-phase response, truncated to a frame with a raised-cosine fade over the
+    // last quarter of it
+    let mut response: Vec<Complex<f32>> = cepstrum.iter().map(|c| c.exp()).collect();
+    ifft.process(&mut response);
+    let fade = frame_size / 4;
+    for (n, h) in response.iter_mut().enumerate() {
+        let window = if n < frame_size - fade {
+            1.0
+        } else if n < frame_size {
+            0.5 + 0.5 * (PI * (n - (frame_size - fade)) as f32 / fade as f32).cos()
+        } else {
+            0.0
+        };
+        *h = Complex::new(h.re * window * scale, 0.0);
+    }
+    fft.process(&mut response);
+    response
+}
+
+/// Filters the spectrum of the last two frames with `response` and returns the current
+/// frame of the result.
+pub(crate) fn filter_frame(ifft: &Arc<dyn Fft<f32>>, mut spectrum: Vec<Complex<f32>>, response: &[Complex<f32>]) -> Vec<f32> {
+    for (x, h) in spectrum.iter_mut().zip(response) {
+        *x *= h;
+    }
+    ifft.process(&mut spectrum);
+    let scale = 1.0 / spectrum.len() as f32;
+    spectrum[spectrum.len() / 2..].iter().map(|x| x.re * scale).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustfft::FftPlanner;
+
+    #[test]
+    fn builds_short_causal_filters() {
+        let mut planner = FftPlanner::new();
+        let (fft, ifft) = (planner.plan_fft_forward(512), planner.plan_fft_inverse(512));
+        let unity = causal_response(&fft, &ifft, &[1.0; 257]);
+        assert!(unity.iter().all(|h| (h - Complex::new(1.0, 0.0)).norm() < 1e-5));
+
+        // A smooth low-pass gain is kept, and the response has no taps beyond a frame.
+        let gains: Vec<f32> = (0..257).map(|k| 0.1 + 0.9 / (1.0 + (k as f32 / 64.0).powi(4))).collect();
+        let response = causal_response(&fft, &ifft, &gains);
+        for (k, &gain) in gains.iter().enumerate().step_by(16) {
+            assert!((response[k].norm() - gain).abs() < 0.02, "bin {}: {} != {}", k, response[k].norm(), gain);
+        }
+        let mut taps = response.clone();
+        ifft.process(&mut taps);
+        let taps: Vec<f32> = taps.iter().map(|h| h.re / 512.0).collect();
+        assert!(taps[256..].iter().all(|h| h.abs() < 1e-6));
+
+        // Filtering a frame is a linear convolution with the taps, without wrap-around.
+        let signal = crate::test_util::white_noise(512, 0.3, 106);
+        let mut spectrum: Vec<Complex<f32>> = signal.iter().map(|&x| Complex::new(x, 0.0)).collect();
+        fft.process(&mut spectrum);
+        let filtered = filter_frame(&ifft, spectrum, &response);
+        for (n, &y) in filtered.iter().enumerate() {
+            let expected: f32 = (0..256).map(|k| taps[k] * signal[256 + n - k]).sum();
+            assert!((y - expected).abs() < 1e-4, "sample {}: {} != {}", n, y, expected);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Conversions between echo tail, block size, partitions and latency.
+//!
+//! The parameters of a canceller are tied together: the block size sets both the frame
+//! latency and, with the number of partitions, the echo tail covered by the filter. A UI
+//! offering "tail length" and "latency" sliders has to translate between these and the
+//! power-of-two block sizes the FFT needs. [`Geometry::solve`] takes whatever subset of the
+//! parameters is known, derives the rest, and rounds towards the feasible side: block sizes
+//! go to powers of two, latencies are never exceeded, and tails are always covered.
+
+use crate::{FdafAecConfig, MdfConfig};
+use std::fmt;
+
+/// The parameters known in advance, as input to [`Geometry::solve`]. Unset parameters are
+/// derived.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GeometryConstraints {
+    /// The sample rate, in Hz.
+    pub sample_rate: Option<u32>,
+    /// The echo tail, in milliseconds, the filter must cover.
+    pub tail_ms: Option<f32>,
+    /// The block (frame) size, in samples. Must be a power of two.
+    pub block_size: Option<usize>,
+    /// The number of filter partitions, each one block long.
+    pub partitions: Option<usize>,
+    /// The largest acceptable frame latency, in milliseconds.
+    pub latency_ms: Option<f32>,
+}
+
+/// The reason a set of constraints has no solution.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeometryError {
+    /// Too few parameters are known to derive the others.
+    Underdetermined,
+    /// A parameter is zero, negative or not finite.
+    Invalid(&'static str),
+    /// The block size is not a power of two.
+    BlockSizeNotPowerOfTwo(usize),
+    /// The latency is shorter than one block at the given sample rate.
+    LatencyTooShort { latency_ms: f32, block_latency_ms: f32 },
+    /// The partitions do not cover the requested tail.
+    TailNotCovered { tail_ms: f32, covered_ms: f32 },
+}
+
+impl fmt::Display for GeometryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeometryError::Underdetermined => write!(f, "too few parameters to derive the geometry"),
+            GeometryError::Invalid(name) => write!(f, "{} must be positive", name),
+            GeometryError::BlockSizeNotPowerOfTwo(size) => write!(f, "block size {} is not a power of two", size),
+            GeometryError::LatencyTooShort { latency_ms, block_latency_ms } => {
+                write!(f, "latency of {:.2} ms is shorter than one block of {:.2} ms", latency_ms, block_latency_ms)
+            }
+            GeometryError::TailNotCovered { tail_ms, covered_ms } => {
+                write!(f, "tail of {:.2} ms exceeds the {:.2} ms covered by the partitions", tail_ms, covered_ms)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GeometryError {}
+
+/// A consistent set of canceller dimensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Geometry {
+    /// The sample rate, in Hz.
+    pub sample_rate: u32,
+    /// The block (frame) size, in samples, a power of two.
+    pub block_size: usize,
+    /// The number of filter partitions, each one block long.
+    pub partitions: usize,
+}
+
+impl Geometry {
+    /// Derives the missing parameters from the known ones.
+    ///
+    /// - The sample rate is taken as given, or derived from a block size and a latency, or
+    ///   from a block size, partitions and a tail.
+    /// - The block size is taken as given, or is the largest power of two within the
+    ///   latency, or the smallest power of two covering the tail with the given number of
+    ///   partitions (one if unset).
+    /// - The partitions are taken as given, or are as many as needed to cover the tail, or
+    ///   one.
+    ///
+    /// Parameters that are given but not needed for the derivation are validated against
+    /// the result.
+    pub fn solve(constraints: GeometryConstraints) -> Result<Geometry, GeometryError> {
+        let GeometryConstraints { sample_rate, tail_ms, block_size, partitions, latency_ms } = constraints;
+        let positive = |value: Option<f32>, name| match value {
+            Some(value) if !(value.is_finite() && value > 0.0) => Err(GeometryError::Invalid(name)),
+            _ => Ok(value),
+        };
+        let tail_ms = positive(tail_ms, "tail_ms")?;
+        let latency_ms = positive(latency_ms, "latency_ms")?;
+        if sample_rate == Some(0) {
+            return Err(GeometryError::Invalid("sample_rate"));
+        }
+        if partitions == Some(0) {
+            return Err(GeometryError::Invalid("partitions"));
+        }
+        if let Some(block_size) = block_size.filter(|size| !size.is_power_of_two()) {
+            return Err(GeometryError::BlockSizeNotPowerOfTwo(block_size));
+        }
+
+        let sample_rate = match (sample_rate, block_size, latency_ms, tail_ms, partitions) {
+            (Some(rate), ..) => rate,
+            (None, Some(block), Some(latency), ..) => (block as f32 * 1000.0 / latency).round() as u32,
+            (None, Some(block), None, Some(tail), Some(partitions)) => {
+                (block as f32 * partitions as f32 * 1000.0 / tail).round() as u32
+            }
+            _ => return Err(GeometryError::Underdetermined),
+        };
+        let samples = |ms: f32| ms * sample_rate as f32 / 1000.0;
+        let block_size = match (block_size, latency_ms, tail_ms) {
+            (Some(block), ..) => block,
+            (None, Some(latency), _) => {
+                let max_block = samples(latency).floor() as usize;
+                if max_block == 0 {
+                    return Err(GeometryError::LatencyTooShort { latency_ms: latency, block_latency_ms: 1000.0 / sample_rate as f32 });
+                }
+                // The largest power of two not above the limit.
+                1 << max_block.ilog2()
+            }
+            (None, None, Some(tail)) => {
+                let tail_samples = samples(tail).ceil() as usize;
+                tail_samples.div_ceil(partitions.unwrap_or(1)).max(1).next_power_of_two()
+            }
+            (None, None, None) => return Err(GeometryError::Underdetermined),
+        };
+        let partitions = partitions
+            .or_else(|| tail_ms.map(|tail| (samples(tail).ceil() as usize).div_ceil(block_size).max(1)))
+            .unwrap_or(1);
+
+        let geometry = Geometry { sample_rate, block_size, partitions };
+        if let Some(latency) = latency_ms.filter(|&latency| geometry.latency_ms() > latency * (1.0 + 1e-6)) {
+            return Err(GeometryError::LatencyTooShort { latency_ms: latency, block_latency_ms: geometry.latency_ms() });
+        }
+        if let Some(tail) = tail_ms.filter(|&tail| geometry.tail_samples() < samples(tail).ceil() as usize) {
+            return Err(GeometryError::TailNotCovered { tail_ms: tail, covered_ms: geometry.tail_ms() });
+        }
+        Ok(geometry)
+    }
+
+    /// Returns the FFT size, twice the block size.
+    pub fn fft_size(&self) -> usize {
+        2 * self.block_size
+    }
+
+    /// Returns the number of filter taps, covering the echo tail.
+    pub fn tail_samples(&self) -> usize {
+        self.block_size * self.partitions
+    }
+
+    /// Returns the echo tail covered by the filter, in milliseconds.
+    pub fn tail_ms(&self) -> f32 {
+        self.tail_samples() as f32 * 1000.0 / self.sample_rate as f32
+    }
+
+    /// Returns the latency of buffering one block, in milliseconds.
+    pub fn latency_ms(&self) -> f32 {
+        self.block_size as f32 * 1000.0 / self.sample_rate as f32
+    }
+
+    /// Returns the configuration of a single-partition [`FdafAec`](crate::FdafAec) with
+    /// this geometry, or `None` if the geometry has more than one partition.
+    pub fn fdaf_config(&self) -> Option<FdafAecConfig> {
+        (self.partitions == 1).then(|| FdafAecConfig {
+            fft_size: self.fft_size(),
+            sample_rate: self.sample_rate,
+            ..FdafAecConfig::default()
+        })
+    }
+
+    /// Returns the configuration of an [`MdfAec`](crate::MdfAec) with this geometry.
+    pub fn mdf_config(&self) -> MdfConfig {
+        MdfConfig {
+            frame_size: self.block_size,
+            partitions: self.partitions,
+            sample_rate: self.sample_rate,
+            ..MdfConfig::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_missing_parameters() {
+        // Tail and latency: the largest block within 10 ms, and enough partitions for 200 ms.
+        let geometry = Geometry::solve(GeometryConstraints {
+            sample_rate: Some(48000),
+            tail_ms: Some(200.0),
+            latency_ms: Some(10.0),
+            ..GeometryConstraints::default()
+        })
+        .unwrap();
+        assert_eq!(geometry, Geometry { sample_rate: 48000, block_size: 256, partitions: 38 });
+        assert!(geometry.latency_ms() <= 10.0 && geometry.tail_ms() >= 200.0);
+
+        // Block size and latency imply the sample rate.
+        let geometry = Geometry::solve(GeometryConstraints {
+            block_size: Some(512),
+            latency_ms: Some(32.0),
+            ..GeometryConstraints::default()
+        })
+        .unwrap();
+        assert_eq!((geometry.sample_rate, geometry.partitions), (16000, 1));
+        assert_eq!(geometry.fdaf_config().unwrap().fft_size, 1024);
+
+        // Infeasible or incomplete requests are rejected.
+        let too_short = GeometryConstraints {
+            sample_rate: Some(16000),
+            tail_ms: Some(100.0),
+            block_size: Some(256),
+            partitions: Some(2),
+            ..GeometryConstraints::default()
+        };
+        assert!(matches!(Geometry::solve(too_short), Err(GeometryError::TailNotCovered { .. })));
+        let odd = GeometryConstraints { sample_rate: Some(16000), block_size: Some(300), ..GeometryConstraints::default() };
+        assert_eq!(Geometry::solve(odd), Err(GeometryError::BlockSizeNotPowerOfTwo(300)));
+        let rate_only = GeometryConstraints { sample_rate: Some(16000), ..GeometryConstraints::default() };
+        assert_eq!(Geometry::solve(rate_only), Err(GeometryError::Underdetermined));
+    }
+}
@@ -0,0 +1,101 @@
+//! Exclusion of the DC, Nyquist and lowest bins from adaptation.
+//!
+//! The DC and Nyquist bins of the real-valued overlap-save FFT carry no phase information and
+//! mostly DC offsets and aliasing, so adapting them adds noise and offset artifacts to the
+//! output. Loudspeakers also reproduce almost nothing below about 100 Hz, which leaves the
+//! lowest bins with little echo but plenty of near-end rumble. These bins can be kept out
+//! of the weight update.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+
+/// The bins excluded from adaptation.
+///
+/// Nothing is excluded by default: with broadband far-end signals that reach DC and Nyquist,
+/// such as test noise, excluding bins caps the achievable echo attenuation. For speech
+/// through real loudspeakers, [`GuardBandConfig::speech`] is the better choice.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GuardBandConfig {
+    /// Keep the DC bin out of adaptation.
+    pub exclude_dc: bool,
+    /// Keep the Nyquist bin out of adaptation.
+    pub exclude_nyquist: bool,
+    /// Keep every bin whose center frequency is below this frequency, in Hz, out of
+    /// adaptation. 0.0 disables the guard band.
+    pub low_guard_hz: f32,
+}
+
+impl GuardBandConfig {
+    /// Excludes DC, Nyquist and everything below 80 Hz, where loudspeakers reproduce little
+    /// echo.
+    pub fn speech() -> Self {
+        Self { exclude_dc: true, exclude_nyquist: true, low_guard_hz: 80.0 }
+    }
+
+    /// Returns the FFT bins, including their mirror images, that must not adapt.
+    pub(crate) fn excluded_bins(&self, fft_size: usize, sample_rate: u32) -> Vec<usize> {
+        let bin_hz = sample_rate as f32 / fft_size as f32;
+        (0..fft_size)
+            .filter(|&k| {
+                let half_k = k.min(fft_size - k);
+                (half_k == 0 && self.exclude_dc)
+                    || (half_k == fft_size / 2 && self.exclude_nyquist)
+                    || (half_k as f32 * bin_hz) < self.low_guard_hz
+            })
+            .collect()
+    }
+}
+
+impl FdafAec {
+    /// Changes the bins excluded from adaptation.
+    pub fn set_guard_band(&mut self, config: GuardBandConfig) {
+        self.note_config_change();
+        self.guard_band = config;
+        self.guarded_bins = config.excluded_bins(self.fft_size, self.sample_rate);
+    }
+
+    /// Zeroes the guarded bins of a weight update.
+    pub(crate) fn exclude_guarded_bins(&self, gradient: &mut DVector<Complex<f32>>) {
+        for &k in &self.guarded_bins {
+            gradient[k] = Complex::new(0.0, 0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use crate::FdafAecConfig;
+
+    #[test]
+    fn excluded_bins_include_mirror_images() {
+        let config = GuardBandConfig { exclude_dc: true, exclude_nyquist: true, low_guard_hz: 100.0 };
+        assert_eq!(config.excluded_bins(256, 16000), [0, 1, 128, 255]);
+        assert!(GuardBandConfig::default().excluded_bins(256, 16000).is_empty());
+    }
+
+    #[test]
+    fn guarded_bins_do_not_adapt() {
+        let far = white_noise(256 * 400, 0.3, 40);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let guard_band = GuardBandConfig { low_guard_hz: 200.0, ..GuardBandConfig::speech() };
+        for constrained_update in [false, true] {
+            let config = FdafAecConfig { fft_size: 512, step_size: 0.5, guard_band, constrained_update, ..FdafAecConfig::default() };
+            let mut aec = FdafAec::with_config(config);
+            let output = process_all(&mut aec, &far, &mic);
+            for k in [0, 1, 6, 256, 506, 511] {
+                assert_eq!(aec.weights[k].norm(), 0.0, "bin {} adapted", k);
+            }
+            // Only the echo in the 13 guarded bins of 512 is left
+            let tail = output.len() - 5120..;
+            let erle_db = 10.0 * (crate::mean_square(&mic[tail.clone()]) / crate::mean_square(&output[tail])).log10();
+            assert!(erle_db > 12.0, "constrained: {}, ERLE {} dB", constrained_update, erle_db);
+            for k in [20, 50, 100] {
+                assert!((aec.weights[k].norm() - 0.5).abs() < 0.05, "bin {} is {}", k, aec.weights[k].norm());
+            }
+        }
+    }
+}
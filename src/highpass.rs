@@ -0,0 +1,138 @@
+//! DC-blocking high-pass pre-filter for the microphone and far-end inputs.
+//!
+//! DC offsets and subsonic rumble from cheap microphones carry a lot of energy that the
+//! echo path model cannot explain, which biases the adaptation. When enabled, both inputs
+//! pass through the same second-order Butterworth high-pass, so the echo path seen by the
+//! filter is unchanged. The far end is filtered after resampling to the capture rate, the
+//! playback path model, the bulk delay and drift compensation; the microphone after the
+//! hum notch and before the lookahead delay.
+
+use crate::FdafAec;
+use std::f32::consts::PI;
+
+/// Parameters of the input high-pass filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HighPassConfig {
+    /// The -3 dB cutoff frequency, in Hz.
+    pub cutoff_hz: f32,
+}
+
+impl Default for HighPassConfig {
+    fn default() -> Self {
+        Self { cutoff_hz: 80.0 }
+    }
+}
+
+/// A second-order Butterworth high-pass filter (transposed direct form II).
+#[derive(Debug, Clone)]
+pub struct HighPassFilter {
+    b: [f32; 3],
+    a: [f32; 2],
+    state: [f32; 2],
+}
+
+impl HighPassFilter {
+    /// Creates a filter for the given configuration at `sample_rate` Hz.
+    pub fn new(config: HighPassConfig, sample_rate: u32) -> Self {
+        assert!(
+            config.cutoff_hz > 0.0 && config.cutoff_hz < sample_rate as f32 / 2.0,
+            "High-pass cutoff must be between 0 and the Nyquist frequency."
+        );
+        let w0 = 2.0 * PI * config.cutoff_hz / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * std::f32::consts::FRAC_1_SQRT_2);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha;
+        let b0 = (1.0 + cos_w0) / 2.0 / a0;
+        Self {
+            b: [b0, -2.0 * b0, b0],
+            a: [-2.0 * cos_w0 / a0, (1.0 - alpha) / a0],
+            state: [0.0; 2],
+        }
+    }
+
+    /// Filters a block of samples in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for x in samples.iter_mut() {
+            let y = self.b[0] * *x + self.state[0];
+            self.state[0] = self.b[1] * *x - self.a[0] * y + self.state[1];
+            self.state[1] = self.b[2] * *x - self.a[1] * y;
+            *x = y;
+        }
+    }
+
+    /// Clears the filter state.
+    pub fn reset(&mut self) {
+        self.state = [0.0; 2];
+    }
+}
+
+/// The pair of filters applied to the canceller inputs.
+#[derive(Debug, Clone)]
+pub(crate) struct InputHighPass {
+    pub(crate) config: HighPassConfig,
+    pub(crate) far_end: HighPassFilter,
+    pub(crate) mic: HighPassFilter,
+}
+
+impl InputHighPass {
+    pub(crate) fn new(config: HighPassConfig, sample_rate: u32) -> Self {
+        let filter = HighPassFilter::new(config, sample_rate);
+        Self { config, far_end: filter.clone(), mic: filter }
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the input high-pass filter, designed
+    /// for the sample rate of the canceller.
+    ///
+    /// Reconfiguring resets the filter state.
+    pub fn set_high_pass(&mut self, config: Option<HighPassConfig>) {
+        self.note_config_change();
+        self.high_pass = config.map(|config| InputHighPass::new(config, self.sample_rate));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_dc_and_keeps_speech_band() {
+        let mut filter = HighPassFilter::new(HighPassConfig::default(), 16000);
+        let mut dc = vec![0.5; 16000];
+        filter.process(&mut dc);
+        assert!(dc[15000..].iter().all(|x| x.abs() < 1e-3));
+
+        let mut filter = HighPassFilter::new(HighPassConfig::default(), 16000);
+        let mut tone: Vec<f32> = (0..16000).map(|i| (2.0 * PI * 1000.0 * i as f32 / 16000.0).sin()).collect();
+        filter.process(&mut tone);
+        let peak = tone[8000..].iter().fold(0.0f32, |m, x| m.max(x.abs()));
+        assert!((peak - 1.0).abs() < 0.02, "1 kHz peak was {}", peak);
+    }
+
+    #[test]
+    fn canceller_output_has_no_dc_offset() {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_high_pass(Some(HighPassConfig::default()));
+        let far = vec![0.0; 256];
+        let mic = vec![0.2; 256];
+        let mut output = Vec::new();
+        for _ in 0..40 {
+            output = aec.process(&far, &mic);
+        }
+        assert!(output.iter().all(|x| x.abs() < 1e-3));
+    }
+
+    #[test]
+    fn follows_the_canceller_sample_rate() {
+        let designed = |rate| HighPassFilter::new(HighPassConfig::default(), rate).b;
+        let config = crate::FdafAecConfig { sample_rate: 48000, high_pass: Some(HighPassConfig::default()), ..crate::FdafAecConfig::default() };
+        let mut aec = FdafAec::with_config(config);
+        assert_eq!(aec.high_pass.as_ref().unwrap().mic.b, designed(48000));
+        aec.notify_sample_rate_change(16000).unwrap();
+        assert_eq!(aec.high_pass.as_ref().unwrap().far_end.b, designed(16000));
+        aec.set_high_pass(Some(HighPassConfig { cutoff_hz: 120.0 }));
+        assert_eq!(aec.high_pass.as_ref().unwrap().mic.b, HighPassFilter::new(HighPassConfig { cutoff_hz: 120.0 }, 16000).b);
+    }
+}
@@ -0,0 +1,276 @@
+//! Adaptive notch filter for mains hum on the microphone input.
+//!
+//! Ground loops and unshielded cables add hum at the mains frequency and its harmonics to
+//! the microphone signal. The hum is not in the far-end reference, so the filter tries to
+//! explain a strong, stationary low-frequency component it cannot model, which disturbs the
+//! adaptation of the low bins. When enabled, the microphone input is tracked by narrowband
+//! demodulators at the harmonics of both 50 Hz and 60 Hz. Once one of the two harmonic
+//! series clearly dominates the other, notch filters at its harmonics remove the hum before
+//! any other processing. Without detected hum the microphone passes unchanged.
+//!
+//! The notches of both series run on every frame, whether their output is used or not, so
+//! their state is always settled on the current input. When the detection changes, the
+//! output cross-fades from the previous path to the new one over a frame instead of
+//! switching to a filter that starts from rest or dropping the notches at once, either of
+//! which clicks.
+
+use crate::FdafAec;
+use num_complex::Complex;
+use std::f32::consts::PI;
+
+/// The candidate mains frequencies, in Hz.
+const MAINS_HZ: [f32; 2] = [50.0, 60.0];
+
+/// Parameters of the hum notch filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HumNotchConfig {
+    /// The number of harmonics to detect and notch, including the fundamental.
+    pub harmonics: usize,
+    /// The -3 dB bandwidth of each notch, in Hz.
+    pub bandwidth_hz: f32,
+    /// How much more power, as a ratio, one harmonic series must carry than the other for
+    /// hum to be detected.
+    pub detection_ratio: f32,
+    /// The time constant of the hum power estimates, in seconds.
+    pub time_constant_s: f32,
+}
+
+impl Default for HumNotchConfig {
+    fn default() -> Self {
+        Self { harmonics: 4, bandwidth_hz: 4.0, detection_ratio: 4.0, time_constant_s: 0.5 }
+    }
+}
+
+/// A second-order notch filter (transposed direct form II).
+#[derive(Debug, Clone)]
+struct Notch {
+    b: [f32; 3],
+    a: [f32; 2],
+    state: [f32; 2],
+}
+
+impl Notch {
+    fn new(frequency_hz: f32, bandwidth_hz: f32, sample_rate: u32) -> Self {
+        let w0 = 2.0 * PI * frequency_hz / sample_rate as f32;
+        let alpha = w0.sin() * bandwidth_hz / (2.0 * frequency_hz);
+        let a0 = 1.0 + alpha;
+        let b1 = -2.0 * w0.cos() / a0;
+        Self { b: [1.0 / a0, b1, 1.0 / a0], a: [b1, (1.0 - alpha) / a0], state: [0.0; 2] }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for x in samples.iter_mut() {
+            let y = self.b[0] * *x + self.state[0];
+            self.state[0] = self.b[1] * *x - self.a[0] * y + self.state[1];
+            self.state[1] = self.b[2] * *x - self.a[1] * y;
+            *x = y;
+        }
+    }
+}
+
+/// Tracks the complex amplitude of one frequency.
+#[derive(Debug, Clone)]
+struct Demodulator {
+    phasor: Complex<f32>,
+    rotation: Complex<f32>,
+    amplitude: Complex<f32>,
+}
+
+/// Detects hum at 50 Hz or 60 Hz and notches it out of the microphone input.
+#[derive(Debug, Clone)]
+pub(crate) struct HumNotch {
+    pub(crate) config: HumNotchConfig,
+    sample_rate: u32,
+    smoothing: f32,
+    /// The demodulators of every harmonic, per candidate.
+    demodulators: [Vec<Demodulator>; 2],
+    /// The candidate whose harmonics are notched, as an index into [`MAINS_HZ`].
+    detected: Option<usize>,
+    /// The notches at the harmonics below Nyquist, per candidate.
+    notches: [Vec<Notch>; 2],
+    /// The output of the notches of each candidate for the current frame.
+    filtered: [Vec<f32>; 2],
+}
+
+impl HumNotch {
+    pub(crate) fn new(config: HumNotchConfig, sample_rate: u32) -> Self {
+        assert!(config.harmonics > 0, "At least one harmonic must be notched.");
+        let demodulators = MAINS_HZ.map(|mains| {
+            (1..=config.harmonics)
+                .map(|k| {
+                    let w = 2.0 * PI * mains * k as f32 / sample_rate as f32;
+                    Demodulator {
+                        phasor: Complex::new(1.0, 0.0),
+                        rotation: Complex::from_polar(1.0, -w),
+                        amplitude: Complex::new(0.0, 0.0),
+                    }
+                })
+                .collect()
+        });
+        let notches = MAINS_HZ.map(|mains| {
+            (1..=config.harmonics)
+                .map(|k| mains * k as f32)
+                .filter(|&frequency| frequency < sample_rate as f32 / 2.0)
+                .map(|frequency| Notch::new(frequency, config.bandwidth_hz, sample_rate))
+                .collect()
+        });
+        Self {
+            config,
+            sample_rate,
+            smoothing: (-1.0 / (config.time_constant_s * sample_rate as f32)).exp(),
+            demodulators,
+            detected: None,
+            notches,
+            filtered: [Vec::new(), Vec::new()],
+        }
+    }
+
+    /// Returns the detected mains frequency, in Hz.
+    pub(crate) fn frequency(&self) -> Option<f32> {
+        self.detected.map(|candidate| MAINS_HZ[candidate])
+    }
+
+    /// Updates the detection with a microphone frame and removes the hum from it in place.
+    pub(crate) fn process(&mut self, mic: &mut [f32]) {
+        let a = self.smoothing;
+        let nyquist = self.sample_rate as f32 / 2.0;
+        let mut power = [0.0f32; 2];
+        for (candidate, demodulators) in self.demodulators.iter_mut().enumerate() {
+            for (k, demodulator) in demodulators.iter_mut().enumerate() {
+                for &x in mic.iter() {
+                    demodulator.amplitude = demodulator.amplitude * a + demodulator.phasor * (x * (1.0 - a));
+                    demodulator.phasor *= demodulator.rotation;
+                }
+                // Keep the phasor on the unit circle despite rounding errors.
+                demodulator.phasor /= demodulator.phasor.norm();
+                if MAINS_HZ[candidate] * ((k + 1) as f32) < nyquist {
+                    power[candidate] += demodulator.amplitude.norm_sqr();
+                }
+            }
+        }
+
+        let ratio = self.config.detection_ratio;
+        let detected = if power[0] > ratio * power[1] {
+            Some(0)
+        } else if power[1] > ratio * power[0] {
+            Some(1)
+        } else {
+            None
+        };
+        for (notches, filtered) in self.notches.iter_mut().zip(&mut self.filtered) {
+            filtered.clear();
+            filtered.extend_from_slice(mic);
+            for notch in notches {
+                notch.process(filtered);
+            }
+        }
+
+        let previous = std::mem::replace(&mut self.detected, detected);
+        let len = mic.len() as f32;
+        for (n, x) in mic.iter_mut().enumerate() {
+            let path = |candidate: Option<usize>| candidate.map_or(*x, |candidate| self.filtered[candidate][n]);
+            let fade = if previous == detected { 1.0 } else { (n + 1) as f32 / len };
+            *x = (1.0 - fade) * path(previous) + fade * path(detected);
+        }
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the hum notch filter on the
+    /// microphone input. Reconfiguring restarts the detection.
+    pub fn set_hum_notch(&mut self, config: Option<HumNotchConfig>) {
+        self.note_config_change();
+        self.hum_notch = config.map(|config| HumNotch::new(config, self.sample_rate));
+    }
+
+    /// Returns the mains frequency, in Hz, whose harmonics are currently notched out of the
+    /// microphone input, or `None` if no hum is detected or the notch filter is disabled.
+    pub fn hum_frequency(&self) -> Option<f32> {
+        self.hum_notch.as_ref().and_then(HumNotch::frequency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    /// Returns the power of `signal` at `frequency_hz`, sampled at 16 kHz.
+    fn tone_power(signal: &[f32], frequency_hz: f32) -> f32 {
+        let w = 2.0 * PI * frequency_hz / 16000.0;
+        let sum: Complex<f32> = signal.iter().enumerate().map(|(n, &x)| Complex::from_polar(x, -w * n as f32)).sum();
+        sum.norm_sqr() / (signal.len() * signal.len()) as f32
+    }
+
+    #[test]
+    fn detects_and_removes_mains_hum() {
+        let far = white_noise(256 * 250, 0.3, 65);
+        let echoed = echo(&far, &[(10, 0.5)]);
+        for mains in MAINS_HZ {
+            let mic: Vec<f32> = echoed
+                .iter()
+                .enumerate()
+                .map(|(n, &e)| {
+                    let t = n as f32 / 16000.0;
+                    e + (1..=3).map(|k| 0.05 / k as f32 * (2.0 * PI * mains * k as f32 * t).sin()).sum::<f32>()
+                })
+                .collect();
+            let run = |notch: bool| {
+                let mut aec = FdafAec::new(512, 0.2);
+                if notch {
+                    aec.set_hum_notch(Some(HumNotchConfig::default()));
+                }
+                let output = process_all(&mut aec, &far, &mic);
+                (output, aec.hum_frequency())
+            };
+            let (hummed, _) = run(false);
+            let (output, frequency) = run(true);
+            assert_eq!(frequency, Some(mains));
+
+            let tail = &output[output.len() - 16000..];
+            let hummed_tail = &hummed[hummed.len() - 16000..];
+            for k in 1..=3 {
+                let frequency = mains * k as f32;
+                assert!(tone_power(tail, frequency) < tone_power(hummed_tail, frequency) / 100.0, "{} Hz hum left", frequency);
+            }
+        }
+
+        // White noise alone is not mistaken for hum.
+        let mut aec = FdafAec::new(512, 0.2);
+        aec.set_hum_notch(Some(HumNotchConfig::default()));
+        process_all(&mut aec, &far, &echoed);
+        assert_eq!(aec.hum_frequency(), None);
+    }
+
+    #[test]
+    fn switches_without_clicks() {
+        // 50 Hz hum fades into 60 Hz hum between 2 s and 4 s, which moves the detection from
+        // one series to the other.
+        let mic: Vec<f32> = (0..16000 * 6)
+            .map(|n| {
+                let t = n as f32 / 16000.0;
+                let fade = ((t - 2.0) / 2.0).clamp(0.0, 1.0);
+                let hum = |mains: f32| (1..=3).map(|k| (2.0 * PI * mains * k as f32 * t).sin() / k as f32).sum::<f32>();
+                0.1 * ((1.0 - fade) * hum(50.0) + fade * hum(60.0))
+            })
+            .collect();
+        let mut notch = HumNotch::new(HumNotchConfig::default(), 16000);
+        let mut output = Vec::new();
+        let mut detections = Vec::new();
+        for frame in mic.chunks(256) {
+            let mut frame = frame.to_vec();
+            notch.process(&mut frame);
+            output.extend(frame);
+            if detections.last() != Some(&notch.frequency()) {
+                detections.push(notch.frequency());
+            }
+        }
+        assert_eq!(detections[..2], [None, Some(50.0)]);
+        assert_eq!(detections.last(), Some(&Some(60.0)));
+
+        // The output changes no faster than the hum itself.
+        let max_step = |signal: &[f32]| signal.windows(2).map(|pair| (pair[1] - pair[0]).abs()).fold(0.0f32, f32::max);
+        assert!(max_step(&output) < max_step(&mic), "{} >= {}", max_step(&output), max_step(&mic));
+    }
+}
@@ -0,0 +1,147 @@
+//! Convenience entry point for interleaved 16-bit duplex callbacks.
+//!
+//! Android and ALSA duplex callbacks typically hand over one interleaved `i16` buffer for
+//! capture and one for render, with a length chosen by the audio driver. [`InterleavedDuplex`]
+//! buffers these into canceller frames, runs one [`FdafAec`] per capture channel against the
+//! mono downmix of the render buffer, and writes the cancelled audio back in place.
+
+use crate::{FdafAec, FdafAecConfig};
+use std::collections::VecDeque;
+
+/// Scale between `i16` samples and the `[-1.0, 1.0]` float range used by the canceller.
+const I16_SCALE: f32 = 32768.0;
+
+/// Echo cancellation for interleaved `i16` capture and render buffers of any length.
+///
+/// Because callback buffers rarely match the canceller frame size, the output is delayed by
+/// one frame; see [`InterleavedDuplex::latency_samples`].
+pub struct InterleavedDuplex {
+    cancellers: Vec<FdafAec>,
+    render_channels: usize,
+    frame_size: usize,
+    far_end: Vec<f32>,
+    mic: Vec<Vec<f32>>,
+    output: Vec<VecDeque<f32>>,
+}
+
+impl InterleavedDuplex {
+    /// Creates a processor for the given channel layout.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: The configuration used for the canceller of every capture channel.
+    /// * `capture_channels`: The number of interleaved channels in the capture buffer.
+    /// * `render_channels`: The number of interleaved channels in the render buffer. They
+    ///   are averaged into a single far-end reference.
+    pub fn new(config: FdafAecConfig, capture_channels: usize, render_channels: usize) -> Self {
+        assert!(capture_channels > 0 && render_channels > 0, "Channel counts must be non-zero.");
+        assert!(
+            config.far_end_sample_rate.is_none_or(|rate| rate == config.sample_rate),
+            "Duplex callbacks must render and capture at the same rate."
+        );
+        let frame_size = config.fft_size / 2;
+        Self {
+            cancellers: (0..capture_channels).map(|_| FdafAec::with_config(config.clone())).collect(),
+            render_channels,
+            frame_size,
+            far_end: Vec::with_capacity(frame_size),
+            mic: vec![Vec::with_capacity(frame_size); capture_channels],
+            output: vec![std::iter::repeat_n(0.0, frame_size).collect(); capture_channels],
+        }
+    }
+
+    /// Returns the delay, in samples per channel, between the capture input and the output.
+    pub fn latency_samples(&self) -> usize {
+        self.frame_size + self.cancellers[0].latency_samples()
+    }
+
+    /// Returns the canceller of a capture channel, e.g. to query its state.
+    pub fn canceller(&self, capture_channel: usize) -> &FdafAec {
+        &self.cancellers[capture_channel]
+    }
+
+    /// Cancels echo in an interleaved capture buffer, in place.
+    ///
+    /// # Arguments
+    ///
+    /// * `capture`: The interleaved microphone samples. They are replaced by the cancelled output.
+    /// * `render`: The interleaved samples played out during the same callback. It must contain
+    ///   the same number of sample frames as `capture`.
+    pub fn process(&mut self, capture: &mut [i16], render: &[i16]) {
+        let capture_channels = self.cancellers.len();
+        assert_eq!(capture.len() % capture_channels, 0, "Capture buffer length must be a multiple of the channel count.");
+        assert_eq!(render.len() % self.render_channels, 0, "Render buffer length must be a multiple of the channel count.");
+        assert_eq!(
+            capture.len() / capture_channels,
+            render.len() / self.render_channels,
+            "Capture and render buffers must contain the same number of sample frames."
+        );
+
+        for (capture_frame, render_frame) in capture
+            .chunks_exact_mut(capture_channels)
+            .zip(render.chunks_exact(self.render_channels))
+        {
+            let far: f32 = render_frame.iter().map(|&s| s as f32).sum::<f32>() / self.render_channels as f32;
+            self.far_end.push(far / I16_SCALE);
+            for (mic, &sample) in self.mic.iter_mut().zip(capture_frame.iter()) {
+                mic.push(sample as f32 / I16_SCALE);
+            }
+
+            if self.far_end.len() == self.frame_size {
+                self.process_frame();
+            }
+
+            for (sample, output) in capture_frame.iter_mut().zip(self.output.iter_mut()) {
+                let value = output.pop_front().unwrap_or(0.0);
+                *sample = (value * I16_SCALE).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+    }
+
+    fn process_frame(&mut self) {
+        let channels = self.cancellers.iter_mut().zip(self.mic.iter_mut()).zip(self.output.iter_mut());
+        for ((aec, mic), output) in channels {
+            output.extend(aec.process(&self.far_end, mic));
+            mic.clear();
+        }
+        self.far_end.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    fn to_i16(signal: &[f32]) -> Vec<i16> {
+        signal.iter().map(|&x| (x * I16_SCALE) as i16).collect()
+    }
+
+    #[test]
+    fn cancels_every_capture_channel_with_odd_callback_sizes() {
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, ..FdafAecConfig::default() };
+        let mut duplex = InterleavedDuplex::new(config, 2, 1);
+        assert_eq!(duplex.latency_samples(), 256);
+
+        // Stereo capture with a different echo path on each channel.
+        let far = white_noise(160 * 300, 0.3, 8);
+        let echoes = [echo(&far, &[(6, 0.5)]), echo(&far, &[(20, -0.3), (40, 0.1)])];
+        let render = to_i16(&far);
+        let mut capture: Vec<i16> = to_i16(&echoes[0])
+            .into_iter()
+            .zip(to_i16(&echoes[1]))
+            .flat_map(|(left, right)| [left, right])
+            .collect();
+
+        for (capture_chunk, render_chunk) in capture.chunks_mut(2 * 160).zip(render.chunks(160)) {
+            duplex.process(capture_chunk, render_chunk);
+        }
+
+        let tail = &capture[capture.len() - 2 * 1600..];
+        for (channel, echo) in echoes.iter().enumerate() {
+            let residual = tail.iter().skip(channel).step_by(2).map(|&s| (s as f32).powi(2)).sum::<f32>();
+            let input = echo[echo.len() - 1600..].iter().map(|x| (x * I16_SCALE).powi(2)).sum::<f32>();
+            assert!(residual < input / 100.0, "channel {} was not cancelled", channel);
+        }
+    }
+}
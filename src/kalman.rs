@@ -0,0 +1,228 @@
+//! Diagonalized frequency-domain Kalman filter adaptation.
+//!
+//! NLMS adapts every bin with the same step size, normalized by the far-end power, so it
+//! either re-converges slowly after an echo path change or misadjusts heavily in steady
+//! state. The frequency-domain Kalman filter treats the weights as a state that drifts by a
+//! first-order Markov model, and keeps, per bin, an estimate of the state error variance and
+//! of the observation noise (near-end signal and residual echo). The Kalman gain of a bin is
+//! large while its state is uncertain and shrinks as it converges. The observation noise
+//! follows the floor of the error power, so a sudden rise of the error above that floor is
+//! read as state error: the gain grows again at once when the echo path moves. With the
+//! diagonal approximation the cost per frame is the same as NLMS.
+
+use crate::reset::ResetCrossfade;
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+
+/// The ratio of frame size to FFT size of the overlap-save scheme.
+const FRAME_RATIO: f32 = 0.5;
+/// Factor by which the observation noise estimate may rise per frame (about 0.2 dB).
+const NOISE_RISE: f32 = 1.05;
+
+/// How the filter weights are adapted.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AdaptationMode {
+    /// Normalized least mean squares with the configured step size.
+    #[default]
+    Nlms,
+    /// The diagonalized frequency-domain Kalman filter. The step size is not used; the
+    /// Kalman gain takes its place.
+    Kalman(KalmanConfig),
+}
+
+/// Parameters of the frequency-domain Kalman filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KalmanConfig {
+    /// The transition factor of the echo path model, slightly below 1.0. Smaller values
+    /// assume a faster moving echo path and keep the filter more agile.
+    pub transition: f32,
+    /// The smoothing factor of the per-bin error and far-end power estimates from which the
+    /// observation noise and the Kalman gain are derived.
+    pub noise_smoothing: f32,
+    /// The initial state error variance of every bin.
+    pub initial_state_error: f32,
+}
+
+impl Default for KalmanConfig {
+    fn default() -> Self {
+        Self { transition: 0.9999, noise_smoothing: 0.5, initial_state_error: 1.0 }
+    }
+}
+
+/// Per-bin state of the Kalman filter.
+#[derive(Debug, Clone)]
+pub(crate) struct KalmanState {
+    pub(crate) config: KalmanConfig,
+    state_error: Vec<f32>,
+    observation_noise: Vec<f32>,
+    error_power: Vec<f32>,
+    far_power: Vec<f32>,
+}
+
+impl KalmanState {
+    pub(crate) fn new(config: KalmanConfig, fft_size: usize) -> Self {
+        Self {
+            config,
+            state_error: vec![config.initial_state_error; fft_size],
+            observation_noise: vec![f32::MAX; fft_size],
+            error_power: vec![0.0; fft_size],
+            far_power: vec![0.0; fft_size],
+        }
+    }
+
+    /// Returns the Kalman step of every bin for the current frame, the factor that turns the
+    /// gradient `conj(X) * E` into the weight update, and advances the state error.
+    pub(crate) fn update(
+        &mut self,
+        x_f: &DVector<Complex<f32>>,
+        e_f: &DVector<Complex<f32>>,
+        weights: &DVector<Complex<f32>>,
+    ) -> Vec<f32> {
+        let a = self.config.transition;
+        let b = self.config.noise_smoothing;
+        (0..self.state_error.len())
+            .map(|k| {
+                let far_power = x_f[k].norm_sqr();
+                self.far_power[k] = b * self.far_power[k] + (1.0 - b) * far_power;
+                self.error_power[k] = b * self.error_power[k] + (1.0 - b) * e_f[k].norm_sqr();
+                // The observation noise follows dips of the error power at once and rises
+                // slowly, so that an error well above it is attributed to the state
+                self.observation_noise[k] = (self.observation_noise[k] * NOISE_RISE).min(self.error_power[k]);
+                let excess = (self.error_power[k] - 2.0 * self.observation_noise[k]).max(0.0) / FRAME_RATIO;
+                let p = self.state_error[k].max(excess / (self.far_power[k] + 1e-20));
+
+                let step = p / (p * self.far_power[k] + self.observation_noise[k] / FRAME_RATIO + 1e-20);
+                let process_noise = (1.0 - a * a) * weights[k].norm_sqr();
+                self.state_error[k] = a * a * (1.0 - FRAME_RATIO * step * self.far_power[k]) * p + process_noise;
+                step
+            })
+            .collect()
+    }
+
+    /// Returns the mean state error variance over all bins.
+    pub(crate) fn mean_state_error(&self) -> f32 {
+        self.state_error.iter().sum::<f32>() / self.state_error.len() as f32
+    }
+}
+
+impl FdafAec {
+    /// Returns the adaptation mode in use.
+    pub fn adaptation_mode(&self) -> AdaptationMode {
+        self.kalman.as_ref().map_or(AdaptationMode::Nlms, |kalman| AdaptationMode::Kalman(kalman.config))
+    }
+
+    /// Switches between the NLMS and the Kalman weight update mid-stream, e.g. to evaluate
+    /// one against the other during a call. The weights, the far-end buffer and PSD, and the
+    /// delay state carry over, so the new update continues from the current echo path
+    /// estimate; the Kalman state starts from [`KalmanConfig::initial_state_error`]. The
+    /// output switches immediately; see [`FdafAec::swap_adaptation_mode`] for a crossfade.
+    pub fn set_adaptation_mode(&mut self, mode: AdaptationMode) {
+        self.note_config_change();
+        self.kalman = match mode {
+            AdaptationMode::Nlms => None,
+            AdaptationMode::Kalman(config) => Some(KalmanState::new(config, self.fft_size)),
+        };
+    }
+
+    /// Switches the adaptation mode like [`FdafAec::set_adaptation_mode`], but keeps the
+    /// filter from before the swap running for `frames` frames and crossfades from its output
+    /// to the output of the new update, as [`FdafAec::reset_with_crossfade`] does. 0 frames
+    /// is a hard switch.
+    pub fn swap_adaptation_mode(&mut self, mode: AdaptationMode, frames: u32) {
+        let crossfade = ResetCrossfade::capture(self, frames);
+        self.set_adaptation_mode(mode);
+        self.reset_crossfade = crossfade;
+    }
+
+    /// Returns the mean per-bin state error variance of the Kalman filter, a measure of how
+    /// uncertain the echo path estimate is, or `None` in NLMS mode.
+    pub fn kalman_state_error(&self) -> Option<f32> {
+        self.kalman.as_ref().map(KalmanState::mean_state_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use crate::{FdafAec, FdafAecConfig};
+
+    #[test]
+    fn tracks_echo_path_change() {
+        let far = white_noise(256 * 300, 0.3, 56);
+        let before = echo(&far, &[(10, 0.5)]);
+        let after = echo(&far, &[(40, 0.4), (90, -0.2)]);
+        let noise = white_noise(far.len(), 0.003, 57);
+        let mic: Vec<f32> = before[..256 * 150]
+            .iter()
+            .chain(&after[256 * 150..])
+            .zip(&noise)
+            .map(|(e, n)| e + n)
+            .collect();
+
+        let run = |adaptation: AdaptationMode, step_size: f32| {
+            let config = FdafAecConfig { fft_size: 512, step_size, adaptation, ..FdafAecConfig::default() };
+            let mut aec = FdafAec::with_config(config);
+            let output = process_all(&mut aec, &far, &mic);
+            (output, aec)
+        };
+        let (fast, _) = run(AdaptationMode::Nlms, 0.5);
+        let (slow, _) = run(AdaptationMode::Nlms, 0.1);
+        let (kalman, aec) = run(AdaptationMode::Kalman(KalmanConfig::default()), 0.1);
+        assert!(matches!(aec.adaptation_mode(), AdaptationMode::Kalman(_)));
+
+        // Re-converges after the change faster than even the fast NLMS, and settles as low
+        // as the slow one.
+        let residual = |output: &[f32], frames: std::ops::Range<usize>| {
+            crate::mean_square(&output[frames.start * 256..frames.end * 256])
+        };
+        assert!(residual(&kalman, 155..175) < residual(&fast, 155..175) / 2.0);
+        assert!(residual(&kalman, 280..300) < residual(&slow, 280..300) * 2.0);
+    }
+
+    #[test]
+    fn swaps_engine_without_reconverging() {
+        let far = white_noise(256 * 200, 0.3, 58);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let run = |frames: Option<u32>| {
+            let mut aec = FdafAec::new(512, 0.5);
+            let mut output = Vec::new();
+            for (i, (f, m)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+                if i == 100 {
+                    let kalman = AdaptationMode::Kalman(KalmanConfig::default());
+                    match frames {
+                        Some(frames) => aec.swap_adaptation_mode(kalman, frames),
+                        None => aec.set_adaptation_mode(kalman),
+                    }
+                    // The weights carry over.
+                    assert!((aec.impulse_response()[10] - 0.5).abs() < 0.01);
+                }
+                output.extend(aec.process(f, m));
+            }
+            (output, aec)
+        };
+
+        let (hard, _) = run(None);
+        let (faded, aec) = run(Some(8));
+        assert!(matches!(aec.adaptation_mode(), AdaptationMode::Kalman(_)));
+        assert!(!aec.in_reset_crossfade());
+        // Neither swap lets the echo through, unlike starting the Kalman filter over.
+        let mut fresh = FdafAec::with_config(FdafAecConfig {
+            fft_size: 512,
+            adaptation: AdaptationMode::Kalman(KalmanConfig::default()),
+            ..FdafAecConfig::default()
+        });
+        let fresh = process_all(&mut fresh, &far[100 * 256..], &mic[100 * 256..]);
+        let after = 100 * 256..110 * 256;
+        for output in [&hard, &faded] {
+            let residual = crate::mean_square(&output[after.clone()]);
+            assert!(residual < crate::mean_square(&mic[after.clone()]) / 1000.0, "{}", residual);
+            assert!(residual < crate::mean_square(&fresh[..10 * 256]) / 100.0, "{}", residual);
+        }
+        let jump = (faded[100 * 256] - faded[100 * 256 - 1]).abs();
+        assert!(jump < 0.05, "jump {}", jump);
+    }
+}
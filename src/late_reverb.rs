@@ -0,0 +1,238 @@
+//! Statistical suppression of late reverberation beyond the filter length.
+//!
+//! In very reverberant rooms the echo keeps decaying long after the last tap of any
+//! practical filter, and this late echo is left in the output untouched. Its detailed shape
+//! is random, but its power follows the exponential decay of the room: every frame past the
+//! end of the filter carries the echo power of the frame before, attenuated by the decay
+//! over one frame. The suppressor models the late echo power per frequency bin with that
+//! recursion, driven by the power of the linear echo estimate, and removes it with a Wiener
+//! gain applied as a short causal filter, see the `gain_filter` module. The decay
+//! time is either configured or estimated from the energy envelope of the
+//! converged impulse response.
+
+use crate::gain_filter::{causal_response, filter_frame};
+use crate::{DuplexState, FdafAec};
+use num_complex::Complex;
+use rustfft::Fft;
+use std::sync::Arc;
+
+/// The number of frames between two decay time estimates.
+const ESTIMATE_INTERVAL: u32 = 50;
+/// The number of envelope blocks the impulse response is split into for the decay fit.
+const ENVELOPE_BLOCKS: usize = 16;
+/// The range of decay times the estimate is confined to, in seconds.
+const DECAY_RANGE_S: (f32, f32) = (0.02, 2.0);
+/// Smoothing factor applied to successive decay time estimates.
+const DECAY_SMOOTHING: f32 = 0.7;
+
+/// How the reverberation time of the room is obtained.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReverbDecay {
+    /// A known reverberation time (T60), in seconds.
+    Fixed { t60_s: f32 },
+    /// Estimated from the converged impulse response, starting from `initial_t60_s`.
+    Estimated { initial_t60_s: f32 },
+}
+
+/// Parameters of the late reverberation suppressor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LateReverbConfig {
+    /// The reverberation time used by the late echo model.
+    pub decay: ReverbDecay,
+    /// Smoothing factor of the output power spectrum.
+    pub smoothing: f32,
+    /// The factor applied to the late echo estimate before the Wiener gain is computed.
+    pub overestimation: f32,
+    /// Lower bound of the per-bin suppression gain.
+    pub min_gain: f32,
+}
+
+impl Default for LateReverbConfig {
+    fn default() -> Self {
+        Self { decay: ReverbDecay::Estimated { initial_t60_s: 0.3 }, smoothing: 0.7, overestimation: 1.0, min_gain: 0.1 }
+    }
+}
+
+/// A Wiener-gain suppressor of the echo beyond the filter length.
+#[derive(Debug, Clone)]
+pub(crate) struct LateReverbSuppressor {
+    pub(crate) config: LateReverbConfig,
+    frame_s: f32,
+    sample_rate: u32,
+    t60_s: f32,
+    frames_to_estimate: u32,
+    output_buffer: Vec<f32>,
+    echo_buffer: Vec<f32>,
+    output_power: Vec<f32>,
+    previous_echo_power: Vec<f32>,
+    late_power: Vec<f32>,
+}
+
+impl LateReverbSuppressor {
+    pub(crate) fn new(config: LateReverbConfig, fft_size: usize, sample_rate: u32) -> Self {
+        let t60_s = match config.decay {
+            ReverbDecay::Fixed { t60_s } | ReverbDecay::Estimated { initial_t60_s: t60_s } => t60_s,
+        };
+        assert!(t60_s > 0.0, "The reverberation time must be positive.");
+        let bins = fft_size / 2 + 1;
+        Self {
+            config,
+            frame_s: (fft_size / 2) as f32 / sample_rate as f32,
+            sample_rate,
+            t60_s,
+            frames_to_estimate: ESTIMATE_INTERVAL,
+            output_buffer: vec![0.0; fft_size],
+            echo_buffer: vec![0.0; fft_size],
+            output_power: vec![0.0; bins],
+            previous_echo_power: vec![0.0; bins],
+            late_power: vec![0.0; bins],
+        }
+    }
+
+    /// Returns whether the next frame should provide the impulse response for a decay
+    /// estimate. Only a converged filter during far-end single talk gives a reliable one.
+    pub(crate) fn wants_impulse_response(&mut self, reliable: bool) -> bool {
+        if !matches!(self.config.decay, ReverbDecay::Estimated { .. }) || !reliable {
+            return false;
+        }
+        self.frames_to_estimate = self.frames_to_estimate.saturating_sub(1);
+        self.frames_to_estimate == 0
+    }
+
+    /// Updates the decay time from the energy envelope of the impulse response.
+    pub(crate) fn estimate_decay(&mut self, impulse_response: &[f32]) {
+        self.frames_to_estimate = ESTIMATE_INTERVAL;
+        let block = (impulse_response.len() / ENVELOPE_BLOCKS).max(1);
+        let envelope: Vec<f32> = impulse_response.chunks(block).map(crate::mean_square).collect();
+        // Fit the decay from the strongest block on, past the direct path
+        let Some((peak, &peak_energy)) = envelope.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)) else {
+            return;
+        };
+        if peak_energy <= 0.0 || envelope.len() - peak < 3 {
+            return;
+        }
+        let points: Vec<(f32, f32)> = envelope[peak..]
+            .iter()
+            .enumerate()
+            .map(|(i, &energy)| ((i * block) as f32, 10.0 * (energy / peak_energy).max(1e-12).log10()))
+            .collect();
+        let n = points.len() as f32;
+        let (mean_t, mean_db) = points.iter().fold((0.0, 0.0), |(t, db), p| (t + p.0 / n, db + p.1 / n));
+        let covariance = points.iter().map(|(t, db)| (t - mean_t) * (db - mean_db)).sum::<f32>();
+        let variance = points.iter().map(|(t, _)| (t - mean_t).powi(2)).sum::<f32>();
+        let slope_db_per_sample = covariance / variance;
+        let t60_s = if slope_db_per_sample < 0.0 {
+            (-60.0 / slope_db_per_sample / self.sample_rate as f32).clamp(DECAY_RANGE_S.0, DECAY_RANGE_S.1)
+        } else {
+            DECAY_RANGE_S.1
+        };
+        self.t60_s = DECAY_SMOOTHING * self.t60_s + (1.0 - DECAY_SMOOTHING) * t60_s;
+    }
+
+    /// Returns the suppressed output frame.
+    ///
+    /// `output` is the frame about to be returned by the canceller and `echo` the echo
+    /// estimate the linear filter subtracted from it.
+    pub(crate) fn process(
+        &mut self,
+        fft: &Arc<dyn Fft<f32>>,
+        ifft: &Arc<dyn Fft<f32>>,
+        output: &[f32],
+        echo: &[f32],
+    ) -> Vec<f32> {
+        let frame_size = output.len();
+        let bins = self.output_power.len();
+        let spectrum = |buffer: &mut Vec<f32>, frame: &[f32]| {
+            buffer.copy_within(frame_size.., 0);
+            buffer[frame_size..].copy_from_slice(frame);
+            let mut spectrum: Vec<Complex<f32>> = buffer.iter().map(|&x| Complex::new(x, 0.0)).collect();
+            fft.process(&mut spectrum);
+            spectrum
+        };
+        let e_f = spectrum(&mut self.output_buffer, output);
+        let y_f = spectrum(&mut self.echo_buffer, echo);
+
+        // Power decays by 60 dB over the reverberation time
+        let decay = 10f32.powf(-6.0 * self.frame_s / self.t60_s);
+        let a = self.config.smoothing;
+        let mut gains = vec![1.0; bins];
+        for k in 0..bins {
+            self.output_power[k] = a * self.output_power[k] + (1.0 - a) * e_f[k].norm_sqr();
+            self.late_power[k] = decay * (self.late_power[k] + self.previous_echo_power[k]);
+            self.previous_echo_power[k] = y_f[k].norm_sqr();
+            let late = self.config.overestimation * self.late_power[k];
+            gains[k] = (1.0 - late / (self.output_power[k] + 1e-20)).clamp(self.config.min_gain, 1.0);
+        }
+        filter_frame(ifft, e_f, &causal_response(fft, ifft, &gains))
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the late reverberation suppressor.
+    pub fn set_late_reverb(&mut self, config: Option<LateReverbConfig>) {
+        self.note_config_change();
+        self.late_reverb = config.map(|config| LateReverbSuppressor::new(config, self.fft_size, self.sample_rate));
+    }
+
+    /// Returns the reverberation time, in seconds, the late reverberation suppressor is
+    /// currently using, or `None` if it is disabled.
+    pub fn late_reverb_t60_s(&self) -> Option<f32> {
+        self.late_reverb.as_ref().map(|late_reverb| late_reverb.t60_s)
+    }
+
+    /// Runs the late reverberation suppressor on an output frame, estimating the decay
+    /// time from the filter when due.
+    pub(crate) fn suppress_late_reverb(&mut self, output: Vec<f32>, echo: &[f32]) -> Vec<f32> {
+        if self.late_reverb.is_none() {
+            return output;
+        }
+        let reliable = self.quality.quality().converged && self.duplex.state() == DuplexState::FarEndOnly;
+        let impulse_response =
+            self.late_reverb.as_mut().is_some_and(|late_reverb| late_reverb.wants_impulse_response(reliable)).then(|| self.impulse_response());
+        match self.late_reverb.as_mut() {
+            Some(late_reverb) => {
+                if let Some(impulse_response) = impulse_response {
+                    late_reverb.estimate_decay(&impulse_response);
+                }
+                late_reverb.process(&self.fft, &self.ifft, &output, echo)
+            }
+            None => output,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{process_all, white_noise};
+
+    #[test]
+    fn suppresses_echo_beyond_the_filter() {
+        // A diffuse room response with a T60 of 0.15 s, ten times longer than the filter.
+        let t60_s = 0.15;
+        let taps = (t60_s * 16000.0) as usize;
+        let noise = white_noise(taps, 1.0, 93);
+        let room: Vec<f32> = noise.iter().enumerate().map(|(n, x)| 0.3 * x * 10f32.powf(-3.0 * n as f32 / taps as f32)).collect();
+        let far = white_noise(256 * 300, 0.3, 94);
+        let mic: Vec<f32> = (0..far.len())
+            .map(|i| room.iter().enumerate().take(i + 1).map(|(d, h)| h * far[i - d]).sum())
+            .collect();
+
+        let run = |late_reverb: Option<LateReverbConfig>| {
+            let mut aec = FdafAec::new(512, 0.5);
+            aec.set_late_reverb(late_reverb);
+            let output = process_all(&mut aec, &far, &mic);
+            (crate::mean_square(&output[250 * 256..]), aec.late_reverb_t60_s())
+        };
+        let (linear, _) = run(None);
+        let (fixed, _) = run(Some(LateReverbConfig { decay: ReverbDecay::Fixed { t60_s }, ..LateReverbConfig::default() }));
+        assert!(fixed < linear / 2.0, "fixed {} linear {}", fixed, linear);
+
+        let (estimated, t60) = run(Some(LateReverbConfig::default()));
+        let t60 = t60.unwrap();
+        assert!((t60 - t60_s).abs() < 0.05, "estimated t60 {}", t60);
+        assert!(estimated < linear / 2.0, "estimated {} linear {}", estimated, linear);
+    }
+}
@@ -0,0 +1,147 @@
+//! Validation of the total latency of a chain of processing stages.
+//!
+//! A capture path typically chains several stages (resampling, echo cancellation, further
+//! post-processing), each adding its own delay. [`LatencyBudget`] collects the latency every
+//! stage reports when the chain is built and checks the sum against a budget, so that a
+//! chain that is too slow fails early with a per-stage breakdown instead of being discovered
+//! in a call.
+
+use crate::{FdafAec, InterleavedDuplex};
+use std::fmt;
+use std::time::Duration;
+
+/// A processing stage that adds a known delay to the signal passing through it.
+pub trait LatencyStage {
+    /// Returns the delay between the stage's input and its output.
+    fn latency(&self) -> Duration;
+}
+
+impl LatencyStage for FdafAec {
+    fn latency(&self) -> Duration {
+        samples_to_duration(self.latency_samples(), self.sample_rate())
+    }
+}
+
+impl LatencyStage for InterleavedDuplex {
+    fn latency(&self) -> Duration {
+        samples_to_duration(self.latency_samples(), self.canceller(0).sample_rate())
+    }
+}
+
+/// Converts a number of samples at `sample_rate` to a duration.
+pub fn samples_to_duration(samples: usize, sample_rate: u32) -> Duration {
+    Duration::from_secs_f64(samples as f64 / sample_rate as f64)
+}
+
+/// The latency contributed by one stage of a chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageLatency {
+    /// The name given to the stage when it was added.
+    pub name: String,
+    /// The latency of the stage.
+    pub latency: Duration,
+}
+
+/// The per-stage latencies of a chain and their sum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyBreakdown {
+    /// The stages in the order they were added.
+    pub stages: Vec<StageLatency>,
+    /// The total latency of the chain.
+    pub total: Duration,
+}
+
+impl fmt::Display for LatencyBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for stage in &self.stages {
+            write!(f, "{}: {:.2} ms, ", stage.name, stage.latency.as_secs_f64() * 1000.0)?;
+        }
+        write!(f, "total: {:.2} ms", self.total.as_secs_f64() * 1000.0)
+    }
+}
+
+/// The error returned when a chain exceeds its latency budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyBudgetError {
+    /// The budget the chain was validated against.
+    pub budget: Duration,
+    /// The latency of every stage of the chain.
+    pub breakdown: LatencyBreakdown,
+}
+
+impl fmt::Display for LatencyBudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "latency exceeds budget of {:.2} ms ({})",
+            self.budget.as_secs_f64() * 1000.0,
+            self.breakdown
+        )
+    }
+}
+
+impl std::error::Error for LatencyBudgetError {}
+
+/// Collects the latencies of the stages of a chain and validates them against a budget.
+#[derive(Debug, Clone)]
+pub struct LatencyBudget {
+    budget: Duration,
+    stages: Vec<StageLatency>,
+}
+
+impl LatencyBudget {
+    /// Creates an empty chain with the given maximum total latency.
+    pub fn new(budget: Duration) -> Self {
+        Self { budget, stages: Vec::new() }
+    }
+
+    /// Adds a stage that reports its own latency.
+    pub fn stage(self, name: &str, stage: &impl LatencyStage) -> Self {
+        self.fixed(name, stage.latency())
+    }
+
+    /// Adds a stage with a known latency, e.g. an external resampler or the audio driver.
+    pub fn fixed(mut self, name: &str, latency: Duration) -> Self {
+        self.stages.push(StageLatency { name: name.to_string(), latency });
+        self
+    }
+
+    /// Returns the breakdown of the chain if its total latency is within the budget.
+    pub fn validate(self) -> Result<LatencyBreakdown, LatencyBudgetError> {
+        let total = self.stages.iter().map(|stage| stage.latency).sum();
+        let breakdown = LatencyBreakdown { stages: self.stages, total };
+        if total <= self.budget {
+            Ok(breakdown)
+        } else {
+            Err(LatencyBudgetError { budget: self.budget, breakdown })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FdafAecConfig;
+
+    #[test]
+    fn budget_reports_breakdown() {
+        let config = FdafAecConfig { fft_size: 512, far_end_lookahead: 32, ..FdafAecConfig::default() };
+        let aec = FdafAec::with_config(config.clone());
+        let duplex = InterleavedDuplex::new(config, 1, 2);
+        assert_eq!(aec.latency(), Duration::from_millis(2));
+        assert_eq!(duplex.latency(), Duration::from_millis(18));
+
+        let chain = LatencyBudget::new(Duration::from_millis(30))
+            .fixed("resampler", Duration::from_millis(5))
+            .stage("aec", &duplex);
+        let breakdown = chain.clone().validate().unwrap();
+        assert_eq!(breakdown.total, Duration::from_millis(23));
+
+        let error = chain.fixed("agc", Duration::from_millis(10)).validate().unwrap_err();
+        assert_eq!(error.breakdown.stages.len(), 3);
+        assert_eq!(
+            error.to_string(),
+            "latency exceeds budget of 30.00 ms (resampler: 5.00 ms, aec: 18.00 ms, agc: 10.00 ms, total: 33.00 ms)"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Measurement of echo leaking through the canceller, for automated integration tests.
+//!
+//! When a test rig plays a known near-end signal into the microphone together with the
+//! echo, everything in the output that is not the near-end signal is leaked echo (plus any
+//! distortion of the near-end). [`measure_echo_leak`] separates the two and reports the
+//! echo-to-near-end ratio, so device-farm tests can assert "no audible echo leak" with a
+//! number instead of by listening.
+
+/// The echo leak found in a cancelled signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EchoLeak {
+    /// The level of the leaked echo, in dBFS (mean square relative to a full-scale sine's
+    /// peak of 1.0).
+    pub leak_dbfs: f32,
+    /// The level of the near-end signal in the output, in dBFS.
+    pub near_end_dbfs: f32,
+    /// The leaked echo relative to the near-end signal, in dB. Lower is better.
+    pub echo_to_near_end_db: f32,
+}
+
+/// Measures the echo left in `output` given the clean near-end signal that was mixed into
+/// the microphone input.
+///
+/// `output` is delayed by `latency_samples` relative to `clean_near_end`, e.g.
+/// [`FdafAec::latency_samples`](crate::FdafAec::latency_samples). The near-end component is
+/// found with a least-squares gain, so a fixed level change of the near-end does not count
+/// as leak.
+pub fn measure_echo_leak(output: &[f32], clean_near_end: &[f32], latency_samples: usize) -> EchoLeak {
+    let output = output.get(latency_samples..).unwrap_or_default();
+    let len = output.len().min(clean_near_end.len());
+    let (output, near) = (&output[..len], &clean_near_end[..len]);
+
+    let near_power: f32 = near.iter().map(|x| x * x).sum();
+    let gain = if near_power > 0.0 {
+        output.iter().zip(near).map(|(o, n)| o * n).sum::<f32>() / near_power
+    } else {
+        0.0
+    };
+    let leak: Vec<f32> = output.iter().zip(near).map(|(o, n)| o - gain * n).collect();
+    let near_end: Vec<f32> = near.iter().map(|n| gain * n).collect();
+
+    let leak_dbfs = to_db(crate::mean_square(&leak));
+    let near_end_dbfs = to_db(crate::mean_square(&near_end));
+    EchoLeak { leak_dbfs, near_end_dbfs, echo_to_near_end_db: leak_dbfs - near_end_dbfs }
+}
+
+fn to_db(power: f32) -> f32 {
+    10.0 * power.max(1e-12).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use crate::FdafAec;
+
+    #[test]
+    fn leak_drops_once_converged() {
+        let mut aec = FdafAec::new(512, 0.1);
+        let far = white_noise(256 * 300, 0.3, 41);
+        let near = white_noise(256 * 300, 0.05, 42);
+        let mic: Vec<f32> = echo(&far, &[(10, 0.5)]).iter().zip(&near).map(|(e, n)| e + n).collect();
+        let output = process_all(&mut aec, &far, &mic);
+
+        let before = measure_echo_leak(&mic[..2560], &near[..2560], 0);
+        let after = measure_echo_leak(&output[66560..], &near[66560..], 0);
+        assert!(before.echo_to_near_end_db > 5.0);
+        assert!(after.echo_to_near_end_db < -10.0, "leak was {} dB", after.echo_to_near_end_db);
+        assert!((after.near_end_dbfs - to_db(0.05 * 0.05 / 3.0)).abs() < 0.5);
+    }
+}
@@ -0,0 +1,67 @@
+//! Leakage of the filter weights towards zero (leaky NLMS).
+//!
+//! The weight update is driven by the far-end, so while it is quiet for a long time the
+//! filter keeps whatever echo path it learned last. When the path has changed in the
+//! meantime, the stale weights produce a burst of wrong echo estimates before they adapt.
+//! Leakage multiplies the weights by `1 - leakage` on every adapted frame, so an echo path
+//! that is no longer reinforced by the update fades out. Leakage is suspended during the
+//! fast-start window, where it would only slow down the initial convergence.
+
+use crate::FdafAec;
+
+impl FdafAec {
+    /// Sets the fraction by which the weights shrink on every adapted frame. 0.0, the
+    /// default, disables leakage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `leakage` is not in `[0.0, 1.0)`.
+    pub fn set_weight_leakage(&mut self, leakage: f32) {
+        self.note_config_change();
+        assert!((0.0..1.0).contains(&leakage), "Weight leakage must be in [0, 1).");
+        self.weight_leakage = leakage;
+    }
+
+    /// Returns the fraction by which the weights shrink on every adapted frame.
+    pub fn weight_leakage(&self) -> f32 {
+        self.weight_leakage
+    }
+
+    /// Applies the leakage to the weights before the update of an adapted frame.
+    pub(crate) fn leak_weights(&mut self) {
+        if self.weight_leakage > 0.0 && !self.fast_start.is_active() {
+            self.weights *= num_complex::Complex::new(1.0 - self.weight_leakage, 0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{echo, white_noise};
+    use crate::FdafAec;
+
+    #[test]
+    fn stale_weights_fade_while_far_end_is_silent() {
+        let mut far = white_noise(256 * 250, 0.3, 84);
+        let mic = echo(&far, &[(10, 0.5)]);
+        far[150 * 256..].fill(0.0);
+        let run = |leakage: f32| {
+            let mut aec = FdafAec::new(512, 0.5);
+            aec.set_weight_leakage(leakage);
+            let mut taps = Vec::new();
+            for (f, m) in far.chunks(256).zip(mic.chunks(256)) {
+                aec.process(f, m);
+                taps.push(aec.impulse_response()[10]);
+            }
+            taps
+        };
+
+        let kept = run(0.0);
+        let leaky = run(0.01);
+        // Leakage barely affects the converged filter while the far-end talks...
+        assert!((kept[149] - 0.5).abs() < 0.01 && (leaky[149] - 0.5).abs() < 0.05, "{} {}", kept[149], leaky[149]);
+        // ...but lets it fade once it is no longer reinforced.
+        assert!((kept[249] - kept[149]).abs() < 1e-3);
+        assert!(leaky[249] < leaky[149] * 0.99f32.powi(90), "{} {}", leaky[149], leaky[249]);
+    }
+}
@@ -2,6 +2,237 @@ use nalgebra::DVector;
 use num_complex::Complex;
 use rustfft::{Fft, FftPlanner};
 use std::sync::Arc;
+use std::time::Instant;
+
+mod clock;
+pub mod adaptive_step;
+pub mod agc;
+pub mod audio_view;
+pub mod band_dtd;
+pub mod block;
+pub mod bulk_delay;
+pub mod clipping;
+pub mod config;
+mod constraint;
+mod convergence;
+pub mod comfort_noise;
+pub mod content;
+pub mod convolver;
+pub mod cpu_budget;
+pub mod crosstalk;
+pub mod delay;
+pub mod delay_line;
+pub mod diagnostics;
+pub mod dtd;
+pub mod drift;
+pub mod dual;
+pub mod duplex;
+pub mod duplex_processor;
+mod echo_level;
+pub mod echo_path;
+pub mod engines;
+pub mod factory;
+mod fast_start;
+mod fdaf;
+mod freeze;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod gain_clamp;
+mod gain_filter;
+pub mod geometry;
+pub mod guard_band;
+pub mod highpass;
+pub mod hum;
+pub mod interleaved;
+pub mod kalman;
+pub mod latency;
+pub mod leak;
+pub mod late_reverb;
+mod leakage;
+pub mod low_power;
+pub mod mdf;
+pub mod metrics;
+#[cfg(feature = "metrics-log")]
+pub mod metrics_log;
+pub mod multichannel;
+pub mod multimic;
+pub mod preset;
+pub mod nlp;
+pub mod nonlinear_echo;
+pub mod overlap_add;
+pub mod playback_path;
+pub mod post_filters;
+pub mod postfilter;
+pub mod precision;
+mod priming;
+pub mod profile;
+pub mod protection;
+pub mod quality;
+mod rate_change;
+pub mod reference;
+pub mod regularization;
+pub mod residual_ceiling;
+mod resample;
+mod reset;
+mod saturation;
+pub mod self_test;
+pub mod session;
+pub mod shadow;
+mod stability;
+pub mod state;
+pub mod step_control;
+pub mod step_profile;
+pub mod stereo;
+pub mod stream;
+pub mod tonality;
+mod transfer;
+pub mod tuning;
+pub mod vad;
+pub mod volume;
+pub mod watchdog;
+#[cfg(feature = "watermark")]
+pub mod watermark;
+pub mod webrtc;
+pub mod weights;
+
+pub use adaptive_step::AdaptiveStepConfig;
+use adaptive_step::AdaptiveStep;
+pub use agc::AgcConfig;
+use agc::Agc;
+pub use audio_view::{AudioView, AudioViewMut};
+pub use band_dtd::BandDoubleTalkConfig;
+use band_dtd::BandDoubleTalkDetector;
+pub use block::{BlockIo, BlockProcessor};
+pub use bulk_delay::BulkDelayConfig;
+use bulk_delay::BulkDelay;
+pub use clipping::{ClippingConfig, ClippingStats};
+use clipping::ClippingDetector;
+pub use comfort_noise::ComfortNoiseConfig;
+use comfort_noise::ComfortNoise;
+pub use config::{FdafAecConfig, ResolvedConfig};
+pub use content::ContentMode;
+use content::ContentModeState;
+pub use convergence::ConvergenceState;
+use convergence::ConvergenceTracker;
+pub use convolver::OlsConvolver;
+pub use cpu_budget::CpuBudget;
+pub use crosstalk::CrosstalkConfig;
+use crosstalk::CoherenceGate;
+pub use delay::{DelayEstimate, DelayEstimator, DelayEstimatorConfig};
+pub use delay_line::{DelayLine, PassthroughDelay};
+pub use diagnostics::HealthFlags;
+use diagnostics::InputDiagnostics;
+pub use drift::{DriftConfig, DriftResync, DriftResyncKind, DriftStats};
+use drift::DriftCompensator;
+pub use dual::DualOutput;
+pub use dtd::{CoherenceDtdConfig, GeigelConfig};
+use dtd::{CoherenceDetector, GeigelDetector};
+pub use duplex::DuplexState;
+pub use duplex_processor::DuplexProcessor;
+use duplex::DuplexDetector;
+use echo_level::EchoLevelTracker;
+pub use echo_path::{EchoPathPartition, EchoPathSnapshot};
+pub use factory::{ConfigError, EchoCancellerFactory};
+use fast_start::FastStart;
+#[cfg(feature = "fault-injection")]
+pub use fault::Fault;
+#[cfg(feature = "ffi")]
+pub use ffi::{FdafAecConfigC, FdafAecStatsC};
+pub use geometry::{Geometry, GeometryConstraints, GeometryError};
+pub use guard_band::GuardBandConfig;
+pub use highpass::{HighPassConfig, HighPassFilter};
+use highpass::InputHighPass;
+pub use hum::HumNotchConfig;
+use hum::HumNotch;
+pub use interleaved::InterleavedDuplex;
+pub use kalman::{AdaptationMode, KalmanConfig};
+use kalman::KalmanState;
+pub use late_reverb::{LateReverbConfig, ReverbDecay};
+use late_reverb::LateReverbSuppressor;
+pub use leak::{measure_echo_leak, EchoLeak};
+pub use low_power::LowPowerConfig;
+use low_power::LowPower;
+pub use latency::{LatencyBreakdown, LatencyBudget, LatencyBudgetError, LatencyStage, StageLatency};
+pub use mdf::{MdfAec, MdfConfig};
+pub use metrics::{DelayHistogram, DelayHistogramConfig, MetricsHandle, MetricsSnapshot};
+use metrics::{DelayHistogramTracker, SharedMetrics};
+#[cfg(feature = "metrics-log")]
+pub use metrics_log::{MetricsLogConfig, MetricsLogFormat, MetricsLogger};
+pub use multichannel::{MultiChannelAec, MultiChannelConfig, MultiChannelFrame};
+pub use multimic::{MultiMicAec, MultiMicConfig};
+pub use nlp::NlpLevel;
+pub use nonlinear_echo::NonlinearEchoConfig;
+use nonlinear_echo::NonlinearEcho;
+use nlp::NonlinearProcessor;
+pub use overlap_add::BlockMethod;
+use overlap_add::OverlapAdd;
+pub use playback_path::{PlaybackCompressor, PlaybackCompressorConfig};
+use playback_path::PlaybackPathModel;
+pub use postfilter::PostFilterConfig;
+use postfilter::PostFilter;
+pub use precision::{Precision, PrecisionConfig};
+use precision::PrecisionState;
+pub use preset::Preset;
+pub use profile::{DeviceProfile, ProfileError, ProfileStore};
+pub use protection::ConvergenceProtectionConfig;
+use protection::ConvergenceProtection;
+pub use quality::{AsrFrame, FrameQuality};
+use quality::QualityTracker;
+pub use reference::ReferenceId;
+use reference::ReferenceMixer;
+pub use rate_change::SampleRateChange;
+pub use regularization::{NormalizationConfig, RegularizationProfile};
+use regularization::Regularizer;
+pub use residual_ceiling::{ResidualCeilingConfig, ResidualCeilingStats};
+use residual_ceiling::{ResidualCeiling, ResidualEstimate};
+use resample::FarEndResampler;
+use reset::ResetCrossfade;
+use stability::StabilityTracker;
+pub use self_test::{SelfTestError, SelfTestReport};
+pub use session::{SessionEntry, SessionEvent, SessionLog};
+use session::SessionRecorder;
+pub use shadow::{ShadowConfig, ShadowHandle, ShadowReport, ShadowWindow};
+use shadow::ShadowEvaluation;
+pub use state::StateError;
+pub use step_control::FrameContext;
+use step_control::StepSizeController;
+pub use step_profile::StepProfile;
+use step_profile::BinStepSizes;
+pub use stereo::StereoCanceller;
+pub use stream::{PushStatus, StreamError, StreamLimits, StreamingAec};
+pub use tonality::TonalityConfig;
+use tonality::TonalityDetector;
+pub use vad::VadConfig;
+use vad::VoiceActivityDetector;
+pub use volume::VolumeRampConfig;
+use volume::VolumeRamp;
+pub use watchdog::{DeadlineStats, Overrun};
+use watchdog::DeadlineWatchdog;
+#[cfg(feature = "watermark")]
+pub use watermark::{WatermarkConfig, WatermarkDetector, WatermarkInjector, WatermarkMeasurement};
+pub use weights::{FilterWeights, WeightDomain, WeightValues, WeightsError};
+
+#[cfg(test)]
+mod test_util;
+
+/// Mean-square energies of the signals involved in one processed frame.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct FrameEnergies {
+    pub far_end: f32,
+    pub mic: f32,
+    pub echo_estimate: f32,
+    pub error: f32,
+}
+
+/// Returns the mean-square energy of a block of samples.
+pub(crate) fn mean_square<'a>(samples: impl IntoIterator<Item = &'a f32>) -> f32 {
+    let (sum, count) = samples
+        .into_iter()
+        .fold((0.0, 0usize), |(sum, count), &x| (sum + x * x, count + 1));
+    if count == 0 { 0.0 } else { sum / count as f32 }
+}
 
 /// Implements an Acoustic Echo Canceller using the Frequency Domain Adaptive Filter (FDAF)
 /// algorithm with the Overlap-Save method.
@@ -17,6 +248,65 @@ pub struct FdafAec {
     mu: f32,
     psd: DVector<f32>,
     smoothing_factor: f32,
+    quality: QualityTracker,
+    stability: StabilityTracker,
+    echo_level: EchoLevelTracker,
+    convergence: ConvergenceTracker,
+    references: ReferenceMixer,
+    duplex: DuplexDetector,
+    high_pass: Option<InputHighPass>,
+    hum_notch: Option<HumNotch>,
+    tonality: Option<TonalityDetector>,
+    content: ContentModeState,
+    crosstalk: Option<CoherenceGate>,
+    lookahead: Option<DelayLine>,
+    max_weight_update: Option<f32>,
+    weight_leakage: f32,
+    saturated_bins: usize,
+    watchdog: Option<DeadlineWatchdog>,
+    sample_rate: u32,
+    frames_processed: u64,
+    metrics: Arc<SharedMetrics>,
+    delay_histogram: Option<DelayHistogramTracker>,
+    regularizer: Regularizer,
+    normalization: NormalizationConfig,
+    precision: PrecisionState,
+    fast_start: FastStart,
+    band_dtd: Option<BandDoubleTalkDetector>,
+    diagnostics: Option<InputDiagnostics>,
+    far_end_resampler: Option<FarEndResampler>,
+    step_controller: Option<StepSizeController>,
+    playback_path: Option<PlaybackPathModel>,
+    max_echo_path_gain: Option<f32>,
+    echo_path_clamps: u64,
+    volume_ramp: Option<VolumeRamp>,
+    guard_band: GuardBandConfig,
+    guarded_bins: Vec<usize>,
+    protection: Option<ConvergenceProtection>,
+    constrained_update: bool,
+    overlap_add: Option<OverlapAdd>,
+    nonlinear_echo: Option<NonlinearEcho>,
+    geigel: Option<GeigelDetector>,
+    coherence_dtd: Option<CoherenceDetector>,
+    vad: Option<VoiceActivityDetector>,
+    adaptive_step: Option<AdaptiveStep>,
+    step_profile: BinStepSizes,
+    kalman: Option<KalmanState>,
+    late_reverb: Option<LateReverbSuppressor>,
+    post_filter: Option<PostFilter>,
+    nlp: Option<NonlinearProcessor>,
+    residual_ceiling: Option<ResidualCeiling>,
+    comfort_noise: Option<ComfortNoise>,
+    low_power: Option<LowPower>,
+    agc: Option<Agc>,
+    cpu_budget: CpuBudget,
+    clipping: Option<ClippingDetector>,
+    adaptation_enabled: bool,
+    reset_crossfade: Option<ResetCrossfade>,
+    session: Option<SessionRecorder>,
+    shadow: Option<ShadowEvaluation>,
+    bulk_delay: Option<BulkDelay>,
+    drift: Option<DriftCompensator>,
 }
 
 impl FdafAec {
@@ -32,31 +322,161 @@ impl FdafAec {
     ///   filter adapts. A larger value leads to faster convergence but can be less stable.
     ///   A typical value is between 0.1 and 1.0.
     pub fn new(fft_size: usize, step_size: f32) -> Self {
-        assert!(fft_size > 0 && fft_size.is_power_of_two(), "fft_size must be a power of two.");
-        let frame_size = fft_size / 2;
+        Self::with_config(FdafAecConfig { fft_size, step_size, ..FdafAecConfig::default() })
+    }
+
+    /// Creates a new `FdafAec` instance from a full configuration.
+    ///
+    /// See [`FdafAecConfig`] for the meaning of each parameter. To create many instances
+    /// from one configuration, see [`EchoCancellerFactory`].
+    pub fn with_config(config: FdafAecConfig) -> Self {
         let mut fft_planner = FftPlanner::new();
-        let fft = fft_planner.plan_fft_forward(fft_size);
-        let ifft = fft_planner.plan_fft_inverse(fft_size);
+        let fft = fft_planner.plan_fft_forward(config.fft_size);
+        let ifft = fft_planner.plan_fft_inverse(config.fft_size);
+        Self::with_ffts(config, fft, ifft)
+    }
 
+    /// Creates a new instance with FFTs planned for `config.fft_size`.
+    pub(crate) fn with_ffts(config: FdafAecConfig, fft: Arc<dyn Fft<f32>>, ifft: Arc<dyn Fft<f32>>) -> Self {
+        let fft_size = config.fft_size;
+        assert!(fft_size > 0 && fft_size.is_power_of_two(), "fft_size must be a power of two.");
+        assert!(config.far_end_lookahead < fft_size / 2, "far_end_lookahead must be shorter than the filter.");
+        assert!((0.0..1.0).contains(&config.weight_leakage), "weight_leakage must be in [0, 1).");
+        assert!(config.normalization.epsilon_for(&config.regularization, fft_size) > 0.0, "normalization.epsilon must be positive.");
+        let frame_size = fft_size / 2;
+        let far_end_resampler = config.far_end_sample_rate.filter(|&rate| rate != config.sample_rate).map(|rate| {
+            assert!(
+                (frame_size * rate as usize).is_multiple_of(config.sample_rate as usize),
+                "far_end_sample_rate must give a whole number of far-end samples per frame."
+            );
+            FarEndResampler::new(rate, config.sample_rate)
+        });
+        let weights = DVector::from_element(fft_size, Complex::new(0.0, 0.0));
+        let psd_initial = config.normalization.psd_initial.max(config.normalization.psd_floor);
+        let psd = DVector::from_element(fft_size, psd_initial);
+        let precision = PrecisionState::new(config.precision, &psd, &weights);
         Self {
             fft_size,
             frame_size,
             fft,
             ifft,
-            weights: DVector::from_element(fft_size, Complex::new(0.0, 0.0)),
+            weights,
             far_end_buffer: DVector::from_element(fft_size, 0.0),
-            mu: step_size,
-            psd: DVector::from_element(fft_size, 1.0), // Initialize with 1 to avoid division by zero
-            smoothing_factor: 0.98,
+            mu: config.step_size,
+            psd,
+            smoothing_factor: config.psd_smoothing,
+            quality: QualityTracker::new(),
+            stability: StabilityTracker::new(fft_size),
+            echo_level: EchoLevelTracker::default(),
+            convergence: ConvergenceTracker::default(),
+            references: ReferenceMixer::default(),
+            duplex: DuplexDetector::new(),
+            high_pass: config.high_pass.map(|high_pass| InputHighPass::new(high_pass, config.sample_rate)),
+            hum_notch: config.hum_notch.map(|hum_notch| HumNotch::new(hum_notch, config.sample_rate)),
+            tonality: config.tonality.map(|tonality| TonalityDetector::new(tonality, fft_size)),
+            content: ContentModeState::new(config.content_mode),
+            crosstalk: config.crosstalk.map(|crosstalk| CoherenceGate::new(crosstalk, fft_size)),
+            lookahead: (config.far_end_lookahead > 0).then(|| DelayLine::new(config.far_end_lookahead)),
+            max_weight_update: config.max_weight_update,
+            weight_leakage: config.weight_leakage,
+            saturated_bins: 0,
+            watchdog: config.deadline.map(DeadlineWatchdog::new),
+            sample_rate: config.sample_rate,
+            frames_processed: 0,
+            metrics: Arc::default(),
+            delay_histogram: None,
+            regularizer: config.normalization.regularizer(config.regularization, fft_size),
+            normalization: config.normalization,
+            precision,
+            fast_start: FastStart::new(config.fast_start_frames, config.fast_start_step_boost),
+            band_dtd: config.band_double_talk.map(|band_dtd| BandDoubleTalkDetector::new(band_dtd, fft_size)),
+            diagnostics: None,
+            far_end_resampler,
+            step_controller: None,
+            playback_path: None,
+            max_echo_path_gain: config.max_echo_path_gain,
+            echo_path_clamps: 0,
+            volume_ramp: config.volume_ramp.map(VolumeRamp::new),
+            guard_band: config.guard_band,
+            guarded_bins: config.guard_band.excluded_bins(fft_size, config.sample_rate),
+            protection: config.convergence_protection.map(|protection| ConvergenceProtection::new(protection, fft_size)),
+            constrained_update: config.constrained_update || config.block_method == BlockMethod::OverlapAdd,
+            overlap_add: (config.block_method == BlockMethod::OverlapAdd).then(|| OverlapAdd::new(frame_size)),
+            nonlinear_echo: config.nonlinear_echo.map(|nonlinear_echo| NonlinearEcho::new(nonlinear_echo, fft_size, psd_initial)),
+            geigel: config.geigel_dtd.map(GeigelDetector::new),
+            coherence_dtd: config.coherence_dtd.map(|dtd| CoherenceDetector::new(dtd, fft_size)),
+            vad: config.vad.map(|vad| VoiceActivityDetector::new(vad, fft_size, config.sample_rate)),
+            adaptive_step: config.adaptive_step.map(AdaptiveStep::new),
+            step_profile: BinStepSizes::new(config.step_profile, fft_size),
+            kalman: match config.adaptation {
+                AdaptationMode::Nlms => None,
+                AdaptationMode::Kalman(kalman) => Some(KalmanState::new(kalman, fft_size)),
+            },
+            late_reverb: config.late_reverb.map(|late_reverb| LateReverbSuppressor::new(late_reverb, fft_size, config.sample_rate)),
+            post_filter: config.post_filter.map(|post_filter| PostFilter::new(post_filter, fft_size)),
+            nlp: config.nlp.map(NonlinearProcessor::new),
+            residual_ceiling: config.residual_ceiling.map(ResidualCeiling::new),
+            comfort_noise: config.comfort_noise.map(ComfortNoise::new),
+            low_power: config.low_power.map(|low_power| LowPower::new(low_power, fft_size)),
+            agc: config.agc.map(|agc| Agc::new(agc, fft_size / 2, config.sample_rate)),
+            cpu_budget: config.cpu_budget,
+            clipping: config.clipping_detection.map(ClippingDetector::new),
+            adaptation_enabled: true,
+            reset_crossfade: None,
+            session: None,
+            shadow: None,
+            bulk_delay: config.bulk_delay.map(BulkDelay::new),
+            drift: config.drift_compensation.map(DriftCompensator::new),
         }
     }
 
+    /// Returns the delay, in samples, between a microphone sample and the corresponding sample
+    /// of the echo-cancelled output.
+    ///
+    /// This excludes the buffering the caller does to assemble frames of `fft_size / 2`
+    /// samples. Channels that bypass the canceller should be delayed by this amount, e.g.
+    /// with [`DelayLine::matching`], to stay in sync with the processed channels.
+    ///
+    /// The latency equals the configured far-end lookahead, see
+    /// [`FdafAecConfig::far_end_lookahead`].
+    pub fn latency_samples(&self) -> usize {
+        self.lookahead.as_ref().map_or(0, DelayLine::delay)
+    }
+
+    /// Changes the learning rate (mu) of the weight update.
+    pub fn set_step_size(&mut self, step_size: f32) {
+        self.note_config_change();
+        self.mu = step_size;
+    }
+
+    /// Returns the learning rate (mu) of the weight update.
+    pub fn step_size(&self) -> f32 {
+        self.mu
+    }
+
+    /// Returns the time-domain impulse response of the estimated echo path.
+    ///
+    /// The response has `fft_size / 2` taps, the span covered by the adaptive filter.
+    pub fn impulse_response(&self) -> Vec<f32> {
+        fdaf::impulse_response(&self.ifft, &self.weights)
+    }
+
+    /// Returns the delay, in samples, of the strongest tap of the estimated echo path.
+    pub(crate) fn dominant_tap(&self) -> usize {
+        self.impulse_response()
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            .map_or(0, |(i, _)| i)
+    }
+
     /// Processes a frame of audio data to remove echo.
     ///
     /// # Arguments
     ///
     /// * `far_end_frame`: A slice representing the audio frame from the far-end (the reference signal, e.g., loudspeaker).
-    ///   Its length must be `fft_size / 2`.
+    ///   Its length must be `fft_size / 2`, or [`FdafAec::far_end_frame_size`] if the far-end runs at a different
+    ///   sample rate.
     /// * `mic_frame`: A slice representing the audio frame from the near-end microphone, containing both the
     ///   near-end speaker's voice and the echo from the far-end. Its length must be `fft_size / 2`.
     ///
@@ -64,8 +484,64 @@ impl FdafAec {
     ///
     /// A `Vec<f32>` containing the echo-cancelled audio frame. The length of the vector is `fft_size / 2`.
     pub fn process(&mut self, far_end_frame: &[f32], mic_frame: &[f32]) -> Vec<f32> {
-        assert_eq!(far_end_frame.len(), self.frame_size, "Input far-end frame size must be half of FFT size.");
+        self.process_frame(far_end_frame, mic_frame).0
+    }
+
+    /// Processes a frame and returns the echo-cancelled signal and the component removed from
+    /// the (preprocessed) microphone signal.
+    pub(crate) fn process_frame(&mut self, far_end_frame: &[f32], mic_frame: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        assert_eq!(far_end_frame.len(), self.far_end_frame_size(), "Input far-end frame size must match the far-end frame size.");
         assert_eq!(mic_frame.len(), self.frame_size, "Input mic frame size must be half of FFT size.");
+        let started = self.watchdog.is_some().then(Instant::now);
+        self.record_session_changes();
+        self.detect_far_end_clipping(far_end_frame);
+        self.stage_shadow_frame(far_end_frame, mic_frame);
+
+        // Bring the far-end reference to the capture rate
+        let resampled_far_end = self.far_end_resampler.as_mut().map(|resampler| {
+            let mut far = vec![0.0; self.frame_size];
+            resampler.process(far_end_frame, &mut far);
+            far
+        });
+        let far_end_frame = resampled_far_end.as_deref().unwrap_or(far_end_frame);
+
+        // 0. Optional playback path model, bulk delay and drift compensation on the far-end,
+        //    hum notch on the mic, DC-blocking high-pass on both inputs, and mic delay for
+        //    far-end lookahead
+        let preprocess = self.playback_path.is_some()
+            || self.bulk_delay.is_some()
+            || self.drift.is_some()
+            || self.hum_notch.is_some()
+            || self.high_pass.is_some()
+            || self.lookahead.is_some();
+        let filtered_inputs = preprocess.then(|| {
+            let mut far = far_end_frame.to_vec();
+            let mut mic = mic_frame.to_vec();
+            if let Some(model) = self.playback_path.as_mut() {
+                model(&mut far);
+            }
+            if let Some(bulk_delay) = self.bulk_delay.as_mut() {
+                bulk_delay.process(&mut far, &mic);
+            }
+            if let Some(drift) = self.drift.as_mut() {
+                drift.process(&mut far, self.frames_processed);
+            }
+            if let Some(hum_notch) = self.hum_notch.as_mut() {
+                hum_notch.process(&mut mic);
+            }
+            if let Some(filters) = self.high_pass.as_mut() {
+                filters.far_end.process(&mut far);
+                filters.mic.process(&mut mic);
+            }
+            if let Some(lookahead) = self.lookahead.as_mut() {
+                lookahead.process(&mut mic);
+            }
+            (far, mic)
+        });
+        let (far_end_frame, mic_frame) = match &filtered_inputs {
+            Some((far, mic)) => (far.as_slice(), mic.as_slice()),
+            None => (far_end_frame, mic_frame),
+        };
 
         // 1. Update far-end buffer (shift old data, add new data)
         // This creates a rolling window of the last `fft_size` samples.
@@ -73,38 +549,37 @@ impl FdafAec {
         self.far_end_buffer
             .rows_mut(self.frame_size, self.frame_size)
             .copy_from_slice(far_end_frame);
+        if let Some(output) = self.process_low_power(far_end_frame, mic_frame, started) {
+            // Only monitor for echo while there has been none for a long time
+            return output;
+        }
 
         // 2. FFT of the far-end signal block
-        let mut x_t_buffer: Vec<Complex<f32>> = self
-            .far_end_buffer
-            .iter()
-            .map(|&x| Complex::new(x, 0.0))
-            .collect();
-        self.fft.process(&mut x_t_buffer);
-        let x_f = DVector::from_vec(x_t_buffer);
+        let x_f = fdaf::spectrum(&self.fft, self.far_end_buffer.as_slice());
 
         // 3. Update Power Spectral Density (PSD) of the far-end signal
-        for i in 0..self.fft_size {
-            let power = x_f[i].norm_sqr();
-            self.psd[i] = self.smoothing_factor * self.psd[i] + (1.0 - self.smoothing_factor) * power;
+        self.precision.smooth_psd(&mut self.psd, &x_f, self.smoothing_factor, self.normalization.psd_floor);
+        if let Some(tonality) = self.tonality.as_mut() {
+            tonality.update(&self.psd);
         }
+        self.content.observe(&self.psd, far_end_frame);
+        self.regularizer.update(&self.psd, x_f.iter().take(self.fft_size / 2 + 1).map(|c| c.norm_sqr()));
 
-        // 4. Estimate echo in frequency domain
-        let y_f = self.weights.component_mul(&x_f);
-
-        // 5. Inverse FFT of the estimated echo
-        let mut y_t_complex = y_f.as_slice().to_vec();
-        self.ifft.process(&mut y_t_complex);
+        let estimated_echo = match self.overlap_add.as_mut() {
+            // 4.-6. Filter the zero-padded frame and add the previous tail (Overlap-Add method)
+            Some(overlap_add) => overlap_add.estimate(&self.fft, &self.ifft, &self.weights, far_end_frame),
+            None => {
+                // 4. Estimate echo in frequency domain
+                let y_f = self.weights.component_mul(&x_f);
 
-        // IFFT normalization and extract real part
-        let fft_size_f32 = self.fft_size as f32;
-        let y_t: DVector<f32> = DVector::from_iterator(
-            self.fft_size,
-            y_t_complex.iter().map(|c| c.re / fft_size_f32),
-        );
+                // 5.-6. Inverse FFT of the estimated echo, keeping the valid part of the
+                //       convolution (Overlap-Save method)
+                fdaf::overlap_save(&self.ifft, &y_f)
+            }
+        };
 
-        // 6. Extract the valid part of the convolution (Overlap-Save method)
-        let estimated_echo = y_t.rows(self.frame_size, self.frame_size);
+        // Add the echo of the loudspeaker distortion
+        let estimated_echo = self.add_nonlinear_echo(far_end_frame, estimated_echo);
 
         // 7. Calculate the error signal (mic signal - estimated echo)
         let error_signal: Vec<f32> = mic_frame
@@ -112,28 +587,187 @@ impl FdafAec {
             .zip(estimated_echo.iter())
             .map(|(mic, echo)| mic - echo)
             .collect();
+        // Fade in the reset filter's output; it still adapts on its own error
+        let crossfaded = self.crossfade_reset(far_end_frame, mic_frame, &error_signal);
+        let linear_output = crossfaded.as_deref().unwrap_or(&error_signal);
+
+        let energies = FrameEnergies {
+            far_end: self.precision.mean_square(far_end_frame),
+            mic: self.precision.mean_square(mic_frame),
+            echo_estimate: self.precision.mean_square(estimated_echo.iter()),
+            error: self.precision.mean_square(&error_signal),
+        };
+        self.quality.update(&energies);
+        self.convergence.update(&energies, self.quality.quality().double_talk);
+        self.duplex.update(&energies, self.quality.erle());
+        let far_end_single_talk = self.duplex.state() == DuplexState::FarEndOnly;
+        self.echo_level.update(&energies, self.quality.quality().converged && far_end_single_talk);
+        if let Some(diagnostics) = self.diagnostics.as_mut() {
+            diagnostics.update(far_end_frame, mic_frame, &energies);
+        }
+        self.detect_voice_activity(&error_signal);
+        let output = match self.protection.as_mut() {
+            // Suppress coherent echo while the filter is still converging
+            Some(protection) => {
+                let erle_db = 10.0 * self.quality.erle().max(1e-10).log10();
+                protection.process(&self.fft, &self.ifft, &x_f, mic_frame, linear_output, erle_db)
+            }
+            None => linear_output.to_vec(),
+        };
+        let unsuppressed = self.comfort_noise.is_some().then(|| output.clone());
+        // Remove the late echo beyond the filter length
+        let output = self.suppress_late_reverb(output, estimated_echo.as_slice());
+        let mut output = match self.post_filter.as_mut() {
+            // Suppress the residual echo the linear filter leaves behind
+            Some(post_filter) => {
+                let echo: Vec<f32> = estimated_echo.iter().copied().collect();
+                post_filter.process(&self.fft, &self.ifft, &output, &echo, self.duplex.state())
+            }
+            None => output,
+        };
+        let (nlp_release, content) = (self.vad_nlp_release(), self.content_estimate());
+        if let Some(nlp) = self.nlp.as_mut() {
+            // Silence the residual echo while the far-end talks alone, backing off when the
+            // VAD hears near-end speech
+            let echo: Vec<f32> = estimated_echo.iter().copied().collect();
+            nlp.process(&mut output, &echo, self.duplex.state(), content, nlp_release);
+        }
+        if let Some(ceiling) = self.residual_ceiling.as_mut() {
+            // Deepen the suppression until the residual echo is far enough below near-end speech
+            let residual = ResidualEstimate { echo: energies.echo_estimate, error: energies.error, erle: self.quality.erle() };
+            ceiling.process(&mut output, residual, self.duplex.state());
+        }
+        if let (Some(comfort_noise), Some(unsuppressed)) = (self.comfort_noise.as_mut(), unsuppressed) {
+            // Fill what the suppressors removed so the output never drops to digital zero
+            comfort_noise.process(&mut output, &unsuppressed);
+        }
+        if let Some(agc) = self.agc.as_mut() {
+            // Bring the near-end voice to the target level
+            agc.process(&mut output, self.duplex.state());
+        }
+
+        if !self.adaptation_enabled || !self.cpu_budget.adapts(self.frames_processed) {
+            // Skip the weight update while adaptation is frozen or to save CPU
+            return self.finish_frame(energies.far_end, mic_frame, output, started);
+        }
 
         // 8. FFT of the error signal for weight update
         // The error signal is placed in the second half of the buffer (the first half
         // is zero-padded) to ensure correct time alignment for the gradient calculation.
-        let mut e_t_buffer = vec![Complex::new(0.0, 0.0); self.fft_size];
-        for (i, &sample) in error_signal.iter().enumerate() {
-            e_t_buffer[i + self.frame_size] = Complex::new(sample, 0.0);
-        }
-        
-        self.fft.process(&mut e_t_buffer);
-        let e_f = DVector::from_vec(e_t_buffer);
-        
+        let e_f = fdaf::padded_spectrum(&self.fft, &error_signal);
+
         // 9. Update filter weights using Normalized LMS algorithm
         let mut gradient = x_f.map(|c| c.conj()).component_mul(&e_f);
-        for i in 0..self.fft_size {
-            // Normalize by the PSD of the far-end signal
-            gradient[i] /= self.psd[i] + 1e-10; // Add a small epsilon for stability
+        match self.kalman.as_mut() {
+            // Scale by the per-bin Kalman step
+            Some(kalman) => {
+                for (g, step) in gradient.iter_mut().zip(kalman.update(&x_f, &e_f, &self.weights)) {
+                    *g *= step;
+                }
+            }
+            None => {
+                for i in 0..self.fft_size {
+                    // Normalize by the PSD of the far-end signal
+                    gradient[i] /= self.psd[i] + self.regularizer.values()[i]; // Add regularization for stability
+                }
+            }
+        }
+        if let Some(tonality) = &self.tonality {
+            // Slow down adaptation in bins dominated by a far-end tone
+            for (g, &scale) in gradient.iter_mut().zip(tonality.step_scale()) {
+                *g *= scale;
+            }
         }
-        self.weights += &gradient * Complex::new(self.mu, 0.0);
+        if let Some(crosstalk) = self.crosstalk.as_mut() {
+            // Keep near-end speech, which is incoherent with the far-end, out of the update
+            crosstalk.update(&x_f, &e_f);
+            for (g, &gate) in gradient.iter_mut().zip(crosstalk.gates()) {
+                *g *= gate;
+            }
+        }
+        // Spectrum of the estimated echo aligned like the error signal, for the detectors
+        // that compare it with the microphone
+        let echo_f: Option<Vec<Complex<f32>>> = (self.band_dtd.is_some() || self.coherence_dtd.is_some() || self.step_profile.is_adaptive())
+            .then(|| fdaf::padded_spectrum(&self.fft, estimated_echo.as_slice()).data.into());
+        if let (Some(band_dtd), Some(echo_f)) = (self.band_dtd.as_mut(), &echo_f) {
+            // Freeze adaptation only in the bands with near-end speech
+            band_dtd.update(&e_f, echo_f);
+            for (g, &gate) in gradient.iter_mut().zip(band_dtd.gates()) {
+                *g *= gate;
+            }
+        }
+        if let Some(echo_f) = &echo_f {
+            self.step_profile.update(echo_f, e_f.as_slice());
+        }
+        // Scale the step size per bin
+        self.step_profile.apply(gradient.as_mut_slice());
+        // Keep the DC, Nyquist and guard band bins out of the update
+        self.exclude_guarded_bins(&mut gradient);
+        if self.constrained_update {
+            // Keep circular-convolution wrap-around out of the filter. The constraint spreads
+            // the gradient into the guarded bins again, where nothing pulls it back, so they
+            // are cleared once more.
+            constraint::constrain_gradient(&self.fft, &self.ifft, &mut gradient, self.frame_size);
+            self.exclude_guarded_bins(&mut gradient);
+        }
+        let volume_boost = self.volume_ramp.as_mut().map_or(1.0, |ramp| ramp.update(energies.far_end));
+        let geigel_scale = match self.geigel.as_mut() {
+            // Freeze or slow adaptation while the microphone peak betrays near-end speech
+            Some(geigel) => geigel.update(self.far_end_buffer.as_slice(), mic_frame),
+            None => 1.0,
+        };
+        let coherence_scale = match (self.coherence_dtd.as_mut(), &echo_f) {
+            // Slow adaptation by the probability of near-end speech
+            (Some(detector), Some(echo_f)) => {
+                let mic_f: Vec<Complex<f32>> = e_f.iter().zip(echo_f).map(|(e, y)| e + y).collect();
+                detector.update(&mic_f, echo_f)
+            }
+            _ => 1.0,
+        };
+        // Adapt fast on a clean echo residual and slowly when near-end activity is likely
+        let eer_scale = self.adaptive_step.as_mut().map_or(1.0, |step| step.update(energies.echo_estimate, energies.error));
+        let base_step = if self.kalman.is_some() { 1.0 } else { self.mu };
+        let clipping_scale = self.clipping_step_scale();
+        let vad_scale = self.vad_step_scale();
+        let mu = base_step * self.content.update() * self.fast_start.update() * volume_boost * geigel_scale * coherence_scale * eer_scale * clipping_scale * vad_scale;
+        let mu = self.controlled_step_size(mu, energies.far_end);
+        self.adapt_nonlinear_echo(&e_f, mu);
+        let mut update = gradient * Complex::new(mu, 0.0);
+        self.saturated_bins = match self.max_weight_update {
+            Some(bound) => saturation::saturate_update(&mut update, bound),
+            None => 0,
+        };
+        self.leak_weights();
+        self.precision.accumulate(&mut self.weights, update);
+        self.clamp_echo_path_gain();
+        self.stability.update(&self.weights);
+        self.update_low_power_detection();
+        self.update_drift();
 
-        // 10. Return the echo-cancelled (error) signal
-        error_signal
+        self.finish_frame(energies.far_end, mic_frame, output, started)
+    }
+
+    /// Updates the statistics at the end of a frame and returns the echo-cancelled signal and
+    /// what was removed from the microphone.
+    fn finish_frame(&mut self, far_end_energy: f32, mic_frame: &[f32], output: Vec<f32>, started: Option<Instant>) -> (Vec<f32>, Vec<f32>) {
+        self.update_delay_histogram(far_end_energy);
+        self.frames_processed += 1;
+        self.metrics.publish(&MetricsSnapshot {
+            frames: self.frames_processed,
+            erle_db: 10.0 * self.quality.erle().max(1e-10).log10(),
+            quality: self.quality.quality(),
+            duplex_state: self.duplex.state(),
+        });
+
+        self.feed_shadow(&output);
+
+        if let (Some(watchdog), Some(started)) = (self.watchdog.as_mut(), started) {
+            watchdog.record(self.frames_processed - 1, started.elapsed());
+        }
+
+        // 10. Return the echo-cancelled signal and what was removed from the microphone
+        let removed = mic_frame.iter().zip(&output).map(|(mic, out)| mic - out).collect();
+        (output, removed)
     }
 }
 
@@ -161,6 +795,23 @@ mod tests {
         assert!(error_signal.iter().all(|&x| x.is_finite()), "Output contains NaN or Infinity");
     }
 
+    #[test]
+    fn lookahead_models_non_causal_echo() {
+        use crate::test_util::{process_all, white_noise};
+
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, far_end_lookahead: 16, ..FdafAecConfig::default() };
+        let mut aec = FdafAec::with_config(config);
+        assert_eq!(aec.latency_samples(), 16);
+
+        // The echo reaches the microphone 5 samples before the far-end reference is delivered.
+        let signal = white_noise(256 * 100, 0.3, 15);
+        let far: Vec<f32> = std::iter::repeat_n(0.0, 5).chain(signal.iter().copied()).take(signal.len()).collect();
+        let mic: Vec<f32> = signal.iter().map(|x| 0.5 * x).collect();
+        process_all(&mut aec, &far, &mic);
+        let ir = aec.impulse_response();
+        assert!((ir[11] - 0.5).abs() < 0.05, "estimated tap was {}", ir[11]);
+    }
+
     #[test]
     #[should_panic]
     fn test_new_with_non_power_of_two_fft_size() {
@@ -0,0 +1,229 @@
+//! Low-power monitoring while there is no acoustic echo.
+//!
+//! With a headset, or with the loudspeaker muted, the microphone never picks up the far-end
+//! and the adaptive filter converges to zero. Running the full filter then only costs CPU.
+//! When enabled, the canceller watches for a long stretch of far-end activity during which
+//! the estimated echo path stays negligible and then switches to a monitoring mode: the
+//! microphone is passed through with only the AGC applied, the filter is neither applied
+//! nor adapted, and every few frames the coherence between the far-end and the microphone
+//! is measured with two FFTs. As soon as the microphone becomes coherent with the far-end
+//! again, e.g. because the headset was unplugged, full processing resumes with the next
+//! frame.
+
+use crate::{DuplexState, FdafAec, FrameEnergies};
+use nalgebra::DVector;
+use num_complex::Complex;
+use std::time::Instant;
+
+/// Parameters of the echo-free detection and the low-power monitoring mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LowPowerConfig {
+    /// The echo path gain, see [`FdafAec::echo_path_gain`], below which the echo counts as
+    /// negligible.
+    pub gain_threshold: f32,
+    /// The number of consecutive frames with an active far-end and a negligible echo path
+    /// after which the canceller enters low-power mode.
+    pub detection_frames: u32,
+    /// While in low-power mode, the coherence is measured every this many frames.
+    pub probe_interval: u32,
+    /// Smoothing factor, across probes, of the spectra used to estimate the coherence. The
+    /// first `1 / (1 - smoothing)` probes only fill the estimate, as a coherence estimated
+    /// from few probes is biased towards one.
+    pub smoothing: f32,
+    /// The far-end weighted mean coherence above which full processing resumes.
+    pub resume_coherence: f32,
+}
+
+impl Default for LowPowerConfig {
+    fn default() -> Self {
+        Self { gain_threshold: 0.01, detection_frames: 300, probe_interval: 4, smoothing: 0.9, resume_coherence: 0.3 }
+    }
+}
+
+/// Detects the absence of echo and monitors for its return.
+#[derive(Debug, Clone)]
+pub(crate) struct LowPower {
+    pub(crate) config: LowPowerConfig,
+    pub(crate) active: bool,
+    quiet_frames: u32,
+    frames_to_probe: u32,
+    probes: u32,
+    mic_buffer: Vec<f32>,
+    cross: DVector<Complex<f32>>,
+    far_power: DVector<f32>,
+    mic_power: DVector<f32>,
+    coherence: f32,
+}
+
+impl LowPower {
+    pub(crate) fn new(config: LowPowerConfig, fft_size: usize) -> Self {
+        assert!(config.probe_interval > 0, "probe_interval must be at least one frame.");
+        Self {
+            config,
+            active: false,
+            quiet_frames: 0,
+            frames_to_probe: config.probe_interval,
+            probes: 0,
+            mic_buffer: vec![0.0; fft_size],
+            cross: DVector::from_element(fft_size, Complex::new(0.0, 0.0)),
+            far_power: DVector::from_element(fft_size, 0.0),
+            mic_power: DVector::from_element(fft_size, 0.0),
+            coherence: 0.0,
+        }
+    }
+
+    /// Counts the frames without echo after a fully processed frame, and switches to
+    /// low-power mode once there have been enough of them.
+    fn update_detection(&mut self, far_end_active: bool, echo_path_gain: f32) {
+        if echo_path_gain >= self.config.gain_threshold {
+            self.quiet_frames = 0;
+        } else if far_end_active {
+            self.quiet_frames += 1;
+        }
+        if self.quiet_frames >= self.config.detection_frames {
+            self.active = true;
+            self.quiet_frames = 0;
+            self.frames_to_probe = self.config.probe_interval;
+            self.probes = 0;
+            self.cross.fill(Complex::new(0.0, 0.0));
+            self.far_power.fill(0.0);
+            self.mic_power.fill(0.0);
+            self.coherence = 0.0;
+        }
+    }
+
+    /// Buffers a microphone frame and, on probe frames, measures the coherence between the
+    /// far-end spectrum `x_f` and the spectrum `mic_f` of the buffered microphone signal.
+    /// Returns whether echo has reappeared.
+    fn monitor(
+        &mut self,
+        mic_frame: &[f32],
+        x_f: impl FnOnce() -> Vec<Complex<f32>>,
+        mic_f: impl FnOnce(&[f32]) -> Vec<Complex<f32>>,
+    ) -> bool {
+        let frame_size = mic_frame.len();
+        self.mic_buffer.copy_within(frame_size.., 0);
+        self.mic_buffer[frame_size..].copy_from_slice(mic_frame);
+        self.frames_to_probe -= 1;
+        if self.frames_to_probe > 0 {
+            return false;
+        }
+        self.frames_to_probe = self.config.probe_interval;
+
+        let (x_f, d_f) = (x_f(), mic_f(&self.mic_buffer));
+        let a = self.config.smoothing;
+        let (mut coherent, mut total) = (0.0, 0.0);
+        for (i, (x, d)) in x_f.iter().zip(&d_f).enumerate() {
+            self.cross[i] = self.cross[i] * a + x.conj() * d * (1.0 - a);
+            self.far_power[i] = a * self.far_power[i] + (1.0 - a) * x.norm_sqr();
+            self.mic_power[i] = a * self.mic_power[i] + (1.0 - a) * d.norm_sqr();
+            // Weighting the coherence of every bin by its far-end power keeps bins the far-end
+            // does not excite from deciding.
+            coherent += self.cross[i].norm_sqr() / (self.mic_power[i] + 1e-20);
+            total += self.far_power[i];
+        }
+        self.coherence = if total > 0.0 { coherent / total } else { 0.0 };
+        self.probes += 1;
+        let settled = self.probes as f32 * (1.0 - a) >= 1.0;
+        if settled && self.coherence > self.config.resume_coherence {
+            self.active = false;
+        }
+        !self.active
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the automatic low-power mode.
+    /// Reconfiguring resumes full processing and restarts the detection.
+    pub fn set_low_power(&mut self, config: Option<LowPowerConfig>) {
+        self.note_config_change();
+        self.low_power = config.map(|config| LowPower::new(config, self.fft_size));
+    }
+
+    /// Returns whether the canceller is in low-power mode, passing the microphone through
+    /// while it only monitors for echo.
+    pub fn is_low_power(&self) -> bool {
+        self.low_power.as_ref().is_some_and(|low_power| low_power.active)
+    }
+
+    /// Updates the echo-free detection after a fully processed frame.
+    pub(crate) fn update_low_power_detection(&mut self) {
+        let far_end_active = matches!(self.duplex.state(), DuplexState::FarEndOnly | DuplexState::DoubleTalk);
+        let echo_path_gain = self.echo_path_gain();
+        if let Some(low_power) = self.low_power.as_mut() {
+            low_power.update_detection(far_end_active, echo_path_gain);
+        }
+    }
+
+    /// Processes a frame in low-power mode, after the far-end buffer has been updated.
+    ///
+    /// Returns the output and the removed component, or `None` if the frame needs full
+    /// processing because low-power mode is off or echo has just reappeared.
+    pub(crate) fn process_low_power(
+        &mut self,
+        far_end_frame: &[f32],
+        mic_frame: &[f32],
+        started: Option<Instant>,
+    ) -> Option<(Vec<f32>, Vec<f32>)> {
+        let low_power = self.low_power.as_mut().filter(|low_power| low_power.active)?;
+        let (fft, far_end_buffer) = (&self.fft, &self.far_end_buffer);
+        let spectrum = |samples: &[f32]| {
+            let mut buffer: Vec<Complex<f32>> = samples.iter().map(|&x| Complex::new(x, 0.0)).collect();
+            fft.process(&mut buffer);
+            buffer
+        };
+        if low_power.monitor(mic_frame, || spectrum(far_end_buffer.as_slice()), spectrum) {
+            return None;
+        }
+
+        let energies = FrameEnergies {
+            far_end: crate::mean_square(far_end_frame),
+            mic: crate::mean_square(mic_frame),
+            echo_estimate: 0.0,
+            error: crate::mean_square(mic_frame),
+        };
+        self.quality.update(&energies);
+        self.duplex.update(&energies, self.quality.erle());
+        let mut output = mic_frame.to_vec();
+        if let Some(agc) = self.agc.as_mut() {
+            agc.process(&mut output, self.duplex.state());
+        }
+        Some(self.finish_frame(energies.far_end, mic_frame, output, started))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn idles_without_echo_and_resumes_when_it_returns() {
+        let far = white_noise(256 * 300, 0.3, 66);
+        // A headset: the microphone hears only the near-end room noise.
+        let mut mic = white_noise(256 * 300, 0.001, 67);
+        let unplugged = 150 * 256;
+        let speaker = echo(&far, &[(10, 0.5)]);
+        mic[unplugged..].copy_from_slice(&speaker[unplugged..]);
+
+        let mut aec = FdafAec::new(512, 0.2);
+        aec.set_low_power(Some(LowPowerConfig { detection_frames: 50, ..LowPowerConfig::default() }));
+        let mut resumed = None;
+        for (i, (far_frame, mic_frame)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+            let output = aec.process(far_frame, mic_frame);
+            if (100..150).contains(&i) {
+                assert!(aec.is_low_power(), "frame {}", i);
+                assert_eq!(output, mic_frame);
+            }
+            if i >= 150 && resumed.is_none() && !aec.is_low_power() {
+                resumed = Some(i);
+            }
+        }
+
+        // The echo is noticed within a few probes and cancelled again.
+        assert!(resumed.is_some_and(|frame| frame < 150 + 6 * 4), "resumed at {:?}", resumed);
+        assert!(!aec.is_low_power());
+        assert!((aec.impulse_response()[10] - 0.5).abs() < 0.05);
+    }
+}
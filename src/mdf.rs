@@ -0,0 +1,201 @@
+//! Partitioned-block (multi-delay) frequency-domain adaptive filter for long echo tails.
+//!
+//! [`FdafAec`](crate::FdafAec) models the echo path with a single block of `fft_size / 2`
+//! taps, so the frame length grows with the echo tail: covering 200 ms at 48 kHz needs
+//! frames of almost 10 000 samples. [`MdfAec`] splits the echo path into partitions of one
+//! frame each. Every partition is applied to the far-end spectrum of a correspondingly
+//! older frame, so the tail length is `partitions * frame_size` while the FFT size, and
+//! with it the frame latency, stays at `2 * frame_size`.
+//!
+//! Each partition is constrained to `frame_size` taps after its update; otherwise
+//! neighbouring partitions would model overlapping parts of the echo path and fight each
+//! other. Apart from that, every partition is the filter and update of the single-block
+//! canceller, see the `fdaf` module.
+
+use crate::config::regularization_for;
+use crate::fdaf;
+use crate::{BlockIo, BlockProcessor};
+use nalgebra::DVector;
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Parameters of an [`MdfAec`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MdfConfig {
+    /// The number of samples per frame, and the length of each partition. Must be a power
+    /// of two.
+    pub frame_size: usize,
+    /// The number of partitions. The filter covers `partitions * frame_size` taps.
+    pub partitions: usize,
+    /// The sample rate of the processed audio, in Hz.
+    pub sample_rate: u32,
+    /// The learning rate (mu) of the NLMS weight update.
+    pub step_size: f32,
+    /// The smoothing factor of the far-end power spectral density estimate.
+    pub psd_smoothing: f32,
+}
+
+impl Default for MdfConfig {
+    fn default() -> Self {
+        Self { frame_size: 256, partitions: 8, sample_rate: 16000, step_size: 0.5, psd_smoothing: 0.9 }
+    }
+}
+
+impl MdfConfig {
+    /// Returns a configuration with frames of `frame_size` samples and enough partitions to
+    /// cover an echo tail of `tail_ms` milliseconds at `sample_rate`.
+    pub fn for_tail(tail_ms: f32, frame_size: usize, sample_rate: u32) -> Self {
+        let tail_samples = (tail_ms * sample_rate as f32 / 1000.0).ceil() as usize;
+        Self { frame_size, partitions: tail_samples.div_ceil(frame_size).max(1), sample_rate, ..Self::default() }
+    }
+}
+
+/// An echo canceller with a partitioned-block frequency-domain adaptive filter.
+pub struct MdfAec {
+    frame_size: usize,
+    fft_size: usize,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    weights: Vec<DVector<Complex<f32>>>,
+    /// Far-end spectra of the most recent frames, newest first, one per partition.
+    far_end_spectra: VecDeque<DVector<Complex<f32>>>,
+    far_end_buffer: Vec<f32>,
+    psd: DVector<f32>,
+    mu: f32,
+    smoothing_factor: f32,
+    regularization: f32,
+    sample_rate: u32,
+}
+
+impl MdfAec {
+    /// Creates a partitioned-block canceller.
+    pub fn new(config: MdfConfig) -> Self {
+        let frame_size = config.frame_size;
+        assert!(frame_size > 0 && frame_size.is_power_of_two(), "frame_size must be a power of two.");
+        assert!(config.partitions > 0, "At least one partition is required.");
+        let fft_size = 2 * frame_size;
+        let mut planner = FftPlanner::new();
+        let zero = DVector::from_element(fft_size, Complex::new(0.0, 0.0));
+        Self {
+            frame_size,
+            fft_size,
+            fft: planner.plan_fft_forward(fft_size),
+            ifft: planner.plan_fft_inverse(fft_size),
+            weights: vec![zero.clone(); config.partitions],
+            far_end_spectra: vec![zero; config.partitions].into(),
+            far_end_buffer: vec![0.0; fft_size],
+            psd: DVector::from_element(fft_size, 1.0),
+            mu: config.step_size,
+            smoothing_factor: config.psd_smoothing,
+            regularization: regularization_for(fft_size),
+            sample_rate: config.sample_rate,
+        }
+    }
+
+    /// Returns the number of samples per frame.
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Returns the number of partitions.
+    pub fn partitions(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Returns the length of the modeled echo tail, in milliseconds.
+    pub fn tail_ms(&self) -> f32 {
+        (self.partitions() * self.frame_size) as f32 * 1000.0 / self.sample_rate as f32
+    }
+
+    /// Returns the time-domain impulse response of the estimated echo path, with
+    /// `partitions * frame_size` taps.
+    pub fn impulse_response(&self) -> Vec<f32> {
+        self.weights.iter().flat_map(|w| fdaf::impulse_response(&self.ifft, w)).collect()
+    }
+
+    /// Processes a frame of audio data to remove echo.
+    ///
+    /// Both frames must have `frame_size` samples. Returns the echo-cancelled frame.
+    pub fn process(&mut self, far_end_frame: &[f32], mic_frame: &[f32]) -> Vec<f32> {
+        assert_eq!(far_end_frame.len(), self.frame_size, "Input far-end frame size must match the frame size.");
+        assert_eq!(mic_frame.len(), self.frame_size, "Input mic frame size must match the frame size.");
+
+        // 1. Far-end spectrum of the last two frames, added to the frequency-domain delay line
+        self.far_end_buffer.copy_within(self.frame_size.., 0);
+        self.far_end_buffer[self.frame_size..].copy_from_slice(far_end_frame);
+        let x_f = fdaf::spectrum(&self.fft, &self.far_end_buffer);
+        fdaf::smooth_psd(&mut self.psd, &x_f, self.smoothing_factor);
+        self.far_end_spectra.pop_back();
+        self.far_end_spectra.push_front(x_f);
+
+        // 2. Echo estimate: every partition filters the far-end spectrum of its delay
+        let mut y_f = DVector::from_element(self.fft_size, Complex::new(0.0, 0.0));
+        for (w, x_f) in self.weights.iter().zip(&self.far_end_spectra) {
+            y_f += w.component_mul(x_f);
+        }
+        let echo = fdaf::overlap_save(&self.ifft, &y_f);
+        let error_signal: Vec<f32> = mic_frame.iter().zip(echo.iter()).map(|(mic, echo)| mic - echo).collect();
+
+        // 3. Error spectrum, zero-padded in front like the single-block filter
+        let e_f = fdaf::padded_spectrum(&self.fft, &error_signal);
+
+        // 4. Constrained NLMS update of every partition, normalized by the far-end power over
+        //    the whole filter length
+        let partitions = self.partitions() as f32;
+        let norm = self.psd.map(|p| 1.0 / (partitions * p + self.regularization));
+        for (w, x_f) in self.weights.iter_mut().zip(&self.far_end_spectra) {
+            fdaf::nlms_update(&self.fft, &self.ifft, w, x_f, &e_f, &norm, self.mu);
+        }
+
+        error_signal
+    }
+}
+
+impl BlockProcessor for MdfAec {
+    fn block_size(&self) -> Option<usize> {
+        Some(self.frame_size)
+    }
+
+    fn latency_samples(&self) -> usize {
+        0
+    }
+
+    fn process_block(&mut self, io: &mut BlockIo) {
+        let output = self.process(io.reference, io.signal);
+        io.signal.copy_from_slice(&output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn config_covers_tail() {
+        let config = MdfConfig::for_tail(200.0, 512, 48000);
+        assert_eq!(config.partitions, 19);
+        assert!(MdfAec::new(config).tail_ms() >= 200.0);
+    }
+
+    #[test]
+    fn cancels_echo_beyond_first_partition() {
+        let mut aec = MdfAec::new(MdfConfig { frame_size: 128, partitions: 8, ..MdfConfig::default() });
+        let far = white_noise(128 * 400, 0.3, 44);
+        let mic = echo(&far, &[(20, 0.4), (700, 0.3)]);
+        let mut output = Vec::new();
+        for (far_frame, mic_frame) in far.chunks(128).zip(mic.chunks(128)) {
+            output.extend(aec.process(far_frame, mic_frame));
+        }
+
+        let ir = aec.impulse_response();
+        assert_eq!(ir.len(), 1024);
+        assert!((ir[20] - 0.4).abs() < 0.05, "tap 20 was {}", ir[20]);
+        assert!((ir[700] - 0.3).abs() < 0.05, "tap 700 was {}", ir[700]);
+        let tail = output.len() - 1280;
+        assert!(crate::mean_square(&output[tail..]) < crate::mean_square(&mic[tail..]) / 100.0);
+    }
+}
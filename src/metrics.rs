@@ -0,0 +1,280 @@
+//! Read-only metrics that can be polled from other threads.
+//!
+//! The canceller publishes a small set of statistics after every frame into atomics shared
+//! with any number of [`MetricsHandle`]s. A UI or telemetry thread can take consistent
+//! snapshots without locks and without access to the [`FdafAec`] owned by the audio thread.
+//!
+//! The optional echo delay histogram collects the delay of the dominant echo path tap over
+//! a sliding window. A single narrow peak indicates a stable setup; several peaks reveal
+//! multi-path echo or unstable buffering in the audio stack.
+
+use crate::{DuplexState, FdafAec, FrameQuality};
+use std::collections::VecDeque;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Mean-square far-end level below which no delay estimate is collected (about -60 dBFS).
+const FAR_END_ACTIVITY_THRESHOLD: f32 = 1e-6;
+
+/// The statistics of the most recently processed frame.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MetricsSnapshot {
+    /// The number of frames processed so far.
+    pub frames: u64,
+    /// The long-term echo return loss enhancement, in dB.
+    pub erle_db: f32,
+    /// The quality flags of the last frame.
+    pub quality: FrameQuality,
+    /// The conversation state of the last frame.
+    pub duplex_state: DuplexState,
+}
+
+/// The atomics behind a [`MetricsHandle`], written by the audio thread.
+///
+/// Writes are bracketed by a sequence counter that is odd while a write is in progress, so
+/// readers can detect and retry torn snapshots.
+#[derive(Debug, Default)]
+pub(crate) struct SharedMetrics {
+    sequence: AtomicU64,
+    frames: AtomicU64,
+    erle_db: AtomicU32,
+    flags: AtomicU32,
+}
+
+impl SharedMetrics {
+    pub(crate) fn publish(&self, snapshot: &MetricsSnapshot) {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        self.sequence.store(sequence.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.frames.store(snapshot.frames, Ordering::Relaxed);
+        self.erle_db.store(snapshot.erle_db.to_bits(), Ordering::Relaxed);
+        self.flags.store(pack_flags(snapshot.quality, snapshot.duplex_state), Ordering::Relaxed);
+        self.sequence.store(sequence.wrapping_add(2), Ordering::Release);
+    }
+
+    fn read(&self) -> MetricsSnapshot {
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            let frames = self.frames.load(Ordering::Relaxed);
+            let erle_db = f32::from_bits(self.erle_db.load(Ordering::Relaxed));
+            let flags = self.flags.load(Ordering::Relaxed);
+            fence(Ordering::Acquire);
+            if before & 1 == 0 && self.sequence.load(Ordering::Relaxed) == before {
+                let (quality, duplex_state) = unpack_flags(flags);
+                return MetricsSnapshot { frames, erle_db, quality, duplex_state };
+            }
+            std::hint::spin_loop();
+        }
+    }
+}
+
+pub(crate) fn pack_flags(quality: FrameQuality, state: DuplexState) -> u32 {
+    let state = match state {
+        DuplexState::Silence => 0,
+        DuplexState::FarEndOnly => 1,
+        DuplexState::NearEndOnly => 2,
+        DuplexState::DoubleTalk => 3,
+    };
+    quality.converged as u32 | (quality.echo_free as u32) << 1 | (quality.double_talk as u32) << 2 | state << 3
+}
+
+fn unpack_flags(flags: u32) -> (FrameQuality, DuplexState) {
+    let quality = FrameQuality {
+        converged: flags & 1 != 0,
+        echo_free: flags & 2 != 0,
+        double_talk: flags & 4 != 0,
+    };
+    let state = match flags >> 3 {
+        1 => DuplexState::FarEndOnly,
+        2 => DuplexState::NearEndOnly,
+        3 => DuplexState::DoubleTalk,
+        _ => DuplexState::Silence,
+    };
+    (quality, state)
+}
+
+/// A cheap, cloneable, read-only view of a canceller's metrics, usable from any thread.
+#[derive(Debug, Clone)]
+pub struct MetricsHandle {
+    shared: Arc<SharedMetrics>,
+}
+
+impl MetricsHandle {
+    /// Returns a consistent snapshot of the latest published metrics.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.shared.read()
+    }
+}
+
+/// Parameters of the echo delay histogram.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DelayHistogramConfig {
+    /// The width of each histogram bin, in samples.
+    pub bin_width: usize,
+    /// The length of the sliding window the histogram covers, in milliseconds.
+    pub window_ms: f64,
+}
+
+impl Default for DelayHistogramConfig {
+    fn default() -> Self {
+        Self { bin_width: 8, window_ms: 10_000.0 }
+    }
+}
+
+/// A histogram of the echo delay estimates collected over the configured window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DelayHistogram {
+    /// The width of each bin, in samples. Bin `i` covers delays
+    /// `i * bin_width..(i + 1) * bin_width`.
+    pub bin_width: usize,
+    /// The number of estimates that fell into each bin.
+    pub counts: Vec<u32>,
+}
+
+impl DelayHistogram {
+    /// Returns the number of estimates in the window.
+    pub fn total(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the first delay, in samples, of the bin holding the most estimates.
+    pub fn mode(&self) -> Option<usize> {
+        let (bin, &count) = self.counts.iter().enumerate().max_by_key(|&(i, &c)| (c, std::cmp::Reverse(i)))?;
+        (count > 0).then_some(bin * self.bin_width)
+    }
+
+    /// Returns the number of bins holding at least `min_share` of the estimates, e.g. to
+    /// detect multi-path echo or jittering buffers.
+    pub fn peaks(&self, min_share: f32) -> usize {
+        let threshold = (self.total() as f32 * min_share).max(1.0);
+        self.counts.iter().filter(|&&c| c as f32 >= threshold).count()
+    }
+}
+
+/// Collects delay estimates into a [`DelayHistogram`] over a sliding window of frames.
+#[derive(Debug, Clone)]
+pub(crate) struct DelayHistogramTracker {
+    window_frames: usize,
+    window: VecDeque<usize>,
+    histogram: DelayHistogram,
+}
+
+impl DelayHistogramTracker {
+    pub(crate) fn new(bin_width: usize, window_frames: usize, max_delay: usize) -> Self {
+        assert!(bin_width > 0, "Histogram bin width must be non-zero.");
+        Self {
+            window_frames: window_frames.max(1),
+            window: VecDeque::new(),
+            histogram: DelayHistogram { bin_width, counts: vec![0; max_delay.div_ceil(bin_width)] },
+        }
+    }
+
+    /// Returns an empty tracker with the same bin width and window for delays up to
+    /// `max_delay`.
+    pub(crate) fn restarted(&self, max_delay: usize) -> Self {
+        Self::new(self.histogram.bin_width, self.window_frames, max_delay)
+    }
+
+    pub(crate) fn push(&mut self, delay: usize) {
+        if self.window.len() == self.window_frames {
+            if let Some(oldest) = self.window.pop_front() {
+                self.histogram.counts[oldest] -= 1;
+            }
+        }
+        let bin = (delay / self.histogram.bin_width).min(self.histogram.counts.len() - 1);
+        self.histogram.counts[bin] += 1;
+        self.window.push_back(bin);
+    }
+}
+
+impl FdafAec {
+    /// Returns a handle for reading this canceller's metrics from another thread.
+    pub fn metrics_handle(&self) -> MetricsHandle {
+        MetricsHandle { shared: Arc::clone(&self.metrics) }
+    }
+
+    /// Enables, reconfigures, or (with `None`) disables the echo delay histogram.
+    ///
+    /// A delay estimate is collected on every frame in which the far-end is active and the
+    /// filter is converged. Enabling clears the histogram.
+    pub fn set_delay_histogram(&mut self, config: Option<DelayHistogramConfig>) {
+        self.delay_histogram = config.map(|config| {
+            let window_frames = self.ms_to_frames(config.window_ms) as usize;
+            DelayHistogramTracker::new(config.bin_width, window_frames, self.frame_size)
+        });
+    }
+
+    /// Returns the echo delay histogram, or `None` if it is disabled.
+    pub fn delay_histogram(&self) -> Option<&DelayHistogram> {
+        self.delay_histogram.as_ref().map(|tracker| &tracker.histogram)
+    }
+
+    /// Adds the delay of the current echo path estimate to the histogram, if enabled.
+    pub(crate) fn update_delay_histogram(&mut self, far_end_energy: f32) {
+        if self.delay_histogram.is_none() || far_end_energy <= FAR_END_ACTIVITY_THRESHOLD || !self.quality.quality().converged {
+            return;
+        }
+        let delay = self.dominant_tap();
+        if let Some(tracker) = self.delay_histogram.as_mut() {
+            tracker.push(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use std::thread;
+
+    #[test]
+    fn flags_round_trip() {
+        let quality = FrameQuality { converged: true, echo_free: false, double_talk: true };
+        for state in [DuplexState::Silence, DuplexState::FarEndOnly, DuplexState::NearEndOnly, DuplexState::DoubleTalk] {
+            assert_eq!(unpack_flags(pack_flags(quality, state)), (quality, state));
+        }
+    }
+
+    #[test]
+    fn histogram_window_slides() {
+        let mut tracker = DelayHistogramTracker::new(4, 3, 16);
+        for delay in [1, 2, 9, 10] {
+            tracker.push(delay);
+        }
+        assert_eq!(tracker.histogram.counts, [1, 0, 2, 0]);
+        assert_eq!(tracker.histogram.mode(), Some(8));
+        assert_eq!(tracker.histogram.peaks(0.3), 2);
+    }
+
+    #[test]
+    fn histogram_tracks_echo_delay() {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_delay_histogram(Some(DelayHistogramConfig { bin_width: 4, window_ms: 1000.0 }));
+        let far = white_noise(256 * 150, 0.3, 27);
+        let mic = echo(&far, &[(42, 0.5)]);
+        process_all(&mut aec, &far, &mic);
+        let histogram = aec.delay_histogram().unwrap();
+        assert_eq!(histogram.counts.len(), 64);
+        assert_eq!(histogram.total(), 62);
+        assert_eq!(histogram.mode(), Some(40));
+        assert_eq!(histogram.peaks(0.1), 1);
+    }
+
+    #[test]
+    fn handle_reads_from_another_thread() {
+        let mut aec = FdafAec::new(512, 0.5);
+        let handle = aec.metrics_handle();
+        assert_eq!(handle.snapshot(), MetricsSnapshot::default());
+
+        let far = white_noise(256 * 100, 0.3, 19);
+        let mic = echo(&far, &[(8, 0.5)]);
+        process_all(&mut aec, &far, &mic);
+
+        let snapshot = thread::spawn(move || handle.snapshot()).join().unwrap();
+        assert_eq!(snapshot.frames, 100);
+        assert!(snapshot.erle_db > 15.0);
+        assert!(snapshot.quality.converged);
+        assert_eq!(snapshot.duplex_state, DuplexState::FarEndOnly);
+    }
+}
@@ -0,0 +1,255 @@
+//! Metrics rows written to CSV or JSON Lines files, enabled with the `metrics-log` feature.
+//!
+//! Long soak tests need the canceller's metrics over hours of audio. A [`MetricsLogger`]
+//! appends one row per logging interval and rotates the file once it reaches a size limit,
+//! keeping a bounded number of older files next to it (`metrics.csv.1`, `metrics.csv.2`, ...).
+
+use crate::MetricsSnapshot;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const CSV_HEADER: &str = "frames,erle_db,converged,echo_free,double_talk,duplex_state\n";
+
+/// The file format of a metrics log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MetricsLogFormat {
+    /// Comma-separated values with a header line at the start of every file.
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+/// Parameters of a [`MetricsLogger`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetricsLogConfig {
+    /// The file rows are appended to.
+    pub path: PathBuf,
+    /// The file format.
+    pub format: MetricsLogFormat,
+    /// Only every `interval_frames`-th frame is logged. Use 1 for per-frame rows, or
+    /// [`FdafAec::ms_to_frames`](crate::FdafAec::ms_to_frames)`(1000.0)` for about one row per second.
+    pub interval_frames: u64,
+    /// The size in bytes at which the file is rotated.
+    pub max_file_bytes: u64,
+    /// The number of rotated files kept in addition to the current one.
+    pub max_rotated_files: usize,
+}
+
+impl MetricsLogConfig {
+    /// Returns a configuration logging every frame to `path`, rotating at 10 MB and keeping
+    /// four rotated files.
+    pub fn new(path: impl Into<PathBuf>, format: MetricsLogFormat) -> Self {
+        Self { path: path.into(), format, interval_frames: 1, max_file_bytes: 10 << 20, max_rotated_files: 4 }
+    }
+}
+
+/// Appends metrics snapshots to a size-bounded, rotating log file.
+#[derive(Debug)]
+pub struct MetricsLogger {
+    config: MetricsLogConfig,
+    writer: BufWriter<File>,
+    file_bytes: u64,
+}
+
+impl MetricsLogger {
+    /// Opens the log file, appending to it if it already exists.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if `config.interval_frames` is 0.
+    pub fn new(config: MetricsLogConfig) -> io::Result<Self> {
+        if config.interval_frames == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The logging interval must be at least one frame."));
+        }
+        let (writer, file_bytes) = open(&config.path)?;
+        let mut logger = Self { config, writer, file_bytes };
+        if logger.file_bytes == 0 {
+            logger.write_header()?;
+        }
+        Ok(logger)
+    }
+
+    /// Writes a row for `snapshot` if its frame falls on the logging interval.
+    pub fn log(&mut self, snapshot: &MetricsSnapshot) -> io::Result<()> {
+        if !snapshot.frames.is_multiple_of(self.config.interval_frames) {
+            return Ok(());
+        }
+        let row = self.format_row(snapshot);
+        if self.file_bytes > 0 && self.file_bytes + row.len() as u64 > self.config.max_file_bytes {
+            self.rotate()?;
+        }
+        self.write(&row)
+    }
+
+    /// Flushes buffered rows to the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn format_row(&self, s: &MetricsSnapshot) -> String {
+        let q = s.quality;
+        match self.config.format {
+            MetricsLogFormat::Csv => format!(
+                "{},{},{},{},{},{:?}\n",
+                s.frames, s.erle_db, q.converged, q.echo_free, q.double_talk, s.duplex_state
+            ),
+            MetricsLogFormat::JsonLines => format!(
+                "{{\"frames\":{},\"erle_db\":{},\"converged\":{},\"echo_free\":{},\"double_talk\":{},\"duplex_state\":\"{:?}\"}}\n",
+                s.frames,
+                json_number(s.erle_db),
+                q.converged,
+                q.echo_free,
+                q.double_talk,
+                s.duplex_state
+            ),
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        match self.config.format {
+            MetricsLogFormat::Csv => self.write(CSV_HEADER),
+            MetricsLogFormat::JsonLines => Ok(()),
+        }
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.writer.write_all(text.as_bytes())?;
+        self.file_bytes += text.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts `path.N` to `path.N+1`, dropping the oldest, and starts a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let path = &self.config.path;
+        let keep = self.config.max_rotated_files;
+        if keep == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for n in (1..keep).rev() {
+                let from = rotated_path(path, n);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(path, n + 1))?;
+                }
+            }
+            fs::rename(path, rotated_path(path, 1))?;
+        }
+        (self.writer, self.file_bytes) = open(path)?;
+        self.write_header()
+    }
+}
+
+impl Drop for MetricsLogger {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+fn open(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = File::options().create(true).append(true).open(path)?;
+    let len = file.metadata()?.len();
+    Ok((BufWriter::new(file), len))
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+/// JSON has no representation for infinities and NaN, so they are written as `null`.
+fn json_number(value: f32) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DuplexState, FrameQuality};
+
+    /// A directory for the files of one test, removed with everything in it when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("fdaf-aec-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn log_path(&self) -> PathBuf {
+            self.0.join("metrics.log")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn snapshot(frames: u64) -> MetricsSnapshot {
+        MetricsSnapshot {
+            frames,
+            erle_db: 12.5,
+            quality: FrameQuality { converged: true, echo_free: false, double_talk: false },
+            duplex_state: DuplexState::FarEndOnly,
+        }
+    }
+
+    #[test]
+    fn csv_rows_follow_interval() {
+        let dir = TempDir::new("csv");
+        let path = dir.log_path();
+        let config = MetricsLogConfig { interval_frames: 2, ..MetricsLogConfig::new(&path, MetricsLogFormat::Csv) };
+        let mut logger = MetricsLogger::new(config).unwrap();
+        for frames in 1..=4 {
+            logger.log(&snapshot(frames)).unwrap();
+        }
+        logger.flush().unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            text,
+            format!("{CSV_HEADER}2,12.5,true,false,false,FarEndOnly\n4,12.5,true,false,false,FarEndOnly\n")
+        );
+    }
+
+    #[test]
+    fn json_lines_rotate_at_size_limit() {
+        let dir = TempDir::new("jsonl");
+        let path = dir.log_path();
+        let config = MetricsLogConfig {
+            max_file_bytes: 250,
+            max_rotated_files: 2,
+            ..MetricsLogConfig::new(&path, MetricsLogFormat::JsonLines)
+        };
+        let mut logger = MetricsLogger::new(config).unwrap();
+        for frames in 1..=20 {
+            logger.log(&snapshot(frames)).unwrap();
+        }
+        drop(logger);
+
+        for file in [path.clone(), rotated_path(&path, 1), rotated_path(&path, 2)] {
+            let text = fs::read_to_string(&file).unwrap();
+            assert!(!text.is_empty() && text.len() <= 250);
+            assert!(text.lines().all(|line| line.starts_with("{\"frames\":") && line.ends_with("\"FarEndOnly\"}")));
+        }
+        assert!(!rotated_path(&path, 3).exists());
+        let last = fs::read_to_string(&path).unwrap();
+        assert!(last.lines().last().unwrap().starts_with("{\"frames\":20,"));
+    }
+
+    #[test]
+    fn rejects_a_zero_interval() {
+        let dir = TempDir::new("interval");
+        let config = MetricsLogConfig { interval_frames: 0, ..MetricsLogConfig::new(dir.log_path(), MetricsLogFormat::Csv) };
+        assert_eq!(MetricsLogger::new(config).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(!dir.log_path().exists());
+    }
+}
@@ -0,0 +1,233 @@
+//! Echo cancellation for multi-channel far-end playback.
+//!
+//! Stereo loudspeakers produce two echoes through two different acoustic paths. Summing the
+//! channels into one reference, as [`FdafAec::process_references`](crate::FdafAec::process_references)
+//! does, only works while the mix between the channels never changes. [`MultiChannelAec`]
+//! keeps one weight set per far-end channel and subtracts the sum of their echo estimates.
+//! All weight sets are updated from the common error, normalized by the far-end power summed
+//! over the channels; each is the filter and update of the single-channel canceller, see
+//! the `fdaf` module.
+//!
+//! Stereo playback is often strongly correlated between channels, which leaves the
+//! individual echo paths ambiguous: the filters find one of many solutions that cancel the
+//! echo for the current mix and lose it when the mix changes. The optional decorrelation
+//! pre-processor adds a small half-wave rectified copy of each channel to itself, using the
+//! positive half-wave on even and the negative half-wave on odd channels. The nonlinearity is
+//! barely audible but makes the channels distinguishable to the filter. It changes the
+//! far-end signal, so the processed far-end returned by [`MultiChannelAec::process`] must be
+//! played out instead of the original.
+
+use crate::config::regularization_for;
+use crate::{fdaf, AudioViewMut};
+use nalgebra::DVector;
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+
+/// Parameters of a [`MultiChannelAec`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MultiChannelConfig {
+    /// The number of far-end channels.
+    pub channels: usize,
+    /// The size of the FFT. Frames have `fft_size / 2` samples. Must be a power of two.
+    pub fft_size: usize,
+    /// The learning rate (mu) of the NLMS weight update.
+    pub step_size: f32,
+    /// The smoothing factor of the far-end power spectral density estimates.
+    pub psd_smoothing: f32,
+    /// The strength of the half-wave rectifier decorrelating the far-end channels, or `None`
+    /// to leave them unchanged. Values around 0.5 are a common compromise between
+    /// identifiability and audible distortion.
+    pub decorrelation: Option<f32>,
+}
+
+impl Default for MultiChannelConfig {
+    fn default() -> Self {
+        Self { channels: 2, fft_size: 1024, step_size: 0.5, psd_smoothing: 0.9, decorrelation: None }
+    }
+}
+
+/// The output of one [`MultiChannelAec::process`] call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultiChannelFrame {
+    /// The echo-cancelled microphone frame.
+    pub output: Vec<f32>,
+    /// The far-end channels after decorrelation, which should be played out. Equal to the
+    /// input if decorrelation is disabled.
+    pub far_end: Vec<Vec<f32>>,
+}
+
+/// Far-end state and filter weights of one far-end channel.
+struct Channel {
+    buffer: Vec<f32>,
+    spectrum: DVector<Complex<f32>>,
+    psd: DVector<f32>,
+    weights: DVector<Complex<f32>>,
+}
+
+/// An echo canceller with one adaptive filter per far-end channel.
+pub struct MultiChannelAec {
+    fft_size: usize,
+    frame_size: usize,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    channels: Vec<Channel>,
+    mu: f32,
+    smoothing_factor: f32,
+    regularization: f32,
+    decorrelation: Option<f32>,
+}
+
+impl MultiChannelAec {
+    /// Creates a multi-channel canceller.
+    pub fn new(config: MultiChannelConfig) -> Self {
+        let fft_size = config.fft_size;
+        assert!(fft_size > 0 && fft_size.is_power_of_two(), "fft_size must be a power of two.");
+        assert!(config.channels > 0, "At least one far-end channel is required.");
+        let mut planner = FftPlanner::new();
+        let zero = DVector::from_element(fft_size, Complex::new(0.0, 0.0));
+        let channels = (0..config.channels)
+            .map(|_| Channel {
+                buffer: vec![0.0; fft_size],
+                spectrum: zero.clone(),
+                psd: DVector::from_element(fft_size, 1.0),
+                weights: zero.clone(),
+            })
+            .collect();
+        Self {
+            fft_size,
+            frame_size: fft_size / 2,
+            fft: planner.plan_fft_forward(fft_size),
+            ifft: planner.plan_fft_inverse(fft_size),
+            channels,
+            mu: config.step_size,
+            smoothing_factor: config.psd_smoothing,
+            regularization: regularization_for(fft_size),
+            decorrelation: config.decorrelation,
+        }
+    }
+
+    /// Returns the number of far-end channels.
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Returns the number of samples per frame.
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Returns the time-domain impulse response of the echo path estimated for a far-end
+    /// channel.
+    pub fn impulse_response(&self, channel: usize) -> Vec<f32> {
+        fdaf::impulse_response(&self.ifft, &self.channels[channel].weights)
+    }
+
+    /// Processes one frame of every far-end channel and one microphone frame.
+    ///
+    /// All frames must have `fft_size / 2` samples, and one far-end frame must be supplied
+    /// per channel.
+    pub fn process(&mut self, far_end_frames: &[&[f32]], mic_frame: &[f32]) -> MultiChannelFrame {
+        assert_eq!(far_end_frames.len(), self.channels.len(), "One far-end frame must be supplied per channel.");
+        for frame in far_end_frames {
+            assert_eq!(frame.len(), self.frame_size, "Input far-end frame size must be half of FFT size.");
+        }
+        let mut output = mic_frame.to_vec();
+        self.process_frame(|channel, n| far_end_frames[channel][n], &mut output);
+        let far_end = self.channels.iter().map(|channel| channel.buffer[self.frame_size..].to_vec()).collect();
+        MultiChannelFrame { output, far_end }
+    }
+
+    /// Processes a frame of far-end channels in a strided buffer, e.g. an interleaved
+    /// render buffer, without copying them out.
+    ///
+    /// The view must have one channel per far-end channel and `fft_size / 2` frames. The
+    /// far-end samples are replaced by their decorrelated version, which should be played
+    /// out, and `mic_frame` by the echo-cancelled output.
+    pub fn process_view(&mut self, far_end: &mut AudioViewMut, mic_frame: &mut [f32]) {
+        assert_eq!(far_end.channels(), self.channels.len(), "The far-end view must have one channel per far-end channel.");
+        assert_eq!(far_end.frames(), self.frame_size, "The far-end view must hold half of FFT size frames.");
+        let view = far_end.as_view();
+        self.process_frame(|channel, n| view.sample(channel, n), mic_frame);
+        if self.decorrelation.is_some() {
+            for (index, channel) in self.channels.iter().enumerate() {
+                for (n, &sample) in channel.buffer[self.frame_size..].iter().enumerate() {
+                    *far_end.sample_mut(index, n) = sample;
+                }
+            }
+        }
+    }
+
+    /// Cancels the echo of the far-end samples `far_end(channel, n)` in `mic_frame`, in
+    /// place.
+    fn process_frame(&mut self, far_end: impl Fn(usize, usize) -> f32, mic_frame: &mut [f32]) {
+        assert_eq!(mic_frame.len(), self.frame_size, "Input mic frame size must be half of FFT size.");
+
+        // 1. Decorrelate the far-end channels and take their spectra over the last two frames
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            let sign = if index % 2 == 0 { 1.0 } else { -1.0 };
+            channel.buffer.copy_within(self.frame_size.., 0);
+            for (n, sample) in channel.buffer[self.frame_size..].iter_mut().enumerate() {
+                let x = far_end(index, n);
+                *sample = match self.decorrelation {
+                    Some(alpha) => x + 0.5 * alpha * (x + sign * x.abs()),
+                    None => x,
+                };
+            }
+            channel.spectrum = fdaf::spectrum(&self.fft, &channel.buffer);
+            fdaf::smooth_psd(&mut channel.psd, &channel.spectrum, self.smoothing_factor);
+        }
+
+        // 2. Echo estimate: the sum of every channel filtered by its own echo path
+        let mut y_f = DVector::from_element(self.fft_size, Complex::new(0.0, 0.0));
+        for channel in &self.channels {
+            y_f += channel.weights.component_mul(&channel.spectrum);
+        }
+        for (mic, echo) in mic_frame.iter_mut().zip(fdaf::overlap_save(&self.ifft, &y_f).iter()) {
+            *mic -= echo;
+        }
+
+        // 3. Error spectrum, zero-padded in front
+        let e_f = fdaf::padded_spectrum(&self.fft, mic_frame);
+
+        // 4. Constrained NLMS update of every channel, normalized by the total far-end power
+        let norm = DVector::from_fn(self.fft_size, |i, _| {
+            1.0 / (self.channels.iter().map(|channel| channel.psd[i]).sum::<f32>() + self.regularization)
+        });
+        for channel in &mut self.channels {
+            fdaf::nlms_update(&self.fft, &self.ifft, &mut channel.weights, &channel.spectrum, &e_f, &norm, self.mu);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn identifies_one_echo_path_per_channel() {
+        let left = white_noise(256 * 300, 0.3, 77);
+        let right = white_noise(256 * 300, 0.3, 78);
+        let mic: Vec<f32> = echo(&left, &[(20, 0.5)]).iter().zip(echo(&right, &[(90, -0.3)])).map(|(l, r)| l + r).collect();
+        let mut aec = MultiChannelAec::new(MultiChannelConfig { fft_size: 512, ..MultiChannelConfig::default() });
+        let mut output = Vec::new();
+        for ((l, r), m) in left.chunks(256).zip(right.chunks(256)).zip(mic.chunks(256)) {
+            output.extend(aec.process(&[l, r], m).output);
+        }
+
+        let (left_ir, right_ir) = (aec.impulse_response(0), aec.impulse_response(1));
+        assert!((left_ir[20] - 0.5).abs() < 0.05 && left_ir[90].abs() < 0.05);
+        assert!((right_ir[90] + 0.3).abs() < 0.05 && right_ir[20].abs() < 0.05);
+        let tail = output.len() - 20 * 256;
+        assert!(crate::mean_square(&output[tail..]) < crate::mean_square(&mic[tail..]) / 100.0);
+
+        // Decorrelation distorts the two channels with opposite half-waves.
+        let mut aec = MultiChannelAec::new(MultiChannelConfig { decorrelation: Some(0.5), ..MultiChannelConfig::default() });
+        let frame = [1.0, -1.0].repeat(256);
+        let far_end = aec.process(&[&frame, &frame], &[0.0; 512]).far_end;
+        assert_eq!((far_end[0][0], far_end[0][1]), (1.5, -1.0));
+        assert_eq!((far_end[1][0], far_end[1][1]), (1.0, -1.5));
+    }
+}
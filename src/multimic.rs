@@ -0,0 +1,186 @@
+//! Echo cancellation for microphone arrays against a shared far-end reference.
+//!
+//! Conference devices capture with several microphones, each receiving the loudspeaker echo
+//! through its own acoustic path. Running one [`FdafAec`](crate::FdafAec) per microphone
+//! repeats the far-end FFT and power spectral density estimate for every channel, although
+//! they only depend on the far-end. [`MultiMicAec`] computes them once per frame and keeps
+//! one adaptive filter per microphone, so every additional microphone only costs its echo
+//! estimate and weight update. Each filter is that of the single-microphone canceller, see
+//! the `fdaf` module.
+
+use crate::config::regularization_for;
+use crate::{fdaf, AudioViewMut};
+use nalgebra::DVector;
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+
+/// Parameters of a [`MultiMicAec`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MultiMicConfig {
+    /// The number of microphone channels.
+    pub mics: usize,
+    /// The size of the FFT. Frames have `fft_size / 2` samples. Must be a power of two.
+    pub fft_size: usize,
+    /// The learning rate (mu) of the NLMS weight update.
+    pub step_size: f32,
+    /// The smoothing factor of the far-end power spectral density estimate.
+    pub psd_smoothing: f32,
+}
+
+impl Default for MultiMicConfig {
+    fn default() -> Self {
+        Self { mics: 2, fft_size: 1024, step_size: 0.5, psd_smoothing: 0.9 }
+    }
+}
+
+/// An echo canceller with one adaptive filter per microphone and a shared far-end analysis.
+pub struct MultiMicAec {
+    frame_size: usize,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    far_end_buffer: Vec<f32>,
+    psd: DVector<f32>,
+    weights: Vec<DVector<Complex<f32>>>,
+    mu: f32,
+    smoothing_factor: f32,
+    regularization: f32,
+}
+
+impl MultiMicAec {
+    /// Creates a multi-microphone canceller.
+    pub fn new(config: MultiMicConfig) -> Self {
+        let fft_size = config.fft_size;
+        assert!(fft_size > 0 && fft_size.is_power_of_two(), "fft_size must be a power of two.");
+        assert!(config.mics > 0, "At least one microphone is required.");
+        let mut planner = FftPlanner::new();
+        Self {
+            frame_size: fft_size / 2,
+            fft: planner.plan_fft_forward(fft_size),
+            ifft: planner.plan_fft_inverse(fft_size),
+            far_end_buffer: vec![0.0; fft_size],
+            psd: DVector::from_element(fft_size, 1.0),
+            weights: vec![DVector::from_element(fft_size, Complex::new(0.0, 0.0)); config.mics],
+            mu: config.step_size,
+            smoothing_factor: config.psd_smoothing,
+            regularization: regularization_for(fft_size),
+        }
+    }
+
+    /// Returns the number of microphone channels.
+    pub fn mic_count(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Returns the number of samples per frame.
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Returns the time-domain impulse response of the echo path estimated for a
+    /// microphone.
+    pub fn impulse_response(&self, mic: usize) -> Vec<f32> {
+        fdaf::impulse_response(&self.ifft, &self.weights[mic])
+    }
+
+    /// Processes one far-end frame and one frame of every microphone.
+    ///
+    /// All frames must have `fft_size / 2` samples, and one microphone frame must be
+    /// supplied per channel. Returns the echo-cancelled frame of every microphone, in order.
+    pub fn process(&mut self, far_end_frame: &[f32], mic_frames: &[&[f32]]) -> Vec<Vec<f32>> {
+        assert_eq!(mic_frames.len(), self.weights.len(), "One mic frame must be supplied per microphone.");
+        for frame in mic_frames {
+            assert_eq!(frame.len(), self.frame_size, "Input mic frame size must be half of FFT size.");
+        }
+        let mut outputs = Vec::with_capacity(mic_frames.len());
+        self.process_frame(far_end_frame, |mic, signal| {
+            for (sample, &mic) in signal.iter_mut().zip(mic_frames[mic]) {
+                *sample = mic - *sample;
+            }
+            outputs.push(signal.to_vec());
+        });
+        outputs
+    }
+
+    /// Processes one far-end frame and the microphone channels of a strided buffer, e.g. an
+    /// interleaved capture buffer, replacing them by the echo-cancelled output in place.
+    ///
+    /// The view must have one channel per microphone and `fft_size / 2` frames.
+    pub fn process_view(&mut self, far_end_frame: &[f32], mics: &mut AudioViewMut) {
+        assert_eq!(mics.channels(), self.weights.len(), "The mic view must have one channel per microphone.");
+        assert_eq!(mics.frames(), self.frame_size, "The mic view must hold half of FFT size frames.");
+        self.process_frame(far_end_frame, |mic, signal| {
+            for (n, sample) in signal.iter_mut().enumerate() {
+                let mic = mics.sample_mut(mic, n);
+                *mic -= *sample;
+                *sample = *mic;
+            }
+        });
+    }
+
+    /// Runs one frame of every microphone filter. `cancel(index, signal)` receives the echo
+    /// estimate of microphone `index` and must replace it by the echo-cancelled output,
+    /// which drives the weight update.
+    fn process_frame(&mut self, far_end_frame: &[f32], mut cancel: impl FnMut(usize, &mut [f32])) {
+        assert_eq!(far_end_frame.len(), self.frame_size, "Input far-end frame size must be half of FFT size.");
+
+        // 1. Far-end spectrum and PSD, shared by all microphones
+        self.far_end_buffer.copy_within(self.frame_size.., 0);
+        self.far_end_buffer[self.frame_size..].copy_from_slice(far_end_frame);
+        let x_f = fdaf::spectrum(&self.fft, &self.far_end_buffer);
+        fdaf::smooth_psd(&mut self.psd, &x_f, self.smoothing_factor);
+        let norm = self.psd.map(|p| 1.0 / (p + self.regularization));
+
+        for (index, w) in self.weights.iter_mut().enumerate() {
+            // 2. Echo estimate and error of this microphone
+            let mut error_signal = fdaf::overlap_save(&self.ifft, &w.component_mul(&x_f));
+            cancel(index, error_signal.as_mut_slice());
+
+            // 3. Constrained NLMS update from the zero-padded error spectrum
+            let e_f = fdaf::padded_spectrum(&self.fft, error_signal.as_slice());
+            fdaf::nlms_update(&self.fft, &self.ifft, w, &x_f, &e_f, &norm, self.mu);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use crate::AudioView;
+
+    #[test]
+    fn cancels_echo_on_every_microphone() {
+        let far = white_noise(256 * 200, 0.3, 81);
+        let paths = [[(10, 0.5), (60, 0.1)], [(25, -0.4), (80, 0.2)], [(40, 0.3), (120, -0.1)]];
+        let mics: Vec<Vec<f32>> = paths.iter().map(|taps| echo(&far, taps)).collect();
+        let mut aec = MultiMicAec::new(MultiMicConfig { mics: 3, fft_size: 512, ..MultiMicConfig::default() });
+        let mut outputs = vec![Vec::new(); 3];
+        for (frame, far_frame) in far.chunks(256).enumerate() {
+            let mic_frames: Vec<&[f32]> = mics.iter().map(|mic| &mic[frame * 256..(frame + 1) * 256]).collect();
+            for (output, cancelled) in outputs.iter_mut().zip(aec.process(far_frame, &mic_frames)) {
+                output.extend(cancelled);
+            }
+        }
+
+        // Interleaved capture buffers give the same output as separate frames.
+        let mut reference = MultiMicAec::new(MultiMicConfig { fft_size: 512, ..MultiMicConfig::default() });
+        let mut interleaved = MultiMicAec::new(MultiMicConfig { fft_size: 512, ..MultiMicConfig::default() });
+        for (frame, far_frame) in far.chunks(256).take(20).enumerate() {
+            let mic_frames: Vec<&[f32]> = mics[..2].iter().map(|mic| &mic[frame * 256..(frame + 1) * 256]).collect();
+            let expected = reference.process(far_frame, &mic_frames);
+            let mut buffer: Vec<f32> = (0..256).flat_map(|n| [mic_frames[0][n], mic_frames[1][n]]).collect();
+            interleaved.process_view(far_frame, &mut AudioViewMut::interleaved(&mut buffer, 2));
+            let view = AudioView::interleaved(&buffer, 2);
+            assert!(view.channel(0).eq(expected[0].iter().copied()) && view.channel(1).eq(expected[1].iter().copied()));
+        }
+
+        let tail = far.len() - 20 * 256;
+        for (index, (taps, (mic, output))) in paths.iter().zip(mics.iter().zip(&outputs)).enumerate() {
+            let ir = aec.impulse_response(index);
+            assert!((ir[taps[0].0] - taps[0].1).abs() < 0.05, "mic {}: tap was {}", index, ir[taps[0].0]);
+            assert!(crate::mean_square(&output[tail..]) < crate::mean_square(&mic[tail..]) / 100.0, "mic {}", index);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Nonlinear processing of the output during far-end single talk.
+//!
+//! When only the far-end talks, anything left in the output is residual echo, and the
+//! near-end listener expects silence. The nonlinear processor (NLP) center-clips the output,
+//! removing the low-level residual around zero, and attenuates what remains. As soon as the
+//! conversation state leaves far-end single talk the gain returns to unity, so the near-end
+//! voice is never touched. Gain changes are ramped over one frame to avoid clicks.
+
+use crate::{ContentMode, DuplexState, FdafAec};
+
+/// How strongly the NLP suppresses the output during far-end single talk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NlpLevel {
+    /// 10 dB of attenuation and light center clipping.
+    Conservative,
+    /// 20 dB of attenuation.
+    #[default]
+    Moderate,
+    /// 40 dB of attenuation and strong center clipping.
+    Aggressive,
+}
+
+impl NlpLevel {
+    /// Returns the gain applied to the output during far-end single talk.
+    pub fn attenuation(&self) -> f32 {
+        match self {
+            NlpLevel::Conservative => 0.316,
+            NlpLevel::Moderate => 0.1,
+            NlpLevel::Aggressive => 0.01,
+        }
+    }
+
+    /// Returns the center clipping threshold, relative to the RMS of the echo estimate.
+    pub fn clip_threshold(&self) -> f32 {
+        match self {
+            NlpLevel::Conservative => 0.02,
+            NlpLevel::Moderate => 0.05,
+            NlpLevel::Aggressive => 0.1,
+        }
+    }
+}
+
+/// Applies center clipping and attenuation during far-end single talk.
+#[derive(Debug, Clone)]
+pub(crate) struct NonlinearProcessor {
+    pub(crate) level: NlpLevel,
+    gain: f32,
+}
+
+impl NonlinearProcessor {
+    pub(crate) fn new(level: NlpLevel) -> Self {
+        Self { level, gain: 1.0 }
+    }
+
+    /// Processes an output frame in place, given the echo estimate of the frame, the content
+    /// estimate, see [`FdafAec::content_estimate`], and how far to back off towards unity
+    /// gain, between 0 and 1.
+    pub(crate) fn process(&mut self, output: &mut [f32], echo: &[f32], duplex_state: DuplexState, content: f32, release: f32) {
+        // Interpolate between the speech and music levels, in dB for the attenuation
+        let (speech, music) = (ContentMode::Speech.nlp_level(self.level), ContentMode::Music.nlp_level(self.level));
+        let far_end_only = duplex_state == DuplexState::FarEndOnly;
+        if far_end_only {
+            let clip_threshold = speech.clip_threshold() + content * (music.clip_threshold() - speech.clip_threshold());
+            let threshold = (1.0 - release) * clip_threshold * crate::mean_square(echo).sqrt();
+            for sample in output.iter_mut() {
+                *sample = if sample.abs() <= threshold { 0.0 } else { *sample - threshold * sample.signum() };
+            }
+        }
+
+        let attenuation = speech.attenuation().powf(1.0 - content) * music.attenuation().powf(content);
+        let target = if far_end_only { attenuation + release * (1.0 - attenuation) } else { 1.0 };
+        let step = (target - self.gain) / output.len() as f32;
+        for sample in output.iter_mut() {
+            self.gain += step;
+            *sample *= self.gain;
+        }
+        self.gain = target;
+    }
+}
+
+impl FdafAec {
+    /// Enables, changes the level of, or (with `None`) disables the nonlinear processor. In
+    /// [`ContentMode::Music`] the processor runs one level softer, see
+    /// [`ContentMode::nlp_level`]; in [`ContentMode::Auto`] its floor moves between the two
+    /// levels with the content estimate.
+    pub fn set_nlp(&mut self, level: Option<NlpLevel>) {
+        self.note_config_change();
+        self.nlp = level.map(NonlinearProcessor::new);
+    }
+
+    /// Returns the gain the NLP applied at the end of the last frame, or `None` if it is
+    /// disabled.
+    pub fn nlp_gain(&self) -> Option<f32> {
+        self.nlp.as_ref().map(|nlp| nlp.gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn suppresses_far_end_single_talk_only() {
+        // Saturated echo leaves residual the linear filter cannot remove.
+        let mut far = white_noise(256 * 200, 0.5, 61);
+        let mut mic: Vec<f32> = echo(&far, &[(10, 0.5)]).iter().map(|&e| (3.0 * e).tanh() / 3.0).collect();
+        let near_end = 180 * 256;
+        far[near_end..].fill(0.0);
+        mic[near_end..].copy_from_slice(&white_noise(256 * 20, 0.1, 62));
+
+        let run = |level: Option<NlpLevel>| {
+            let mut aec = FdafAec::new(512, 0.2);
+            aec.set_nlp(level);
+            let output = process_all(&mut aec, &far, &mic);
+            (output, aec.nlp_gain())
+        };
+        let (linear, _) = run(None);
+        let echo_only = 150 * 256..180 * 256;
+        let mut previous = crate::mean_square(&linear[echo_only.clone()]);
+        for level in [NlpLevel::Conservative, NlpLevel::Moderate, NlpLevel::Aggressive] {
+            let (output, gain) = run(Some(level));
+            let residual = crate::mean_square(&output[echo_only.clone()]);
+            assert!(residual < previous / 2.0, "{:?}: {} vs {}", level, residual, previous);
+            previous = residual;
+
+            // Near-end speech passes untouched once the far-end has stopped.
+            assert_eq!(gain, Some(1.0));
+            let tail = 190 * 256..200 * 256;
+            assert_eq!(output[tail.clone()], linear[tail]);
+        }
+    }
+}
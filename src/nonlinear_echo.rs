@@ -0,0 +1,202 @@
+//! Hammerstein model of loudspeaker distortion.
+//!
+//! Small loudspeakers driven close to their limits add harmonic distortion to the far-end
+//! signal before it reaches the room. The distorted part of the echo is not a linear function
+//! of the reference, so the linear filter cannot cancel it and leaves it in the output, most
+//! audibly during loud far-end passages. The Hammerstein model describes the loudspeaker as a
+//! memoryless nonlinearity followed by the linear room response. The nonlinearity is expanded
+//! into powers of the far-end signal, and every power above the first gets a branch with its
+//! own frequency-domain filter, adapted by normalized LMS on the common error signal. The
+//! echo estimate is the sum of the linear filter and all branches.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+use rustfft::Fft;
+use std::sync::Arc;
+
+/// Parameters of the nonlinear echo model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NonlinearEchoConfig {
+    /// The highest power of the far-end signal in the basis expansion. Every power from 2 up
+    /// to `order` gets a filter branch, so the cost grows by one filter per power.
+    pub order: u32,
+    /// The step size of the branch filters, relative to the step size of the linear filter.
+    pub relative_step_size: f32,
+}
+
+impl Default for NonlinearEchoConfig {
+    fn default() -> Self {
+        Self { order: 3, relative_step_size: 0.5 }
+    }
+}
+
+/// The filter of one power of the far-end signal.
+#[derive(Debug, Clone)]
+struct Branch {
+    power: i32,
+    far_end_buffer: Vec<f32>,
+    x_f: DVector<Complex<f32>>,
+    psd: DVector<f32>,
+    weights: DVector<Complex<f32>>,
+}
+
+/// The branches of the Hammerstein model above the linear filter.
+#[derive(Debug, Clone)]
+pub(crate) struct NonlinearEcho {
+    pub(crate) config: NonlinearEchoConfig,
+    branches: Vec<Branch>,
+}
+
+impl NonlinearEcho {
+    pub(crate) fn new(config: NonlinearEchoConfig, fft_size: usize, psd_initial: f32) -> Self {
+        assert!(config.order >= 2, "The nonlinear echo model needs an order of at least 2.");
+        let branches = (2..=config.order as i32)
+            .map(|power| Branch {
+                power,
+                far_end_buffer: vec![0.0; fft_size],
+                x_f: DVector::from_element(fft_size, Complex::new(0.0, 0.0)),
+                psd: DVector::from_element(fft_size, psd_initial),
+                weights: DVector::from_element(fft_size, Complex::new(0.0, 0.0)),
+            })
+            .collect();
+        Self { config, branches }
+    }
+
+    /// Clears the weights, the far-end PSDs and the far-end history of every branch.
+    pub(crate) fn reset(&mut self, psd_initial: f32) {
+        for branch in &mut self.branches {
+            branch.far_end_buffer.fill(0.0);
+            branch.psd.fill(psd_initial);
+            branch.weights.fill(Complex::new(0.0, 0.0));
+        }
+    }
+
+    /// Returns the echo estimate of all branches for the current far-end frame.
+    fn estimate(
+        &mut self,
+        fft: &Arc<dyn Fft<f32>>,
+        ifft: &Arc<dyn Fft<f32>>,
+        far_end_frame: &[f32],
+        smoothing: f32,
+        psd_floor: f32,
+    ) -> Vec<f32> {
+        let frame_size = far_end_frame.len();
+        let mut echo = vec![0.0; frame_size];
+        for branch in &mut self.branches {
+            branch.far_end_buffer.copy_within(frame_size.., 0);
+            for (x, &sample) in branch.far_end_buffer[frame_size..].iter_mut().zip(far_end_frame) {
+                *x = sample.powi(branch.power);
+            }
+            let mut x_f: Vec<Complex<f32>> = branch.far_end_buffer.iter().map(|&x| Complex::new(x, 0.0)).collect();
+            fft.process(&mut x_f);
+            branch.x_f = DVector::from_vec(x_f);
+            for (p, x) in branch.psd.iter_mut().zip(branch.x_f.iter()) {
+                *p = (smoothing * *p + (1.0 - smoothing) * x.norm_sqr()).max(psd_floor);
+            }
+
+            let mut y = branch.weights.component_mul(&branch.x_f).as_slice().to_vec();
+            ifft.process(&mut y);
+            let scale = 1.0 / y.len() as f32;
+            for (echo, y) in echo.iter_mut().zip(&y[frame_size..]) {
+                *echo += y.re * scale;
+            }
+        }
+        echo
+    }
+
+    /// Adapts every branch towards the error spectrum `e_f` with step size `mu`.
+    ///
+    /// The powers of the far-end signal are strongly correlated with each other and with
+    /// the far-end signal itself, so the branches are normalized by the total power of all
+    /// filter inputs, `psd` being the far-end PSD of the linear filter. Normalizing each
+    /// branch by its own power alone lets their updates add up and diverge.
+    fn adapt(
+        &mut self,
+        fft: &Arc<dyn Fft<f32>>,
+        ifft: &Arc<dyn Fft<f32>>,
+        e_f: &DVector<Complex<f32>>,
+        mu: f32,
+        psd: &DVector<f32>,
+        regularization: &[f32],
+    ) {
+        let mu = mu * self.config.relative_step_size;
+        let total: Vec<f32> = (0..psd.len())
+            .map(|k| psd[k] + regularization[k] + self.branches.iter().map(|branch| branch.psd[k]).sum::<f32>())
+            .collect();
+        for branch in &mut self.branches {
+            let mut gradient = branch.x_f.map(|c| c.conj()).component_mul(e_f);
+            for (g, &total) in gradient.iter_mut().zip(&total) {
+                *g *= mu / total;
+            }
+            // Wrap-around in one branch would be compensated by the others and accumulate
+            crate::constraint::constrain_gradient(fft, ifft, &mut gradient, e_f.len() / 2);
+            branch.weights += gradient;
+        }
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the nonlinear echo model. The branch
+    /// filters start from zero.
+    pub fn set_nonlinear_echo(&mut self, config: Option<NonlinearEchoConfig>) {
+        self.note_config_change();
+        let psd_initial = self.normalization.psd_initial.max(self.normalization.psd_floor);
+        self.nonlinear_echo = config.map(|config| NonlinearEcho::new(config, self.fft_size, psd_initial));
+    }
+
+    /// Returns the impulse response of the branch for the given power of the far-end signal,
+    /// or `None` if the nonlinear echo model is disabled or has no such branch.
+    pub fn nonlinear_impulse_response(&self, power: u32) -> Option<Vec<f32>> {
+        let branch = self.nonlinear_echo.as_ref()?.branches.iter().find(|branch| branch.power == power as i32)?;
+        let mut h = branch.weights.as_slice().to_vec();
+        self.ifft.process(&mut h);
+        let scale = 1.0 / self.fft_size as f32;
+        Some(h.iter().take(self.frame_size).map(|c| c.re * scale).collect())
+    }
+
+    /// Adds the echo estimate of the nonlinear branches to the linear echo estimate.
+    pub(crate) fn add_nonlinear_echo(&mut self, far_end_frame: &[f32], mut estimated_echo: DVector<f32>) -> DVector<f32> {
+        if let Some(nonlinear_echo) = self.nonlinear_echo.as_mut() {
+            let echo = nonlinear_echo.estimate(&self.fft, &self.ifft, far_end_frame, self.smoothing_factor, self.normalization.psd_floor);
+            for (estimate, echo) in estimated_echo.iter_mut().zip(echo) {
+                *estimate += echo;
+            }
+        }
+        estimated_echo
+    }
+
+    /// Adapts the nonlinear branches with the step size of the linear filter.
+    pub(crate) fn adapt_nonlinear_echo(&mut self, e_f: &DVector<Complex<f32>>, mu: f32) {
+        if let Some(nonlinear_echo) = self.nonlinear_echo.as_mut() {
+            nonlinear_echo.adapt(&self.fft, &self.ifft, e_f, mu, &self.psd, self.regularizer.values());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn cancels_loudspeaker_distortion() {
+        let far = white_noise(256 * 400, 0.9, 101);
+        // A loudspeaker with quadratic and cubic distortion
+        let distorted: Vec<f32> = far.iter().map(|&x| x + 0.3 * x * x - 0.2 * x * x * x).collect();
+        let mic = echo(&distorted, &[(10, 0.5), (30, -0.2)]);
+        let run = |nonlinear_echo: Option<NonlinearEchoConfig>| {
+            let mut aec = FdafAec::new(512, 0.5);
+            aec.set_nonlinear_echo(nonlinear_echo);
+            aec.prime_far_end(&white_noise(2048, 0.9, 102));
+            let output = process_all(&mut aec, &far, &mic);
+            (crate::mean_square(&output[350 * 256..]), aec.nonlinear_impulse_response(2))
+        };
+        let (linear, _) = run(None);
+        let (nonlinear, quadratic) = run(Some(NonlinearEchoConfig::default()));
+        assert!(nonlinear < linear / 10.0, "nonlinear {} linear {}", nonlinear, linear);
+        let quadratic = quadratic.unwrap();
+        assert!((quadratic[10] - 0.15).abs() < 0.02, "tap was {}", quadratic[10]);
+    }
+}
@@ -0,0 +1,117 @@
+//! Overlap-add synthesis of the echo estimate.
+//!
+//! The canceller normally computes the echo estimate with overlap-save: the filter is applied
+//! to the spectrum of the last two far-end frames and the wrapped first half of the result is
+//! discarded. Pipelines built around overlap-add block convolution expect the other scheme:
+//! the current far-end frame is zero-padded to the FFT size and filtered, the first half of
+//! the result is added to the tail left over from the previous frame, and the second half
+//! becomes the new tail. Both schemes compute the same linear convolution as long as the
+//! filter has no taps beyond `fft_size / 2`, so overlap-add always uses the constrained
+//! weight update. The adaptation itself is shared: the weight update still runs on the
+//! overlap-save spectra, only the synthesis of the echo estimate differs.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+use rustfft::Fft;
+use std::sync::Arc;
+
+/// How the echo estimate is synthesized from the frequency-domain filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockMethod {
+    /// Filter the last two far-end frames and discard the wrapped half.
+    #[default]
+    OverlapSave,
+    /// Filter the zero-padded current frame and add the tail of the previous one. Implies
+    /// the constrained weight update.
+    OverlapAdd,
+}
+
+/// The tail of the previous frame's convolution.
+#[derive(Debug, Clone)]
+pub(crate) struct OverlapAdd {
+    tail: Vec<f32>,
+}
+
+impl OverlapAdd {
+    pub(crate) fn new(frame_size: usize) -> Self {
+        Self { tail: vec![0.0; frame_size] }
+    }
+
+    /// Clears the tail of the previous frame.
+    pub(crate) fn reset(&mut self) {
+        self.tail.fill(0.0);
+    }
+
+    /// Returns the echo estimate of the current frame.
+    pub(crate) fn estimate(
+        &mut self,
+        fft: &Arc<dyn Fft<f32>>,
+        ifft: &Arc<dyn Fft<f32>>,
+        weights: &DVector<Complex<f32>>,
+        far_end_frame: &[f32],
+    ) -> DVector<f32> {
+        let frame_size = self.tail.len();
+        let mut y = vec![Complex::new(0.0, 0.0); 2 * frame_size];
+        for (y, &x) in y.iter_mut().zip(far_end_frame) {
+            *y = Complex::new(x, 0.0);
+        }
+        fft.process(&mut y);
+        for (y, w) in y.iter_mut().zip(weights.iter()) {
+            *y *= w;
+        }
+        ifft.process(&mut y);
+
+        let scale = 1.0 / y.len() as f32;
+        let echo = DVector::from_iterator(frame_size, y[..frame_size].iter().zip(&self.tail).map(|(y, tail)| y.re * scale + tail));
+        for (tail, y) in self.tail.iter_mut().zip(&y[frame_size..]) {
+            *tail = y.re * scale;
+        }
+        echo
+    }
+}
+
+impl FdafAec {
+    /// Returns how the echo estimate is synthesized.
+    pub fn block_method(&self) -> BlockMethod {
+        match self.overlap_add {
+            Some(_) => BlockMethod::OverlapAdd,
+            None => BlockMethod::OverlapSave,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use crate::FdafAecConfig;
+
+    #[test]
+    fn matches_overlap_save_synthesis() {
+        let far = white_noise(256 * 150, 0.3, 83);
+        let mic = echo(&far, &[(12, 0.5), (150, -0.2)]);
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, block_method: BlockMethod::OverlapAdd, ..FdafAecConfig::default() };
+        let mut aec = FdafAec::with_config(config);
+        let output = process_all(&mut aec, &far, &mic);
+        assert_eq!(aec.block_method(), BlockMethod::OverlapAdd);
+        assert!(aec.is_constrained());
+        let tail = output.len() - 20 * 256;
+        assert!(crate::mean_square(&output[tail..]) < crate::mean_square(&mic[tail..]) / 1000.0);
+
+        // With the same constrained weights, both schemes produce the same estimate.
+        let mut ola = OverlapAdd::new(256);
+        ola.estimate(&aec.fft, &aec.ifft, &aec.weights, &far[10 * 256..11 * 256]);
+        let ola_echo = ola.estimate(&aec.fft, &aec.ifft, &aec.weights, &far[11 * 256..12 * 256]);
+        let mut y: Vec<Complex<f32>> = far[10 * 256..12 * 256].iter().map(|&x| Complex::new(x, 0.0)).collect();
+        aec.fft.process(&mut y);
+        for (y, w) in y.iter_mut().zip(aec.weights.iter()) {
+            *y *= w;
+        }
+        aec.ifft.process(&mut y);
+        for (a, b) in ola_echo.iter().zip(&y[256..]) {
+            assert!((a - b.re / 512.0).abs() < 1e-4, "{} != {}", a, b.re / 512.0);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Models of device output processing applied to the far-end reference.
+//!
+//! Many devices run dynamics processing, e.g. a speaker-protection limiter or a loudness
+//! compressor, after the point where the application hands over the playback signal that
+//! doubles as the far-end reference. The loudspeaker then plays a signal whose level no
+//! longer follows the reference linearly, which a linear filter cannot model: the echo
+//! leaks through whenever the device DSP changes its gain. A playback path model is a
+//! closure that is applied to every far-end frame before cancellation, so the reference
+//! matches what is actually played out. [`PlaybackCompressor`] is a ready-made model of a
+//! simple feed-forward compressor or limiter.
+
+use crate::FdafAec;
+
+/// A closure transforming a far-end frame in place into the signal the loudspeaker plays.
+pub(crate) type PlaybackPathModel = Box<dyn FnMut(&mut [f32]) + Send>;
+
+/// Parameters of a [`PlaybackCompressor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlaybackCompressorConfig {
+    /// The level, in dBFS of the sample envelope, above which the gain is reduced.
+    pub threshold_dbfs: f32,
+    /// How strongly levels above the threshold are reduced. A large ratio makes the
+    /// compressor a limiter.
+    pub ratio: f32,
+    /// The time constant, in seconds, with which the envelope follows rising levels.
+    pub attack_s: f32,
+    /// The time constant, in seconds, with which the envelope follows falling levels.
+    pub release_s: f32,
+    /// The gain, in dB, applied after compression.
+    pub makeup_gain_db: f32,
+}
+
+impl Default for PlaybackCompressorConfig {
+    fn default() -> Self {
+        Self { threshold_dbfs: -12.0, ratio: 4.0, attack_s: 0.005, release_s: 0.1, makeup_gain_db: 0.0 }
+    }
+}
+
+/// A feed-forward compressor with a per-sample envelope follower, for use as a playback
+/// path model.
+#[derive(Debug, Clone)]
+pub struct PlaybackCompressor {
+    config: PlaybackCompressorConfig,
+    attack: f32,
+    release: f32,
+    envelope: f32,
+}
+
+impl PlaybackCompressor {
+    /// Creates a compressor for audio at `sample_rate`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the ratio is below 1.
+    pub fn new(config: PlaybackCompressorConfig, sample_rate: u32) -> Self {
+        assert!(config.ratio >= 1.0, "The compression ratio must be at least 1.");
+        let sample_s = 1.0 / sample_rate as f32;
+        Self {
+            config,
+            attack: (-sample_s / config.attack_s).exp(),
+            release: (-sample_s / config.release_s).exp(),
+            envelope: 0.0,
+        }
+    }
+
+    /// Compresses a frame in place.
+    pub fn process(&mut self, frame: &mut [f32]) {
+        let slope = 1.0 - 1.0 / self.config.ratio;
+        for sample in frame.iter_mut() {
+            let level = sample.abs();
+            let a = if level > self.envelope { self.attack } else { self.release };
+            self.envelope = a * self.envelope + (1.0 - a) * level;
+            let over_db = 20.0 * self.envelope.max(1e-12).log10() - self.config.threshold_dbfs;
+            let gain_db = self.config.makeup_gain_db - over_db.max(0.0) * slope;
+            *sample *= 10f32.powf(gain_db / 20.0);
+        }
+    }
+}
+
+impl FdafAec {
+    /// Installs a closure that models the device processing between the far-end reference
+    /// and the loudspeaker, replacing any previous model.
+    ///
+    /// The closure is called once per frame with the far-end frame, after resampling and
+    /// before any other processing, and must transform it in place. It must not change the
+    /// delay of the signal, which would shift the estimated echo path.
+    pub fn set_playback_path_model(&mut self, model: impl FnMut(&mut [f32]) + Send + 'static) {
+        self.playback_path = Some(Box::new(model));
+    }
+
+    /// Removes the playback path model, so the far-end reference is used as supplied.
+    pub fn clear_playback_path_model(&mut self) {
+        self.playback_path = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn model_of_output_compressor_restores_cancellation() {
+        // Loud bursts alternating with quiet passages drive the device compressor.
+        let config = PlaybackCompressorConfig { threshold_dbfs: -20.0, ..PlaybackCompressorConfig::default() };
+        let mut far = white_noise(256 * 200, 0.5, 87);
+        for (i, chunk) in far.chunks_mut(256 * 10).enumerate() {
+            let level = if i % 2 == 0 { 1.0 } else { 0.05 };
+            chunk.iter_mut().for_each(|x| *x *= level);
+        }
+        let mut played = far.clone();
+        PlaybackCompressor::new(config, 16000).process(&mut played);
+        let mic = echo(&played, &[(10, 0.5)]);
+
+        let run = |aec: &mut FdafAec| -> f32 {
+            let output = process_all(aec, &far, &mic);
+            let tail = output.len() - 40 * 256;
+            crate::mean_square(&mic[tail..]) / crate::mean_square(&output[tail..])
+        };
+        let unmodeled = run(&mut FdafAec::new(512, 0.5));
+        let mut aec = FdafAec::new(512, 0.5);
+        let mut compressor = PlaybackCompressor::new(config, 16000);
+        aec.set_playback_path_model(move |frame| compressor.process(frame));
+        let modeled = run(&mut aec);
+        assert!(modeled > 1000.0 && modeled > 50.0 * unmodeled, "modeled {} unmodeled {}", modeled, unmodeled);
+    }
+}
@@ -0,0 +1,116 @@
+//! String-keyed registry of post-filters.
+//!
+//! The counterpart of [`engines`](crate::engines) for the stages after the linear filter.
+//! Every post-filter is registered under a stable identifier and enables its stage, with
+//! default parameters, in an [`FdafAecConfig`]; the configuration is then handed to an
+//! engine. Applying several post-filters enables all of them, and they run in the fixed
+//! order of the canceller regardless of the order they were applied in. The identifiers
+//! follow the same stability rules as the engine identifiers.
+
+use crate::{AgcConfig, ComfortNoiseConfig, FdafAecConfig, LateReverbConfig, NlpLevel, PostFilterConfig, ResidualCeilingConfig};
+
+/// The late reverberation suppressor with an estimated decay time.
+pub const LATE_REVERB: &str = "late-reverb";
+/// The Wiener residual echo suppressor.
+pub const WIENER: &str = "wiener";
+/// The non-linear processor at [`NlpLevel::Conservative`].
+pub const NLP_CONSERVATIVE: &str = "nlp-conservative";
+/// The non-linear processor at [`NlpLevel::Moderate`].
+pub const NLP_MODERATE: &str = "nlp-moderate";
+/// The non-linear processor at [`NlpLevel::Aggressive`].
+pub const NLP_AGGRESSIVE: &str = "nlp-aggressive";
+/// The residual echo ceiling.
+pub const RESIDUAL_CEILING: &str = "residual-ceiling";
+/// Comfort noise injection.
+pub const COMFORT_NOISE: &str = "comfort-noise";
+/// Automatic gain control of the output.
+pub const AGC: &str = "agc";
+
+/// A registered post-filter.
+#[derive(Debug, Clone, Copy)]
+pub struct PostFilterEntry {
+    /// The stable identifier of the post-filter.
+    pub name: &'static str,
+    /// A one-line description for listings.
+    pub description: &'static str,
+    apply: fn(&mut FdafAecConfig),
+}
+
+impl PostFilterEntry {
+    /// Enables the post-filter in a configuration, replacing the parameters of its stage.
+    pub fn apply(&self, config: &mut FdafAecConfig) {
+        (self.apply)(config)
+    }
+}
+
+static POST_FILTERS: &[PostFilterEntry] = &[
+    PostFilterEntry {
+        name: LATE_REVERB,
+        description: "suppresses the reverberation beyond the filter length",
+        apply: |config| config.late_reverb = Some(LateReverbConfig::default()),
+    },
+    PostFilterEntry {
+        name: WIENER,
+        description: "suppresses the residual echo with a per-bin Wiener gain",
+        apply: |config| config.post_filter = Some(PostFilterConfig::default()),
+    },
+    PostFilterEntry {
+        name: NLP_CONSERVATIVE,
+        description: "attenuates the residual echo by 10 dB",
+        apply: |config| config.nlp = Some(NlpLevel::Conservative),
+    },
+    PostFilterEntry {
+        name: NLP_MODERATE,
+        description: "attenuates the residual echo by 20 dB",
+        apply: |config| config.nlp = Some(NlpLevel::Moderate),
+    },
+    PostFilterEntry {
+        name: NLP_AGGRESSIVE,
+        description: "attenuates the residual echo by 40 dB",
+        apply: |config| config.nlp = Some(NlpLevel::Aggressive),
+    },
+    PostFilterEntry {
+        name: RESIDUAL_CEILING,
+        description: "limits the residual echo to a level below the near-end",
+        apply: |config| config.residual_ceiling = Some(ResidualCeilingConfig::default()),
+    },
+    PostFilterEntry {
+        name: COMFORT_NOISE,
+        description: "fills the suppressed output with noise",
+        apply: |config| config.comfort_noise = Some(ComfortNoiseConfig::default()),
+    },
+    PostFilterEntry {
+        name: AGC,
+        description: "normalizes the output level",
+        apply: |config| config.agc = Some(AgcConfig::default()),
+    },
+];
+
+/// Returns all registered post-filters, in processing order.
+pub fn all() -> &'static [PostFilterEntry] {
+    POST_FILTERS
+}
+
+/// Returns the post-filter registered under `name`.
+pub fn by_name(name: &str) -> Option<&'static PostFilterEntry> {
+    POST_FILTERS.iter().find(|post_filter| post_filter.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engines;
+
+    #[test]
+    fn pipeline_from_names() {
+        let mut config = FdafAecConfig { fft_size: 512, ..FdafAecConfig::default() };
+        for name in [NLP_AGGRESSIVE, WIENER, COMFORT_NOISE] {
+            by_name(name).unwrap().apply(&mut config);
+        }
+        assert_eq!(config.nlp, Some(NlpLevel::Aggressive));
+        assert!(config.post_filter.is_some() && config.comfort_noise.is_some());
+        assert!(config.agc.is_none());
+        assert!(by_name("spectral-subtraction").is_none());
+        assert!(engines::by_name(engines::FDAF_NLMS).unwrap().build(&config).is_ok());
+    }
+}
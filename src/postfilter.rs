@@ -0,0 +1,159 @@
+//! Residual echo suppression after the linear filter.
+//!
+//! Even a converged linear filter leaves audible residual echo: misadjustment, echo path
+//! changes and loudspeaker nonlinearities all show up in the error signal. The post-filter
+//! estimates the residual echo power spectrum from the echo estimate, scaled by a per-bin
+//! leakage factor, and applies a Wiener gain per bin to the output. The leakage is the ratio
+//! of error power to echo estimate power, learned only while the far-end talks alone, so
+//! near-end speech does not inflate it. The gains are applied as a short causal filter, see
+//! the `gain_filter` module, so they do not wrap the output around the block
+//! edges.
+
+use crate::gain_filter::{causal_response, filter_frame};
+use crate::{DuplexState, FdafAec};
+use num_complex::Complex;
+use rustfft::Fft;
+use std::sync::Arc;
+
+/// Parameters of the residual echo post-filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PostFilterConfig {
+    /// Smoothing factor of the power spectra.
+    pub smoothing: f32,
+    /// Smoothing factor of the per-bin leakage estimate.
+    pub leakage_smoothing: f32,
+    /// The factor applied to the residual echo estimate before the Wiener gain is computed.
+    /// Values above 1.0 suppress more aggressively.
+    pub overestimation: f32,
+    /// Lower bound of the per-bin suppression gain.
+    pub min_gain: f32,
+}
+
+impl Default for PostFilterConfig {
+    fn default() -> Self {
+        Self { smoothing: 0.7, leakage_smoothing: 0.95, overestimation: 2.0, min_gain: 0.1 }
+    }
+}
+
+/// A Wiener-gain residual echo suppressor.
+#[derive(Debug, Clone)]
+pub(crate) struct PostFilter {
+    pub(crate) config: PostFilterConfig,
+    output_buffer: Vec<f32>,
+    echo_buffer: Vec<f32>,
+    error_power: Vec<f32>,
+    echo_power: Vec<f32>,
+    leakage: Vec<f32>,
+    mean_gain: f32,
+}
+
+impl PostFilter {
+    pub(crate) fn new(config: PostFilterConfig, fft_size: usize) -> Self {
+        Self {
+            config,
+            output_buffer: vec![0.0; fft_size],
+            echo_buffer: vec![0.0; fft_size],
+            error_power: vec![0.0; fft_size / 2 + 1],
+            echo_power: vec![0.0; fft_size / 2 + 1],
+            leakage: vec![0.0; fft_size / 2 + 1],
+            mean_gain: 1.0,
+        }
+    }
+
+    /// Returns the suppressed output frame.
+    ///
+    /// `output` is the frame about to be returned by the canceller, `echo` the echo
+    /// estimate the linear filter subtracted from it, and `duplex_state` the conversation
+    /// state of the frame.
+    pub(crate) fn process(
+        &mut self,
+        fft: &Arc<dyn Fft<f32>>,
+        ifft: &Arc<dyn Fft<f32>>,
+        output: &[f32],
+        echo: &[f32],
+        duplex_state: DuplexState,
+    ) -> Vec<f32> {
+        let frame_size = output.len();
+        let bins = self.error_power.len();
+        let spectrum = |buffer: &mut Vec<f32>, frame: &[f32]| {
+            buffer.copy_within(frame_size.., 0);
+            buffer[frame_size..].copy_from_slice(frame);
+            let mut spectrum: Vec<Complex<f32>> = buffer.iter().map(|&x| Complex::new(x, 0.0)).collect();
+            fft.process(&mut spectrum);
+            spectrum
+        };
+        let e_f = spectrum(&mut self.output_buffer, output);
+        let y_f = spectrum(&mut self.echo_buffer, echo);
+
+        let a = self.config.smoothing;
+        let b = self.config.leakage_smoothing;
+        let far_end_only = duplex_state == DuplexState::FarEndOnly;
+        let mut gains = vec![1.0; bins];
+        for k in 0..bins {
+            self.error_power[k] = a * self.error_power[k] + (1.0 - a) * e_f[k].norm_sqr();
+            self.echo_power[k] = a * self.echo_power[k] + (1.0 - a) * y_f[k].norm_sqr();
+            if far_end_only {
+                // Everything left in the output is residual echo
+                let leakage = (self.error_power[k] / (self.echo_power[k] + 1e-20)).min(1.0);
+                self.leakage[k] = b * self.leakage[k] + (1.0 - b) * leakage;
+            }
+            let residual = self.config.overestimation * self.leakage[k] * self.echo_power[k];
+            gains[k] = (1.0 - residual / (self.error_power[k] + 1e-20)).clamp(self.config.min_gain, 1.0);
+        }
+        self.mean_gain = gains.iter().sum::<f32>() / bins as f32;
+        filter_frame(ifft, e_f, &causal_response(fft, ifft, &gains))
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the residual echo post-filter.
+    pub fn set_post_filter(&mut self, config: Option<PostFilterConfig>) {
+        self.note_config_change();
+        self.post_filter = config.map(|config| PostFilter::new(config, self.fft_size));
+    }
+
+    /// Returns the mean suppression gain the post-filter applied to the last frame, from
+    /// the configured minimum gain to 1.0, or `None` if the post-filter is disabled.
+    pub fn post_filter_gain(&self) -> Option<f32> {
+        self.post_filter.as_ref().map(|post_filter| post_filter.mean_gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn suppresses_nonlinear_residual_echo() {
+        // A loudspeaker that saturates leaves echo the linear filter cannot model.
+        let far = white_noise(256 * 200, 0.5, 59);
+        let mut mic: Vec<f32> = echo(&far, &[(10, 0.5)]).iter().map(|&e| (3.0 * e).tanh() / 3.0).collect();
+        // Near-end speech without far-end over the last 20 frames.
+        let near = white_noise(256 * 20, 0.1, 60);
+        let silence = 180 * 256;
+        let mut far = far;
+        far[silence..].fill(0.0);
+        for (m, n) in mic[silence..].iter_mut().zip(&near) {
+            *m = *n;
+        }
+
+        let run = |post_filter: Option<PostFilterConfig>| {
+            let mut aec = FdafAec::new(512, 0.2);
+            aec.set_post_filter(post_filter);
+            let output = process_all(&mut aec, &far, &mic);
+            (output, aec.post_filter_gain())
+        };
+        let (linear, _) = run(None);
+        let (filtered, gain) = run(Some(PostFilterConfig::default()));
+
+        let echo_only = 150 * 256..180 * 256;
+        assert!(crate::mean_square(&filtered[echo_only.clone()]) < crate::mean_square(&linear[echo_only]) / 4.0);
+        // Near-end speech without far-end passes unchanged.
+        assert!(gain.unwrap() > 0.99);
+        let near_end = 185 * 256..200 * 256;
+        let ratio = crate::mean_square(&filtered[near_end.clone()]) / crate::mean_square(&linear[near_end]);
+        assert!(ratio > 0.95, "near-end power ratio {}", ratio);
+    }
+}
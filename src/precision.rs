@@ -0,0 +1,146 @@
+//! Numeric precision of individual processing stages.
+//!
+//! The canceller computes in `f32` throughout, which is accurate enough for most stages and
+//! keeps the FFTs fast. Two stages accumulate over long time spans and can lose accuracy:
+//! the recursive smoothing of the far-end PSD with a factor close to 1.0, and the weights,
+//! whose per-frame updates become tiny next to the weights once the filter has converged.
+//! The frame energies behind the metrics sum many squared samples, which loses accuracy on
+//! long frames. Each of the three stages can run its accumulation in `f64` on its own, so
+//! the extra cost is only paid where it matters for a deployment.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+
+/// The floating-point precision of a processing stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Precision {
+    /// 32-bit floating point.
+    #[default]
+    Single,
+    /// 64-bit floating point.
+    Double,
+}
+
+/// The precision of every configurable processing stage. Single precision by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PrecisionConfig {
+    /// The recursive smoothing of the far-end PSD that normalizes the weight update.
+    pub psd_smoothing: Precision,
+    /// The accumulation of the per-frame updates into the filter weights.
+    pub adaptation: Precision,
+    /// The frame energies behind the quality, duplex and diagnostic metrics.
+    pub metrics: Precision,
+}
+
+/// The `f64` shadows of the stages running in double precision.
+///
+/// Other stages, e.g. leakage or a restored profile, modify the `f32` values directly. A
+/// shadow that no longer rounds to the `f32` value is resynchronized from it before use.
+#[derive(Debug, Clone)]
+pub(crate) struct PrecisionState {
+    pub(crate) config: PrecisionConfig,
+    psd: Option<DVector<f64>>,
+    weights: Option<DVector<Complex<f64>>>,
+}
+
+impl PrecisionState {
+    pub(crate) fn new(config: PrecisionConfig, psd: &DVector<f32>, weights: &DVector<Complex<f32>>) -> Self {
+        Self {
+            config,
+            psd: (config.psd_smoothing == Precision::Double).then(|| psd.map(f64::from)),
+            weights: (config.adaptation == Precision::Double).then(|| weights.map(|w| Complex::new(w.re as f64, w.im as f64))),
+        }
+    }
+
+    /// Smooths the far-end PSD towards the power of the current far-end spectrum.
+    pub(crate) fn smooth_psd(&mut self, psd: &mut DVector<f32>, x_f: &DVector<Complex<f32>>, smoothing: f32, floor: f32) {
+        match self.psd.as_mut() {
+            Some(shadow) => {
+                let smoothing = smoothing as f64;
+                for ((p, s), x) in psd.iter_mut().zip(shadow.iter_mut()).zip(x_f.iter()) {
+                    if *s as f32 != *p {
+                        *s = *p as f64;
+                    }
+                    let power = (x.re as f64).powi(2) + (x.im as f64).powi(2);
+                    *s = (smoothing * *s + (1.0 - smoothing) * power).max(floor as f64);
+                    *p = *s as f32;
+                }
+            }
+            None => {
+                for (p, x) in psd.iter_mut().zip(x_f.iter()) {
+                    *p = (smoothing * *p + (1.0 - smoothing) * x.norm_sqr()).max(floor);
+                }
+            }
+        }
+    }
+
+    /// Adds a weight update to the weights.
+    pub(crate) fn accumulate(&mut self, weights: &mut DVector<Complex<f32>>, update: DVector<Complex<f32>>) {
+        match self.weights.as_mut() {
+            Some(shadow) => {
+                for ((w, s), u) in weights.iter_mut().zip(shadow.iter_mut()).zip(update.iter()) {
+                    if Complex::new(s.re as f32, s.im as f32) != *w {
+                        *s = Complex::new(w.re as f64, w.im as f64);
+                    }
+                    *s += Complex::new(u.re as f64, u.im as f64);
+                    *w = Complex::new(s.re as f32, s.im as f32);
+                }
+            }
+            None => *weights += update,
+        }
+    }
+
+    /// Returns the mean-square energy of a block of samples.
+    pub(crate) fn mean_square<'a>(&self, samples: impl IntoIterator<Item = &'a f32>) -> f32 {
+        match self.config.metrics {
+            Precision::Single => crate::mean_square(samples),
+            Precision::Double => {
+                let (sum, count) = samples.into_iter().fold((0.0f64, 0usize), |(sum, count), &x| (sum + (x as f64).powi(2), count + 1));
+                if count == 0 { 0.0 } else { (sum / count as f64) as f32 }
+            }
+        }
+    }
+}
+
+impl FdafAec {
+    /// Changes the precision of the processing stages. Stages switched to double precision
+    /// continue from their current single-precision state.
+    pub fn set_precision(&mut self, config: PrecisionConfig) {
+        self.note_config_change();
+        self.precision = PrecisionState::new(config, &self.psd, &self.weights);
+    }
+
+    /// Returns the precision of the processing stages.
+    pub fn precision(&self) -> PrecisionConfig {
+        self.precision.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn double_precision_keeps_small_contributions() {
+        // Updates far below the f32 resolution of the weight are lost in single precision.
+        let update = DVector::from_element(4, Complex::new(1e-8, 0.0));
+        let mut results = Vec::new();
+        for precision in [Precision::Single, Precision::Double] {
+            let config = PrecisionConfig { adaptation: precision, psd_smoothing: precision, metrics: precision };
+            let mut weights = DVector::from_element(4, Complex::new(1.0, 0.0));
+            let mut psd = DVector::from_element(4, 1.0);
+            let mut state = PrecisionState::new(config, &psd, &weights);
+            for _ in 0..10_000 {
+                state.accumulate(&mut weights, update.clone());
+                state.smooth_psd(&mut psd, &DVector::from_element(4, Complex::new(1.0 + 1e-4, 0.0)), 0.9999, 0.0);
+            }
+            results.push((weights[0].re, psd[0]));
+        }
+        assert_eq!(results[0].0, 1.0);
+        assert!((results[1].0 - 1.0001).abs() < 1e-6);
+        assert!(results[1].1 > results[0].1);
+    }
+}
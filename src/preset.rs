@@ -0,0 +1,130 @@
+//! Tuned parameter sets for common acoustic environments.
+
+use crate::config::single_partition_fft_size;
+use crate::{
+    CoherenceDtdConfig, ContentMode, FdafAec, FdafAecConfig, GuardBandConfig, NlpLevel, NonlinearEchoConfig, NormalizationConfig, TonalityConfig,
+};
+
+/// The far-end power per sample the PSD estimate starts from, that of a signal at about
+/// -25 dBFS RMS. Starting near a realistic level keeps the first updates of short frames,
+/// whose PSD estimate is slow to follow, from diverging.
+const NOMINAL_FAR_END_POWER: f32 = 0.003;
+
+/// An acoustic scenario with a tuned canceller configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Preset {
+    /// Car cabins: short reverberation but loud playback close to the microphone and
+    /// strong road noise, so adaptation is kept conservative.
+    Automotive,
+    /// Smart speakers and speakerphones: long room reverberation and high playback levels.
+    SmartSpeaker,
+    /// Headsets: a short, mostly mechanical coupling path that changes quickly when the
+    /// headset moves.
+    Headset,
+    /// Music playback: long tails, the gentler adaptation of [`ContentMode::Music`] and
+    /// tonality control so sustained notes do not destabilize the filter.
+    Music,
+}
+
+impl Preset {
+    /// Returns the echo tail length covered by this preset, in milliseconds.
+    pub fn tail_ms(&self) -> f32 {
+        match self {
+            Preset::Automotive => 64.0,
+            Preset::SmartSpeaker => 128.0,
+            Preset::Headset => 16.0,
+            Preset::Music => 256.0,
+        }
+    }
+
+    /// Returns the configuration of this preset for the given sample rate.
+    ///
+    /// The PSD smoothing follows the frame length of the chosen FFT size, see
+    /// [`FdafAecConfig::psd_smoothing_for`], so the long frames of long-tail presets follow
+    /// the far-end power as quickly as the default instead of diverging at startup.
+    pub fn config(&self, sample_rate: u32) -> FdafAecConfig {
+        let step_size = match self {
+            Preset::Automotive => 0.1,
+            Preset::SmartSpeaker => 0.2,
+            Preset::Headset => 0.3,
+            Preset::Music => 0.5,
+        };
+        let music = *self == Preset::Music;
+        let fft_size = single_partition_fft_size(sample_rate, self.tail_ms());
+        FdafAecConfig {
+            fft_size,
+            sample_rate,
+            step_size,
+            psd_smoothing: FdafAecConfig::psd_smoothing_for(fft_size, sample_rate),
+            normalization: NormalizationConfig { psd_initial: NOMINAL_FAR_END_POWER * fft_size as f32, ..NormalizationConfig::default() },
+            // The coherence detector does not depend on the echo return loss, which is low for
+            // loudspeakers close to the microphone. Road noise lowers the coherence of pure
+            // echo, and a headset path that moves must keep adapting, so both tolerate less
+            // coherence before slowing down.
+            coherence_dtd: Some(match self {
+                Preset::Automotive => CoherenceDtdConfig { coherent: 0.8, incoherent: 0.4, ..CoherenceDtdConfig::default() },
+                Preset::Headset => CoherenceDtdConfig { coherent: 0.85, incoherent: 0.45, ..CoherenceDtdConfig::default() },
+                Preset::SmartSpeaker | Preset::Music => CoherenceDtdConfig::default(),
+            }),
+            nlp: Some(match self {
+                Preset::Automotive | Preset::SmartSpeaker => NlpLevel::Moderate,
+                Preset::Headset | Preset::Music => NlpLevel::Conservative,
+            }),
+            // Small drivers played loud distort; headsets and music systems stay linear.
+            nonlinear_echo: match self {
+                Preset::Automotive => Some(NonlinearEchoConfig { order: 2, ..NonlinearEchoConfig::default() }),
+                Preset::SmartSpeaker => Some(NonlinearEchoConfig::default()),
+                Preset::Headset | Preset::Music => None,
+            },
+            // Music playback keeps its bass; speech scenarios ignore the rumble below 80 Hz.
+            guard_band: if music {
+                GuardBandConfig { exclude_dc: true, exclude_nyquist: true, low_guard_hz: 0.0 }
+            } else {
+                GuardBandConfig::speech()
+            },
+            tonality: music.then(TonalityConfig::default),
+            content_mode: if music { ContentMode::Music } else { ContentMode::Speech },
+            ..FdafAecConfig::default()
+        }
+    }
+}
+
+impl FdafAec {
+    /// Creates a new `FdafAec` tuned for the given acoustic scenario.
+    pub fn from_preset(preset: Preset, sample_rate: u32) -> Self {
+        Self::with_config(preset.config(sample_rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn presets_converge_without_startup_divergence() {
+        for sample_rate in [16000, 48000] {
+            for preset in [Preset::Automotive, Preset::SmartSpeaker, Preset::Headset, Preset::Music] {
+                let mut aec = FdafAec::from_preset(preset, sample_rate);
+                let config = aec.resolved_config().config;
+                let tail_samples = (preset.tail_ms() * sample_rate as f32 / 1000.0) as usize;
+                assert!(config.fft_size / 2 >= tail_samples, "{:?} does not cover its tail", preset);
+                assert!(aec.self_test().is_ok(), "{:?} at {} Hz: {:?}", preset, sample_rate, aec.self_test());
+
+                let frame_size = config.fft_size / 2;
+                let second = sample_rate as usize;
+                let far = white_noise(3 * second / frame_size * frame_size, 0.3, 7);
+                let mic = echo(&far, &[(frame_size / 8, 0.5), (frame_size / 4, -0.2)]);
+                let output = process_all(&mut aec, &far, &mic);
+                let erle_db = |range: std::ops::Range<usize>| {
+                    10.0 * (crate::mean_square(&mic[range.clone()]) / crate::mean_square(&output[range])).log10()
+                };
+                let first = erle_db(0..second);
+                let third = erle_db(2 * second..output.len());
+                assert!(first > 5.0, "{:?} at {} Hz: {} dB in the first second", preset, sample_rate, first);
+                assert!(third > 15.0, "{:?} at {} Hz: {} dB in the third second", preset, sample_rate, third);
+            }
+        }
+        assert_eq!(Preset::SmartSpeaker.config(16000).fft_size, 4096);
+    }
+}
@@ -0,0 +1,80 @@
+//! Preloading the far-end history before the first frame.
+//!
+//! A canceller that joins a playback stream already in progress starts with a far-end buffer
+//! of zeros, so the first frame cannot model the echo of samples rendered just before it,
+//! and the far-end power estimate needs a few frames to climb to the real level. Priming
+//! hands the canceller the recent playback history instead: the tail fills the far-end
+//! buffer, and every complete block of `fft_size` samples contributes to the initial power
+//! spectral density.
+
+use crate::FdafAec;
+use num_complex::Complex;
+
+impl FdafAec {
+    /// Preloads the far-end history rendered just before the first frame, oldest sample
+    /// first, at the processing sample rate.
+    ///
+    /// The last `fft_size / 2` samples become the previous far-end frame. If the history
+    /// holds at least `fft_size` samples, the far-end power spectral density is seeded with
+    /// the mean power spectrum of its blocks; shorter histories leave it untouched. The
+    /// history passes through the input high-pass filter, if enabled, to warm up its state.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a frame has already been processed.
+    pub fn prime_far_end(&mut self, history: &[f32]) {
+        assert_eq!(self.frames_processed, 0, "The far-end can only be primed before the first frame.");
+        let mut history = history.to_vec();
+        if let Some(filters) = self.high_pass.as_mut() {
+            filters.far_end.process(&mut history);
+        }
+
+        let tail = history.len().min(self.frame_size);
+        let buffer = self.far_end_buffer.as_mut_slice();
+        buffer.copy_within(tail.., 0);
+        let start = buffer.len() - tail;
+        buffer[start..].copy_from_slice(&history[history.len() - tail..]);
+
+        let blocks: Vec<&[f32]> = history.rchunks_exact(self.fft_size).collect();
+        if blocks.is_empty() {
+            return;
+        }
+        self.psd.fill(0.0);
+        for block in &blocks {
+            let mut spectrum: Vec<Complex<f32>> = block.iter().map(|&x| Complex::new(x, 0.0)).collect();
+            self.fft.process(&mut spectrum);
+            for (psd, x) in self.psd.iter_mut().zip(&spectrum) {
+                *psd += x.norm_sqr() / blocks.len() as f32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{echo, process_all, white_noise};
+    use crate::FdafAec;
+
+    #[test]
+    fn primed_canceller_avoids_cold_start_burst() {
+        let far = white_noise(256 * 60, 0.3, 70);
+        let mic = echo(&far, &[(40, 0.5), (200, -0.2)]);
+        let joined = 256 * 10;
+        let run = |prime: bool| {
+            let mut aec = FdafAec::new(512, 0.5);
+            if prime {
+                aec.prime_far_end(&far[..joined]);
+            }
+            process_all(&mut aec, &far[joined..], &mic[joined..])
+        };
+        let cold = run(false);
+        let primed = run(true);
+
+        // The first frames neither blow up on the initial power estimate nor miss the echo
+        // of the samples rendered before joining.
+        let start = 0..256 * 10;
+        let primed_power = crate::mean_square(&primed[start.clone()]);
+        assert!(primed_power < crate::mean_square(&cold[start.clone()]) / 2.0);
+        assert!(primed_power < crate::mean_square(&mic[joined..][start]) / 2.0, "primed output at {}", primed_power);
+    }
+}
@@ -0,0 +1,157 @@
+//! Persistent per-device adaptive state for warm starts.
+//!
+//! A [`DeviceProfile`] captures what the canceller has learned about a device's echo path so
+//! that the next session on the same device does not start from zero weights. With the `serde`
+//! feature enabled, profiles and [`ProfileStore`]s can be serialized with any serde format.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The learned echo path of one device.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceProfile {
+    /// The identifier of the device the profile was recorded on.
+    pub device_id: String,
+    /// The FFT size of the canceller that produced the profile.
+    pub fft_size: usize,
+    /// The converged frequency-domain filter weights.
+    pub weights: Vec<Complex<f32>>,
+    /// The far-end power spectral density at the time the profile was saved.
+    pub far_end_psd: Vec<f32>,
+    /// The delay of the strongest echo path tap, in samples.
+    pub estimated_delay: usize,
+    /// The broadband gain of the estimated echo path, i.e. the level mismatch between the
+    /// far-end reference and its echo in the microphone signal.
+    pub gain_mismatch: f32,
+}
+
+/// The error returned when a profile cannot be applied to a canceller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileError {
+    /// The profile was recorded with a different FFT size than the canceller uses.
+    FftSizeMismatch { expected: usize, found: usize },
+    /// The stored weight or PSD vectors do not match the recorded FFT size.
+    Corrupted,
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::FftSizeMismatch { expected, found } => {
+                write!(f, "profile FFT size {} does not match canceller FFT size {}", found, expected)
+            }
+            ProfileError::Corrupted => write!(f, "profile vectors do not match its FFT size"),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+/// A collection of device profiles keyed by device identifier.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProfileStore {
+    profiles: BTreeMap<String, DeviceProfile>,
+}
+
+impl ProfileStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a profile, replacing any previous profile for the same device.
+    pub fn insert(&mut self, profile: DeviceProfile) {
+        self.profiles.insert(profile.device_id.clone(), profile);
+    }
+
+    /// Returns the profile of a device, if one has been stored.
+    pub fn get(&self, device_id: &str) -> Option<&DeviceProfile> {
+        self.profiles.get(device_id)
+    }
+
+    /// Removes and returns the profile of a device.
+    pub fn remove(&mut self, device_id: &str) -> Option<DeviceProfile> {
+        self.profiles.remove(device_id)
+    }
+
+    /// Returns an iterator over the stored profiles, ordered by device identifier.
+    pub fn iter(&self) -> impl Iterator<Item = &DeviceProfile> {
+        self.profiles.values()
+    }
+}
+
+impl FdafAec {
+    /// Captures the current adaptive state as a profile for the given device.
+    pub fn save_profile(&self, device_id: impl Into<String>) -> DeviceProfile {
+        let ir = self.impulse_response();
+        DeviceProfile {
+            device_id: device_id.into(),
+            fft_size: self.fft_size,
+            weights: self.weights.as_slice().to_vec(),
+            far_end_psd: self.psd.as_slice().to_vec(),
+            estimated_delay: self.dominant_tap(),
+            gain_mismatch: ir.iter().map(|h| h * h).sum::<f32>().sqrt(),
+        }
+    }
+
+    /// Restores the adaptive state from a profile so that processing starts warm.
+    ///
+    /// The far-end history is left untouched.
+    pub fn load_profile(&mut self, profile: &DeviceProfile) -> Result<(), ProfileError> {
+        if profile.fft_size != self.fft_size {
+            return Err(ProfileError::FftSizeMismatch { expected: self.fft_size, found: profile.fft_size });
+        }
+        if profile.weights.len() != self.fft_size || profile.far_end_psd.len() != self.fft_size {
+            return Err(ProfileError::Corrupted);
+        }
+        self.weights = DVector::from_column_slice(&profile.weights);
+        self.psd = DVector::from_column_slice(&profile.far_end_psd);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::trained_aec;
+
+    #[test]
+    fn profile_round_trip_restores_weights() {
+        let profile = trained_aec(&[(12, 0.5), (30, 0.1)], 4).save_profile("usb-headset");
+        assert_eq!(profile.estimated_delay, 12);
+        assert!((profile.gain_mismatch - 0.26f32.sqrt()).abs() < 0.05);
+
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.load_profile(&profile).unwrap();
+        assert_eq!(aec.save_profile("usb-headset"), profile);
+
+        let mut store = ProfileStore::new();
+        store.insert(profile.clone());
+        assert_eq!(store.get("usb-headset"), Some(&profile));
+    }
+
+    #[test]
+    fn profile_with_other_fft_size_is_rejected() {
+        let profile = trained_aec(&[(12, 0.5), (30, 0.1)], 4).save_profile("speaker");
+        let mut aec = FdafAec::new(1024, 0.5);
+        assert_eq!(
+            aec.load_profile(&profile),
+            Err(ProfileError::FftSizeMismatch { expected: 1024, found: 512 })
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn profile_store_serializes() {
+        let mut store = ProfileStore::new();
+        store.insert(trained_aec(&[(12, 0.5), (30, 0.1)], 4).save_profile("speaker"));
+        let json = serde_json::to_string(&store).unwrap();
+        let restored: ProfileStore = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, store);
+    }
+}
@@ -0,0 +1,143 @@
+//! Echo suppression while the linear filter is still converging.
+//!
+//! A freshly started canceller passes the full echo until its filter has converged, which
+//! fills the first seconds of a call with echo. Convergence protection runs a suppressor that
+//! needs no adaptation: bins of the microphone spectrum that are coherent with the far-end
+//! are attenuated, with the gains applied as a short causal filter (see
+//! the `gain_filter` module) so the suppressed signal stays aligned with the linear
+//! path. Its output is cross-faded with the linear path according to the ERLE reached so
+//! far, so the suppressor fades out as the filter takes over.
+
+use crate::gain_filter::{causal_response, filter_frame};
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+use rustfft::Fft;
+use std::sync::Arc;
+
+/// Parameters of the convergence protection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConvergenceProtectionConfig {
+    /// Smoothing factor of the cross- and auto-spectra used to estimate the coherence.
+    pub smoothing: f32,
+    /// Lower bound of the per-bin suppression gain.
+    pub min_gain: f32,
+    /// The ERLE, in dB, at which the output is taken from the linear path alone. Below it,
+    /// the suppressed signal is mixed in proportionally.
+    pub handover_erle_db: f32,
+}
+
+impl Default for ConvergenceProtectionConfig {
+    fn default() -> Self {
+        Self { smoothing: 0.8, min_gain: 0.05, handover_erle_db: 10.0 }
+    }
+}
+
+/// A coherence-based suppressor cross-faded with the linear path.
+#[derive(Debug, Clone)]
+pub(crate) struct ConvergenceProtection {
+    pub(crate) config: ConvergenceProtectionConfig,
+    mic_buffer: Vec<f32>,
+    cross: DVector<Complex<f32>>,
+    far_power: DVector<f32>,
+    mic_power: DVector<f32>,
+    linear_mix: f32,
+}
+
+impl ConvergenceProtection {
+    pub(crate) fn new(config: ConvergenceProtectionConfig, fft_size: usize) -> Self {
+        Self {
+            config,
+            mic_buffer: vec![0.0; fft_size],
+            cross: DVector::from_element(fft_size / 2 + 1, Complex::new(0.0, 0.0)),
+            far_power: DVector::from_element(fft_size / 2 + 1, 0.0),
+            mic_power: DVector::from_element(fft_size / 2 + 1, 0.0),
+            linear_mix: 0.0,
+        }
+    }
+
+    /// Returns the output for the current frame.
+    ///
+    /// `x_f` is the far-end spectrum of the last two frames, `linear` the output of the
+    /// linear filter and `erle_db` the ERLE reached so far.
+    pub(crate) fn process(
+        &mut self,
+        fft: &Arc<dyn Fft<f32>>,
+        ifft: &Arc<dyn Fft<f32>>,
+        x_f: &DVector<Complex<f32>>,
+        mic_frame: &[f32],
+        linear: &[f32],
+        erle_db: f32,
+    ) -> Vec<f32> {
+        let frame_size = mic_frame.len();
+        self.mic_buffer.copy_within(frame_size.., 0);
+        self.mic_buffer[frame_size..].copy_from_slice(mic_frame);
+        let mut d_f: Vec<Complex<f32>> = self.mic_buffer.iter().map(|&x| Complex::new(x, 0.0)).collect();
+        fft.process(&mut d_f);
+
+        let a = self.config.smoothing;
+        let mut gains = vec![1.0; self.cross.len()];
+        for (i, gain) in gains.iter_mut().enumerate() {
+            let d = d_f[i];
+            self.cross[i] = self.cross[i] * a + x_f[i].conj() * d * (1.0 - a);
+            self.far_power[i] = a * self.far_power[i] + (1.0 - a) * x_f[i].norm_sqr();
+            self.mic_power[i] = a * self.mic_power[i] + (1.0 - a) * d.norm_sqr();
+            let coherence = self.cross[i].norm_sqr() / (self.far_power[i] * self.mic_power[i] + 1e-20);
+            *gain = (1.0 - coherence).clamp(self.config.min_gain, 1.0);
+        }
+        let suppressed = filter_frame(ifft, d_f, &causal_response(fft, ifft, &gains));
+
+        self.linear_mix = (erle_db / self.config.handover_erle_db).clamp(0.0, 1.0);
+        suppressed
+            .iter()
+            .zip(linear)
+            .map(|(suppressed, linear)| self.linear_mix * linear + (1.0 - self.linear_mix) * suppressed)
+            .collect()
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables echo suppression during convergence.
+    pub fn set_convergence_protection(&mut self, config: Option<ConvergenceProtectionConfig>) {
+        self.note_config_change();
+        self.protection = config.map(|config| ConvergenceProtection::new(config, self.fft_size));
+    }
+
+    /// Returns the share of the linear path in the last output frame, from 0.0 (suppressor
+    /// only) to 1.0 (linear filter only), or `None` if convergence protection is disabled.
+    pub fn protection_mix(&self) -> Option<f32> {
+        self.protection.as_ref().map(|protection| protection.linear_mix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn suppresses_echo_until_converged() {
+        let far = white_noise(256 * 150, 0.3, 45);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let run = |protect: bool| {
+            let mut aec = FdafAec::new(512, 0.1);
+            if protect {
+                aec.set_convergence_protection(Some(ConvergenceProtectionConfig::default()));
+            }
+            let output = process_all(&mut aec, &far, &mic);
+            (output, aec.protection_mix())
+        };
+        let (unprotected, _) = run(false);
+        let (protected, mix) = run(true);
+
+        // The first frames, before the filter has converged, carry far less echo.
+        let start = 256..256 * 5;
+        assert!(crate::mean_square(&protected[start.clone()]) < crate::mean_square(&unprotected[start]) / 4.0);
+
+        // Once converged, the output comes from the linear filter alone.
+        assert_eq!(mix, Some(1.0));
+        let tail = protected.len() - 256..protected.len();
+        assert_eq!(protected[tail.clone()], unprotected[tail]);
+    }
+}
@@ -0,0 +1,166 @@
+//! Per-frame reliability flags for speech-recognition frontends.
+//!
+//! ASR engines are sensitive to residual echo and double talk. The canceller tracks the
+//! energies of the far-end, microphone, estimated echo and error signals, and classifies
+//! every processed frame so that a recognizer can drop or down-weight unreliable input.
+
+use crate::{FdafAec, FrameEnergies};
+
+/// Mean-square level below which the far-end is considered silent (about -60 dBFS).
+const FAR_END_ACTIVITY_THRESHOLD: f32 = 1e-6;
+/// Echo return loss enhancement (dB) above which the filter is considered converged.
+const CONVERGED_ERLE_DB: f32 = 6.0;
+/// Echo return loss enhancement (dB) above which residual echo is considered inaudible.
+const ECHO_FREE_ERLE_DB: f32 = 15.0;
+/// Error-to-estimated-echo power ratio above which near-end speech is assumed present.
+const DOUBLE_TALK_RATIO: f32 = 0.5;
+/// Smoothing factor applied to the long-term ERLE estimate on every frame.
+const ERLE_SMOOTHING: f32 = 0.9;
+/// Upper bound for the gain applied by energy normalization (about +30 dB).
+const MAX_NORMALIZATION_GAIN: f32 = 31.6;
+
+/// Reliability flags describing a single processed frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameQuality {
+    /// The adaptive filter has reached a useful level of echo attenuation.
+    pub converged: bool,
+    /// The output frame is not expected to contain audible echo. This is also the case
+    /// when the far-end is silent, regardless of convergence.
+    pub echo_free: bool,
+    /// Both near-end speech and far-end echo were present in the frame.
+    pub double_talk: bool,
+}
+
+impl FrameQuality {
+    /// Returns `true` if the frame can be passed to a recognizer without down-weighting.
+    pub fn is_reliable(&self) -> bool {
+        self.echo_free && !self.double_talk
+    }
+}
+
+/// An echo-cancelled frame together with its quality flags, as returned by
+/// [`FdafAec::process_for_asr`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsrFrame {
+    /// The echo-cancelled samples, optionally energy-normalized.
+    pub samples: Vec<f32>,
+    /// Reliability flags for this frame.
+    pub quality: FrameQuality,
+}
+
+/// Tracks the long-term echo attenuation and derives [`FrameQuality`] flags.
+#[derive(Debug, Clone)]
+pub(crate) struct QualityTracker {
+    erle: f32,
+    quality: FrameQuality,
+}
+
+impl QualityTracker {
+    pub(crate) fn new() -> Self {
+        Self { erle: 1.0, quality: FrameQuality::default() }
+    }
+
+    pub(crate) fn update(&mut self, energies: &FrameEnergies) {
+        let far_active = energies.far_end > FAR_END_ACTIVITY_THRESHOLD;
+        let double_talk = far_active
+            && self.quality.converged
+            && energies.error > DOUBLE_TALK_RATIO * energies.echo_estimate;
+
+        // The ERLE estimate is only meaningful while the far-end is the dominant source.
+        if far_active && !double_talk && energies.mic > 0.0 {
+            let erle = energies.mic / (energies.error + 1e-10);
+            self.erle = ERLE_SMOOTHING * self.erle + (1.0 - ERLE_SMOOTHING) * erle;
+        }
+
+        let erle_db = 10.0 * self.erle.max(1e-10).log10();
+        let converged = erle_db >= CONVERGED_ERLE_DB;
+        self.quality = FrameQuality {
+            converged,
+            echo_free: !far_active || (erle_db >= ECHO_FREE_ERLE_DB && !double_talk),
+            double_talk,
+        };
+    }
+
+    /// Returns the long-term ERLE estimate as a linear power ratio.
+    pub(crate) fn erle(&self) -> f32 {
+        self.erle
+    }
+
+    pub(crate) fn quality(&self) -> FrameQuality {
+        self.quality
+    }
+}
+
+impl FdafAec {
+    /// Returns the quality flags of the most recently processed frame.
+    pub fn frame_quality(&self) -> FrameQuality {
+        self.quality.quality()
+    }
+
+    /// Processes a frame like [`FdafAec::process`] and attaches its quality flags.
+    ///
+    /// # Arguments
+    ///
+    /// * `far_end_frame`, `mic_frame`: See [`FdafAec::process`].
+    /// * `target_rms`: If set, the output frame is scaled to this RMS level so that the
+    ///   recognizer sees a consistent input level. Near-silent frames are left untouched and
+    ///   the applied gain is limited to about +30 dB.
+    pub fn process_for_asr(
+        &mut self,
+        far_end_frame: &[f32],
+        mic_frame: &[f32],
+        target_rms: Option<f32>,
+    ) -> AsrFrame {
+        let mut samples = self.process(far_end_frame, mic_frame);
+        if let Some(target) = target_rms {
+            normalize_rms(&mut samples, target);
+        }
+        AsrFrame { samples, quality: self.frame_quality() }
+    }
+}
+
+fn normalize_rms(samples: &mut [f32], target_rms: f32) {
+    let rms = crate::mean_square(samples.iter()).sqrt();
+    if rms < 1e-6 {
+        return;
+    }
+    let gain = (target_rms / rms).min(MAX_NORMALIZATION_GAIN);
+    samples.iter_mut().for_each(|x| *x *= gain);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn flags_converge_on_single_talk_echo() {
+        let mut aec = FdafAec::new(512, 0.5);
+        let far = white_noise(256 * 200, 0.3, 1);
+        let mic = echo(&far, &[(10, 0.5), (40, -0.2)]);
+
+        let first = aec.process_for_asr(&far[..256], &mic[..256], None);
+        assert!(!first.quality.converged);
+        assert!(!first.quality.is_reliable());
+
+        let mut last = first;
+        for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)).skip(1) {
+            last = aec.process_for_asr(far_frame, mic_frame, None);
+        }
+        assert!(last.quality.converged);
+        assert!(last.quality.echo_free);
+        assert!(!last.quality.double_talk);
+    }
+
+    #[test]
+    fn normalization_reaches_target_rms() {
+        let mut aec = FdafAec::new(512, 0.5);
+        let far = vec![0.0; 256];
+        let mic = white_noise(256, 0.01, 2);
+
+        let frame = aec.process_for_asr(&far, &mic, Some(0.1));
+        let rms = crate::mean_square(frame.samples.iter()).sqrt();
+        assert!((rms - 0.1).abs() < 1e-3, "rms was {}", rms);
+        assert!(frame.quality.echo_free);
+    }
+}
@@ -0,0 +1,189 @@
+//! Switching the processing sample rate mid-stream.
+//!
+//! Some platforms renegotiate the device sample rate during a call, e.g. when a Bluetooth
+//! headset switches between its media and its call profile. Destroying the canceller and
+//! creating a new one loses the converged filter, the metrics handles held by the
+//! application and every closure installed at runtime. A rate change instead rebuilds the
+//! geometry for the new rate, keeping the echo tail it covers, and carries over what still
+//! applies: the echo path is resampled into the new filter, parameters given in samples are
+//! scaled, per-bin profiles are interpolated over frequency, and the statistics, which only
+//! depend on signal levels, are kept.
+
+use crate::config::single_partition_fft_size;
+use crate::latency::samples_to_duration;
+use crate::{
+    BulkDelayConfig, ConfigError, DriftConfig, EchoCancellerFactory, FdafAec, FdafAecConfig, RegularizationProfile, SessionEvent,
+    StepProfile,
+};
+use std::time::Duration;
+
+/// The processing geometry after a sample rate change, as returned by
+/// [`FdafAec::notify_sample_rate_change`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleRateChange {
+    /// The new FFT size.
+    pub fft_size: usize,
+    /// The number of microphone samples every frame must have from now on.
+    pub frame_size: usize,
+    /// The number of far-end samples every frame must have from now on, see
+    /// [`FdafAec::far_end_frame_size`].
+    pub far_end_frame_size: usize,
+    /// The new output latency, see [`FdafAec::latency_samples`].
+    pub latency: Duration,
+}
+
+/// Interpolates per-bin values over frequency from one FFT geometry to another. Bins above
+/// the old Nyquist frequency take the value of the highest old bin.
+fn rescale_bins(values: &[f32], from: (usize, u32), to: (usize, u32)) -> Vec<f32> {
+    let Some(&last) = values.last() else {
+        return Vec::new();
+    };
+    let scale = (to.1 as f64 / to.0 as f64) / (from.1 as f64 / from.0 as f64);
+    (0..=to.0 / 2)
+        .map(|bin| {
+            let position = bin as f64 * scale;
+            let index = position as usize;
+            match values.get(index + 1) {
+                Some(&next) => {
+                    let fraction = (position - index as f64) as f32;
+                    values[index] + fraction * (next - values[index])
+                }
+                None => last,
+            }
+        })
+        .collect()
+}
+
+/// Returns the configuration for `sample_rate` that covers the same echo tail with the same
+/// time constants.
+fn rescale_config(config: FdafAecConfig, sample_rate: u32) -> FdafAecConfig {
+    let ratio = sample_rate as f64 / config.sample_rate as f64;
+    let samples = |n: usize| (n as f64 * ratio).round() as usize;
+    let tail_ms = (config.fft_size / 2) as f32 * 1000.0 / config.sample_rate as f32;
+    let fft_size = single_partition_fft_size(sample_rate, tail_ms);
+    let frame_ratio = (fft_size / 2) as f32 / sample_rate as f32 / ((config.fft_size / 2) as f32 / config.sample_rate as f32);
+    let bins = |values: &[f32]| rescale_bins(values, (config.fft_size, config.sample_rate), (fft_size, sample_rate));
+    FdafAecConfig {
+        fft_size,
+        sample_rate,
+        // A far-end stream at the capture rate follows the change; a separate render rate is kept.
+        far_end_sample_rate: config.far_end_sample_rate.filter(|&rate| rate != config.sample_rate),
+        psd_smoothing: config.psd_smoothing.powf(frame_ratio),
+        step_profile: match &config.step_profile {
+            StepProfile::Custom(scales) => StepProfile::Custom(bins(scales)),
+            profile => profile.clone(),
+        },
+        regularization: match &config.regularization {
+            RegularizationProfile::Custom { strength, weights } => {
+                RegularizationProfile::Custom { strength: *strength, weights: bins(weights) }
+            }
+            profile => profile.clone(),
+        },
+        bulk_delay: config.bulk_delay.map(|bulk_delay| BulkDelayConfig {
+            max_delay: samples(bulk_delay.max_delay),
+            margin: samples(bulk_delay.margin),
+            ..bulk_delay
+        }),
+        drift_compensation: config.drift_compensation.map(|drift| DriftConfig {
+            headroom: samples(drift.headroom),
+            max_delay: samples(drift.max_delay),
+            ..drift
+        }),
+        far_end_lookahead: samples(config.far_end_lookahead),
+        ..config
+    }
+}
+
+impl FdafAec {
+    /// Switches the canceller to a new processing sample rate, e.g. after the capture device
+    /// renegotiated its rate.
+    ///
+    /// The FFT size is chosen to cover the same echo tail at the new rate, so frames may
+    /// change length; the returned [`SampleRateChange`] holds the new frame sizes and
+    /// latency. The converged echo path is resampled into the new filter as by
+    /// [`FdafAec::transfer_echo_path_from`]. A far-end reference that ran at the capture
+    /// rate is assumed to follow the change, while a separately configured far-end rate is
+    /// kept. Metrics handles, runtime closures, a session log in progress and the quality
+    /// and convergence statistics carry over; the far-end history starts empty.
+    ///
+    /// Returns an error, leaving the canceller unchanged, if the configuration scaled to the
+    /// new rate is invalid, e.g. because the far-end rate no longer gives a whole number of
+    /// far-end samples per frame.
+    pub fn notify_sample_rate_change(&mut self, sample_rate: u32) -> Result<SampleRateChange, ConfigError> {
+        if sample_rate == self.sample_rate {
+            return Ok(self.sample_rate_change());
+        }
+        if sample_rate == 0 {
+            return Err(ConfigError { parameter: "sample_rate", requirement: "must be positive" });
+        }
+        let ratio = sample_rate as f64 / self.sample_rate as f64;
+        let config = rescale_config(self.resolved_config().config, sample_rate);
+        let mut next = EchoCancellerFactory::new(config)?.create();
+        next.transfer_echo_path_from(self);
+        next.set_diagnostics(self.diagnostics.is_some());
+
+        let previous = std::mem::replace(self, next);
+        self.metrics = previous.metrics;
+        self.frames_processed = previous.frames_processed;
+        self.step_controller = previous.step_controller;
+        self.playback_path = previous.playback_path;
+        self.adaptation_enabled = previous.adaptation_enabled;
+        self.echo_path_clamps = previous.echo_path_clamps;
+        self.fast_start = previous.fast_start;
+        self.quality = previous.quality;
+        self.echo_level = previous.echo_level;
+        self.convergence = previous.convergence;
+        self.duplex = previous.duplex;
+        self.references = previous.references.rescaled(ratio);
+        self.delay_histogram = previous.delay_histogram.map(|histogram| histogram.restarted(self.frame_size));
+        self.session = previous.session;
+        self.record_session_rebase(SessionEvent::SampleRateChange(sample_rate));
+        Ok(self.sample_rate_change())
+    }
+
+    fn sample_rate_change(&self) -> SampleRateChange {
+        SampleRateChange {
+            fft_size: self.fft_size,
+            frame_size: self.frame_size,
+            far_end_frame_size: self.far_end_frame_size(),
+            latency: samples_to_duration(self.latency_samples(), self.sample_rate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn keeps_the_echo_path_across_a_rate_change() {
+        let config = FdafAecConfig { fft_size: 1024, sample_rate: 32000, step_size: 0.5, ..FdafAecConfig::default() };
+        let mut aec = FdafAec::with_config(config);
+        let metrics = aec.metrics_handle();
+        let far = white_noise(512 * 100, 0.3, 99);
+        let mic = echo(&far, &[(20, 0.5)]);
+        for (f, m) in far.chunks(512).zip(mic.chunks(512)) {
+            aec.process(f, m);
+        }
+
+        let change = aec.notify_sample_rate_change(16000).unwrap();
+        assert_eq!((change.fft_size, change.frame_size, change.far_end_frame_size), (512, 256, 256));
+        assert_eq!(change.latency, Duration::ZERO);
+        assert_eq!(aec.sample_rate(), 16000);
+
+        // The same echo path at half the rate; after one frame to fill the far-end history
+        // the echo is gone without adapting from scratch.
+        let far = white_noise(256 * 20, 0.3, 100);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let output = process_all(&mut aec, &far, &mic);
+        let settled = 256..5 * 256;
+        assert!(crate::mean_square(&output[settled.clone()]) < crate::mean_square(&mic[settled]) / 100.0);
+        assert_eq!(metrics.snapshot().frames, 120);
+
+        let invalid = FdafAecConfig { far_end_sample_rate: Some(48000), ..FdafAecConfig::default() };
+        let mut aec = FdafAec::with_config(invalid);
+        assert_eq!(aec.notify_sample_rate_change(44100).unwrap_err().parameter, "far_end_sample_rate");
+        assert_eq!(aec.sample_rate(), 16000);
+    }
+}
@@ -0,0 +1,140 @@
+//! Support for several far-end references that reach the loudspeaker with different latencies.
+//!
+//! Devices often render system sounds and call audio through separate paths. Each registered
+//! reference is delayed by its own compensation amount and the aligned references are summed
+//! into the single far-end signal fed to the adaptive filter.
+
+use crate::{DelayLine, FdafAec};
+
+/// Identifies a far-end reference registered with [`FdafAec::add_reference`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReferenceId(usize);
+
+/// Delays and sums a set of far-end references into one mono reference.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ReferenceMixer {
+    references: Vec<DelayLine>,
+    scratch: Vec<f32>,
+}
+
+impl ReferenceMixer {
+    /// Returns an empty mixer with the delays of all references scaled by `ratio`, for a
+    /// new sample rate.
+    pub(crate) fn rescaled(&self, ratio: f64) -> Self {
+        let references = self.references.iter().map(|line| DelayLine::new((line.delay() as f64 * ratio).round() as usize)).collect();
+        Self { references, scratch: Vec::new() }
+    }
+
+    /// Pushes one frame per reference and accumulates the delayed output into `mixed`.
+    fn mix(&mut self, frames: &[&[f32]], mixed: &mut [f32]) {
+        mixed.fill(0.0);
+        for (reference, frame) in self.references.iter_mut().zip(frames) {
+            self.scratch.clear();
+            self.scratch.extend_from_slice(frame);
+            reference.process(&mut self.scratch);
+            for (out, sample) in mixed.iter_mut().zip(&self.scratch) {
+                *out += sample;
+            }
+        }
+    }
+}
+
+impl FdafAec {
+    /// Registers an additional far-end reference for use with [`FdafAec::process_references`].
+    ///
+    /// # Arguments
+    ///
+    /// * `delay_samples`: The delay applied to this reference before it is summed with the
+    ///   others, in samples at the far-end rate. Use it to compensate for render paths with
+    ///   different latencies.
+    pub fn add_reference(&mut self, delay_samples: usize) -> ReferenceId {
+        self.references.references.push(DelayLine::new(delay_samples));
+        ReferenceId(self.references.references.len() - 1)
+    }
+
+    /// Changes the delay compensation of a registered reference. See [`DelayLine::set_delay`].
+    pub fn set_reference_delay(&mut self, id: ReferenceId, delay_samples: usize) {
+        self.references.references[id.0].set_delay(delay_samples);
+    }
+
+    /// Returns the number of registered far-end references.
+    pub fn reference_count(&self) -> usize {
+        self.references.references.len()
+    }
+
+    /// Processes a frame using several far-end references.
+    ///
+    /// # Arguments
+    ///
+    /// * `reference_frames`: One frame per registered reference, in registration order. Each
+    ///   frame must have [`FdafAec::far_end_frame_size`] samples.
+    /// * `mic_frame`: The microphone frame, as for [`FdafAec::process`].
+    ///
+    /// # Returns
+    ///
+    /// The echo-cancelled frame, as for [`FdafAec::process`].
+    pub fn process_references(&mut self, reference_frames: &[&[f32]], mic_frame: &[f32]) -> Vec<f32> {
+        assert_eq!(
+            reference_frames.len(),
+            self.reference_count(),
+            "One frame must be supplied per registered reference."
+        );
+        let far_end_frame_size = self.far_end_frame_size();
+        for frame in reference_frames {
+            assert_eq!(frame.len(), far_end_frame_size, "Input reference frame size must match the far-end frame size.");
+        }
+
+        let mut far_end_frame = vec![0.0; far_end_frame_size];
+        self.references.mix(reference_frames, &mut far_end_frame);
+        self.process(&far_end_frame, mic_frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::white_noise;
+    use crate::FdafAecConfig;
+
+    #[test]
+    fn references_are_delayed_and_summed() {
+        let mut mixer = ReferenceMixer::default();
+        mixer.references.push(DelayLine::new(0));
+        mixer.references.push(DelayLine::new(2));
+
+        let mut mixed = [0.0; 4];
+        mixer.mix(&[&[1.0, 1.0, 1.0, 1.0], &[1.0, 2.0, 3.0, 4.0]], &mut mixed);
+        assert_eq!(mixed, [1.0, 1.0, 2.0, 3.0]);
+
+        mixer.references[1].set_delay(1);
+        mixer.mix(&[&[0.0; 4], &[5.0, 6.0, 7.0, 8.0]], &mut mixed);
+        assert_eq!(mixed, [4.0, 5.0, 6.0, 7.0]);
+    }
+
+    #[test]
+    fn references_at_the_far_end_rate() {
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, far_end_sample_rate: Some(48000), ..FdafAecConfig::default() };
+        let mut aec = FdafAec::with_config(config.clone());
+        let mut mixed = FdafAec::with_config(config);
+        aec.add_reference(0);
+        aec.add_reference(3);
+        let (system, call) = (white_noise(768 * 10, 0.1, 110), white_noise(768 * 10, 0.3, 111));
+        let mic = white_noise(256 * 10, 0.3, 112);
+        for (frame, mic_frame) in mic.chunks(256).enumerate() {
+            let samples = frame * 768..(frame + 1) * 768;
+            // The second reference lags the first by three far-end samples.
+            let far: Vec<f32> = samples.clone().map(|n| system[n] + if n >= 3 { call[n - 3] } else { 0.0 }).collect();
+            let output = aec.process_references(&[&system[samples.clone()], &call[samples]], mic_frame);
+            assert_eq!(output, mixed.process(&far, mic_frame));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn process_references_requires_one_frame_per_reference() {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.add_reference(0);
+        aec.add_reference(10);
+        aec.process_references(&[&[0.0; 256]], &[0.0; 256]);
+    }
+}
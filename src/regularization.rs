@@ -0,0 +1,268 @@
+//! Frequency-shaped regularization of the NLMS normalization.
+//!
+//! The weight update divides by the far-end PSD plus a regularization term. A uniform term
+//! is a poor fit for speech and most real playback, whose energy is concentrated in the low
+//! bins: a level large enough to stabilize the weak high bins over-regularizes the low bins
+//! and slows their convergence. A [`RegularizationProfile`] shapes the term over frequency,
+//! either with a fixed shape or from the long-term far-end spectrum.
+//!
+//! The fixed epsilon and the initial far-end PSD suit signals in `[-1.0, 1.0]`. Signals on a
+//! very different scale, e.g. floats in the `i16` range or a quiet, uncalibrated input, need
+//! other values, which [`NormalizationConfig`] sets. It can also raise the epsilon to the
+//! far-end noise floor, so bins that only carry noise between talk spurts are not adapted
+//! with full step.
+
+use crate::config::{regularization_for, DEFAULT_REGULARIZATION};
+use crate::FdafAec;
+use nalgebra::DVector;
+
+/// Smoothing factor of the long-term far-end spectrum used by [`RegularizationProfile::FarEnd`].
+const LONG_TERM_SMOOTHING: f32 = 0.999;
+/// Range of the per-bin weights derived from the long-term far-end spectrum.
+const FAR_END_WEIGHT_RANGE: (f32, f32) = (0.1, 10.0);
+/// Factor by which the far-end noise floor estimate may rise per frame, about 2 dB in 100
+/// frames.
+const NOISE_FLOOR_RISE: f32 = 1.005;
+
+/// The scale-dependent constants of the NLMS normalization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NormalizationConfig {
+    /// The fixed epsilon added to the far-end PSD in the weight update, or `None` for the
+    /// default of `1e-10`, scaled with the FFT size relative to 1024 when the
+    /// [`RegularizationProfile`] is not uniform. Must be positive.
+    pub epsilon: Option<f32>,
+    /// The lower bound of the far-end PSD estimate. 0 by default.
+    pub psd_floor: f32,
+    /// The value the far-end PSD estimate starts from in every bin. Until the estimate has
+    /// followed the actual far-end power, a value far above it slows adaptation and a value
+    /// far below it makes the first updates too large. 1.0 by default.
+    pub psd_initial: f32,
+    /// Ties the epsilon to the far-end noise floor: the epsilon of every bin is raised to
+    /// this factor times the tracked minimum of its far-end PSD. Disabled by default.
+    pub noise_floor_factor: Option<f32>,
+}
+
+impl Default for NormalizationConfig {
+    fn default() -> Self {
+        Self { epsilon: None, psd_floor: 0.0, psd_initial: 1.0, noise_floor_factor: None }
+    }
+}
+
+impl NormalizationConfig {
+    /// Returns the fixed epsilon for an FFT of `fft_size` with the regularization `profile`.
+    pub(crate) fn epsilon_for(&self, profile: &RegularizationProfile, fft_size: usize) -> f32 {
+        self.epsilon.unwrap_or_else(|| match profile {
+            RegularizationProfile::Uniform => DEFAULT_REGULARIZATION,
+            _ => regularization_for(fft_size),
+        })
+    }
+
+    /// Creates the regularizer of a canceller with this normalization.
+    pub(crate) fn regularizer(&self, profile: RegularizationProfile, fft_size: usize) -> Regularizer {
+        let epsilon = self.epsilon_for(&profile, fft_size);
+        let mut regularizer = Regularizer::new(profile, fft_size, epsilon);
+        if let Some(factor) = self.noise_floor_factor {
+            regularizer.noise_floor = Some((factor, vec![f32::INFINITY; fft_size]));
+        }
+        regularizer
+    }
+}
+
+/// The shape of the regularization over frequency.
+///
+/// For the shaped profiles, the regularization of bin `k` is
+/// `strength * mean_psd * weight(k)`, where `mean_psd` is the mean far-end PSD and the
+/// weights average to 1.0 over the spectrum. A small fixed epsilon is always added.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegularizationProfile {
+    /// Only the fixed epsilon, the same in every bin.
+    #[default]
+    Uniform,
+    /// Weights rising linearly with frequency, the inverse of a pink (1/f) spectrum.
+    Pink { strength: f32 },
+    /// Weights inversely proportional to the long-term far-end spectrum, so bins in which the
+    /// far-end is usually weak are regularized more.
+    FarEnd { strength: f32 },
+    /// Caller-provided weights for the bins `0..=fft_size / 2`. They are normalized to an
+    /// average of 1.0.
+    Custom { strength: f32, weights: Vec<f32> },
+}
+
+/// Computes the per-bin regularization added to the far-end PSD.
+#[derive(Debug, Clone)]
+pub(crate) struct Regularizer {
+    pub(crate) profile: RegularizationProfile,
+    pub(crate) epsilon: f32,
+    weights: Vec<f32>,
+    long_term: Vec<f32>,
+    /// The factor applied to the noise floor and the tracked noise floor of every bin.
+    noise_floor: Option<(f32, Vec<f32>)>,
+    values: Vec<f32>,
+}
+
+impl Regularizer {
+    pub(crate) fn new(profile: RegularizationProfile, fft_size: usize, epsilon: f32) -> Self {
+        let bins = fft_size / 2 + 1;
+        let half_weights: Vec<f32> = match &profile {
+            RegularizationProfile::Uniform | RegularizationProfile::FarEnd { .. } => vec![1.0; bins],
+            RegularizationProfile::Pink { .. } => (0..bins).map(|k| k.max(1) as f32).collect(),
+            RegularizationProfile::Custom { weights, .. } => {
+                assert_eq!(weights.len(), bins, "Custom regularization needs one weight per bin up to fft_size / 2.");
+                weights.clone()
+            }
+        };
+        Self {
+            profile,
+            epsilon,
+            weights: mirror(&normalize(half_weights), fft_size),
+            long_term: vec![0.0; bins],
+            noise_floor: None,
+            values: vec![epsilon; fft_size],
+        }
+    }
+
+    /// Updates the regularization from the smoothed and the instantaneous far-end PSD.
+    pub(crate) fn update(&mut self, psd: &DVector<f32>, far_power: impl Iterator<Item = f32>) {
+        if let Some((_, noise_floor)) = self.noise_floor.as_mut() {
+            for (floor, &p) in noise_floor.iter_mut().zip(psd.iter()) {
+                *floor = (*floor * NOISE_FLOOR_RISE).min(p);
+            }
+        }
+        let strength = match self.profile {
+            RegularizationProfile::Uniform if self.noise_floor.is_none() => return,
+            RegularizationProfile::Uniform => 0.0,
+            RegularizationProfile::Pink { strength }
+            | RegularizationProfile::FarEnd { strength }
+            | RegularizationProfile::Custom { strength, .. } => strength,
+        };
+
+        if let RegularizationProfile::FarEnd { .. } = self.profile {
+            for (long_term, power) in self.long_term.iter_mut().zip(far_power) {
+                *long_term = LONG_TERM_SMOOTHING * *long_term + (1.0 - LONG_TERM_SMOOTHING) * power;
+            }
+            let mean = self.long_term.iter().sum::<f32>() / self.long_term.len() as f32;
+            if mean > 0.0 {
+                let (low, high) = FAR_END_WEIGHT_RANGE;
+                let half = self.long_term.iter().map(|&p| (mean / p.max(1e-20)).clamp(low, high)).collect();
+                self.weights = mirror(&normalize(half), psd.len());
+            }
+        }
+
+        let scale = strength * psd.mean();
+        for (k, (value, weight)) in self.values.iter_mut().zip(&self.weights).enumerate() {
+            let epsilon = match &self.noise_floor {
+                Some((factor, noise_floor)) => self.epsilon.max(factor * noise_floor[k]),
+                None => self.epsilon,
+            };
+            *value = epsilon + scale * weight;
+        }
+    }
+
+    /// Returns the regularization of every FFT bin.
+    pub(crate) fn values(&self) -> &[f32] {
+        &self.values
+    }
+}
+
+/// Scales weights to an average of 1.0.
+fn normalize(mut weights: Vec<f32>) -> Vec<f32> {
+    let mean = weights.iter().sum::<f32>() / weights.len() as f32;
+    if mean > 0.0 {
+        weights.iter_mut().for_each(|w| *w /= mean);
+    }
+    weights
+}
+
+/// Extends weights for the bins `0..=fft_size / 2` to the full, symmetric spectrum.
+fn mirror(half: &[f32], fft_size: usize) -> Vec<f32> {
+    (0..fft_size).map(|k| half[k.min(fft_size - k)]).collect()
+}
+
+impl FdafAec {
+    /// Changes the frequency shape of the NLMS regularization.
+    pub fn set_regularization_profile(&mut self, profile: RegularizationProfile) {
+        self.note_config_change();
+        self.regularizer = self.normalization.regularizer(profile, self.fft_size);
+    }
+
+    /// Changes the constants of the NLMS normalization. The far-end PSD estimate keeps its
+    /// current value, only clamped to the new floor.
+    pub fn set_normalization(&mut self, config: NormalizationConfig) {
+        self.note_config_change();
+        assert!(config.epsilon_for(&self.regularizer.profile, self.fft_size) > 0.0, "normalization.epsilon must be positive.");
+        self.normalization = config;
+        self.regularizer = config.regularizer(self.regularizer.profile.clone(), self.fft_size);
+        self.psd.iter_mut().for_each(|p| *p = p.max(config.psd_floor));
+    }
+
+    /// Returns the constants of the NLMS normalization.
+    pub fn normalization(&self) -> NormalizationConfig {
+        self.normalization
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use crate::FdafAecConfig;
+
+    #[test]
+    fn pink_profile_regularizes_high_bins_more() {
+        let mut regularizer = Regularizer::new(RegularizationProfile::Pink { strength: 0.1 }, 16, 0.0);
+        let psd = DVector::from_element(16, 2.0);
+        regularizer.update(&psd, std::iter::empty());
+        let values = regularizer.values();
+        assert!(values[1] < values[4] && values[4] < values[8]);
+        assert_eq!(values[3], values[13]);
+        let mean_half = values[..9].iter().sum::<f32>() / 9.0;
+        assert!((mean_half - 0.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn far_end_profile_follows_long_term_spectrum() {
+        let mut regularizer = Regularizer::new(RegularizationProfile::FarEnd { strength: 0.1 }, 8, 0.0);
+        let psd = DVector::from_element(8, 1.0);
+        for _ in 0..5000 {
+            regularizer.update(&psd, [10.0, 10.0, 10.0, 1.0, 1.0].into_iter());
+        }
+        let values = regularizer.values();
+        assert!(values[4] > 5.0 * values[0]);
+    }
+
+    #[test]
+    fn shaped_regularization_still_converges() {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_regularization_profile(RegularizationProfile::Pink { strength: 0.01 });
+        let far = white_noise(256 * 100, 0.3, 24);
+        let mic = echo(&far, &[(10, 0.5)]);
+        process_all(&mut aec, &far, &mic);
+        assert!((aec.impulse_response()[10] - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn normalization_adapts_to_signal_scale() {
+        // A quiet input, far below the default initial PSD of 1.0.
+        let far: Vec<f32> = white_noise(256 * 100, 0.3, 86).iter().map(|x| x * 1e-3).collect();
+        let mic = echo(&far, &[(10, 0.5)]);
+        let run = |normalization: NormalizationConfig| {
+            let mut aec = FdafAec::with_config(FdafAecConfig {
+                fft_size: 512,
+                step_size: 0.5,
+                normalization,
+                ..FdafAecConfig::default()
+            });
+            process_all(&mut aec, &far, &mic);
+            aec.impulse_response()[10]
+        };
+        assert!(run(NormalizationConfig::default()) < 0.25);
+        let scaled = NormalizationConfig { epsilon: Some(1e-16), psd_initial: 1e-5, ..NormalizationConfig::default() };
+        assert!((run(scaled) - 0.5).abs() < 0.05);
+
+        // Tied to the noise floor, a far-end of pure noise adapts the filter much more slowly.
+        let tied = NormalizationConfig { noise_floor_factor: Some(100.0), ..scaled };
+        assert!(run(tied) < 0.2);
+    }
+}
@@ -0,0 +1,190 @@
+//! Resampling of the far-end reference to the capture rate.
+//!
+//! Some platforms render at one fixed rate (often 48 kHz) and capture at another (often
+//! 16 kHz). The canceller models the echo at the capture rate, so the reference is converted
+//! with a streaming windowed-sinc resampler before it enters the filter. The resampler delays
+//! the reference by half its kernel (16 samples at the lower rate), which the adaptive filter
+//! absorbs as part of the echo path.
+
+use crate::FdafAec;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Half-width of the interpolation kernel, in samples of the lower of the two rates.
+const KERNEL_HALF_WIDTH: usize = 16;
+/// Passband edge relative to the lower Nyquist frequency, leaving room for the transition band.
+const PASSBAND: f64 = 0.9;
+
+/// Converts a stream from one sample rate to another with a polyphase windowed-sinc kernel.
+#[derive(Debug, Clone)]
+pub(crate) struct FarEndResampler {
+    pub(crate) from_rate: u32,
+    /// Output sample `n` is taken at input time `n * step / phases`.
+    step: usize,
+    phases: usize,
+    half_width: usize,
+    /// `kernels[p]` holds the `2 * half_width` taps for the fractional position `p / phases`.
+    kernels: Vec<Vec<f32>>,
+    history: VecDeque<f32>,
+    /// Global index of the first sample in `history`.
+    history_start: usize,
+    output_index: usize,
+}
+
+impl FarEndResampler {
+    pub(crate) fn new(from_rate: u32, to_rate: u32) -> Self {
+        let divisor = gcd(from_rate as usize, to_rate as usize);
+        let step = from_rate as usize / divisor;
+        let phases = to_rate as usize / divisor;
+        // When decimating, the kernel widens so that it keeps the same number of zero
+        // crossings at the output rate.
+        let stretch = (step as f64 / phases as f64).max(1.0);
+        let half_width = (KERNEL_HALF_WIDTH as f64 * stretch).ceil() as usize;
+        let cutoff = 0.5 * PASSBAND / stretch;
+
+        let kernels = (0..phases)
+            .map(|p| {
+                let frac = p as f64 / phases as f64;
+                (0..2 * half_width)
+                    .map(|i| {
+                        let x = frac + half_width as f64 - 1.0 - i as f64;
+                        let window = 0.5 + 0.5 * (PI * x / half_width as f64).cos();
+                        (2.0 * cutoff * sinc(2.0 * cutoff * x) * window) as f32
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            from_rate,
+            step,
+            phases,
+            half_width,
+            kernels,
+            history: std::iter::repeat_n(0.0, 2 * half_width).collect(),
+            history_start: 0,
+            output_index: 0,
+        }
+    }
+
+    /// Returns the number of input samples needed to produce `output_len` samples.
+    pub(crate) fn input_len(&self, output_len: usize) -> usize {
+        output_len * self.step / self.phases
+    }
+
+    /// Appends `input` and returns every resampled sample the input received so far
+    /// determines, for input arriving in chunks of any length.
+    pub(crate) fn process_available(&mut self, input: &[f32]) -> Vec<f32> {
+        self.history.extend(input);
+        let end = self.history_start + self.history.len();
+        let mut count = 0;
+        while (self.output_index + count) * self.step / self.phases + 1 + 2 * self.half_width <= end {
+            count += 1;
+        }
+        let mut output = vec![0.0; count];
+        self.process(&[], &mut output);
+        output
+    }
+
+    /// Appends `input` and writes the next `output.len()` resampled samples.
+    pub(crate) fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        self.history.extend(input);
+        for out in output.iter_mut() {
+            let position = self.output_index * self.step;
+            let (base, phase) = (position / self.phases, position % self.phases);
+            // The history starts with 2 * half_width zeros, so the window of 2 * half_width
+            // samples ending at `base` is centered half_width samples in the past.
+            let first = base + 1 - self.history_start;
+            *out = self.kernels[phase]
+                .iter()
+                .zip(self.history.range(first..first + 2 * self.half_width))
+                .map(|(h, x)| h * x)
+                .sum();
+            self.output_index += 1;
+        }
+        let next_first = (self.output_index * self.step / self.phases + 1).saturating_sub(self.history_start);
+        self.history.drain(..next_first.min(self.history.len()));
+        self.history_start += next_first;
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-12 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl FdafAec {
+    /// Returns the sample rate of the far-end reference, in Hz.
+    ///
+    /// This equals [`FdafAec::sample_rate`] unless
+    /// [`FdafAecConfig::far_end_sample_rate`](crate::FdafAecConfig::far_end_sample_rate) is set.
+    pub fn far_end_sample_rate(&self) -> u32 {
+        self.far_end_resampler.as_ref().map_or(self.sample_rate, |resampler| resampler.from_rate)
+    }
+
+    /// Returns the number of far-end samples [`FdafAec::process`] expects per frame.
+    pub fn far_end_frame_size(&self) -> usize {
+        self.far_end_resampler.as_ref().map_or(self.frame_size, |resampler| resampler.input_len(self.frame_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::echo;
+    use crate::FdafAecConfig;
+
+    fn tone(freq: f32, rate: u32, len: usize) -> Vec<f32> {
+        (0..len).map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / rate as f32).sin()).collect()
+    }
+
+    #[test]
+    fn resamples_tone_between_rates() {
+        for (from, to) in [(48000, 16000), (16000, 48000), (44100, 16000)] {
+            let mut resampler = FarEndResampler::new(from, to);
+            let input = tone(1000.0, from, from as usize / 10);
+            let mut output = vec![0.0; input.len() * to as usize / from as usize];
+            resampler.process(&input, &mut output);
+
+            // The output is the input tone at the new rate, delayed by the kernel.
+            let delay = resampler.half_width as f32 * to as f32 / from as f32;
+            let error: Vec<f32> = (output.len() / 2..output.len())
+                .map(|i| output[i] - (2.0 * std::f32::consts::PI * 1000.0 * (i as f32 - delay) / to as f32).sin())
+                .collect();
+            let error = crate::mean_square(&error);
+            assert!(error < 1e-4, "{} -> {}: error {}", from, to, error);
+        }
+    }
+
+    #[test]
+    fn cancels_echo_of_48k_reference() {
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, far_end_sample_rate: Some(48000), ..FdafAecConfig::default() };
+        let mut aec = FdafAec::with_config(config);
+        assert_eq!(aec.far_end_sample_rate(), 48000);
+        assert_eq!(aec.far_end_frame_size(), 768);
+
+        // The echo is rendered at 48 kHz and picked up at 16 kHz through a pure delay.
+        let far = crate::test_util::white_noise(768 * 120, 0.3, 33);
+        let mut decimator = FarEndResampler::new(48000, 16000);
+        let mut far_16k = vec![0.0; 256 * 120];
+        decimator.process(&far, &mut far_16k);
+        let mic = echo(&far_16k, &[(20, 0.5)]);
+
+        let mut last = Vec::new();
+        for (far_frame, mic_frame) in far.chunks(768).zip(mic.chunks(256)) {
+            last = aec.process(far_frame, mic_frame);
+        }
+        assert!(crate::mean_square(&last) < crate::mean_square(&mic[mic.len() - 256..]) / 100.0);
+    }
+}
@@ -0,0 +1,232 @@
+//! Resetting the adaptive state, optionally with a crossfade.
+//!
+//! After an echo path change the filter is sometimes known to be useless, e.g. when the
+//! application switched from the built-in loudspeaker to an external one, and starting over
+//! converges faster than adapting the old weights away. A hard reset swaps the output of a
+//! converged filter for the output of an empty one from one sample to the next, which is
+//! audible as a click and a jump in the echo level. The crossfading reset keeps the old
+//! filter running on its own far-end history for a few frames and fades its output into the
+//! output of the reset filter. Only the output is blended: the reset filter adapts on its
+//! own error from the first frame.
+
+use crate::adaptive_step::AdaptiveStep;
+use crate::band_dtd::BandDoubleTalkDetector;
+use crate::bulk_delay::BulkDelay;
+use crate::convergence::ConvergenceTracker;
+use crate::crosstalk::CoherenceGate;
+use crate::drift::DriftCompensator;
+use crate::dtd::CoherenceDetector;
+use crate::echo_level::EchoLevelTracker;
+use crate::kalman::KalmanState;
+use crate::quality::QualityTracker;
+use crate::stability::StabilityTracker;
+use crate::step_profile::BinStepSizes;
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+use rustfft::Fft;
+use std::sync::Arc;
+
+/// The filter from before a reset, kept running while its output fades out.
+#[derive(Debug, Clone)]
+pub(crate) struct ResetCrossfade {
+    weights: DVector<Complex<f32>>,
+    far_end_buffer: Vec<f32>,
+    frame: u32,
+    frames: u32,
+}
+
+impl ResetCrossfade {
+    /// Captures the current filter of `aec` for a crossfade over `frames` frames, or returns
+    /// `None` for 0 frames.
+    pub(crate) fn capture(aec: &FdafAec, frames: u32) -> Option<Self> {
+        (frames > 0).then(|| Self {
+            weights: aec.weights.clone(),
+            far_end_buffer: aec.far_end_buffer.as_slice().to_vec(),
+            frame: 0,
+            frames,
+        })
+    }
+
+    /// Returns the crossfaded output of a frame and whether the crossfade has finished.
+    ///
+    /// `far_end_frame` is the far-end frame entering the filter and `output` the output of
+    /// the reset filter.
+    fn process(
+        &mut self,
+        fft: &Arc<dyn Fft<f32>>,
+        ifft: &Arc<dyn Fft<f32>>,
+        far_end_frame: &[f32],
+        mic_frame: &[f32],
+        output: &[f32],
+    ) -> (Vec<f32>, bool) {
+        let frame_size = far_end_frame.len();
+        self.far_end_buffer.copy_within(frame_size.., 0);
+        self.far_end_buffer[frame_size..].copy_from_slice(far_end_frame);
+        let mut y: Vec<Complex<f32>> = self.far_end_buffer.iter().map(|&x| Complex::new(x, 0.0)).collect();
+        fft.process(&mut y);
+        for (y, w) in y.iter_mut().zip(self.weights.iter()) {
+            *y *= w;
+        }
+        ifft.process(&mut y);
+
+        let scale = 1.0 / y.len() as f32;
+        let total = (self.frames as usize * frame_size) as f32;
+        let start = self.frame as usize * frame_size;
+        let crossfaded = y[frame_size..]
+            .iter()
+            .zip(mic_frame.iter().zip(output))
+            .enumerate()
+            .map(|(n, (echo, (mic, new)))| {
+                let gain = (start + n + 1) as f32 / total;
+                (1.0 - gain) * (mic - echo.re * scale) + gain * new
+            })
+            .collect();
+        self.frame += 1;
+        (crossfaded, self.frame >= self.frames)
+    }
+}
+
+impl FdafAec {
+    /// Clears the filter weights, the far-end PSD and the far-end buffer, so the filter
+    /// adapts from scratch as if newly constructed. Everything estimated about the echo path
+    /// starts over too: the quality, convergence and stability tracking, the echo return
+    /// loss, the double-talk detectors and crosstalk gates, the adaptive and per-bin step
+    /// sizes, the Kalman state, and the bulk delay and clock drift estimates; the fast-start
+    /// window restarts. The stages that do not depend on the echo path, such as the input
+    /// filters, the suppression stages and the AGC, and the counters keep their state. The
+    /// output switches to the reset filter immediately; see [`FdafAec::reset_with_crossfade`]
+    /// for a click-free reset.
+    pub fn reset(&mut self) {
+        self.reset_crossfade = None;
+        self.quality = QualityTracker::new();
+        self.convergence = ConvergenceTracker::default();
+        self.stability = StabilityTracker::new(self.fft_size);
+        self.echo_level = EchoLevelTracker::default();
+        if let Some(crosstalk) = self.crosstalk.as_mut() {
+            *crosstalk = CoherenceGate::new(crosstalk.config, self.fft_size);
+        }
+        if let Some(band_dtd) = self.band_dtd.as_mut() {
+            *band_dtd = BandDoubleTalkDetector::new(band_dtd.config, self.fft_size);
+        }
+        if let Some(coherence_dtd) = self.coherence_dtd.as_mut() {
+            *coherence_dtd = CoherenceDetector::new(coherence_dtd.config, self.fft_size);
+        }
+        if let Some(adaptive_step) = self.adaptive_step.as_mut() {
+            *adaptive_step = AdaptiveStep::new(adaptive_step.config);
+        }
+        self.step_profile = BinStepSizes::new(self.step_profile.profile.clone(), self.fft_size);
+        if let Some(kalman) = self.kalman.as_mut() {
+            *kalman = KalmanState::new(kalman.config, self.fft_size);
+        }
+        if let Some(bulk_delay) = self.bulk_delay.as_mut() {
+            *bulk_delay = BulkDelay::new(bulk_delay.config);
+        }
+        if let Some(drift) = self.drift.as_mut() {
+            *drift = DriftCompensator::new(drift.config);
+        }
+        self.fast_start.restart();
+        self.weights.fill(Complex::new(0.0, 0.0));
+        self.psd.fill(self.normalization.psd_initial.max(self.normalization.psd_floor));
+        self.far_end_buffer.fill(0.0);
+        if let Some(overlap_add) = self.overlap_add.as_mut() {
+            overlap_add.reset();
+        }
+        if let Some(nonlinear_echo) = self.nonlinear_echo.as_mut() {
+            nonlinear_echo.reset(self.normalization.psd_initial.max(self.normalization.psd_floor));
+        }
+    }
+
+    /// Resets the canceller like [`FdafAec::reset`], but keeps the filter from before the
+    /// reset running for `frames` frames and crossfades from its output to the output of
+    /// the reset filter. 0 frames is a hard reset.
+    pub fn reset_with_crossfade(&mut self, frames: u32) {
+        let crossfade = ResetCrossfade::capture(self, frames);
+        self.reset();
+        self.reset_crossfade = crossfade;
+    }
+
+    /// Returns `true` while the output is crossfaded after a reset or an adaptation mode
+    /// swap, see [`FdafAec::swap_adaptation_mode`].
+    pub fn in_reset_crossfade(&self) -> bool {
+        self.reset_crossfade.is_some()
+    }
+
+    /// Returns the crossfaded output of the linear filter while a crossfade is running.
+    pub(crate) fn crossfade_reset(&mut self, far_end_frame: &[f32], mic_frame: &[f32], output: &[f32]) -> Option<Vec<f32>> {
+        let crossfade = self.reset_crossfade.as_mut()?;
+        let (crossfaded, finished) = crossfade.process(&self.fft, &self.ifft, far_end_frame, mic_frame, output);
+        if finished {
+            self.reset_crossfade = None;
+        }
+        Some(crossfaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{echo, process_all, white_noise};
+    use crate::{AdaptationMode, ConvergenceState, FdafAec, FdafAecConfig, KalmanConfig};
+
+    #[test]
+    fn crossfade_avoids_echo_burst_after_reset() {
+        let far = white_noise(256 * 120, 0.3, 96);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let run = |crossfade_frames: Option<u32>| {
+            let mut aec = FdafAec::new(512, 0.5);
+            let mut output = Vec::new();
+            for (i, (f, m)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+                if i == 100 {
+                    match crossfade_frames {
+                        Some(frames) => aec.reset_with_crossfade(frames),
+                        None => aec.reset(),
+                    }
+                    assert!(aec.impulse_response().iter().all(|&h| h == 0.0));
+                }
+                output.extend(aec.process(f, m));
+            }
+            (output, aec.in_reset_crossfade())
+        };
+
+        let (hard, _) = run(None);
+        let (faded, fading) = run(Some(8));
+        assert!(!fading);
+        // The hard reset lets the full echo through on the first frame after it.
+        let first = 100 * 256..101 * 256;
+        assert!(crate::mean_square(&hard[first.clone()]) > crate::mean_square(&mic[first.clone()]) / 2.0);
+        assert!(crate::mean_square(&faded[first.clone()]) < crate::mean_square(&mic[first]) / 100.0);
+        // The crossfade is continuous across the reset.
+        let jump = (faded[100 * 256] - faded[100 * 256 - 1]).abs();
+        assert!(jump < 0.05, "jump {}", jump);
+    }
+
+    #[test]
+    fn reset_starts_tracking_over() {
+        let far = white_noise(256 * 100, 0.3, 108);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let config = FdafAecConfig {
+            fft_size: 512,
+            step_size: 0.5,
+            adaptation: AdaptationMode::Kalman(KalmanConfig::default()),
+            fast_start_frames: 20,
+            ..FdafAecConfig::default()
+        };
+        let mut aec = FdafAec::with_config(config);
+        let initial_state_error = aec.kalman_state_error();
+        process_all(&mut aec, &far, &mic);
+        assert!(aec.frame_quality().converged);
+        assert_ne!(aec.convergence_state(), ConvergenceState::Initializing);
+        assert_ne!(aec.kalman_state_error(), initial_state_error);
+        assert!(aec.echo_path_change() > 0.0);
+        assert!(!aec.in_fast_start());
+        assert!(aec.echo_return_loss_db().is_some());
+
+        aec.reset();
+        assert!(!aec.frame_quality().converged);
+        assert_eq!(aec.convergence_state(), ConvergenceState::Initializing);
+        assert_eq!(aec.kalman_state_error(), initial_state_error);
+        assert_eq!((aec.echo_path_change(), aec.echo_path_stability()), (0.0, 1.0));
+        assert!(aec.in_fast_start());
+        assert_eq!(aec.echo_return_loss_db(), None);
+    }
+}
@@ -0,0 +1,153 @@
+//! Enforcement of a ceiling on the residual echo relative to near-end speech.
+//!
+//! Product requirements are often phrased as "residual echo must stay at least X dB below
+//! near-end speech". The fixed levels of the NLP cannot guarantee that: how much echo the
+//! linear filter leaves depends on the device and the moment. The residual ceiling estimates
+//! the residual echo in every frame in which the far-end is active, as the echo estimate
+//! reduced by the long-term ERLE and by whatever the post-filter and the NLP already removed.
+//! Whenever that estimate exceeds the tracked near-end speech level minus the ceiling, the
+//! output is attenuated further until it does not. Frames without far-end activity are never
+//! touched. The canceller counts how often the enforcement engages.
+
+use crate::{DuplexState, FdafAec};
+
+/// Parameters of the residual echo ceiling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResidualCeilingConfig {
+    /// How far, in dB, the residual echo must stay below the near-end speech level.
+    pub ceiling_db: f32,
+    /// The near-end speech level, in dBFS, assumed until near-end speech has been observed.
+    pub initial_near_end_dbfs: f32,
+    /// The smoothing factor of the near-end speech level, per frame of near-end single talk.
+    pub near_end_smoothing: f32,
+    /// The largest additional attenuation, in dB, the enforcement applies.
+    pub max_suppression_db: f32,
+}
+
+impl Default for ResidualCeilingConfig {
+    fn default() -> Self {
+        Self { ceiling_db: 20.0, initial_near_end_dbfs: -26.0, near_end_smoothing: 0.98, max_suppression_db: 60.0 }
+    }
+}
+
+/// How often the residual ceiling was checked and enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResidualCeilingStats {
+    /// The number of frames with far-end activity, in which the ceiling was checked.
+    pub frames_checked: u64,
+    /// The number of checked frames in which the output was attenuated further.
+    pub frames_enforced: u64,
+}
+
+impl ResidualCeilingStats {
+    /// Returns the fraction of checked frames in which the ceiling was enforced.
+    pub fn enforcement_ratio(&self) -> f32 {
+        if self.frames_checked == 0 { 0.0 } else { self.frames_enforced as f32 / self.frames_checked as f32 }
+    }
+}
+
+/// The residual echo estimated for one output frame.
+pub(crate) struct ResidualEstimate {
+    /// The mean-square echo estimate of the linear filter.
+    pub(crate) echo: f32,
+    /// The mean-square error of the linear filter, before any suppression.
+    pub(crate) error: f32,
+    /// The long-term ERLE, as a linear power ratio.
+    pub(crate) erle: f32,
+}
+
+/// Tracks the near-end level and attenuates frames whose residual echo is too loud.
+#[derive(Debug, Clone)]
+pub(crate) struct ResidualCeiling {
+    pub(crate) config: ResidualCeilingConfig,
+    near_end_power: f32,
+    gain: f32,
+    stats: ResidualCeilingStats,
+}
+
+impl ResidualCeiling {
+    pub(crate) fn new(config: ResidualCeilingConfig) -> Self {
+        Self { config, near_end_power: 10f32.powf(config.initial_near_end_dbfs / 10.0), gain: 1.0, stats: ResidualCeilingStats::default() }
+    }
+
+    /// Processes a suppressed output frame in place.
+    pub(crate) fn process(&mut self, output: &mut [f32], residual: ResidualEstimate, duplex_state: DuplexState) {
+        let output_power = crate::mean_square(&*output);
+        if duplex_state == DuplexState::NearEndOnly {
+            let a = self.config.near_end_smoothing;
+            self.near_end_power = a * self.near_end_power + (1.0 - a) * output_power;
+        }
+
+        let far_end_active = matches!(duplex_state, DuplexState::FarEndOnly | DuplexState::DoubleTalk);
+        let mut target = 1.0;
+        if far_end_active {
+            self.stats.frames_checked += 1;
+            // The linear residual, reduced by what the suppressors already removed.
+            let suppression = if residual.error > 0.0 { (output_power / residual.error).min(1.0) } else { 1.0 };
+            let estimate = residual.echo / residual.erle.max(1.0) * suppression;
+            let allowed = self.near_end_power * 10f32.powf(-self.config.ceiling_db / 10.0);
+            if estimate > allowed {
+                self.stats.frames_enforced += 1;
+                let floor = 10f32.powf(-self.config.max_suppression_db / 20.0);
+                target = (allowed / estimate).sqrt().max(floor);
+            }
+        }
+
+        let step = (target - self.gain) / output.len() as f32;
+        for sample in output.iter_mut() {
+            self.gain += step;
+            *sample *= self.gain;
+        }
+        self.gain = target;
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the residual echo ceiling.
+    /// Reconfiguring resets the near-end level and the statistics.
+    pub fn set_residual_ceiling(&mut self, config: Option<ResidualCeilingConfig>) {
+        self.note_config_change();
+        self.residual_ceiling = config.map(ResidualCeiling::new);
+    }
+
+    /// Returns how often the residual ceiling was checked and enforced, or `None` if it is
+    /// disabled.
+    pub fn residual_ceiling_stats(&self) -> Option<ResidualCeilingStats> {
+        self.residual_ceiling.as_ref().map(|ceiling| ceiling.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn holds_residual_below_near_end_level() {
+        // Saturated echo leaves residual the linear filter cannot remove; near-end speech at
+        // about -20 dBFS sets the reference level first.
+        let mut far = white_noise(256 * 200, 0.5, 79);
+        let mut mic: Vec<f32> = echo(&far, &[(10, 0.5)]).iter().map(|&e| (3.0 * e).tanh() / 3.0).collect();
+        far[..40 * 256].fill(0.0);
+        mic[..40 * 256].copy_from_slice(&white_noise(256 * 40, 0.17, 80));
+
+        let run = |config: Option<ResidualCeilingConfig>| {
+            let mut aec = FdafAec::new(512, 0.2);
+            aec.set_residual_ceiling(config);
+            let output = process_all(&mut aec, &far, &mic);
+            (output, aec.residual_ceiling_stats())
+        };
+        let (linear, _) = run(None);
+        let (enforced, stats) = run(Some(ResidualCeilingConfig { ceiling_db: 40.0, ..ResidualCeilingConfig::default() }));
+
+        let near_end = 0..40 * 256;
+        assert_eq!(enforced[near_end.clone()], linear[near_end.clone()]);
+        let echo_only = 150 * 256..200 * 256;
+        let residual = crate::mean_square(&enforced[echo_only.clone()]);
+        assert!(residual < crate::mean_square(&linear[echo_only]) / 10.0);
+        assert!(residual < crate::mean_square(&mic[near_end]) * 1e-4 * 3.0, "residual at {}", residual);
+        let stats = stats.unwrap();
+        assert!(stats.frames_checked >= 150 && stats.enforcement_ratio() > 0.5, "{:?}", stats);
+    }
+}
@@ -0,0 +1,72 @@
+//! Magnitude saturation of the per-frame weight update.
+//!
+//! The normalized update divides by a smoothed far-end PSD, so a sudden loud far-end
+//! transient can produce a gradient far larger than anything the PSD has seen, and push
+//! individual weights to enormous values in a single frame. The frequency-domain weights are
+//! the frequency response of the estimated echo path, so a plausible bound for the change of
+//! any bin in one frame can be stated directly as an echo path gain.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+
+/// Scales every update whose magnitude exceeds `bound` back onto the bound, keeping its
+/// phase. Returns the number of saturated bins.
+pub(crate) fn saturate_update(update: &mut DVector<Complex<f32>>, bound: f32) -> usize {
+    let mut saturated = 0;
+    for u in update.iter_mut() {
+        let magnitude = u.norm();
+        if magnitude > bound {
+            *u *= bound / magnitude;
+            saturated += 1;
+        }
+    }
+    saturated
+}
+
+impl FdafAec {
+    /// Sets the largest change, as a linear echo path gain, that a single frame may apply to
+    /// any frequency bin of the filter. `None` disables the limit.
+    pub fn set_max_weight_update(&mut self, bound: Option<f32>) {
+        self.note_config_change();
+        if let Some(bound) = bound {
+            assert!(bound > 0.0, "Weight update bound must be positive.");
+        }
+        self.max_weight_update = bound;
+    }
+
+    /// Returns the number of bins whose update was saturated in the most recent frame.
+    pub fn saturated_bins(&self) -> usize {
+        self.saturated_bins
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::white_noise;
+
+    #[test]
+    fn saturation_keeps_phase() {
+        let mut update = DVector::from_vec(vec![Complex::new(3.0, 4.0), Complex::new(0.1, 0.0)]);
+        assert_eq!(saturate_update(&mut update, 1.0), 1);
+        assert!((update[0] - Complex::new(0.6, 0.8)).norm() < 1e-6);
+        assert_eq!(update[1], Complex::new(0.1, 0.0));
+    }
+
+    #[test]
+    fn loud_transient_cannot_blow_up_weights() {
+        let mut aec = FdafAec::new(512, 1.0);
+        aec.set_max_weight_update(Some(0.1));
+        let quiet = white_noise(256 * 20, 0.001, 16);
+        for frame in quiet.chunks(256) {
+            aec.process(frame, &[0.0; 256]);
+        }
+
+        let burst = white_noise(256, 1.0, 17);
+        let near = white_noise(256, 1.0, 18);
+        aec.process(&burst, &near);
+        assert!(aec.saturated_bins() > 0);
+        assert!(aec.weights.iter().all(|w| w.norm() <= 0.1 + 1e-6));
+    }
+}
@@ -0,0 +1,138 @@
+//! A quantified sanity check of the canceller on the target machine.
+//!
+//! The canceller's output depends on the FFT backend and on whatever SIMD paths the build
+//! selected for the target CPU. [`FdafAec::self_test`] runs two short simulations on a fresh
+//! canceller with the same configuration: white noise through a known two-tap echo path,
+//! which must be attenuated by at least [`SELF_TEST_MIN_ERLE_DB`], and an impulse on the
+//! microphone with a silent far-end, which must reach the output exactly
+//! [`FdafAec::latency_samples`] later. A broken backend fails one of them within a few
+//! milliseconds of CPU time.
+
+use crate::FdafAec;
+use std::fmt;
+
+/// The echo return loss enhancement, in dB, the self-test requires after convergence.
+pub const SELF_TEST_MIN_ERLE_DB: f32 = 15.0;
+/// The length of the echo simulation, in seconds of audio.
+const ECHO_SECONDS: usize = 2;
+
+/// The measurements of a passed self-test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelfTestReport {
+    /// The echo attenuation over the last quarter of the echo simulation, in dB.
+    pub erle_db: f32,
+    /// The measured delay from microphone to output, in samples.
+    pub latency_samples: usize,
+}
+
+/// The reason a self-test failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelfTestError {
+    /// The output contained NaN or infinite samples.
+    NonFiniteOutput,
+    /// The simulated echo was attenuated less than [`SELF_TEST_MIN_ERLE_DB`].
+    InsufficientErle { erle_db: f32 },
+    /// The microphone impulse reached the output with a different delay than reported by
+    /// [`FdafAec::latency_samples`], or not at all.
+    LatencyMismatch { expected: usize, measured: Option<usize> },
+}
+
+impl fmt::Display for SelfTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelfTestError::NonFiniteOutput => write!(f, "output contains non-finite samples"),
+            SelfTestError::InsufficientErle { erle_db } => {
+                write!(f, "echo attenuated by {:.1} dB, expected at least {:.1} dB", erle_db, SELF_TEST_MIN_ERLE_DB)
+            }
+            SelfTestError::LatencyMismatch { expected, measured: Some(measured) } => {
+                write!(f, "measured latency of {} samples, expected {}", measured, expected)
+            }
+            SelfTestError::LatencyMismatch { expected, measured: None } => {
+                write!(f, "impulse did not reach the output within the expected {} samples", expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SelfTestError {}
+
+impl FdafAec {
+    /// Runs a short simulation on a fresh canceller with this canceller's configuration and
+    /// checks the achieved echo attenuation and latency. This canceller is not modified.
+    ///
+    /// The simulation feeds the far-end at the processing rate, so a configured far-end
+    /// resampler is bypassed.
+    pub fn self_test(&self) -> Result<SelfTestReport, SelfTestError> {
+        let mut config = self.resolved_config().config;
+        config.far_end_sample_rate = None;
+        let frame_size = self.frame_size;
+        let expected_latency = self.latency_samples();
+
+        // White noise from a fixed-seed xorshift generator, echoed within the filter span.
+        let len = ECHO_SECONDS * self.sample_rate as usize / frame_size * frame_size;
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let far: Vec<f32> = (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                0.3 * ((state >> 40) as f32 / (1u64 << 23) as f32 - 1.0)
+            })
+            .collect();
+        let taps = [(frame_size / 8, 0.5), (frame_size / 4, -0.2)];
+        let mic: Vec<f32> = (0..len)
+            .map(|n| taps.iter().filter(|&&(delay, _)| n >= delay).map(|&(delay, gain)| gain * far[n - delay]).sum())
+            .collect();
+
+        let mut aec = FdafAec::with_config(config.clone());
+        let output: Vec<f32> = far.chunks(frame_size).zip(mic.chunks(frame_size)).flat_map(|(f, m)| aec.process(f, m)).collect();
+        if !output.iter().all(|x| x.is_finite()) {
+            return Err(SelfTestError::NonFiniteOutput);
+        }
+        let tail = len - len / 4;
+        let erle_db = 10.0 * (crate::mean_square(&mic[tail..]) / crate::mean_square(&output[tail..]).max(1e-20)).log10();
+        if erle_db < SELF_TEST_MIN_ERLE_DB {
+            return Err(SelfTestError::InsufficientErle { erle_db });
+        }
+
+        // An impulse in the middle of the second frame, with the far-end silent.
+        let mut aec = FdafAec::with_config(config);
+        let silence = vec![0.0; frame_size];
+        let mut impulse = vec![0.0; frame_size];
+        impulse[frame_size / 2] = 0.5;
+        let output: Vec<f32> = [&silence, &impulse, &silence, &silence]
+            .iter()
+            .flat_map(|mic_frame| aec.process(&silence, mic_frame))
+            .collect();
+        let measured = output
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            .filter(|(_, peak)| peak.abs() > 0.1)
+            .and_then(|(index, _)| index.checked_sub(frame_size + frame_size / 2));
+        if measured != Some(expected_latency) {
+            return Err(SelfTestError::LatencyMismatch { expected: expected_latency, measured });
+        }
+        Ok(SelfTestReport { erle_db, latency_samples: expected_latency })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FdafAecConfig;
+
+    #[test]
+    fn passes_on_default_and_lookahead_configurations() {
+        let report = FdafAec::with_config(FdafAecConfig::default()).self_test().unwrap();
+        assert!(report.erle_db >= SELF_TEST_MIN_ERLE_DB);
+        assert_eq!(report.latency_samples, 0);
+
+        let config = FdafAecConfig { far_end_lookahead: 16, ..FdafAecConfig::default() };
+        assert_eq!(FdafAec::with_config(config).self_test().unwrap().latency_samples, 16);
+
+        // A step size too small to converge in the simulated time fails the test.
+        let config = FdafAecConfig { step_size: 1e-4, ..FdafAecConfig::default() };
+        assert!(matches!(FdafAec::with_config(config).self_test(), Err(SelfTestError::InsufficientErle { .. })));
+    }
+}
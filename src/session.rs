@@ -0,0 +1,303 @@
+//! Transcript of runtime parameter changes for reproducing live sessions.
+//!
+//! Issues reported from live calls often involve tuning done while the call was running:
+//! a step size lowered, adaptation frozen around a device switch, a post-filter enabled.
+//! Replaying the recorded audio through a freshly configured canceller then gives a
+//! different result. While a session log is recorded, the canceller notes the frame index
+//! of every change of its runtime configuration, of every adaptation freeze and resume, and
+//! of every render volume notification. [`FdafAec::apply_session_event`] applies a recorded
+//! event to another canceller, and [`SessionLog::replay`] replays a whole session
+//! frame-exactly.
+//!
+//! The setters of runtime parameters mark the configuration as changed, and before the
+//! next frame the effective configuration, see [`FdafAec::resolved_config`], is compared
+//! with the last recorded one; frames without a setter call cost a flag check. Closures
+//! installed at runtime, e.g. a step size controller or a playback path model, cannot be
+//! recorded, and neither can a setter called with the value already in effect.
+
+use crate::{FdafAec, FdafAecConfig};
+
+/// A recorded runtime change.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SessionEvent {
+    /// The runtime configuration changed, e.g. by a setter or a switch to another preset.
+    /// Holds the complete effective configuration after the change.
+    Config(Box<FdafAecConfig>),
+    /// Adaptation was frozen (`false`) or resumed (`true`), see
+    /// [`FdafAec::set_adaptation_enabled`].
+    AdaptationEnabled(bool),
+    /// The render volume changed, see [`FdafAec::notify_render_volume_change`].
+    RenderVolumeChange(f32),
+    /// The processing sample rate changed, see [`FdafAec::notify_sample_rate_change`].
+    SampleRateChange(u32),
+}
+
+/// A change and the frame it took effect on.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionEntry {
+    /// The index of the first frame processed with the change, as counted by
+    /// [`FdafAec::frame_count`].
+    pub frame: u64,
+    /// The change.
+    pub event: SessionEvent,
+}
+
+/// The runtime changes of a canceller since [`FdafAec::start_session_log`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionLog {
+    /// The effective configuration when the log was started.
+    pub initial: FdafAecConfig,
+    /// The frame count when the log was started.
+    pub start_frame: u64,
+    /// The changes, in the order they took effect.
+    pub entries: Vec<SessionEntry>,
+}
+
+impl SessionLog {
+    /// Replays the session: processes `far_end` and `mic` with a new canceller built from the
+    /// initial configuration, applying every change before the frame it took effect on, and
+    /// returns the output. Processing stops when either stream runs out of full frames.
+    ///
+    /// The streams must start at the frame the log was started on.
+    pub fn replay(&self, far_end: &[f32], mic: &[f32]) -> Vec<f32> {
+        let mut aec = FdafAec::with_config(self.initial.clone());
+        let mut entries = self.entries.iter().peekable();
+        let mut output = Vec::with_capacity(mic.len());
+        let (mut far_start, mut mic_start) = (0, 0);
+        for frame in 0u64.. {
+            while let Some(entry) = entries.next_if(|entry| entry.frame - self.start_frame <= frame) {
+                aec.apply_session_event(&entry.event);
+            }
+            // A recorded sample rate change changes the frame sizes
+            let (far_end_frame, mic_frame) = (far_start + aec.far_end_frame_size(), mic_start + aec.frame_size);
+            if far_end_frame > far_end.len() || mic_frame > mic.len() {
+                break;
+            }
+            output.extend(aec.process(&far_end[far_start..far_end_frame], &mic[mic_start..mic_frame]));
+            (far_start, mic_start) = (far_end_frame, mic_frame);
+        }
+        output
+    }
+}
+
+/// Detects changes against the last recorded state.
+#[derive(Debug, Clone)]
+pub(crate) struct SessionRecorder {
+    log: SessionLog,
+    config: FdafAecConfig,
+    /// Whether a setter was called since the configuration was last compared.
+    config_changed: bool,
+    adaptation_enabled: bool,
+}
+
+impl FdafAec {
+    /// Starts recording a session log, discarding any log recorded so far.
+    ///
+    /// A replay starts from a new canceller with the initial configuration of the log, so
+    /// it only reproduces the session exactly if the log is started before the first frame.
+    pub fn start_session_log(&mut self) {
+        let config = self.resolved_config().config;
+        self.session = Some(SessionRecorder {
+            log: SessionLog { initial: config.clone(), start_frame: self.frames_processed, entries: Vec::new() },
+            config,
+            config_changed: false,
+            adaptation_enabled: true,
+        });
+    }
+
+    /// Returns the session log recorded so far, or `None` if no log is being recorded.
+    pub fn session_log(&self) -> Option<&SessionLog> {
+        self.session.as_ref().map(|session| &session.log)
+    }
+
+    /// Stops recording and returns the session log.
+    pub fn take_session_log(&mut self) -> Option<SessionLog> {
+        self.session.take().map(|session| session.log)
+    }
+
+    /// Applies a recorded event, changing only the runtime parameters that differ from the
+    /// current ones.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a configuration event changes a parameter that is fixed at construction,
+    /// such as the FFT size; a canceller constructed from the initial configuration of the
+    /// same log never sees one. Such parameters only change with a recorded sample rate
+    /// change.
+    pub fn apply_session_event(&mut self, event: &SessionEvent) {
+        match event {
+            SessionEvent::Config(config) => self.apply_runtime_config(config),
+            SessionEvent::AdaptationEnabled(enabled) => self.set_adaptation_enabled(*enabled),
+            SessionEvent::RenderVolumeChange(gain_ratio) => self.notify_render_volume_change(*gain_ratio),
+            SessionEvent::SampleRateChange(sample_rate) => {
+                self.notify_sample_rate_change(*sample_rate).expect("A recorded sample rate change is valid.");
+            }
+        }
+    }
+
+    /// Marks the runtime configuration as changed. Called by every setter of a parameter
+    /// in [`FdafAecConfig`] that can change at runtime.
+    pub(crate) fn note_config_change(&mut self) {
+        if let Some(session) = self.session.as_mut() {
+            session.config_changed = true;
+        }
+    }
+
+    /// Records the changes made since the last frame. Called before every frame.
+    pub(crate) fn record_session_changes(&mut self) {
+        let Some(session) = self.session.as_ref() else {
+            return;
+        };
+        let config = session.config_changed.then(|| self.resolved_config().config);
+        let (frame, adaptation_enabled) = (self.frames_processed, self.adaptation_enabled);
+        let Some(session) = self.session.as_mut() else {
+            return;
+        };
+        session.config_changed = false;
+        if let Some(config) = config.filter(|config| *config != session.config) {
+            session.log.entries.push(SessionEntry { frame, event: SessionEvent::Config(Box::new(config.clone())) });
+            session.config = config;
+        }
+        if adaptation_enabled != session.adaptation_enabled {
+            session.log.entries.push(SessionEntry { frame, event: SessionEvent::AdaptationEnabled(adaptation_enabled) });
+            session.adaptation_enabled = adaptation_enabled;
+        }
+    }
+
+    /// Records an event that is not part of the configuration.
+    pub(crate) fn record_session_event(&mut self, event: SessionEvent) {
+        let frame = self.frames_processed;
+        if let Some(session) = self.session.as_mut() {
+            session.log.entries.push(SessionEntry { frame, event });
+        }
+    }
+
+    /// Records an event that changed parameters fixed at construction, and takes the new
+    /// configuration as the baseline so they are not recorded as a configuration change.
+    pub(crate) fn record_session_rebase(&mut self, event: SessionEvent) {
+        if self.session.is_none() {
+            return;
+        }
+        self.record_session_event(event);
+        let config = self.resolved_config().config;
+        if let Some(session) = self.session.as_mut() {
+            session.config = config;
+            session.config_changed = false;
+        }
+    }
+
+    fn apply_runtime_config(&mut self, config: &FdafAecConfig) {
+        let current = self.resolved_config().config;
+        assert!(
+            config.fft_size == current.fft_size
+                && config.sample_rate == current.sample_rate
+                && config.far_end_sample_rate == current.far_end_sample_rate
+                && config.fast_start_frames == current.fast_start_frames
+                && config.fast_start_step_boost == current.fast_start_step_boost
+                && config.psd_smoothing == current.psd_smoothing
+                && config.constrained_update == current.constrained_update
+                && config.block_method == current.block_method
+                && config.far_end_lookahead == current.far_end_lookahead,
+            "Parameters fixed at construction cannot be changed at runtime."
+        );
+        macro_rules! apply {
+            ($($field:ident => $setter:ident),* $(,)?) => {
+                $(if config.$field != current.$field {
+                    self.$setter(config.$field.clone());
+                })*
+            };
+        }
+        apply! {
+            step_size => set_step_size,
+            adaptation => set_adaptation_mode,
+            step_profile => set_step_profile,
+            adaptive_step => set_adaptive_step,
+            volume_ramp => set_volume_ramp,
+            regularization => set_regularization_profile,
+            normalization => set_normalization,
+            precision => set_precision,
+            guard_band => set_guard_band,
+            high_pass => set_high_pass,
+            hum_notch => set_hum_notch,
+            tonality => set_tonality_control,
+            content_mode => set_content_mode,
+            crosstalk => set_crosstalk_resistance,
+            band_double_talk => set_band_double_talk,
+            geigel_dtd => set_geigel_dtd,
+            coherence_dtd => set_coherence_dtd,
+            vad => set_vad,
+            nonlinear_echo => set_nonlinear_echo,
+            convergence_protection => set_convergence_protection,
+            late_reverb => set_late_reverb,
+            post_filter => set_post_filter,
+            nlp => set_nlp,
+            residual_ceiling => set_residual_ceiling,
+            comfort_noise => set_comfort_noise,
+            low_power => set_low_power,
+            agc => set_agc,
+            cpu_budget => set_cpu_budget,
+            clipping_detection => set_clipping_detection,
+            bulk_delay => set_bulk_delay,
+            drift_compensation => set_drift_compensation,
+            max_weight_update => set_max_weight_update,
+            weight_leakage => set_weight_leakage,
+            max_echo_path_gain => set_max_echo_path_gain,
+            deadline => set_deadline,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use crate::{NlpLevel, PostFilterConfig};
+
+    #[test]
+    fn replay_reproduces_live_tuning() {
+        let far = white_noise(256 * 120, 0.3, 95);
+        let mic = echo(&far, &[(10, 0.5), (30, -0.2)]);
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.start_session_log();
+        let mut live = Vec::new();
+        for (i, (f, m)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+            match i {
+                20 => aec.set_step_size(0.2),
+                40 => aec.set_adaptation_enabled(false),
+                60 => {
+                    aec.set_adaptation_enabled(true);
+                    aec.set_post_filter(Some(PostFilterConfig::default()));
+                    aec.notify_render_volume_change(0.5);
+                }
+                80 => aec.set_nlp(Some(NlpLevel::Moderate)),
+                _ => {}
+            }
+            live.extend(aec.process(f, m));
+        }
+
+        let log = aec.take_session_log().unwrap();
+        let frames: Vec<u64> = log.entries.iter().map(|entry| entry.frame).collect();
+        assert_eq!(frames, [20, 40, 60, 60, 60, 80]);
+        assert_eq!(log.entries[1].event, SessionEvent::AdaptationEnabled(false));
+        assert_eq!(log.replay(&far, &mic), live);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn session_logs_round_trip_through_serde() {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.start_session_log();
+        let silence = vec![0.0; 256];
+        aec.process(&silence, &silence);
+        aec.set_step_size(0.2);
+        aec.notify_render_volume_change(0.5);
+        aec.process(&silence, &silence);
+        let log = aec.take_session_log().unwrap();
+        assert_eq!(log.entries.len(), 2);
+        let json = serde_json::to_string(&log).unwrap();
+        assert_eq!(serde_json::from_str::<SessionLog>(&json).unwrap(), log);
+    }
+}
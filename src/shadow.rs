@@ -0,0 +1,340 @@
+//! On-device A/B evaluation of a second configuration.
+//!
+//! Offline sweeps (see [`tuning`](crate::tuning)) only cover the recordings at hand. To
+//! evaluate a new tuning on the devices and in the rooms of real users, a shadow canceller
+//! with the candidate configuration runs next to the shipping one on the same far-end and
+//! microphone streams. Only the output of the shipping canceller is ever returned; the
+//! shadow output is used to compare the echo return loss enhancement (ERLE) of both over
+//! time.
+//!
+//! The shadow runs on its own thread. The audio thread copies the inputs and the output of
+//! every frame into a bounded queue without waiting; when the shadow falls behind, frames
+//! are dropped and counted instead of delaying the audio. The frame buffers are allocated
+//! once when the evaluation starts and handed back by the shadow thread after use, so the
+//! audio thread does not allocate for the shadow. The thread is spawned at the
+//! default priority, as the standard library has no portable way to lower it; the
+//! application can lower it through the platform API from the thread name
+//! [`SHADOW_THREAD_NAME`].
+
+use crate::{mean_square, ConfigError, EchoCancellerFactory, FdafAec, FdafAecConfig};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// The name of the thread that runs the shadow canceller.
+pub const SHADOW_THREAD_NAME: &str = "fdaf-aec-shadow";
+
+/// Parameters of the shadow evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ShadowConfig {
+    /// The number of frames over which each entry of [`ShadowReport::windows`] measures ERLE.
+    pub window_frames: usize,
+    /// The number of frames the queue to the shadow thread holds before frames are dropped.
+    pub queue_frames: usize,
+    /// The number of most recent windows kept in the report.
+    pub history: usize,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self { window_frames: 100, queue_frames: 32, history: 60 }
+    }
+}
+
+/// The ERLE of both cancellers over one window of frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowWindow {
+    /// The index of the first frame of the window, counted from the start of the evaluation.
+    pub first_frame: u64,
+    /// ERLE in dB of the shipping canceller.
+    pub primary_erle_db: f32,
+    /// ERLE in dB of the shadow canceller.
+    pub shadow_erle_db: f32,
+}
+
+impl ShadowWindow {
+    /// Returns by how many dB the shadow cancelled more echo than the shipping canceller.
+    pub fn erle_gain_db(&self) -> f32 {
+        self.shadow_erle_db - self.primary_erle_db
+    }
+}
+
+/// The comparison of the shipping and the shadow canceller so far.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ShadowReport {
+    /// The number of frames processed by both cancellers.
+    pub frames_compared: u64,
+    /// The number of frames the shadow missed because its queue was full. Every dropped
+    /// frame is a gap in the far-end history of the shadow, which briefly lowers its ERLE.
+    pub frames_dropped: u64,
+    /// ERLE in dB of the shipping canceller over all compared frames.
+    pub primary_erle_db: f32,
+    /// ERLE in dB of the shadow canceller over all compared frames.
+    pub shadow_erle_db: f32,
+    /// The most recent complete windows, oldest first.
+    pub windows: Vec<ShadowWindow>,
+}
+
+impl ShadowReport {
+    /// Returns by how many dB the shadow cancels more echo than the shipping canceller.
+    pub fn erle_gain_db(&self) -> f32 {
+        self.shadow_erle_db - self.primary_erle_db
+    }
+}
+
+/// The state shared by the shadow thread, the audio thread and the handles.
+#[derive(Debug, Default)]
+struct SharedReport {
+    report: Mutex<ShadowReport>,
+    frames_dropped: AtomicU64,
+}
+
+/// A cloneable, thread-safe view of the running shadow evaluation.
+#[derive(Debug, Clone)]
+pub struct ShadowHandle {
+    shared: Arc<SharedReport>,
+}
+
+impl ShadowHandle {
+    /// Returns the comparison so far.
+    pub fn report(&self) -> ShadowReport {
+        let mut report = self.shared.report.lock().unwrap().clone();
+        report.frames_dropped = self.shared.frames_dropped.load(Ordering::Relaxed);
+        report
+    }
+}
+
+/// The inputs and the shipping output of one frame.
+#[derive(Debug)]
+struct ShadowFrame {
+    far_end: Vec<f32>,
+    mic: Vec<f32>,
+    output: Vec<f32>,
+}
+
+/// The mean-square energies summed over a number of frames.
+#[derive(Debug, Clone, Copy, Default)]
+struct Energies {
+    mic: f64,
+    primary: f64,
+    shadow: f64,
+}
+
+impl Energies {
+    fn add(&mut self, other: &Energies) {
+        self.mic += other.mic;
+        self.primary += other.primary;
+        self.shadow += other.shadow;
+    }
+
+    fn erle_db(&self, output: f64) -> f32 {
+        (10.0 * (self.mic / (output + 1e-10)).log10()) as f32
+    }
+}
+
+impl ShadowFrame {
+    fn with_capacity(far_end_frame_size: usize, frame_size: usize) -> Self {
+        Self { far_end: Vec::with_capacity(far_end_frame_size), mic: Vec::with_capacity(frame_size), output: Vec::with_capacity(frame_size) }
+    }
+}
+
+/// The audio-thread side of a running shadow evaluation.
+#[derive(Debug)]
+pub(crate) struct ShadowEvaluation {
+    sender: SyncSender<ShadowFrame>,
+    /// Frame buffers the shadow is done with.
+    recycled: Receiver<ShadowFrame>,
+    /// Frame buffers ready to be filled.
+    spare: Vec<ShadowFrame>,
+    shared: Arc<SharedReport>,
+    thread: JoinHandle<()>,
+    /// The frame in progress, holding its inputs.
+    pending: Option<ShadowFrame>,
+}
+
+/// Runs the shadow canceller until the audio side hangs up, handing every frame buffer back
+/// through `recycle` once it is processed.
+fn run_shadow(mut aec: FdafAec, frames: Receiver<ShadowFrame>, recycle: SyncSender<ShadowFrame>, shared: &SharedReport, config: ShadowConfig) {
+    let mut total = Energies::default();
+    let mut window = Energies::default();
+    let mut window_start = 0;
+    let mut compared = 0u64;
+    for frame in frames {
+        let shadow = aec.process(&frame.far_end, &frame.mic);
+        let energies = Energies {
+            mic: mean_square(&frame.mic) as f64,
+            primary: mean_square(&frame.output) as f64,
+            shadow: mean_square(&shadow) as f64,
+        };
+        total.add(&energies);
+        window.add(&energies);
+        compared += 1;
+
+        let mut report = shared.report.lock().unwrap();
+        report.frames_compared = compared;
+        report.primary_erle_db = total.erle_db(total.primary);
+        report.shadow_erle_db = total.erle_db(total.shadow);
+        if compared - window_start == config.window_frames as u64 {
+            if report.windows.len() == config.history {
+                report.windows.remove(0);
+            }
+            report.windows.push(ShadowWindow {
+                first_frame: window_start,
+                primary_erle_db: window.erle_db(window.primary),
+                shadow_erle_db: window.erle_db(window.shadow),
+            });
+            window = Energies::default();
+            window_start = compared;
+        }
+        drop(report);
+        // The return queue holds every buffer, so this only fails once the audio side is gone
+        let _ = recycle.try_send(frame);
+    }
+}
+
+impl FdafAec {
+    /// Starts evaluating `config` in a shadow canceller on a separate thread, replacing any
+    /// shadow evaluation in progress. The output of this canceller is not affected.
+    ///
+    /// A replaced evaluation finishes its queued frames in the background without being
+    /// waited for; its [`ShadowHandle`]s keep reporting its final state. Starting allocates
+    /// the frame buffers and spawns a thread, so it is best called off the audio thread.
+    ///
+    /// The shadow receives the same far-end and microphone frames as this canceller, so
+    /// `config` must give the same frame sizes. A sample rate change stops the evaluation.
+    pub fn start_shadow(&mut self, config: FdafAecConfig, shadow: ShadowConfig) -> Result<ShadowHandle, ConfigError> {
+        let check = |ok: bool, parameter, requirement| if ok { Ok(()) } else { Err(ConfigError { parameter, requirement }) };
+        check(shadow.window_frames > 0, "shadow.window_frames", "must be at least one frame")?;
+        check(shadow.queue_frames > 0, "shadow.queue_frames", "must be at least one frame")?;
+        check(shadow.history > 0, "shadow.history", "must be at least one window")?;
+        let aec = EchoCancellerFactory::new(config)?.create();
+        check(aec.frame_size == self.frame_size, "fft_size", "must match the running canceller")?;
+        check(aec.far_end_frame_size() == self.far_end_frame_size(), "far_end_sample_rate", "must match the running canceller")?;
+
+        // Enough buffers for a full queue, the frame the shadow is processing and the frame
+        // in progress on the audio side
+        let buffers = shadow.queue_frames + 2;
+        let spare = (0..buffers).map(|_| ShadowFrame::with_capacity(self.far_end_frame_size(), self.frame_size)).collect();
+        let shared = Arc::new(SharedReport::default());
+        let (sender, frames) = mpsc::sync_channel(shadow.queue_frames);
+        let (recycle, recycled) = mpsc::sync_channel(buffers);
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name(SHADOW_THREAD_NAME.into())
+                .spawn(move || run_shadow(aec, frames, recycle, &shared, shadow))
+                .expect("Failed to spawn the shadow thread.")
+        };
+        self.shadow = Some(ShadowEvaluation { sender, recycled, spare, shared: Arc::clone(&shared), thread, pending: None });
+        Ok(ShadowHandle { shared })
+    }
+
+    /// Stops the shadow evaluation, waiting for the shadow to process the queued frames,
+    /// and returns the final report, or `None` if no evaluation was running.
+    ///
+    /// This blocks until the shadow thread has finished, so it should not be called on the
+    /// audio thread; [`ShadowHandle::report`] reads the report without stopping.
+    pub fn stop_shadow(&mut self) -> Option<ShadowReport> {
+        let shadow = self.shadow.take()?;
+        let handle = ShadowHandle { shared: shadow.shared };
+        drop(shadow.sender);
+        // A panic in the shadow must not take down the audio thread; the report so far stands
+        let _ = shadow.thread.join();
+        Some(handle.report())
+    }
+
+    /// Copies the inputs of the current frame into a spare buffer for the shadow.
+    pub(crate) fn stage_shadow_frame(&mut self, far_end_frame: &[f32], mic_frame: &[f32]) {
+        let Some(shadow) = self.shadow.as_mut() else { return };
+        shadow.spare.extend(shadow.recycled.try_iter());
+        let Some(mut frame) = shadow.spare.pop() else {
+            // Every buffer is still queued
+            shadow.shared.frames_dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        frame.far_end.clear();
+        frame.far_end.extend_from_slice(far_end_frame);
+        frame.mic.clear();
+        frame.mic.extend_from_slice(mic_frame);
+        shadow.pending = Some(frame);
+    }
+
+    /// Hands the current frame and its output to the shadow without waiting.
+    pub(crate) fn feed_shadow(&mut self, output: &[f32]) {
+        let Some(shadow) = self.shadow.as_mut() else { return };
+        let Some(mut frame) = shadow.pending.take() else { return };
+        frame.output.clear();
+        frame.output.extend_from_slice(output);
+        match shadow.sender.try_send(frame) {
+            Ok(()) => {}
+            Err(TrySendError::Full(frame)) => {
+                shadow.spare.push(frame);
+                shadow.shared.frames_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            // The shadow panicked; stop feeding it without waiting for its thread
+            Err(TrySendError::Disconnected(_)) => {
+                self.shadow = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn compares_erle_with_the_shadow() {
+        let far = white_noise(256 * 200, 0.3, 104);
+        let mic = echo(&far, &[(10, 0.5)]);
+        // The shipping configuration never adapts; the candidate does.
+        let mut aec = FdafAec::with_config(FdafAecConfig { fft_size: 512, step_size: 0.0, ..FdafAecConfig::default() });
+        let candidate = FdafAecConfig { fft_size: 512, step_size: 0.5, ..FdafAecConfig::default() };
+        let handle = aec.start_shadow(candidate, ShadowConfig { window_frames: 50, queue_frames: 200, history: 3 }).unwrap();
+        let output = process_all(&mut aec, &far, &mic);
+        assert_eq!(output, mic, "the shadow must not change the output");
+
+        let report = aec.stop_shadow().unwrap();
+        assert_eq!((report.frames_compared, report.frames_dropped), (200, 0));
+        assert_eq!(report.windows.iter().map(|window| window.first_frame).collect::<Vec<_>>(), [50, 100, 150]);
+        assert!(report.primary_erle_db.abs() < 0.1);
+        // The cumulative ERLE includes the initial convergence of the shadow; the windows
+        // show where it settles.
+        assert!(report.windows[1..].iter().all(|window| window.erle_gain_db() > 20.0), "{:?}", report.windows);
+        assert_eq!(handle.report(), report);
+        assert!(aec.stop_shadow().is_none());
+
+        let larger = FdafAecConfig { fft_size: 1024, ..FdafAecConfig::default() };
+        assert_eq!(aec.start_shadow(larger, ShadowConfig::default()).unwrap_err().parameter, "fft_size");
+    }
+
+    #[test]
+    fn reuses_the_frame_buffers() {
+        let far = white_noise(256 * 200, 0.3, 109);
+        let mic = echo(&far, &[(10, 0.5)]);
+        let mut aec = FdafAec::new(512, 0.5);
+        let handle = aec.start_shadow(FdafAecConfig { fft_size: 512, ..FdafAecConfig::default() }, ShadowConfig { queue_frames: 4, ..ShadowConfig::default() }).unwrap();
+        let buffers = |shadow: &ShadowEvaluation| {
+            let mut buffers: Vec<*const f32> = shadow.spare.iter().map(|frame| frame.mic.as_ptr()).collect();
+            buffers.sort();
+            buffers
+        };
+        let allocated = buffers(aec.shadow.as_ref().unwrap());
+        assert_eq!(allocated.len(), 6);
+        process_all(&mut aec, &far, &mic);
+
+        // Once the shadow has caught up, every buffer is back and none was added.
+        while handle.report().frames_compared + handle.report().frames_dropped < 200 {
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let shadow = aec.shadow.as_mut().unwrap();
+        while shadow.spare.len() < allocated.len() {
+            shadow.spare.extend(shadow.recycled.try_iter());
+        }
+        assert_eq!(buffers(shadow), allocated);
+        assert_eq!(aec.stop_shadow().unwrap().frames_compared + handle.report().frames_dropped, 200);
+    }
+}
@@ -0,0 +1,84 @@
+//! Frame-to-frame stability of the estimated echo path.
+//!
+//! A converged filter barely changes from one frame to the next. When the acoustic
+//! environment moves, e.g. a laptop lid is tilted or a phone is rotated, the filter has to
+//! follow and its weights change quickly until it has re-converged. The canceller measures
+//! the change of the weight vector on every adapted frame, relative to the size of the
+//! weights, and condenses it into a stability score between 0.0 (changing fast) and 1.0
+//! (still) that upstream code can use for UI hints or its own adaptive behavior.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+
+/// The smoothed relative weight change per frame at which the stability score is 0.5.
+const REFERENCE_CHANGE: f32 = 0.01;
+/// Smoothing factor applied to the relative weight change on every adapted frame.
+const CHANGE_SMOOTHING: f32 = 0.8;
+
+/// Tracks the relative change of the weights between adapted frames.
+#[derive(Debug, Clone)]
+pub(crate) struct StabilityTracker {
+    previous: DVector<Complex<f32>>,
+    change: f32,
+    smoothed_change: f32,
+}
+
+impl StabilityTracker {
+    pub(crate) fn new(fft_size: usize) -> Self {
+        Self { previous: DVector::from_element(fft_size, Complex::new(0.0, 0.0)), change: 0.0, smoothed_change: 0.0 }
+    }
+
+    /// Compares the weights after an adapted frame with those after the previous one.
+    pub(crate) fn update(&mut self, weights: &DVector<Complex<f32>>) {
+        let norm = weights.iter().map(|w| w.norm_sqr()).sum::<f32>();
+        let difference = weights.iter().zip(self.previous.iter()).map(|(w, p)| (w - p).norm_sqr()).sum::<f32>();
+        self.change = if norm > 0.0 { (difference / norm).sqrt() } else { 0.0 };
+        self.smoothed_change = CHANGE_SMOOTHING * self.smoothed_change + (1.0 - CHANGE_SMOOTHING) * self.change;
+        self.previous.copy_from(weights);
+    }
+
+    fn score(&self) -> f32 {
+        1.0 / (1.0 + self.smoothed_change / REFERENCE_CHANGE)
+    }
+}
+
+impl FdafAec {
+    /// Returns the change of the weights in the last adapted frame, as the norm of the
+    /// difference relative to the norm of the weights.
+    pub fn echo_path_change(&self) -> f32 {
+        self.stability.change
+    }
+
+    /// Returns the stability of the estimated echo path, from 0.0 while it changes fast to
+    /// 1.0 while it is still. The score is 0.5 when the weights change by about 1% per frame.
+    pub fn echo_path_stability(&self) -> f32 {
+        self.stability.score()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{echo, white_noise};
+    use crate::FdafAec;
+
+    #[test]
+    fn score_drops_when_echo_path_moves() {
+        let far = white_noise(256 * 300, 0.3, 85);
+        let mut mic = echo(&far, &[(10, 0.5), (40, 0.2)]);
+        let moved = echo(&far, &[(30, -0.4), (80, 0.2)]);
+        mic[150 * 256..].copy_from_slice(&moved[150 * 256..]);
+
+        let mut aec = FdafAec::new(512, 0.5);
+        let mut scores = Vec::new();
+        for (f, m) in far.chunks(256).zip(mic.chunks(256)) {
+            aec.process(f, m);
+            scores.push(aec.echo_path_stability());
+        }
+
+        assert!(scores[5] < 0.2, "converging: {}", scores[5]);
+        assert!(scores[149] > 0.9, "converged: {}", scores[149]);
+        assert!(scores[155] < 0.5, "moved: {}", scores[155]);
+        assert!(scores[299] > 0.9, "re-converged: {}", scores[299]);
+    }
+}
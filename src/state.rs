@@ -0,0 +1,187 @@
+//! Compact versioned binary snapshots of the adaptive state.
+//!
+//! Unlike [`DeviceProfile`](crate::DeviceProfile), the binary format needs no serde and also
+//! stores the far-end history, so a canceller can be checkpointed every second on an embedded
+//! device and resumed without a glitch. Statistics such as the quality flags are not stored
+//! and settle again within a few frames.
+//!
+//! Layout, all little-endian:
+//!
+//! | Field            | Size                |
+//! |------------------|---------------------|
+//! | magic `b"FDAF"`  | 4 bytes             |
+//! | format version   | `u16`               |
+//! | FFT size `n`     | `u32`               |
+//! | weights          | `n` × 2 × `f32`     |
+//! | far-end PSD      | `n` × `f32`         |
+//! | far-end history  | `n` × `f32`         |
+//! | CRC-32 (IEEE)    | `u32` over all of the above |
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const MAGIC: [u8; 4] = *b"FDAF";
+const VERSION: u16 = 1;
+
+/// The error returned when a binary state snapshot cannot be restored.
+#[derive(Debug)]
+pub enum StateError {
+    /// Reading the snapshot failed.
+    Io(io::Error),
+    /// The data does not start with the snapshot magic bytes.
+    BadMagic,
+    /// The snapshot was written by an unsupported format version.
+    UnsupportedVersion(u16),
+    /// The snapshot was written by a canceller with a different FFT size.
+    FftSizeMismatch { expected: usize, found: usize },
+    /// The checksum does not match the snapshot contents.
+    ChecksumMismatch,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Io(err) => write!(f, "failed to read state: {}", err),
+            StateError::BadMagic => write!(f, "data is not a canceller state snapshot"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported state format version {}", version),
+            StateError::FftSizeMismatch { expected, found } => {
+                write!(f, "state FFT size {} does not match canceller FFT size {}", found, expected)
+            }
+            StateError::ChecksumMismatch => write!(f, "state checksum mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StateError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StateError {
+    fn from(err: io::Error) -> Self {
+        StateError::Io(err)
+    }
+}
+
+/// Computes the CRC-32 (IEEE 802.3, reflected) of `data`, continuing from `crc`.
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+impl FdafAec {
+    /// Writes the adaptive state in the binary snapshot format.
+    pub fn write_state(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut buffer = Vec::with_capacity(14 + 16 * self.fft_size);
+        buffer.extend_from_slice(&MAGIC);
+        buffer.extend_from_slice(&VERSION.to_le_bytes());
+        buffer.extend_from_slice(&(self.fft_size as u32).to_le_bytes());
+        for w in self.weights.iter() {
+            buffer.extend_from_slice(&w.re.to_le_bytes());
+            buffer.extend_from_slice(&w.im.to_le_bytes());
+        }
+        for x in self.psd.iter().chain(self.far_end_buffer.iter()) {
+            buffer.extend_from_slice(&x.to_le_bytes());
+        }
+        let crc = crc32_update(0, &buffer);
+        buffer.extend_from_slice(&crc.to_le_bytes());
+        writer.write_all(&buffer)
+    }
+
+    /// Restores the adaptive state from a binary snapshot written by [`FdafAec::write_state`].
+    ///
+    /// The canceller is left unchanged if an error is returned.
+    pub fn read_state(&mut self, reader: &mut impl Read) -> Result<(), StateError> {
+        let mut header = [0u8; 10];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let found = u32::from_le_bytes([header[6], header[7], header[8], header[9]]) as usize;
+        if found != self.fft_size {
+            return Err(StateError::FftSizeMismatch { expected: self.fft_size, found });
+        }
+
+        let mut body = vec![0u8; 16 * self.fft_size + 4];
+        reader.read_exact(&mut body)?;
+        let (payload, crc) = body.split_at(body.len() - 4);
+        let expected = crc32_update(crc32_update(0, &header), payload);
+        if crc != expected.to_le_bytes() {
+            return Err(StateError::ChecksumMismatch);
+        }
+
+        let mut values = payload.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        let n = self.fft_size;
+        let weights: Vec<Complex<f32>> = (0..n).map(|_| Complex::new(values.next().unwrap(), values.next().unwrap())).collect();
+        self.weights = DVector::from_vec(weights);
+        self.psd = DVector::from_iterator(n, values.by_ref().take(n));
+        self.far_end_buffer = DVector::from_iterator(n, values);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn crc_matches_reference_value() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn state_round_trip_resumes_identically() {
+        let mut aec = FdafAec::new(512, 0.5);
+        let far = white_noise(256 * 40, 0.3, 20);
+        let mic = echo(&far, &[(7, 0.5)]);
+        for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)).take(30) {
+            aec.process(far_frame, mic_frame);
+        }
+
+        let mut snapshot = Vec::new();
+        aec.write_state(&mut snapshot).unwrap();
+        assert_eq!(snapshot.len(), 14 + 16 * 512);
+        let mut restored = FdafAec::new(512, 0.5);
+        restored.read_state(&mut snapshot.as_slice()).unwrap();
+
+        for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)).skip(30) {
+            assert_eq!(aec.process(far_frame, mic_frame), restored.process(far_frame, mic_frame));
+        }
+    }
+
+    #[test]
+    fn corrupted_state_is_rejected() {
+        let aec = FdafAec::new(512, 0.5);
+        let mut snapshot = Vec::new();
+        aec.write_state(&mut snapshot).unwrap();
+
+        let mut other = FdafAec::new(256, 0.5);
+        assert!(matches!(
+            other.read_state(&mut snapshot.as_slice()),
+            Err(StateError::FftSizeMismatch { expected: 256, found: 512 })
+        ));
+
+        snapshot[100] ^= 0x01;
+        let mut restored = FdafAec::new(512, 0.5);
+        assert!(matches!(restored.read_state(&mut snapshot.as_slice()), Err(StateError::ChecksumMismatch)));
+        assert!(matches!(restored.read_state(&mut &snapshot[..20]), Err(StateError::Io(_))));
+    }
+}
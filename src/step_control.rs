@@ -0,0 +1,100 @@
+//! Caller-defined step size schedules.
+//!
+//! The built-in step size logic (content mode, fast start) covers the common cases. Power
+//! users with their own adaptation strategy can install a step size controller, a closure
+//! that is called once per frame with the statistics of that frame and returns the step
+//! size to use for its weight update.
+
+use crate::{DuplexState, FdafAec};
+
+/// The statistics of the current frame passed to a step size controller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameContext {
+    /// The zero-based index of the frame, see [`FdafAec::frame_count`].
+    pub frame: u64,
+    /// The long-term echo return loss enhancement, in dB.
+    pub erle_db: f32,
+    /// Whether double talk was detected in this frame.
+    pub double_talk: bool,
+    /// Whether the far-end is active, i.e. the conversation state is
+    /// [`DuplexState::FarEndOnly`] or [`DuplexState::DoubleTalk`].
+    pub far_end_active: bool,
+    /// The conversation state of this frame.
+    pub duplex_state: DuplexState,
+    /// The mean-square level of the far-end frame.
+    pub far_end_energy: f32,
+    /// The step size the canceller would use without a controller, including the content
+    /// mode and fast-start factors.
+    pub default_step_size: f32,
+}
+
+/// A closure returning the step size for a frame.
+pub(crate) type StepSizeController = Box<dyn FnMut(&FrameContext) -> f32 + Send>;
+
+impl FdafAec {
+    /// Installs a closure that decides the step size of every frame, replacing the
+    /// built-in schedule.
+    ///
+    /// The closure is called once per frame, after the frame statistics have been updated
+    /// and before the weight update. Returning 0.0 freezes adaptation for the frame.
+    pub fn set_step_size_controller(&mut self, controller: impl FnMut(&FrameContext) -> f32 + Send + 'static) {
+        self.step_controller = Some(Box::new(controller));
+    }
+
+    /// Removes the step size controller and returns to the built-in schedule.
+    pub fn clear_step_size_controller(&mut self) {
+        self.step_controller = None;
+    }
+
+    /// Returns the step size for the current frame, asking the controller if one is set.
+    pub(crate) fn controlled_step_size(&mut self, default_step_size: f32, far_end_energy: f32) -> f32 {
+        let Some(controller) = self.step_controller.as_mut() else {
+            return default_step_size;
+        };
+        let duplex_state = self.duplex.state();
+        controller(&FrameContext {
+            frame: self.frames_processed,
+            erle_db: 10.0 * self.quality.erle().max(1e-10).log10(),
+            double_talk: self.quality.quality().double_talk,
+            far_end_active: matches!(duplex_state, DuplexState::FarEndOnly | DuplexState::DoubleTalk),
+            duplex_state,
+            far_end_energy,
+            default_step_size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn controller_sets_step_size_per_frame() {
+        let mut aec = FdafAec::new(512, 0.5);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        // Only adapt during the second half of the run.
+        aec.set_step_size_controller(move |context| {
+            log.lock().unwrap().push(*context);
+            if context.frame < 50 { 0.0 } else { context.default_step_size }
+        });
+
+        let far = white_noise(256 * 150, 0.3, 34);
+        let mic = echo(&far, &[(10, 0.5)]);
+        for (i, (far_frame, mic_frame)) in far.chunks(256).zip(mic.chunks(256)).enumerate() {
+            aec.process(far_frame, mic_frame);
+            if i == 49 {
+                assert!(aec.impulse_response().iter().all(|&h| h == 0.0));
+            }
+        }
+        assert!((aec.impulse_response()[10] - 0.5).abs() < 0.05);
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 150);
+        assert_eq!(seen[149].frame, 149);
+        assert_eq!(seen[149].default_step_size, 0.5);
+        assert!(seen[149].far_end_active && seen[149].erle_db > 15.0);
+    }
+}
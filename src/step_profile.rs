@@ -0,0 +1,134 @@
+//! Per-bin step sizes.
+//!
+//! Echo paths and near-end noise are strongly frequency dependent. A single step size
+//! over-adapts bins in which the residual is mostly noise and under-adapts bins in which it
+//! is mostly echo. A [`StepProfile`] scales the step size per frequency bin, either with
+//! caller-provided factors or from a running per-bin estimate of the echo-to-noise ratio.
+
+use crate::FdafAec;
+use num_complex::Complex;
+
+/// Smoothing factor of the per-bin echo and noise powers used by [`StepProfile::Snr`].
+const SNR_SMOOTHING: f32 = 0.9;
+
+/// The per-bin scaling of the step size.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StepProfile {
+    /// The same step size in every bin.
+    #[default]
+    Scalar,
+    /// Caller-provided factors for the bins `0..=fft_size / 2`.
+    Custom(Vec<f32>),
+    /// Factors derived from the per-bin ratio of estimated echo power to residual (noise and
+    /// near-end) power: `snr / (1 + snr)`, bounded below by `min_scale`.
+    Snr { min_scale: f32 },
+}
+
+/// Computes the per-bin step size factors.
+#[derive(Debug, Clone)]
+pub(crate) struct BinStepSizes {
+    pub(crate) profile: StepProfile,
+    scales: Vec<f32>,
+    echo_power: Vec<f32>,
+    noise_power: Vec<f32>,
+}
+
+impl BinStepSizes {
+    pub(crate) fn new(profile: StepProfile, fft_size: usize) -> Self {
+        let bins = fft_size / 2 + 1;
+        let scales = match &profile {
+            StepProfile::Custom(factors) => {
+                assert_eq!(factors.len(), bins, "Custom step profile needs one factor per bin up to fft_size / 2.");
+                factors.clone()
+            }
+            StepProfile::Scalar | StepProfile::Snr { .. } => vec![1.0; bins],
+        };
+        Self { profile, scales, echo_power: vec![0.0; bins], noise_power: vec![0.0; bins] }
+    }
+
+    /// Returns whether the factors are derived from the signal spectra.
+    pub(crate) fn is_adaptive(&self) -> bool {
+        matches!(self.profile, StepProfile::Snr { .. })
+    }
+
+    /// Updates the per-bin estimates with the echo estimate and error spectra of the
+    /// current frame.
+    pub(crate) fn update(&mut self, echo_f: &[Complex<f32>], e_f: &[Complex<f32>]) {
+        let StepProfile::Snr { min_scale } = self.profile else {
+            return;
+        };
+        let a = SNR_SMOOTHING;
+        for k in 0..self.scales.len() {
+            self.echo_power[k] = a * self.echo_power[k] + (1.0 - a) * echo_f[k].norm_sqr();
+            self.noise_power[k] = a * self.noise_power[k] + (1.0 - a) * e_f[k].norm_sqr();
+            let share = self.echo_power[k] / (self.echo_power[k] + self.noise_power[k] + 1e-20);
+            self.scales[k] = share.max(min_scale);
+        }
+    }
+
+    /// Scales every bin of `gradient`, mirroring the factors onto the upper half.
+    pub(crate) fn apply(&self, gradient: &mut [Complex<f32>]) {
+        let fft_size = gradient.len();
+        for (k, g) in gradient.iter_mut().enumerate() {
+            *g *= self.scales[k.min(fft_size - k)];
+        }
+    }
+
+    pub(crate) fn scales(&self) -> &[f32] {
+        &self.scales
+    }
+}
+
+impl FdafAec {
+    /// Changes the per-bin scaling of the step size.
+    pub fn set_step_profile(&mut self, profile: StepProfile) {
+        self.note_config_change();
+        self.step_profile = BinStepSizes::new(profile, self.fft_size);
+    }
+
+    /// Returns the step size factor of every bin `0..=fft_size / 2` used in the last frame.
+    pub fn bin_step_scales(&self) -> &[f32] {
+        self.step_profile.scales()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn custom_profile_freezes_bins() {
+        let mut factors = vec![1.0; 257];
+        factors[128..].fill(0.0);
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_step_profile(StepProfile::Custom(factors.clone()));
+        let far = white_noise(256 * 20, 0.3, 53);
+        let mic = echo(&far, &[(10, 0.5)]);
+        process_all(&mut aec, &far, &mic);
+        assert_eq!(aec.bin_step_scales(), factors.as_slice());
+        assert!(aec.weights.iter().skip(128).take(257).all(|w| *w == Complex::new(0.0, 0.0)));
+        assert!(aec.weights[10] != Complex::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn snr_profile_slows_noisy_bins() {
+        // A continuous near-end tone at 2 kHz, bin 64 of 512 at 16 kHz.
+        let far = white_noise(256 * 150, 0.3, 54);
+        let mic: Vec<f32> = echo(&far, &[(10, 0.5)])
+            .iter()
+            .enumerate()
+            .map(|(i, e)| e + 0.3 * (2.0 * PI * 2000.0 * i as f32 / 16000.0).sin())
+            .collect();
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_step_profile(StepProfile::Snr { min_scale: 0.05 });
+        process_all(&mut aec, &far, &mic);
+
+        let scales = aec.bin_step_scales();
+        assert!(scales[64] < 0.1, "tone bin scale {}", scales[64]);
+        let clean = scales[100..250].iter().sum::<f32>() / 150.0;
+        assert!(clean > 0.9, "mean scale of clean bins {}", clean);
+    }
+}
@@ -0,0 +1,188 @@
+//! Stereo microphone processing with a mono cancellation core.
+//!
+//! Running one canceller per microphone channel doubles the cost and can remove different
+//! amounts of echo from each side, which shifts the stereo image. [`StereoCanceller`] instead
+//! cancels echo on the mono downmix and derives per-bin gains from the ratio between the
+//! cancelled and the original downmix spectra. The same gains are applied to both original
+//! channels, so the echo is removed while the inter-channel differences are preserved.
+//!
+//! The mono core runs the full [`FdafAec`] pipeline, so its output lags the downmix by
+//! [`FdafAec::latency_samples`]; the downmix and both channels are delayed by as much before
+//! the gains are derived and applied. The gains are applied as a causal filter of at most
+//! `fft_size / 2` taps, which adds no further latency.
+
+use crate::gain_filter::{causal_response, filter_frame};
+use crate::{DelayLine, FdafAec, FdafAecConfig};
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+
+/// Downmix spectrum magnitude below which a bin is passed through unchanged.
+const MIN_DOWNMIX_MAGNITUDE: f32 = 1e-6;
+
+/// Echo cancellation for a stereo microphone using a single mono [`FdafAec`].
+pub struct StereoCanceller {
+    aec: FdafAec,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    frame_size: usize,
+    /// Delays the downmix and both channels by the latency of the mono core.
+    delays: [DelayLine; 3],
+    downmix: Vec<f32>,
+    cancelled: Vec<f32>,
+    channels: [Vec<f32>; 2],
+}
+
+impl StereoCanceller {
+    /// Creates a stereo canceller whose mono core uses `config`.
+    pub fn new(config: FdafAecConfig) -> Self {
+        let aec = FdafAec::with_config(config);
+        let fft_size = aec.fft_size;
+        let mut planner = FftPlanner::new();
+        Self {
+            fft: planner.plan_fft_forward(fft_size),
+            ifft: planner.plan_fft_inverse(fft_size),
+            frame_size: aec.frame_size,
+            delays: [DelayLine::matching(&aec), DelayLine::matching(&aec), DelayLine::matching(&aec)],
+            downmix: vec![0.0; fft_size],
+            cancelled: vec![0.0; fft_size],
+            channels: [vec![0.0; fft_size], vec![0.0; fft_size]],
+            aec,
+        }
+    }
+
+    /// Returns the mono canceller, e.g. to query its state.
+    pub fn canceller(&self) -> &FdafAec {
+        &self.aec
+    }
+
+    /// Returns the delay of the output behind the microphone input, the latency of the mono
+    /// canceller.
+    pub fn latency_samples(&self) -> usize {
+        self.aec.latency_samples()
+    }
+
+    /// Processes one frame of far-end audio and one frame of each microphone channel.
+    ///
+    /// All frames must have `fft_size / 2` samples. Returns the echo-cancelled left and
+    /// right frames, delayed by [`StereoCanceller::latency_samples`].
+    pub fn process(&mut self, far_end_frame: &[f32], left: &[f32], right: &[f32]) -> [Vec<f32>; 2] {
+        assert_eq!(left.len(), self.frame_size, "Input left frame size must be half of FFT size.");
+        assert_eq!(right.len(), self.frame_size, "Input right frame size must be half of FFT size.");
+
+        let mut downmix: Vec<f32> = left.iter().zip(right).map(|(l, r)| 0.5 * (l + r)).collect();
+        let cancelled = self.aec.process(far_end_frame, &downmix);
+        let [downmix_delay, left_delay, right_delay] = &mut self.delays;
+        downmix_delay.process(&mut downmix);
+        let mut left = left.to_vec();
+        let mut right = right.to_vec();
+        left_delay.process(&mut left);
+        right_delay.process(&mut right);
+
+        // Keep the previous and the current frame of every signal, as in overlap-save.
+        push_frame(&mut self.downmix, &downmix);
+        push_frame(&mut self.cancelled, &cancelled);
+        push_frame(&mut self.channels[0], &left);
+        push_frame(&mut self.channels[1], &right);
+
+        let downmix_f = self.spectrum(&self.downmix);
+        let cancelled_f = self.spectrum(&self.cancelled);
+        let gains: Vec<f32> = downmix_f[..=self.frame_size]
+            .iter()
+            .zip(&cancelled_f)
+            .map(|(d, e)| {
+                let d = d.norm();
+                if d < MIN_DOWNMIX_MAGNITUDE { 1.0 } else { (e.norm() / d).min(1.0) }
+            })
+            .collect();
+        let response = causal_response(&self.fft, &self.ifft, &gains);
+
+        let spectra = [self.spectrum(&self.channels[0]), self.spectrum(&self.channels[1])];
+        spectra.map(|spectrum| filter_frame(&self.ifft, spectrum, &response))
+    }
+
+    fn spectrum(&self, signal: &[f32]) -> Vec<Complex<f32>> {
+        let mut buffer: Vec<Complex<f32>> = signal.iter().map(|&x| Complex::new(x, 0.0)).collect();
+        self.fft.process(&mut buffer);
+        buffer
+    }
+}
+
+/// Shifts a two-frame history buffer by one frame and appends `frame`.
+fn push_frame(history: &mut [f32], frame: &[f32]) {
+    let frame_size = frame.len();
+    history.copy_within(frame_size.., 0);
+    history[frame_size..].copy_from_slice(frame);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mean_square;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn cancels_echo_and_keeps_near_end_panning() {
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, ..FdafAecConfig::default() };
+        let mut stereo = StereoCanceller::new(config);
+
+        let far = white_noise(256 * 150, 0.3, 11);
+        let left_echo = echo(&far, &[(10, 0.5)]);
+        let right_echo = echo(&far, &[(10, 0.3)]);
+        let mut outputs = [Vec::new(), Vec::new()];
+        for ((far_frame, left), right) in far.chunks(256).zip(left_echo.chunks(256)).zip(right_echo.chunks(256)) {
+            let [l, r] = stereo.process(far_frame, left, right);
+            outputs[0].extend(l);
+            outputs[1].extend(r);
+        }
+        let tail = outputs[0].len() - 2560;
+        assert!(mean_square(&outputs[0][tail..]) < mean_square(&left_echo[tail..]) / 10.0);
+        assert!(mean_square(&outputs[1][tail..]) < mean_square(&right_echo[tail..]) / 10.0);
+
+        // Near-end speech on the left only stays on the left.
+        let silence = vec![0.0; 256];
+        let near = white_noise(256 * 4, 0.3, 12);
+        let mut left_out = Vec::new();
+        let mut right_out = Vec::new();
+        for frame in near.chunks(256) {
+            let [l, r] = stereo.process(&silence, frame, &silence);
+            left_out.extend(l);
+            right_out.extend(r);
+        }
+        assert!(mean_square(&left_out[256..]) > 0.5 * mean_square(&near[256..]));
+        assert!(mean_square(&right_out[256..]) < 1e-6);
+    }
+
+    #[test]
+    fn aligns_channels_with_the_core_latency() {
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, far_end_lookahead: 32, ..FdafAecConfig::default() };
+        let mut stereo = StereoCanceller::new(config);
+        assert_eq!(stereo.latency_samples(), 32);
+
+        let far = white_noise(256 * 150, 0.3, 13);
+        let left_echo = echo(&far, &[(40, 0.5)]);
+        let right_echo = echo(&far, &[(40, 0.3)]);
+        let mut outputs = [Vec::new(), Vec::new()];
+        for ((far_frame, left), right) in far.chunks(256).zip(left_echo.chunks(256)).zip(right_echo.chunks(256)) {
+            let [l, r] = stereo.process(far_frame, left, right);
+            outputs[0].extend(l);
+            outputs[1].extend(r);
+        }
+        let tail = outputs[0].len() - 2560;
+        assert!(mean_square(&outputs[0][tail..]) < mean_square(&left_echo[tail..]) / 10.0);
+        assert!(mean_square(&outputs[1][tail..]) < mean_square(&right_echo[tail..]) / 10.0);
+
+        // Once the far-end history has left the buffers, the channels come out unchanged and
+        // 32 samples late.
+        let silence = vec![0.0; 256];
+        let near = white_noise(256 * 4, 0.3, 14);
+        let mut left_out = Vec::new();
+        for frame in near.chunks(256) {
+            let [l, _] = stereo.process(&silence, frame, &silence);
+            left_out.extend(l);
+        }
+        for (out, input) in left_out[512..].iter().zip(&near[512 - 32..]) {
+            assert!((out - input).abs() < 1e-3, "{} != {}", out, input);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Push-based streaming input with strict validation for untrusted sources.
+//!
+//! Server deployments receive far-end and microphone audio as independent chunks over the
+//! network, with sizes and rates chosen by a remote peer. [`StreamingAec`] validates every
+//! chunk before touching any state, so a rejected chunk leaves the stream exactly as it was.
+//! All internal queues are bounded; producers are told to slow down through [`PushStatus`]
+//! before a queue fills up, and chunks that would overflow it are rejected.
+
+use crate::{FdafAec, FdafAecConfig};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Limits applied to the pushed audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamLimits {
+    /// The largest chunk accepted by a single push, in samples.
+    pub max_chunk_samples: usize,
+    /// The capacity of each internal queue (far-end, microphone and output), in samples.
+    pub max_buffered_samples: usize,
+}
+
+impl Default for StreamLimits {
+    fn default() -> Self {
+        Self { max_chunk_samples: 16_384, max_buffered_samples: 65_536 }
+    }
+}
+
+/// The reason a pushed chunk was rejected. The stream state is unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamError {
+    /// The chunk contains no samples.
+    EmptyChunk,
+    /// The chunk is longer than [`StreamLimits::max_chunk_samples`].
+    ChunkTooLarge { len: usize, max: usize },
+    /// The chunk was sent at a different sample rate than the canceller expects for its
+    /// channel, see [`FdafAec::far_end_sample_rate`].
+    SampleRateMismatch { expected: u32, found: u32 },
+    /// The sample at `index` is NaN or infinite.
+    NonFiniteSample { index: usize },
+    /// Accepting the chunk would exceed the queue capacity.
+    BufferFull { buffered: usize, capacity: usize },
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::EmptyChunk => write!(f, "chunk is empty"),
+            StreamError::ChunkTooLarge { len, max } => write!(f, "chunk of {} samples exceeds limit of {}", len, max),
+            StreamError::SampleRateMismatch { expected, found } => {
+                write!(f, "chunk sample rate {} Hz does not match stream rate {} Hz", found, expected)
+            }
+            StreamError::NonFiniteSample { index } => write!(f, "sample {} is not finite", index),
+            StreamError::BufferFull { buffered, capacity } => {
+                write!(f, "queue holds {} of {} samples and cannot take the chunk", buffered, capacity)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+/// The state of the queues after an accepted push.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushStatus {
+    /// The queues have room; keep pushing.
+    Ready,
+    /// A queue is more than half full. The producer should slow down, or the consumer should
+    /// drain the output, before further chunks are rejected.
+    HighWater,
+}
+
+/// An echo canceller fed by independent, validated far-end and microphone chunks.
+pub struct StreamingAec {
+    aec: FdafAec,
+    limits: StreamLimits,
+    frame_size: usize,
+    far_end_frame_size: usize,
+    far_end: VecDeque<f32>,
+    mic: VecDeque<f32>,
+    output: VecDeque<f32>,
+}
+
+impl StreamingAec {
+    /// Creates a stream running a canceller with the given configuration.
+    pub fn new(config: FdafAecConfig, limits: StreamLimits) -> Self {
+        let aec = FdafAec::with_config(config);
+        let frame_size = aec.frame_size;
+        let far_end_frame_size = aec.far_end_frame_size();
+        assert!(
+            limits.max_buffered_samples >= 2 * frame_size.max(far_end_frame_size),
+            "Queue capacity must hold at least two canceller frames."
+        );
+        Self {
+            aec,
+            limits,
+            frame_size,
+            far_end_frame_size,
+            far_end: VecDeque::new(),
+            mic: VecDeque::new(),
+            output: VecDeque::new(),
+        }
+    }
+
+    /// Returns the canceller, e.g. to query its state.
+    pub fn canceller(&self) -> &FdafAec {
+        &self.aec
+    }
+
+    /// Queues a chunk of far-end samples recorded at `sample_rate`.
+    pub fn push_far_end(&mut self, samples: &[f32], sample_rate: u32) -> Result<PushStatus, StreamError> {
+        self.validate(samples, sample_rate, self.aec.far_end_sample_rate(), self.far_end.len())?;
+        self.far_end.extend(samples);
+        self.process_frames();
+        Ok(self.status())
+    }
+
+    /// Queues a chunk of microphone samples recorded at `sample_rate`.
+    pub fn push_mic(&mut self, samples: &[f32], sample_rate: u32) -> Result<PushStatus, StreamError> {
+        self.validate(samples, sample_rate, self.aec.sample_rate(), self.mic.len())?;
+        self.mic.extend(samples);
+        self.process_frames();
+        Ok(self.status())
+    }
+
+    /// Moves up to `output.len()` echo-cancelled samples into `output` and returns how many
+    /// were written.
+    pub fn pop_output(&mut self, output: &mut [f32]) -> usize {
+        let count = output.len().min(self.output.len());
+        for (out, sample) in output.iter_mut().zip(self.output.drain(..count)) {
+            *out = sample;
+        }
+        self.process_frames();
+        count
+    }
+
+    /// Returns the number of echo-cancelled samples waiting to be popped.
+    pub fn output_available(&self) -> usize {
+        self.output.len()
+    }
+
+    fn validate(&self, samples: &[f32], sample_rate: u32, expected_rate: u32, buffered: usize) -> Result<(), StreamError> {
+        let capacity = self.limits.max_buffered_samples;
+        if samples.is_empty() {
+            return Err(StreamError::EmptyChunk);
+        }
+        if samples.len() > self.limits.max_chunk_samples {
+            return Err(StreamError::ChunkTooLarge { len: samples.len(), max: self.limits.max_chunk_samples });
+        }
+        if sample_rate != expected_rate {
+            return Err(StreamError::SampleRateMismatch { expected: expected_rate, found: sample_rate });
+        }
+        if let Some(index) = samples.iter().position(|x| !x.is_finite()) {
+            return Err(StreamError::NonFiniteSample { index });
+        }
+        if buffered + samples.len() > capacity {
+            return Err(StreamError::BufferFull { buffered, capacity });
+        }
+        Ok(())
+    }
+
+    /// Runs the canceller on every complete frame pair, as long as the output has room.
+    fn process_frames(&mut self) {
+        while self.far_end.len() >= self.far_end_frame_size
+            && self.mic.len() >= self.frame_size
+            && self.output.len() + self.frame_size <= self.limits.max_buffered_samples
+        {
+            let far: Vec<f32> = self.far_end.drain(..self.far_end_frame_size).collect();
+            let mic: Vec<f32> = self.mic.drain(..self.frame_size).collect();
+            self.output.extend(self.aec.process(&far, &mic));
+        }
+    }
+
+    fn status(&self) -> PushStatus {
+        let high_water = self.limits.max_buffered_samples / 2;
+        let fullest = self.far_end.len().max(self.mic.len()).max(self.output.len());
+        if fullest > high_water {
+            PushStatus::HighWater
+        } else {
+            PushStatus::Ready
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn stream() -> StreamingAec {
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, ..FdafAecConfig::default() };
+        StreamingAec::new(config, StreamLimits { max_chunk_samples: 1024, max_buffered_samples: 2048 })
+    }
+
+    #[test]
+    fn rejects_invalid_chunks_without_side_effects() {
+        let mut stream = stream();
+        assert_eq!(stream.push_mic(&[], 16000), Err(StreamError::EmptyChunk));
+        assert_eq!(stream.push_mic(&[0.0; 1025], 16000), Err(StreamError::ChunkTooLarge { len: 1025, max: 1024 }));
+        assert_eq!(
+            stream.push_far_end(&[0.0; 10], 48000),
+            Err(StreamError::SampleRateMismatch { expected: 16000, found: 48000 })
+        );
+        let mut chunk = [0.1; 300];
+        chunk[299] = f32::NAN;
+        assert_eq!(stream.push_far_end(&chunk, 16000), Err(StreamError::NonFiniteSample { index: 299 }));
+
+        // Nothing was queued by the rejected pushes.
+        assert_eq!(stream.push_far_end(&[0.0; 200], 16000), Ok(PushStatus::Ready));
+        assert_eq!(stream.push_mic(&[0.0; 200], 16000), Ok(PushStatus::Ready));
+        assert_eq!(stream.output_available(), 0);
+        assert_eq!(stream.canceller().frame_count(), 0);
+    }
+
+    #[test]
+    fn signals_backpressure_before_overflow() {
+        let mut stream = stream();
+        assert_eq!(stream.push_far_end(&[0.0; 1000], 16000), Ok(PushStatus::Ready));
+        assert_eq!(stream.push_far_end(&[0.0; 1000], 16000), Ok(PushStatus::HighWater));
+        assert_eq!(
+            stream.push_far_end(&[0.0; 100], 16000),
+            Err(StreamError::BufferFull { buffered: 2000, capacity: 2048 })
+        );
+
+        // Microphone audio drains the far-end queue until the output queue is full.
+        for _ in 0..2 {
+            stream.push_mic(&[0.0; 1000], 16000).unwrap();
+        }
+        assert_eq!(stream.output_available(), 1792);
+        let mut out = [0.0; 1024];
+        assert_eq!(stream.pop_output(&mut out), 1024);
+        assert_eq!(stream.output_available(), 768);
+    }
+
+    #[test]
+    fn survives_adversarial_push_patterns() {
+        let mut rng = StdRng::seed_from_u64(28);
+        let mut stream = stream();
+        let mut out = [0.0; 700];
+        for _ in 0..1000 {
+            let len = match rng.gen_range(0..4) {
+                0 => 0,
+                1 => rng.gen_range(1..4),
+                2 => rng.gen_range(1..1500),
+                _ => rng.gen_range(5000..100_000),
+            };
+            let mut chunk: Vec<f32> = (0..len).map(|_| rng.gen_range(-1.0..1.0)).collect();
+            if len > 0 && rng.gen_bool(0.1) {
+                chunk[rng.gen_range(0..len)] = f32::INFINITY;
+            }
+            let rate = if rng.gen_bool(0.8) { 16000 } else { 44100 };
+            match rng.gen_range(0..3) {
+                0 => drop(stream.push_far_end(&chunk, rate)),
+                1 => drop(stream.push_mic(&chunk, rate)),
+                _ => drop(stream.pop_output(&mut out[..rng.gen_range(0..700)])),
+            }
+            assert!(stream.far_end.len() <= 2048 && stream.mic.len() <= 2048 && stream.output.len() <= 2048);
+            assert!(stream.output.iter().all(|x| x.is_finite()));
+        }
+        assert!(stream.canceller().frame_count() > 0);
+    }
+
+    #[test]
+    fn cancels_echo_with_uneven_chunks() {
+        let mut stream = stream();
+        let far = white_noise(256 * 150, 0.3, 29);
+        let mic = echo(&far, &[(12, 0.5)]);
+        let mut output = Vec::new();
+        let mut out = [0.0; 512];
+        let (mut far_pos, mut mic_pos) = (0, 0);
+        for step in 0.. {
+            if far_pos == far.len() && mic_pos == mic.len() {
+                break;
+            }
+            let far_end = (far_pos + 97 + step % 300).min(far.len());
+            let mic_end = (mic_pos + 131 + step % 200).min(mic.len());
+            if far_end > far_pos && stream.push_far_end(&far[far_pos..far_end], 16000).is_ok() {
+                far_pos = far_end;
+            }
+            if mic_end > mic_pos && stream.push_mic(&mic[mic_pos..mic_end], 16000).is_ok() {
+                mic_pos = mic_end;
+            }
+            let count = stream.pop_output(&mut out);
+            output.extend_from_slice(&out[..count]);
+        }
+        assert_eq!(output.len(), far.len());
+        let tail = &output[output.len() - 2560..];
+        assert!(crate::mean_square(tail) < crate::mean_square(&mic[mic.len() - 2560..]) / 100.0);
+    }
+}
@@ -0,0 +1,44 @@
+//! Signal helpers shared by the unit tests.
+
+use crate::FdafAec;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Generates reproducible uniform white noise in `[-amplitude, amplitude]`.
+pub fn white_noise(len: usize, amplitude: f32, seed: u64) -> Vec<f32> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..len).map(|_| rng.gen_range(-amplitude..amplitude)).collect()
+}
+
+/// Simulates an echo path by convolving `signal` with a sparse impulse response given as
+/// `(delay, gain)` taps.
+pub fn echo(signal: &[f32], taps: &[(usize, f32)]) -> Vec<f32> {
+    let mut out = vec![0.0; signal.len()];
+    for (i, sample) in out.iter_mut().enumerate() {
+        for &(delay, gain) in taps {
+            if i >= delay {
+                *sample += signal[i - delay] * gain;
+            }
+        }
+    }
+    out
+}
+
+/// Processes `far` and `mic` frame by frame with `aec` and returns the output. Trailing
+/// samples that do not fill a frame are left out.
+pub fn process_all(aec: &mut FdafAec, far: &[f32], mic: &[f32]) -> Vec<f32> {
+    let mut output = Vec::with_capacity(mic.len());
+    for (far_frame, mic_frame) in far.chunks_exact(aec.far_end_frame_size()).zip(mic.chunks_exact(aec.frame_size)) {
+        output.extend(aec.process(far_frame, mic_frame));
+    }
+    output
+}
+
+/// Returns a canceller with an FFT size of 512 and a step size of 0.5, trained for 100
+/// frames on white noise echoed through the `(delay, gain)` taps.
+pub fn trained_aec(taps: &[(usize, f32)], seed: u64) -> FdafAec {
+    let mut aec = FdafAec::new(512, 0.5);
+    let far = white_noise(256 * 100, 0.3, seed);
+    process_all(&mut aec, &far, &echo(&far, taps));
+    aec
+}
@@ -0,0 +1,140 @@
+//! Far-end tonality detection for adaptation control.
+//!
+//! Pure tones such as ring tones or held musical notes concentrate the far-end energy in a
+//! few bins. The normalized update in those bins is driven by a nearly deterministic signal,
+//! which makes the adaptation ill-conditioned and lets the weights grow without bound. When
+//! enabled, the detector measures the spectral flatness of the far-end spectrum and scales
+//! down the step size of bins that stand out as narrow spectral peaks.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+
+/// Parameters of the far-end tonality detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TonalityConfig {
+    /// Power ratio between a bin and the mean of its neighbourhood above which the bin is
+    /// considered tonal.
+    pub peak_ratio: f32,
+    /// Number of bins on each side of a bin that form its neighbourhood.
+    pub neighborhood: usize,
+    /// Factor applied to the step size of tonal bins, between 0.0 (freeze) and 1.0 (no change).
+    pub tonal_step_scale: f32,
+}
+
+impl Default for TonalityConfig {
+    fn default() -> Self {
+        Self { peak_ratio: 10.0, neighborhood: 4, tonal_step_scale: 0.1 }
+    }
+}
+
+/// Computes the far-end spectral flatness and the per-bin step size scaling.
+#[derive(Debug, Clone)]
+pub(crate) struct TonalityDetector {
+    pub(crate) config: TonalityConfig,
+    flatness: f32,
+    step_scale: Vec<f32>,
+}
+
+impl TonalityDetector {
+    pub(crate) fn new(config: TonalityConfig, fft_size: usize) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&config.tonal_step_scale),
+            "Tonal step scale must be between 0.0 and 1.0."
+        );
+        Self { config, flatness: 1.0, step_scale: vec![1.0; fft_size] }
+    }
+
+    /// Updates the detector from the far-end power spectral density.
+    pub(crate) fn update(&mut self, psd: &DVector<f32>) {
+        let n = psd.len();
+        // The spectrum of a real signal is symmetric, so the flatness only needs the
+        // non-negative frequencies.
+        let half = &psd.as_slice()[..n / 2 + 1];
+        let log_mean = half.iter().map(|p| (p + 1e-20).ln()).sum::<f32>() / half.len() as f32;
+        let mean = half.iter().sum::<f32>() / half.len() as f32;
+        self.flatness = if mean > 0.0 { (log_mean.exp() / mean).min(1.0) } else { 1.0 };
+
+        let width = self.config.neighborhood.min((n - 1) / 2).max(1);
+        for (i, scale) in self.step_scale.iter_mut().enumerate() {
+            let neighbours = (1..=width).map(|d| psd[(i + d) % n] + psd[(i + n - d) % n]).sum::<f32>();
+            let local_mean = neighbours / (2 * width) as f32;
+            *scale = if psd[i] > self.config.peak_ratio * local_mean {
+                self.config.tonal_step_scale
+            } else {
+                1.0
+            };
+        }
+    }
+
+    pub(crate) fn flatness(&self) -> f32 {
+        self.flatness
+    }
+
+    /// Returns the step size factor of every FFT bin.
+    pub(crate) fn step_scale(&self) -> &[f32] {
+        &self.step_scale
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables tonality-based step size control.
+    pub fn set_tonality_control(&mut self, config: Option<TonalityConfig>) {
+        self.note_config_change();
+        self.tonality = config.map(|config| TonalityDetector::new(config, self.fft_size));
+    }
+
+    /// Returns the spectral flatness of the far-end signal, or `None` if tonality control is
+    /// disabled.
+    ///
+    /// The flatness is close to 1.0 for noise-like far-end signals and close to 0.0 for
+    /// strongly tonal ones.
+    pub fn far_end_flatness(&self) -> Option<f32> {
+        self.tonality.as_ref().map(TonalityDetector::flatness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::white_noise;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn peaks_are_slowed_down() {
+        let mut detector = TonalityDetector::new(TonalityConfig::default(), 64);
+        let mut psd = DVector::from_element(64, 1.0);
+        detector.update(&psd);
+        assert!(detector.flatness() > 0.99);
+        assert!(detector.step_scale().iter().all(|&s| s == 1.0));
+
+        psd[10] = 1000.0;
+        psd[54] = 1000.0;
+        detector.update(&psd);
+        assert!(detector.flatness() < 0.5);
+        assert_eq!(detector.step_scale()[10], 0.1);
+        assert_eq!(detector.step_scale()[54], 0.1);
+        assert_eq!(detector.step_scale()[11], 1.0);
+    }
+
+    #[test]
+    fn flatness_separates_tone_from_noise() {
+        let mut aec = FdafAec::new(512, 0.5);
+        assert_eq!(aec.far_end_flatness(), None);
+        aec.set_tonality_control(Some(TonalityConfig::default()));
+
+        let noise = white_noise(256 * 200, 0.3, 9);
+        for frame in noise.chunks(256) {
+            aec.process(frame, frame);
+        }
+        let noise_flatness = aec.far_end_flatness().unwrap();
+
+        let tone: Vec<f32> = (0..256 * 200).map(|i| 0.3 * (2.0 * PI * 1000.0 * i as f32 / 16000.0).sin()).collect();
+        for frame in tone.chunks(256) {
+            aec.process(frame, frame);
+        }
+        let tone_flatness = aec.far_end_flatness().unwrap();
+        assert!(noise_flatness > 0.5, "noise flatness was {}", noise_flatness);
+        assert!(tone_flatness < 0.1, "tone flatness was {}", tone_flatness);
+    }
+}
@@ -0,0 +1,137 @@
+//! Seeding a canceller with the echo path of another one.
+//!
+//! Reconfiguring to a different FFT size or sample rate normally means starting over with an
+//! empty filter and a burst of echo. The converged impulse response of the old canceller can
+//! seed the new one instead: it is resampled to the new rate with a windowed-sinc kernel,
+//! truncated or zero-padded to the new filter length, shifted by the difference in far-end
+//! lookahead, and transformed into the new weights. The kernel keeps the passband gain of the
+//! echo path, so the echo estimate has the same level at either rate.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+use std::f64::consts::PI;
+
+/// Half-width of the resampling kernel, in samples of the lower of the two rates.
+const KERNEL_HALF_WIDTH: f64 = 16.0;
+
+/// Resamples an impulse response from `from_rate` to `to_rate`, keeping its passband gain.
+pub(crate) fn resample_impulse_response(ir: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate {
+        return ir.to_vec();
+    }
+    let ratio = to_rate as f64 / from_rate as f64;
+    // The kernel is a low-pass at the lower Nyquist frequency, in input samples. When
+    // upsampling, every input tap is spread over `ratio` output taps, so its gain is divided
+    // among them.
+    let cutoff = ratio.min(1.0);
+    let scale = (1.0 / ratio).min(1.0);
+    let half_width = KERNEL_HALF_WIDTH / cutoff;
+    let len = (ir.len() as f64 * ratio).ceil() as usize;
+    (0..len)
+        .map(|n| {
+            let position = n as f64 / ratio;
+            let first = (position - half_width).ceil().max(0.0) as usize;
+            let last = ((position + half_width).floor() as usize).min(ir.len().saturating_sub(1));
+            let sum: f64 = (first..=last)
+                .map(|m| {
+                    let x = position - m as f64;
+                    let window = 0.5 + 0.5 * (PI * x / half_width).cos();
+                    f64::from(ir[m]) * sinc(cutoff * x) * window
+                })
+                .sum();
+            (scale * sum) as f32
+        })
+        .collect()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-12 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl FdafAec {
+    /// Replaces the echo path estimate with the one of `source`, which may use a different
+    /// FFT size, sample rate, or far-end lookahead.
+    ///
+    /// The far-end PSD estimate is seeded from the source as well, so that the first weight
+    /// updates are normalized sensibly. The far-end history and all statistics are kept.
+    pub fn transfer_echo_path_from(&mut self, source: &FdafAec) {
+        let ir = resample_impulse_response(&source.impulse_response(), source.sample_rate, self.sample_rate);
+        // The lookahead delays the microphone, and with it the echo path, by its length.
+        let source_lookahead =
+            (source.latency_samples() as f64 * self.sample_rate as f64 / source.sample_rate as f64).round() as usize;
+        let target_lookahead = self.latency_samples();
+
+        let mut h = vec![Complex::new(0.0, 0.0); self.fft_size];
+        for (n, &tap) in ir.iter().enumerate() {
+            let Some(shifted) = (n + target_lookahead).checked_sub(source_lookahead) else {
+                continue;
+            };
+            if shifted < self.frame_size {
+                h[shifted] = Complex::new(tap, 0.0);
+            }
+        }
+        self.fft.process(&mut h);
+        self.weights = DVector::from_vec(h);
+
+        // The unnormalized PSD grows with the FFT size.
+        let mean_psd = source.psd.mean() * self.fft_size as f32 / source.fft_size as f32;
+        self.psd.fill(mean_psd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use crate::FdafAecConfig;
+
+    #[test]
+    fn resampled_tap_keeps_delay_and_gain() {
+        let mut ir = vec![0.0; 64];
+        ir[10] = 0.5;
+        let up = resample_impulse_response(&ir, 16000, 48000);
+        assert_eq!(up.len(), 192);
+        let peak = up.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).unwrap().0;
+        assert_eq!(peak, 30);
+        assert!((up.iter().sum::<f32>() - 0.5).abs() < 0.01);
+
+        let down = resample_impulse_response(&up, 48000, 16000);
+        assert!((down[10] - 0.5).abs() < 0.03, "tap was {}", down[10]);
+    }
+
+    #[test]
+    fn seeds_new_fft_size_with_converged_path() {
+        let far = white_noise(256 * 110, 0.3, 58);
+        let mic = echo(&far, &[(10, 0.5), (100, -0.2)]);
+        let mut source = FdafAec::new(512, 0.5);
+        for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)).take(100) {
+            source.process(far_frame, mic_frame);
+        }
+
+        for fft_size in [256, 1024] {
+            let config = FdafAecConfig { fft_size, step_size: 0.5, far_end_lookahead: 4, ..FdafAecConfig::default() };
+            let mut seeded = FdafAec::with_config(config);
+            seeded.transfer_echo_path_from(&source);
+            let ir = seeded.impulse_response();
+            assert!((ir[14] - 0.5).abs() < 0.02, "{}: tap was {}", fft_size, ir[14]);
+
+            // After the first frames, which only fill the far-end history, the output is
+            // free of echo.
+            let frame_size = fft_size / 2;
+            let start = 100 * 256;
+            let output: Vec<f32> = far[start..]
+                .chunks(frame_size)
+                .zip(mic[start..].chunks(frame_size))
+                .flat_map(|(f, m)| seeded.process(f, m))
+                .collect();
+            let settled = 2 * frame_size..2 * frame_size + 1024;
+            let (output, mic) = (&output[settled.clone()], &mic[start..][settled]);
+            assert!(crate::mean_square(output) < crate::mean_square(mic) / 100.0, "{}", fft_size);
+        }
+    }
+}
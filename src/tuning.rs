@@ -0,0 +1,147 @@
+//! Offline parameter sweeps for selecting a configuration for a specific device.
+//!
+//! A recording of the far-end and microphone signals is run through every configuration
+//! of a [`ParameterGrid`] in parallel, and each run is scored by its echo return loss
+//! enhancement (ERLE).
+
+use crate::{mean_square, FdafAec, FdafAecConfig};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// The parameter values to combine in a sweep. Every combination is evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterGrid {
+    /// Candidate FFT sizes, which set the filter tail length to `fft_size / 2` samples.
+    pub fft_sizes: Vec<usize>,
+    /// Candidate NLMS step sizes.
+    pub step_sizes: Vec<f32>,
+    /// Candidate PSD smoothing factors.
+    pub psd_smoothing: Vec<f32>,
+}
+
+impl ParameterGrid {
+    /// Expands the grid into the list of configurations it describes.
+    pub fn configs(&self) -> Vec<FdafAecConfig> {
+        let mut configs = Vec::new();
+        for &fft_size in &self.fft_sizes {
+            for &step_size in &self.step_sizes {
+                for &psd_smoothing in &self.psd_smoothing {
+                    configs.push(FdafAecConfig {
+                        fft_size,
+                        step_size,
+                        psd_smoothing,
+                        ..FdafAecConfig::default()
+                    });
+                }
+            }
+        }
+        configs
+    }
+}
+
+/// The metrics of one configuration evaluated on a recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuningResult {
+    /// The evaluated configuration.
+    pub config: FdafAecConfig,
+    /// ERLE in dB over the whole recording.
+    pub erle_db: f32,
+    /// ERLE in dB over the second half of the recording, after the initial convergence.
+    pub steady_state_erle_db: f32,
+}
+
+/// Runs a single configuration over a recording and measures its ERLE.
+///
+/// `far_end` is at the far-end rate of `config`, and `mic` at its sample rate. Only complete
+/// frames are processed. For a meaningful score the recording should contain
+/// far-end single talk, since near-end speech in the microphone signal lowers the ERLE of
+/// every configuration.
+pub fn evaluate(config: &FdafAecConfig, far_end: &[f32], mic: &[f32]) -> TuningResult {
+    let mut aec = FdafAec::with_config(config.clone());
+    let (far_end_frame_size, frame_size) = (aec.far_end_frame_size(), config.fft_size / 2);
+    let mut output = Vec::with_capacity(mic.len());
+    for (far_frame, mic_frame) in far_end.chunks_exact(far_end_frame_size).zip(mic.chunks_exact(frame_size)) {
+        output.extend(aec.process(far_frame, mic_frame));
+    }
+
+    let processed = &mic[..output.len()];
+    let half = output.len() / 2;
+    TuningResult {
+        config: config.clone(),
+        erle_db: erle_db(processed, &output),
+        steady_state_erle_db: erle_db(&processed[half..], &output[half..]),
+    }
+}
+
+/// Evaluates every configuration of `grid` on the same recording, in parallel.
+///
+/// The results are returned in the order of [`ParameterGrid::configs`].
+pub fn sweep(grid: &ParameterGrid, far_end: &[f32], mic: &[f32]) -> Vec<TuningResult> {
+    let configs = grid.configs();
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; configs.len()]);
+    let workers = thread::available_parallelism().map_or(1, |n| n.get()).min(configs.len());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(config) = configs.get(index) else { break };
+                let result = evaluate(config, far_end, mic);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    results.into_inner().unwrap().into_iter().flatten().collect()
+}
+
+/// Returns the result with the highest steady-state ERLE.
+pub fn best(results: &[TuningResult]) -> Option<&TuningResult> {
+    results
+        .iter()
+        .filter(|r| r.steady_state_erle_db.is_finite())
+        .max_by(|a, b| a.steady_state_erle_db.total_cmp(&b.steady_state_erle_db))
+}
+
+fn erle_db(mic: &[f32], output: &[f32]) -> f32 {
+    10.0 * (mean_square(mic) / (mean_square(output) + 1e-10)).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn sweep_scores_every_config_in_order() {
+        let far = white_noise(16000, 0.3, 3);
+        let mic = echo(&far, &[(5, 0.6), (30, 0.2)]);
+        let grid = ParameterGrid { fft_sizes: vec![256, 512], step_sizes: vec![0.0, 0.5], psd_smoothing: vec![0.98] };
+
+        let results = sweep(&grid, &far, &mic);
+        assert_eq!(results.len(), 4);
+        for (result, config) in results.iter().zip(grid.configs()) {
+            assert_eq!(result.config, config);
+        }
+
+        // A zero step size never adapts, so it cannot be the best choice.
+        let best = best(&results).unwrap();
+        assert!(best.config.step_size > 0.0);
+        assert!(best.steady_state_erle_db > 10.0);
+    }
+    #[test]
+    fn evaluates_a_far_end_at_another_rate() {
+        // The echo is rendered at 48 kHz and picked up at 16 kHz through a pure delay.
+        let far = white_noise(768 * 120, 0.3, 113);
+        let mut decimator = crate::resample::FarEndResampler::new(48000, 16000);
+        let mut far_16k = vec![0.0; 256 * 120];
+        decimator.process(&far, &mut far_16k);
+        let mic = echo(&far_16k, &[(20, 0.5)]);
+
+        let config = FdafAecConfig { fft_size: 512, step_size: 0.5, far_end_sample_rate: Some(48000), ..FdafAecConfig::default() };
+        let result = evaluate(&config, &far, &mic);
+        assert!(result.steady_state_erle_db > 20.0, "{:?}", result);
+    }
+}
@@ -0,0 +1,175 @@
+//! Near-end voice activity detection on the error signal.
+//!
+//! Once the filter has removed the echo, what is left in the error signal is near-end
+//! sound: speech, background noise, and the residual echo. The detector tells speech from
+//! noise with two cheap features. The frame energy is compared with a noise floor that
+//! follows the minimum of the error energy down at once and creeps up slowly, so
+//! stationary noise ends up at the floor. The spectral flatness, the ratio of the geometric
+//! to the arithmetic mean of the power spectrum in the speech band, is high for noise and
+//! low for the harmonic spectrum of voiced speech. Both are mapped to a score between 0 and
+//! 1, and their geometric mean, smoothed with a fast attack and a slower release, is the
+//! speech probability of the frame.
+//!
+//! Besides being exposed to the application, e.g. to gate an encoder, the probability
+//! slows adaptation, since near-end speech in the error signal disturbs the update, and
+//! makes the NLP back off, since a far-end-only state with speech in the error signal is
+//! more likely a missed double talk than residual echo. Residual echo of a far-end talker
+//! is speech too, so the probability is only meaningful once the filter has converged.
+
+use crate::FdafAec;
+use num_complex::Complex;
+use rustfft::Fft;
+use std::sync::Arc;
+
+/// The factor by which the noise floor may rise per frame, about 0.5 dB/s at 16 kHz with
+/// 256-sample frames.
+const NOISE_FLOOR_RISE: f32 = 1.002;
+/// The frame energy above the noise floor, in dB, at which the energy score starts to rise
+/// and at which it reaches 1.
+const SNR_RANGE_DB: (f32, f32) = (3.0, 12.0);
+/// The spectral flatness at which the flatness score reaches 1 and at which it drops to 0.
+/// White noise has a flatness of about 0.56 without windowing.
+const FLATNESS_RANGE: (f32, f32) = (0.15, 0.45);
+/// The frequency range in which the spectral flatness is measured, in Hz.
+const SPEECH_BAND_HZ: (f32, f32) = (300.0, 4000.0);
+/// Smoothing factors of the speech probability while it rises and while it falls.
+const ATTACK: f32 = 0.5;
+const RELEASE: f32 = 0.9;
+
+/// Parameters of the near-end voice activity detector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VadConfig {
+    /// The factor applied to the step size when speech is certain, interpolated linearly
+    /// down from 1.0 with the speech probability. 1.0 leaves adaptation alone.
+    pub adaptation_floor: f32,
+    /// How far the NLP backs off towards unity gain when speech is certain, interpolated
+    /// linearly with the speech probability. 0.0 leaves the NLP alone.
+    pub nlp_release: f32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self { adaptation_floor: 0.2, nlp_release: 0.5 }
+    }
+}
+
+/// Estimates the probability of near-end speech from the error signal.
+#[derive(Debug, Clone)]
+pub(crate) struct VoiceActivityDetector {
+    pub(crate) config: VadConfig,
+    band: std::ops::Range<usize>,
+    noise_floor: Option<f32>,
+    probability: f32,
+}
+
+impl VoiceActivityDetector {
+    pub(crate) fn new(config: VadConfig, fft_size: usize, sample_rate: u32) -> Self {
+        assert!((0.0..=1.0).contains(&config.adaptation_floor), "The adaptation floor must be between 0 and 1.");
+        assert!((0.0..=1.0).contains(&config.nlp_release), "The NLP release must be between 0 and 1.");
+        let bin = |hz: f32| ((hz * fft_size as f32 / sample_rate as f32).round() as usize).clamp(1, fft_size / 2);
+        let band = bin(SPEECH_BAND_HZ.0)..bin(SPEECH_BAND_HZ.1).max(bin(SPEECH_BAND_HZ.0) + 1);
+        Self { config, band, noise_floor: None, probability: 0.0 }
+    }
+
+    /// Updates the speech probability with the error signal of a frame.
+    fn update(&mut self, fft: &Arc<dyn Fft<f32>>, error_signal: &[f32]) {
+        let energy = crate::mean_square(error_signal).max(1e-10);
+        let floor = match self.noise_floor {
+            Some(floor) => energy.min(floor * NOISE_FLOOR_RISE),
+            None => energy,
+        };
+        self.noise_floor = Some(floor);
+        let snr_db = 10.0 * (energy / floor).log10();
+        let energy_score = ((snr_db - SNR_RANGE_DB.0) / (SNR_RANGE_DB.1 - SNR_RANGE_DB.0)).clamp(0.0, 1.0);
+
+        let mut spectrum = vec![Complex::new(0.0, 0.0); 2 * error_signal.len()];
+        for (x, &sample) in spectrum.iter_mut().zip(error_signal) {
+            *x = Complex::new(sample, 0.0);
+        }
+        fft.process(&mut spectrum);
+        let power: Vec<f32> = spectrum[self.band.clone()].iter().map(|x| x.norm_sqr().max(1e-20)).collect();
+        let geometric = (power.iter().map(|p| p.ln()).sum::<f32>() / power.len() as f32).exp();
+        let arithmetic = power.iter().sum::<f32>() / power.len() as f32;
+        let flatness = geometric / arithmetic;
+        let flatness_score = ((FLATNESS_RANGE.1 - flatness) / (FLATNESS_RANGE.1 - FLATNESS_RANGE.0)).clamp(0.0, 1.0);
+
+        let target = (energy_score * flatness_score).sqrt();
+        let smoothing = if target > self.probability { ATTACK } else { RELEASE };
+        self.probability = smoothing * self.probability + (1.0 - smoothing) * target;
+    }
+
+    /// Returns the factor applied to the step size.
+    fn step_scale(&self) -> f32 {
+        1.0 - self.probability * (1.0 - self.config.adaptation_floor)
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the near-end voice activity
+    /// detector. It starts with a speech probability of 0 and learns the noise floor from
+    /// the first frame on.
+    pub fn set_vad(&mut self, config: Option<VadConfig>) {
+        self.note_config_change();
+        self.vad = config.map(|config| VoiceActivityDetector::new(config, self.fft_size, self.sample_rate));
+    }
+
+    /// Returns the probability, between 0 and 1, that the last frame contained near-end
+    /// speech, or `None` if the detector is disabled.
+    pub fn speech_probability(&self) -> Option<f32> {
+        self.vad.as_ref().map(|vad| vad.probability)
+    }
+
+    /// Updates the speech probability with the error signal of the current frame.
+    pub(crate) fn detect_voice_activity(&mut self, error_signal: &[f32]) {
+        if let Some(vad) = self.vad.as_mut() {
+            vad.update(&self.fft, error_signal);
+        }
+    }
+
+    /// Returns the factor applied to the step size for the current speech probability.
+    pub(crate) fn vad_step_scale(&self) -> f32 {
+        self.vad.as_ref().map_or(1.0, |vad| vad.step_scale())
+    }
+
+    /// Returns how far the NLP backs off towards unity gain for the current speech
+    /// probability.
+    pub(crate) fn vad_nlp_release(&self) -> f32 {
+        self.vad.as_ref().map_or(0.0, |vad| vad.probability * vad.config.nlp_release)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::white_noise;
+
+    #[test]
+    fn detects_voiced_speech_in_noise() {
+        let frames = 200;
+        let noise = white_noise(256 * frames, 0.01, 105);
+        // A voiced vowel at 150 Hz with a falling spectral envelope, in frames 100 to 150
+        let speech = 100 * 256..150 * 256;
+        let mic: Vec<f32> = noise
+            .iter()
+            .enumerate()
+            .map(|(n, &x)| {
+                let t = n as f32 / 16000.0;
+                let vowel: f32 = (1..=20).map(|h| (2.0 * std::f32::consts::PI * 150.0 * h as f32 * t).sin() * 0.2 / h as f32).sum();
+                if speech.contains(&n) { x + vowel } else { x }
+            })
+            .collect();
+        let silence = vec![0.0; 256];
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_vad(Some(VadConfig::default()));
+        let mut probabilities = Vec::new();
+        for frame in mic.chunks(256) {
+            aec.process(&silence, frame);
+            probabilities.push(aec.speech_probability().unwrap());
+        }
+        assert!(probabilities[50..100].iter().all(|&p| p < 0.1), "{:?}", &probabilities[50..100]);
+        assert!(probabilities[105..150].iter().all(|&p| p > 0.8), "{:?}", &probabilities[105..150]);
+        assert!(probabilities[180..].iter().all(|&p| p < 0.1));
+        assert!((aec.vad_step_scale() - 1.0).abs() < 0.1);
+    }
+}
@@ -0,0 +1,153 @@
+//! Step size ramp and weight rescaling on render volume changes.
+//!
+//! When the user changes the playback volume, the echo level jumps while the far-end
+//! reference, usually tapped before the volume control, stays the same. The filter then
+//! misadapts until it has relearned the new gain. If the application reports the change, the
+//! weights are rescaled by the known gain ratio right away; in addition, reported changes and
+//! detected far-end level steps start a short step size boost that fades out like the
+//! fast-start window.
+
+use crate::fast_start::FastStart;
+use crate::{FdafAec, SessionEvent};
+use num_complex::Complex;
+
+/// Mean-square far-end level below which level steps are not tracked (about -60 dBFS).
+const FAR_END_ACTIVITY_THRESHOLD: f32 = 1e-6;
+/// Smoothing factors of the fast and slow far-end level trackers.
+const FAST_LEVEL_SMOOTHING: f32 = 0.5;
+const SLOW_LEVEL_SMOOTHING: f32 = 0.95;
+
+/// Parameters of the step size ramp after volume changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VolumeRampConfig {
+    /// The number of frames over which the boost fades out.
+    pub frames: u32,
+    /// The factor applied to the step size right after the change.
+    pub boost: f32,
+    /// The far-end level change, in dB, that is treated as a volume change even without a
+    /// notification. `None` only reacts to
+    /// [`FdafAec::notify_render_volume_change`].
+    pub level_step_db: Option<f32>,
+}
+
+impl Default for VolumeRampConfig {
+    fn default() -> Self {
+        Self { frames: 25, boost: 3.0, level_step_db: Some(10.0) }
+    }
+}
+
+/// Detects far-end level steps and runs the step size boost.
+#[derive(Debug, Clone)]
+pub(crate) struct VolumeRamp {
+    pub(crate) config: VolumeRampConfig,
+    boost: FastStart,
+    fast_level_db: Option<f32>,
+    slow_level_db: f32,
+}
+
+impl VolumeRamp {
+    pub(crate) fn new(config: VolumeRampConfig) -> Self {
+        Self { config, boost: FastStart::idle(config.frames, config.boost), fast_level_db: None, slow_level_db: 0.0 }
+    }
+
+    /// Tracks the far-end level of the current frame and returns the step size factor.
+    pub(crate) fn update(&mut self, far_end_energy: f32) -> f32 {
+        if far_end_energy > FAR_END_ACTIVITY_THRESHOLD {
+            let level_db = 10.0 * far_end_energy.log10();
+            let fast = match self.fast_level_db {
+                Some(fast) => FAST_LEVEL_SMOOTHING * fast + (1.0 - FAST_LEVEL_SMOOTHING) * level_db,
+                None => {
+                    self.slow_level_db = level_db;
+                    level_db
+                }
+            };
+            self.fast_level_db = Some(fast);
+            self.slow_level_db = SLOW_LEVEL_SMOOTHING * self.slow_level_db + (1.0 - SLOW_LEVEL_SMOOTHING) * level_db;
+            if self.config.level_step_db.is_some_and(|step| (fast - self.slow_level_db).abs() > step) {
+                // Trigger once per step by adopting the new level as the long-term level.
+                self.slow_level_db = fast;
+                self.boost.restart();
+            }
+        }
+        self.boost.update()
+    }
+
+    pub(crate) fn restart(&mut self) {
+        self.boost.restart();
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.boost.is_active()
+    }
+}
+
+impl FdafAec {
+    /// Enables, reconfigures, or (with `None`) disables the step size ramp after volume
+    /// changes.
+    pub fn set_volume_ramp(&mut self, config: Option<VolumeRampConfig>) {
+        self.note_config_change();
+        self.volume_ramp = config.map(VolumeRamp::new);
+    }
+
+    /// Reports that the render volume changed by `gain_ratio` (linear, new over old), with
+    /// the far-end reference tapped before the volume control.
+    ///
+    /// The estimated echo path is rescaled by the ratio, and the step size ramp starts if it
+    /// is enabled.
+    pub fn notify_render_volume_change(&mut self, gain_ratio: f32) {
+        assert!(gain_ratio.is_finite() && gain_ratio >= 0.0, "Volume gain ratio must be finite and non-negative.");
+        self.record_session_event(SessionEvent::RenderVolumeChange(gain_ratio));
+        self.weights *= Complex::new(gain_ratio, 0.0);
+        if let Some(ramp) = self.volume_ramp.as_mut() {
+            ramp.restart();
+        }
+    }
+
+    /// Returns `true` while the step size ramp after a volume change is running.
+    pub fn in_volume_ramp(&self) -> bool {
+        self.volume_ramp.as_ref().is_some_and(VolumeRamp::is_active)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    fn converged_aec() -> (FdafAec, Vec<f32>) {
+        let mut aec = FdafAec::new(512, 0.5);
+        aec.set_volume_ramp(Some(VolumeRampConfig::default()));
+        let far = white_noise(256 * 200, 0.3, 39);
+        let mic = echo(&far, &[(10, 0.5)]);
+        for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)).take(100) {
+            aec.process(far_frame, mic_frame);
+        }
+        assert!(!aec.in_volume_ramp());
+        (aec, far)
+    }
+
+    #[test]
+    fn notification_rescales_echo_path() {
+        let (mut aec, far) = converged_aec();
+        aec.notify_render_volume_change(2.0);
+        assert!(aec.in_volume_ramp());
+        assert!((aec.impulse_response()[10] - 1.0).abs() < 0.1);
+
+        // The louder echo is cancelled from the first frame after the change.
+        let mic = echo(&far, &[(10, 1.0)]);
+        let output = aec.process(&far[100 * 256..101 * 256], &mic[100 * 256..101 * 256]);
+        assert!(crate::mean_square(&output) < crate::mean_square(&mic[100 * 256..101 * 256]) / 100.0);
+    }
+
+    #[test]
+    fn far_end_level_step_starts_ramp() {
+        let (mut aec, far) = converged_aec();
+        let far: Vec<f32> = far.iter().map(|x| x * 10.0).collect();
+        let mic = echo(&far, &[(10, 0.5)]);
+        for (far_frame, mic_frame) in far.chunks(256).zip(mic.chunks(256)).skip(100).take(2) {
+            aec.process(far_frame, mic_frame);
+        }
+        assert!(aec.in_volume_ramp());
+    }
+}
@@ -0,0 +1,130 @@
+//! Wall-clock deadline monitoring of [`FdafAec::process`].
+//!
+//! A real-time audio callback has one frame duration to produce its output. When enabled,
+//! the watchdog times every call to [`FdafAec::process`] against a budget and records the
+//! calls that exceed it, so integrations can detect CPU starvation before it turns into
+//! audible dropouts.
+
+use crate::FdafAec;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Maximum number of overrun events kept until they are drained.
+const MAX_PENDING_OVERRUNS: usize = 64;
+
+/// A single call to [`FdafAec::process`] that exceeded the deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overrun {
+    /// The index of the overrunning frame, see [`FdafAec::frame_count`].
+    pub frame: u64,
+    /// The wall time the frame took.
+    pub elapsed: Duration,
+}
+
+/// Timing statistics collected by the watchdog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DeadlineStats {
+    /// The number of frames timed.
+    pub frames: u64,
+    /// The number of frames that exceeded the budget.
+    pub overruns: u64,
+    /// The wall time of the most recent frame.
+    pub last: Duration,
+    /// The longest wall time of any frame.
+    pub worst: Duration,
+}
+
+/// Times frames against a budget and records overruns.
+#[derive(Debug, Clone)]
+pub(crate) struct DeadlineWatchdog {
+    pub(crate) budget: Duration,
+    stats: DeadlineStats,
+    pending: VecDeque<Overrun>,
+}
+
+impl DeadlineWatchdog {
+    pub(crate) fn new(budget: Duration) -> Self {
+        Self { budget, stats: DeadlineStats::default(), pending: VecDeque::new() }
+    }
+
+    pub(crate) fn record(&mut self, frame: u64, elapsed: Duration) {
+        if elapsed > self.budget {
+            if self.pending.len() == MAX_PENDING_OVERRUNS {
+                self.pending.pop_front();
+            }
+            self.pending.push_back(Overrun { frame, elapsed });
+            self.stats.overruns += 1;
+        }
+        self.stats.frames += 1;
+        self.stats.last = elapsed;
+        self.stats.worst = self.stats.worst.max(elapsed);
+    }
+}
+
+impl FdafAec {
+    /// Returns the duration of one frame of `fft_size / 2` samples at the configured sample
+    /// rate.
+    ///
+    /// This is the natural processing budget of a real-time callback; see
+    /// [`FdafAec::set_deadline`].
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frame_size as f64 / self.sample_rate as f64)
+    }
+
+    /// Enables the processing watchdog with the given per-frame budget, or disables it with
+    /// `None`. Enabling resets the collected statistics.
+    pub fn set_deadline(&mut self, budget: Option<Duration>) {
+        self.note_config_change();
+        self.watchdog = budget.map(DeadlineWatchdog::new);
+    }
+
+    /// Returns the timing statistics, or `None` if the watchdog is disabled.
+    pub fn deadline_stats(&self) -> Option<DeadlineStats> {
+        self.watchdog.as_ref().map(|watchdog| watchdog.stats)
+    }
+
+    /// Removes and returns the overrun events recorded since the last call.
+    ///
+    /// At most the 64 most recent events are kept; [`DeadlineStats::overruns`] counts all of them.
+    pub fn drain_overruns(&mut self) -> Vec<Overrun> {
+        self.watchdog.as_mut().map_or_else(Vec::new, |watchdog| watchdog.pending.drain(..).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overruns_are_counted_and_drained() {
+        let mut aec = FdafAec::new(512, 0.5);
+        assert_eq!(aec.frame_duration(), Duration::from_millis(16));
+        assert_eq!(aec.deadline_stats(), None);
+
+        aec.set_deadline(Some(Duration::ZERO));
+        for _ in 0..3 {
+            aec.process(&[0.1; 256], &[0.1; 256]);
+        }
+        let stats = aec.deadline_stats().unwrap();
+        assert_eq!((stats.frames, stats.overruns), (3, 3));
+        assert!(stats.worst >= stats.last);
+        let frames: Vec<u64> = aec.drain_overruns().iter().map(|o| o.frame).collect();
+        assert_eq!(frames, [0, 1, 2]);
+        assert!(aec.drain_overruns().is_empty());
+
+        aec.set_deadline(Some(Duration::from_secs(60)));
+        aec.process(&[0.1; 256], &[0.1; 256]);
+        assert_eq!(aec.deadline_stats().unwrap().overruns, 0);
+    }
+
+    #[test]
+    fn pending_events_are_bounded() {
+        let mut watchdog = DeadlineWatchdog::new(Duration::ZERO);
+        for frame in 0..100 {
+            watchdog.record(frame, Duration::from_micros(1));
+        }
+        assert_eq!(watchdog.stats.overruns, 100);
+        assert_eq!(watchdog.pending.len(), MAX_PENDING_OVERRUNS);
+        assert_eq!(watchdog.pending[0].frame, 36);
+    }
+}
@@ -0,0 +1,220 @@
+//! Far-end watermarking for end-to-end alignment validation, enabled with the `watermark`
+//! feature.
+//!
+//! The delay and level the canceller estimates are hard to verify on a real device: the true
+//! acoustic delay depends on buffering deep inside the audio stack. A [`WatermarkInjector`]
+//! adds a quiet pilot, a periodic maximum-length sequence (MLS), to the rendered far-end. The
+//! matching [`WatermarkDetector`] averages the captured microphone signal over the periods of
+//! the sequence and cross-correlates it with the sequence, which yields the impulse response
+//! from render to capture: its peak is the true delay and level, against which the
+//! canceller's estimates can be checked. The pilot sits far below the programme material and
+//! only needs to be present during validation runs, not in production builds.
+
+use num_complex::Complex;
+use rustfft::FftPlanner;
+
+/// Feedback taps of a maximal-length Fibonacci LFSR for every supported order.
+const LFSR_TAPS: [(u32, &[u32]); 9] = [
+    (8, &[8, 6, 5, 4]),
+    (9, &[9, 5]),
+    (10, &[10, 7]),
+    (11, &[11, 9]),
+    (12, &[12, 6, 4, 1]),
+    (13, &[13, 4, 3, 1]),
+    (14, &[14, 5, 3, 1]),
+    (15, &[15, 14]),
+    (16, &[16, 15, 13, 4]),
+];
+
+/// Parameters of the watermark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WatermarkConfig {
+    /// The level of the pilot, in dBFS.
+    pub level_dbfs: f32,
+    /// The order of the maximum-length sequence, from 8 to 16. The period is `2^order - 1`
+    /// samples and must exceed the largest delay to be measured.
+    pub order: u32,
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self { level_dbfs: -40.0, order: 12 }
+    }
+}
+
+/// The end-to-end delay and level measured by a [`WatermarkDetector`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatermarkMeasurement {
+    /// The delay from render to capture, in samples.
+    pub delay_samples: usize,
+    /// The linear gain from render to capture at that delay.
+    pub gain: f32,
+    /// The correlation peak relative to the RMS of all other lags, in dB. Values below
+    /// about 15 dB indicate that the pilot is buried in noise and the result is unreliable.
+    pub peak_to_noise_db: f32,
+    /// The number of complete sequence periods averaged.
+    pub periods: u64,
+}
+
+/// Adds the pilot to the far-end before it is rendered.
+#[derive(Debug, Clone)]
+pub struct WatermarkInjector {
+    sequence: Vec<f32>,
+    amplitude: f32,
+    position: usize,
+}
+
+impl WatermarkInjector {
+    /// Creates an injector. Its first sample is aligned with the first sample of the
+    /// detectors created with [`WatermarkInjector::detector`].
+    pub fn new(config: WatermarkConfig) -> Self {
+        Self { sequence: mls(config.order), amplitude: 10f32.powf(config.level_dbfs / 20.0), position: 0 }
+    }
+
+    /// Returns a detector for the captured microphone signal.
+    ///
+    /// The measured delay is relative to the start of both streams, so the first sample
+    /// passed to [`WatermarkDetector::process`] must be captured at the time the first
+    /// sample passed to [`WatermarkInjector::process`] is rendered.
+    pub fn detector(&self) -> WatermarkDetector {
+        WatermarkDetector {
+            sequence: self.sequence.clone(),
+            amplitude: self.amplitude,
+            sum: vec![0.0; self.sequence.len()],
+            position: 0,
+            periods: 0,
+        }
+    }
+
+    /// Adds the pilot to a block of the rendered far-end.
+    pub fn process(&mut self, far_end: &mut [f32]) {
+        for sample in far_end {
+            *sample += self.amplitude * self.sequence[self.position];
+            self.position = (self.position + 1) % self.sequence.len();
+        }
+    }
+}
+
+/// Measures the delay and level of the pilot in the captured microphone signal.
+#[derive(Debug, Clone)]
+pub struct WatermarkDetector {
+    sequence: Vec<f32>,
+    amplitude: f32,
+    /// Sum of the microphone samples at every position of the sequence period.
+    sum: Vec<f64>,
+    position: usize,
+    periods: u64,
+}
+
+impl WatermarkDetector {
+    /// Accumulates a block of the captured microphone signal.
+    pub fn process(&mut self, mic: &[f32]) {
+        for &sample in mic {
+            self.sum[self.position] += f64::from(sample);
+            self.position += 1;
+            if self.position == self.sum.len() {
+                self.position = 0;
+                self.periods += 1;
+            }
+        }
+    }
+
+    /// Returns the measurement over all complete periods so far, or `None` before the first
+    /// period has been captured.
+    pub fn measurement(&self) -> Option<WatermarkMeasurement> {
+        if self.periods == 0 {
+            return None;
+        }
+        // Circular cross-correlation of the averaged period with the sequence, r[k] =
+        // sum_n avg[n + k] * s[n]. For a pilot g * a * s[n - d] it is g * a * N at k = d.
+        let len = self.sum.len();
+        let mut planner = FftPlanner::<f64>::new();
+        let (fft, ifft) = (planner.plan_fft_forward(len), planner.plan_fft_inverse(len));
+        let mut average: Vec<Complex<f64>> =
+            self.sum.iter().map(|&x| Complex::new(x / self.periods as f64, 0.0)).collect();
+        let mut sequence: Vec<Complex<f64>> = self.sequence.iter().map(|&s| Complex::new(f64::from(s), 0.0)).collect();
+        fft.process(&mut average);
+        fft.process(&mut sequence);
+        let mut correlation: Vec<Complex<f64>> = average.iter().zip(&sequence).map(|(a, s)| a * s.conj()).collect();
+        ifft.process(&mut correlation);
+        let correlation: Vec<f64> = correlation.iter().map(|c| c.re / len as f64).collect();
+
+        let (delay, peak) = correlation
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            .unwrap_or((0, 0.0));
+        let noise = correlation.iter().enumerate().filter(|&(k, _)| k != delay).map(|(_, r)| r * r).sum::<f64>()
+            / (len - 1) as f64;
+        Some(WatermarkMeasurement {
+            delay_samples: delay,
+            gain: (peak / (f64::from(self.amplitude) * len as f64)) as f32,
+            peak_to_noise_db: (10.0 * (peak * peak / noise.max(1e-30)).log10()) as f32,
+            periods: self.periods,
+        })
+    }
+}
+
+/// Returns one period of a ±1 maximum-length sequence of the given order.
+fn mls(order: u32) -> Vec<f32> {
+    let taps = LFSR_TAPS
+        .iter()
+        .find(|(o, _)| *o == order)
+        .map(|(_, taps)| *taps)
+        .expect("Watermark order must be between 8 and 16.");
+    let mut state: u32 = 1;
+    (0..(1u32 << order) - 1)
+        .map(|_| {
+            let output = state & 1;
+            let feedback = taps.iter().fold(0, |bit, &tap| bit ^ ((state >> (order - tap)) & 1));
+            state = (state >> 1) | (feedback << (order - 1));
+            if output == 1 { 1.0 } else { -1.0 }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+    use crate::FdafAec;
+
+    #[test]
+    fn sequences_are_maximal() {
+        for (order, _) in LFSR_TAPS {
+            let s = mls(order);
+            let len = s.len();
+            // A maximum-length sequence has a two-valued circular autocorrelation.
+            for lag in [1, 2, len / 3, len - 1] {
+                let r: f32 = (0..len).map(|n| s[n] * s[(n + lag) % len]).sum();
+                assert_eq!(r, -1.0, "order {} lag {}", order, lag);
+            }
+        }
+    }
+
+    #[test]
+    fn measures_delay_under_programme_material() {
+        let mut injector = WatermarkInjector::new(WatermarkConfig::default());
+        let mut detector = injector.detector();
+        assert_eq!(detector.measurement(), None);
+
+        let mut far = white_noise(4095 * 40, 0.3, 55);
+        injector.process(&mut far);
+        let mic = echo(&far, &[(37, 0.5)]);
+        let mut aec = FdafAec::new(512, 0.5);
+        for (far_frame, mic_frame) in far.chunks_exact(256).zip(mic.chunks_exact(256)) {
+            aec.process(far_frame, mic_frame);
+            detector.process(mic_frame);
+        }
+
+        let measurement = detector.measurement().unwrap();
+        assert_eq!(measurement.periods, 39);
+        assert_eq!(measurement.delay_samples, 37);
+        assert!((measurement.gain - 0.5).abs() < 0.05, "gain {}", measurement.gain);
+        assert!(measurement.peak_to_noise_db > 15.0, "peak to noise {}", measurement.peak_to_noise_db);
+        // The ground truth validates the canceller's own delay estimate.
+        assert_eq!(aec.dominant_tap(), measurement.delay_samples);
+    }
+}
@@ -0,0 +1,251 @@
+//! A compatibility adapter for applications integrated with `webrtc-audio-processing`.
+//!
+//! Teams evaluating this crate usually have an integration built around the
+//! `webrtc-audio-processing` crate: interleaved 10 ms frames, render audio handed over with
+//! `process_render_frame` (`ProcessReverseStream` in the C++ API), capture audio cancelled in
+//! place with `process_capture_frame` (`ProcessStream`), and a stream delay hint set with
+//! `set_stream_delay_ms`. [`Processor`] implements that subset of the API on top of one
+//! [`FdafAec`] per capture channel, so the canceller can be swapped in behind the existing
+//! integration.
+//!
+//! Render and capture frames are matched in the order they arrive. The render channels are
+//! averaged into a single reference, which is delayed by the stream delay before it reaches
+//! the cancellers. Since 10 ms frames rarely match the canceller frame size, the capture
+//! output is delayed by one canceller frame; see [`Processor::latency_samples`].
+
+use crate::{DelayLine, EchoCancellerFactory, FdafAec, FdafAecConfig};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The largest amount of render audio, in seconds, queued ahead of the capture stream.
+/// Older render samples are dropped.
+const MAX_RENDER_QUEUE_SECONDS: usize = 1;
+
+/// The channel layout and sample rate of a [`Processor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InitializationConfig {
+    /// The number of interleaved channels in a capture frame.
+    pub num_capture_channels: usize,
+    /// The number of interleaved channels in a render frame.
+    pub num_render_channels: usize,
+    /// The sample rate of both streams, in Hz.
+    pub sample_rate_hz: u32,
+}
+
+impl Default for InitializationConfig {
+    fn default() -> Self {
+        Self { num_capture_channels: 1, num_render_channels: 1, sample_rate_hz: 48000 }
+    }
+}
+
+/// The error returned by [`Processor`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A channel count is zero.
+    NoChannels,
+    /// The sample rate is not a multiple of 100 Hz, so it has no whole 10 ms frames.
+    UnsupportedSampleRate(u32),
+    /// The canceller configuration was rejected.
+    InvalidConfig(crate::ConfigError),
+    /// A frame does not hold 10 ms of audio for every channel.
+    FrameSize { expected: usize, found: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoChannels => write!(f, "channel counts must be non-zero"),
+            Error::UnsupportedSampleRate(rate) => write!(f, "sample rate {} Hz has no whole 10 ms frames", rate),
+            Error::InvalidConfig(error) => write!(f, "invalid canceller configuration: {}", error),
+            Error::FrameSize { expected, found } => write!(f, "frame of {} samples, expected {}", found, expected),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Statistics in the shape reported by `webrtc-audio-processing`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Stats {
+    /// The echo return loss of the first capture channel, in dB.
+    pub echo_return_loss: Option<f64>,
+    /// The long-term echo return loss enhancement of the first capture channel, in dB.
+    pub echo_return_loss_enhancement: Option<f64>,
+    /// The stream delay currently applied to the render reference, in milliseconds.
+    pub delay_ms: Option<i32>,
+}
+
+/// Echo cancellation with the frame interface of `webrtc-audio-processing`.
+pub struct Processor {
+    cancellers: Vec<FdafAec>,
+    num_render_channels: usize,
+    samples_per_frame: usize,
+    sample_rate: u32,
+    frame_size: usize,
+    stream_delay_ms: Option<i32>,
+    render_delay: DelayLine,
+    render_queue: VecDeque<f32>,
+    far_end: Vec<f32>,
+    mic: Vec<Vec<f32>>,
+    output: Vec<VecDeque<f32>>,
+}
+
+impl Processor {
+    /// Creates a processor with the default canceller configuration for the sample rate, see
+    /// [`FdafAecConfig::for_sample_rate`].
+    pub fn new(config: &InitializationConfig) -> Result<Self, Error> {
+        Self::with_config(config, FdafAecConfig::for_sample_rate(config.sample_rate_hz))
+    }
+
+    /// Creates a processor whose capture channels use `aec_config`. Its sample rate is
+    /// replaced by the one of `config`.
+    pub fn with_config(config: &InitializationConfig, aec_config: FdafAecConfig) -> Result<Self, Error> {
+        if config.num_capture_channels == 0 || config.num_render_channels == 0 {
+            return Err(Error::NoChannels);
+        }
+        let sample_rate = config.sample_rate_hz;
+        if sample_rate == 0 || !sample_rate.is_multiple_of(100) {
+            return Err(Error::UnsupportedSampleRate(sample_rate));
+        }
+        let aec_config = FdafAecConfig { sample_rate, far_end_sample_rate: None, ..aec_config };
+        let factory = EchoCancellerFactory::new(aec_config).map_err(Error::InvalidConfig)?;
+        let frame_size = factory.config().fft_size / 2;
+        let channels = config.num_capture_channels;
+        Ok(Self {
+            cancellers: (0..channels).map(|_| factory.create()).collect(),
+            num_render_channels: config.num_render_channels,
+            samples_per_frame: sample_rate as usize / 100,
+            sample_rate,
+            frame_size,
+            stream_delay_ms: None,
+            render_delay: DelayLine::new(0),
+            render_queue: VecDeque::new(),
+            far_end: Vec::with_capacity(frame_size),
+            mic: vec![Vec::with_capacity(frame_size); channels],
+            output: vec![std::iter::repeat_n(0.0, frame_size).collect(); channels],
+        })
+    }
+
+    /// Returns the number of samples per channel in a 10 ms frame.
+    pub fn num_samples_per_frame(&self) -> usize {
+        self.samples_per_frame
+    }
+
+    /// Returns the delay, in samples per channel, between the capture input and the output.
+    pub fn latency_samples(&self) -> usize {
+        self.frame_size + self.cancellers[0].latency_samples()
+    }
+
+    /// Returns the canceller of a capture channel, e.g. to query its state.
+    pub fn canceller(&self, capture_channel: usize) -> &FdafAec {
+        &self.cancellers[capture_channel]
+    }
+
+    /// Sets the delay, in milliseconds, between a render frame being handed over and its
+    /// echo reaching the capture stream. The render reference is delayed by this amount.
+    /// Negative values are treated as zero.
+    pub fn set_stream_delay_ms(&mut self, delay_ms: i32) {
+        let delay_ms = delay_ms.max(0);
+        self.stream_delay_ms = Some(delay_ms);
+        self.render_delay.set_delay(delay_ms as usize * self.sample_rate as usize / 1000);
+    }
+
+    /// Returns the stream delay set with [`Processor::set_stream_delay_ms`].
+    pub fn stream_delay_ms(&self) -> Option<i32> {
+        self.stream_delay_ms
+    }
+
+    /// Hands over an interleaved 10 ms render frame, as played out. The frame is not
+    /// modified.
+    pub fn process_render_frame(&mut self, frame: &mut [f32]) -> Result<(), Error> {
+        self.check_frame(frame, self.num_render_channels)?;
+        let channels = self.num_render_channels as f32;
+        self.render_queue.extend(frame.chunks_exact(self.num_render_channels).map(|s| s.iter().sum::<f32>() / channels));
+        let capacity = MAX_RENDER_QUEUE_SECONDS * self.sample_rate as usize;
+        if self.render_queue.len() > capacity {
+            self.render_queue.drain(..self.render_queue.len() - capacity);
+        }
+        Ok(())
+    }
+
+    /// Cancels echo in an interleaved 10 ms capture frame, in place.
+    pub fn process_capture_frame(&mut self, frame: &mut [f32]) -> Result<(), Error> {
+        let channels = self.cancellers.len();
+        self.check_frame(frame, channels)?;
+        // Render audio that has not arrived in time is treated as silence.
+        let mut far: Vec<f32> = (0..self.samples_per_frame).map(|_| self.render_queue.pop_front().unwrap_or(0.0)).collect();
+        self.render_delay.process(&mut far);
+
+        for (capture, far) in frame.chunks_exact_mut(channels).zip(far) {
+            self.far_end.push(far);
+            for (mic, &sample) in self.mic.iter_mut().zip(capture.iter()) {
+                mic.push(sample);
+            }
+            if self.far_end.len() == self.frame_size {
+                let channels = self.cancellers.iter_mut().zip(self.mic.iter_mut()).zip(self.output.iter_mut());
+                for ((aec, mic), output) in channels {
+                    output.extend(aec.process(&self.far_end, mic));
+                    mic.clear();
+                }
+                self.far_end.clear();
+            }
+            for (sample, output) in capture.iter_mut().zip(self.output.iter_mut()) {
+                *sample = output.pop_front().unwrap_or(0.0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the current statistics.
+    pub fn get_stats(&self) -> Stats {
+        let erle = self.cancellers[0].quality.erle();
+        Stats {
+            echo_return_loss: self.cancellers[0].echo_return_loss_db().map(f64::from),
+            echo_return_loss_enhancement: Some(10.0 * (erle.max(1e-10) as f64).log10()),
+            delay_ms: self.stream_delay_ms,
+        }
+    }
+
+    fn check_frame(&self, frame: &[f32], channels: usize) -> Result<(), Error> {
+        let expected = self.samples_per_frame * channels;
+        if frame.len() != expected {
+            return Err(Error::FrameSize { expected, found: frame.len() });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, white_noise};
+
+    #[test]
+    fn cancels_delayed_echo_in_10ms_frames() {
+        let config = InitializationConfig { sample_rate_hz: 16000, ..InitializationConfig::default() };
+        let mut processor = Processor::with_config(&config, FdafAecConfig { fft_size: 512, ..FdafAecConfig::default() }).unwrap();
+        assert_eq!(processor.num_samples_per_frame(), 160);
+        // The echo arrives 30 ms after the render frame, beyond the 256-sample filter.
+        processor.set_stream_delay_ms(30);
+
+        let far = white_noise(160 * 500, 0.3, 82);
+        let mic = echo(&far, &[(480 + 20, 0.5), (480 + 60, -0.2)]);
+        let mut output = Vec::new();
+        for (render, capture) in far.chunks(160).zip(mic.chunks(160)) {
+            processor.process_render_frame(&mut render.to_vec()).unwrap();
+            let mut capture = capture.to_vec();
+            processor.process_capture_frame(&mut capture).unwrap();
+            output.extend(capture);
+        }
+
+        let latency = processor.latency_samples();
+        let tail = output.len() - 160 * 50;
+        assert!(crate::mean_square(&output[tail..]) < crate::mean_square(&mic[tail - latency..output.len() - latency]) / 100.0);
+        assert!(processor.get_stats().echo_return_loss_enhancement.unwrap() > 20.0);
+        assert_eq!(
+            processor.process_capture_frame(&mut [0.0; 100]),
+            Err(Error::FrameSize { expected: 160, found: 100 })
+        );
+    }
+}
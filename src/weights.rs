@@ -0,0 +1,176 @@
+//! Export and import of the adaptive filter weights.
+//!
+//! A [`DeviceProfile`](crate::DeviceProfile) bundles the weights with device metadata and a
+//! state snapshot adds the far-end history; applications that manage persistence themselves
+//! often only want the learned echo path. The weights are exported either as they are kept,
+//! one complex value per FFT bin, or as the time-domain impulse response, which is
+//! independent of the FFT implementation and can be inspected or edited directly.
+//!
+//! Weights alone are not enough for a warm start: the update is normalized by the far-end
+//! PSD, and a new canceller starts from a PSD far below the real far-end level, so its first
+//! updates are large enough to wreck an imported filter. The export therefore carries the
+//! mean far-end power, which seeds the PSD on import like
+//! [`FdafAec::transfer_echo_path_from`] does. With the `serde` feature enabled, exported
+//! weights can be serialized with any serde format.
+
+use crate::FdafAec;
+use nalgebra::DVector;
+use num_complex::Complex;
+use std::fmt;
+
+/// The representation of exported filter weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WeightDomain {
+    /// The impulse response of the echo path, `fft_size / 2` taps.
+    Time,
+    /// The frequency-domain weights, one complex value per FFT bin.
+    Frequency,
+}
+
+/// The adaptive filter weights of a canceller, as returned by [`FdafAec::export_weights`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FilterWeights {
+    /// The filter coefficients.
+    pub values: WeightValues,
+    /// The mean-square level of the far-end signal the filter was adapted on, i.e. the mean
+    /// far-end PSD divided by the FFT size. 0.0 leaves the PSD untouched on import.
+    pub far_end_power: f32,
+}
+
+/// The filter coefficients in one of the [`WeightDomain`] representations.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WeightValues {
+    /// The impulse response of the echo path. Shorter responses are zero-padded on import.
+    Time(Vec<f32>),
+    /// The frequency-domain weights, one complex value per FFT bin.
+    Frequency(Vec<Complex<f32>>),
+}
+
+/// The error returned when weights cannot be imported into a canceller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WeightsError {
+    /// The impulse response has more taps than the filter.
+    TooManyTaps { max: usize, found: usize },
+    /// The frequency-domain weights have a different number of bins than the FFT size.
+    FftSizeMismatch { expected: usize, found: usize },
+    /// The weights or the far-end power contain NaN, infinite, or negative values.
+    NonFinite,
+}
+
+impl fmt::Display for WeightsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WeightsError::TooManyTaps { max, found } => {
+                write!(f, "impulse response has {} taps, the filter at most {}", found, max)
+            }
+            WeightsError::FftSizeMismatch { expected, found } => {
+                write!(f, "weights FFT size {} does not match canceller FFT size {}", found, expected)
+            }
+            WeightsError::NonFinite => write!(f, "weights contain NaN, infinite, or negative values"),
+        }
+    }
+}
+
+impl std::error::Error for WeightsError {}
+
+impl FdafAec {
+    /// Returns the adaptive filter weights in the requested representation.
+    pub fn export_weights(&self, domain: WeightDomain) -> FilterWeights {
+        let values = match domain {
+            WeightDomain::Time => WeightValues::Time(self.impulse_response()),
+            WeightDomain::Frequency => WeightValues::Frequency(self.weights.as_slice().to_vec()),
+        };
+        FilterWeights { values, far_end_power: self.psd.mean() / self.fft_size as f32 }
+    }
+
+    /// Replaces the adaptive filter weights, e.g. with the weights exported at the end of the
+    /// previous session on the same device, so that processing starts warm.
+    ///
+    /// The far-end PSD is seeded with the exported far-end power. The far-end history and
+    /// all statistics are kept.
+    pub fn import_weights(&mut self, weights: &FilterWeights) -> Result<(), WeightsError> {
+        if !weights.far_end_power.is_finite() || weights.far_end_power < 0.0 {
+            return Err(WeightsError::NonFinite);
+        }
+        let values = match &weights.values {
+            WeightValues::Time(taps) => {
+                if taps.len() > self.frame_size {
+                    return Err(WeightsError::TooManyTaps { max: self.frame_size, found: taps.len() });
+                }
+                if !taps.iter().all(|tap| tap.is_finite()) {
+                    return Err(WeightsError::NonFinite);
+                }
+                let mut h = vec![Complex::new(0.0, 0.0); self.fft_size];
+                for (h, &tap) in h.iter_mut().zip(taps) {
+                    *h = Complex::new(tap, 0.0);
+                }
+                self.fft.process(&mut h);
+                h
+            }
+            WeightValues::Frequency(bins) => {
+                if bins.len() != self.fft_size {
+                    return Err(WeightsError::FftSizeMismatch { expected: self.fft_size, found: bins.len() });
+                }
+                if !bins.iter().all(|w| w.is_finite()) {
+                    return Err(WeightsError::NonFinite);
+                }
+                bins.clone()
+            }
+        };
+        self.weights = DVector::from_vec(values);
+        if weights.far_end_power > 0.0 {
+            self.psd.fill((weights.far_end_power * self.fft_size as f32).max(self.normalization.psd_floor));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{echo, process_all, white_noise};
+
+    #[test]
+    fn imported_weights_start_warm() {
+        let far = white_noise(256 * 120, 0.3, 97);
+        let mic = echo(&far, &[(12, 0.5), (40, -0.2)]);
+        let mut trained = FdafAec::new(512, 0.5);
+        for (f, m) in far.chunks(256).zip(mic.chunks(256)).take(100) {
+            trained.process(f, m);
+        }
+
+        for domain in [WeightDomain::Time, WeightDomain::Frequency] {
+            let weights = trained.export_weights(domain);
+            let mut aec = FdafAec::new(512, 0.5);
+            aec.import_weights(&weights).unwrap();
+            let restored = aec.export_weights(domain);
+            assert!((restored.far_end_power / weights.far_end_power - 1.0).abs() < 1e-4);
+            match (&restored.values, &weights.values) {
+                (WeightValues::Time(restored), WeightValues::Time(taps)) => {
+                    assert!(restored.iter().zip(taps).all(|(a, b)| (a - b).abs() < 1e-6));
+                }
+                (restored, values) => assert_eq!(restored, values),
+            }
+
+            // After the first frame, which only fills the far-end history, the echo is gone.
+            let start = 100 * 256;
+            let output = process_all(&mut aec, &far[start..], &mic[start..]);
+            let settled = 256..5 * 256;
+            assert!(
+                crate::mean_square(&output[settled.clone()]) < crate::mean_square(&mic[start..][settled]) / 100.0,
+                "{:?}",
+                domain
+            );
+        }
+
+        let mut aec = FdafAec::new(512, 0.5);
+        let import = |aec: &mut FdafAec, values| aec.import_weights(&FilterWeights { values, far_end_power: 0.0 });
+        assert_eq!(import(&mut aec, WeightValues::Time(vec![0.0; 300])), Err(WeightsError::TooManyTaps { max: 256, found: 300 }));
+        assert_eq!(
+            import(&mut aec, WeightValues::Frequency(vec![Complex::new(0.0, 0.0); 1024])),
+            Err(WeightsError::FftSizeMismatch { expected: 512, found: 1024 })
+        );
+    }
+}
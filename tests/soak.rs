@@ -0,0 +1,112 @@
+//! Long-call soak test.
+//!
+//! Simulates a two-hour call with a slowly wandering echo delay, periodic render volume
+//! changes, far-end pauses and a near-end noise floor, and checks every minute that the
+//! canceller still removes echo and stays numerically healthy. Slow degradation (drifting
+//! weights, denormals, creeping PSD estimates) only shows up after hours of audio, which is
+//! why the test is ignored by default. Run it in release mode:
+//!
+//! ```sh
+//! cargo test --release --test soak -- --ignored
+//! ```
+//!
+//! `SOAK_MINUTES` overrides the simulated call length.
+
+use fdaf_aec::{FdafAec, FdafAecConfig, VolumeRampConfig};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const SAMPLE_RATE: usize = 16000;
+const FRAME_SIZE: usize = 512;
+const FRAMES_PER_MINUTE: usize = 60 * SAMPLE_RATE / FRAME_SIZE;
+/// The render volume toggles between two levels every five minutes.
+const VOLUME_PERIOD_FRAMES: usize = 5 * FRAMES_PER_MINUTE;
+const VOLUMES: [f32; 2] = [1.0, 0.4];
+/// The echo delay wanders by this many samples around its mean, once every ten minutes.
+const DELAY_WANDER: f64 = 4.0;
+const DELAY_PERIOD_SAMPLES: f64 = 10.0 * 60.0 * SAMPLE_RATE as f64;
+const MEAN_DELAY: f64 = 40.0;
+/// The far-end talks for three seconds and pauses for one.
+const TALK_CYCLE_FRAMES: usize = 4 * SAMPLE_RATE / FRAME_SIZE;
+const PAUSE_FRAMES: usize = SAMPLE_RATE / FRAME_SIZE;
+/// The smallest ERLE, in dB, accepted for any minute after the first.
+const MIN_ERLE_DB: f32 = 15.0;
+
+/// Renders the far-end through a room with a fractional, slowly wandering delay.
+struct EchoPath {
+    history: Vec<f32>,
+    written: usize,
+}
+
+impl EchoPath {
+    const HISTORY: usize = 256;
+
+    fn new() -> Self {
+        Self { history: vec![0.0; Self::HISTORY], written: 0 }
+    }
+
+    fn process(&mut self, far_end: f32, gain: f32) -> f32 {
+        self.history[self.written % Self::HISTORY] = far_end;
+        let t = self.written as f64;
+        self.written += 1;
+
+        let delay = MEAN_DELAY + DELAY_WANDER * (2.0 * std::f64::consts::PI * t / DELAY_PERIOD_SAMPLES).sin();
+        let tap = |delay: f64| {
+            let (whole, frac) = (delay.floor() as usize, delay.fract() as f32);
+            let at = |d: usize| self.history[(self.written - 1 + Self::HISTORY - d) % Self::HISTORY];
+            at(whole) * (1.0 - frac) + at(whole + 1) * frac
+        };
+        gain * (0.5 * tap(delay) - 0.2 * tap(delay + 60.0) + 0.1 * tap(delay + 150.0))
+    }
+}
+
+#[test]
+#[ignore = "simulates a two-hour call; run with --ignored in release mode"]
+fn two_hour_call_stays_healthy() {
+    let minutes: usize = std::env::var("SOAK_MINUTES").ok().and_then(|m| m.parse().ok()).unwrap_or(120);
+    let config = FdafAecConfig {
+        fft_size: 2 * FRAME_SIZE,
+        step_size: 0.1,
+        volume_ramp: Some(VolumeRampConfig::default()),
+        ..FdafAecConfig::default()
+    };
+    let mut aec = FdafAec::with_config(config);
+    aec.set_diagnostics(true);
+    let mut path = EchoPath::new();
+    let mut rng = StdRng::seed_from_u64(2024);
+
+    let mut far = vec![0.0; FRAME_SIZE];
+    let mut mic = vec![0.0; FRAME_SIZE];
+    for minute in 0..minutes {
+        let (mut echo_energy, mut residual_energy) = (0.0f64, 0.0f64);
+        for frame in minute * FRAMES_PER_MINUTE..(minute + 1) * FRAMES_PER_MINUTE {
+            let volume = VOLUMES[(frame / VOLUME_PERIOD_FRAMES) % 2];
+            if frame > 0 && frame % VOLUME_PERIOD_FRAMES == 0 {
+                aec.notify_render_volume_change(volume / VOLUMES[(frame / VOLUME_PERIOD_FRAMES + 1) % 2]);
+            }
+            let talking = frame % TALK_CYCLE_FRAMES >= PAUSE_FRAMES;
+            for (f, m) in far.iter_mut().zip(mic.iter_mut()) {
+                *f = if talking { rng.gen_range(-0.3..0.3) } else { 0.0 };
+                // Near-end noise floor at about -70 dBFS.
+                *m = path.process(*f, volume) + rng.gen_range(-5e-4..5e-4);
+            }
+
+            let output = aec.process(&far, &mic);
+            assert!(output.iter().all(|x| x.is_finite()), "non-finite output in frame {}", frame);
+            if talking {
+                echo_energy += mic.iter().map(|&x| f64::from(x * x)).sum::<f64>();
+                residual_energy += output.iter().map(|&x| f64::from(x * x)).sum::<f64>();
+            }
+        }
+
+        let erle_db = (10.0 * (echo_energy / residual_energy.max(1e-30)).log10()) as f32;
+        let gain = aec.echo_path_gain();
+        println!("minute {:>3}: ERLE {:>5.1} dB, echo path gain {:.3}", minute + 1, erle_db, gain);
+        assert!(gain.is_finite() && gain < 1.0, "minute {}: echo path gain {}", minute + 1, gain);
+        assert!(aec.impulse_response().iter().all(|h| h.is_finite()), "minute {}: non-finite weights", minute + 1);
+        assert!(aec.health().unwrap().is_healthy(), "minute {}: {:?}", minute + 1, aec.health());
+        if minute > 0 {
+            assert!(erle_db > MIN_ERLE_DB, "minute {}: ERLE {:.1} dB", minute + 1, erle_db);
+        }
+    }
+}